@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -7,6 +8,10 @@ pub struct Config {
     pub model: ModelConfig,
     pub inference: InferenceConfig,
     pub queue: QueueConfig,
+    #[serde(default)]
+    pub health: HealthConfig,
+    #[serde(default)]
+    pub logging: LoggingConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -17,15 +22,170 @@ pub struct ServerConfig {
     pub rest_port: u16,
     pub max_concurrent_requests: usize,
     pub request_timeout_seconds: u64,
+    /// Key required in the `X-Admin-Key` header for admin endpoints.
+    /// Admin endpoints are disabled when unset.
+    #[serde(default)]
+    pub admin_key: Option<String>,
+    #[serde(default = "default_max_upload_bytes")]
+    pub max_upload_bytes: usize,
+    #[serde(default = "default_upload_ttl_seconds")]
+    pub upload_ttl_seconds: u64,
+    /// Reject REST bodies with unknown fields (400) instead of ignoring them
+    #[serde(default)]
+    pub strict_request_fields: bool,
+    /// Send ETags for seeded binary generations and honor If-None-Match
+    #[serde(default = "default_true")]
+    pub etag_enabled: bool,
+    #[serde(default = "default_true")]
+    pub rest_enabled: bool,
+    #[serde(default = "default_true")]
+    pub grpc_enabled: bool,
+    /// REST route paths to expose, e.g. "/v1/generate". All routes are
+    /// exposed when unset; others answer 404.
+    #[serde(default)]
+    pub rest_routes: Option<Vec<String>>,
+    /// Limits on the client metadata echoed back with each response
+    #[serde(default = "default_max_metadata_entries")]
+    pub max_metadata_entries: usize,
+    #[serde(default = "default_max_metadata_bytes")]
+    pub max_metadata_bytes: usize,
+    #[serde(default)]
+    pub maintenance: MaintenanceConfig,
+    /// Directory of `<name>.json` recipes requests can name as their base.
+    /// Recipes are disabled when unset.
+    #[serde(default)]
+    pub recipes_dir: Option<PathBuf>,
+    /// Download file name, without extension. `{prompt}` (slugified),
+    /// `{seed}` and `{index}` are substituted.
+    #[serde(default = "default_download_filename")]
+    pub download_filename: String,
+    /// Output format for REST / gRPC requests that don't set one; falls
+    /// back to `inference.output_format`
+    #[serde(default)]
+    pub rest_output_format: Option<String>,
+    #[serde(default)]
+    pub grpc_output_format: Option<String>,
+}
+
+/// Planned downtime: generation is refused with 503 while health and admin
+/// endpoints keep answering
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MaintenanceConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_maintenance_message")]
+    pub message: String,
+    /// Seconds sent in `Retry-After`
+    #[serde(default)]
+    pub retry_after: Option<u64>,
+}
+
+impl Default for MaintenanceConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            message: default_maintenance_message(),
+            retry_after: None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ModelConfig {
     pub model_path: PathBuf,
     pub device: String,
+    /// Default precision, "fp32", "fp16" or "bf16" (CUDA only); checked
+    /// against the device at startup
     pub precision: String,
     pub cache_dir: PathBuf,
+    /// Run `warmup_runs` small generations before accepting traffic, so
+    /// CUDA kernels are compiled before the first real request
     pub warmup_on_start: bool,
+    #[serde(default = "default_warmup_runs")]
+    pub warmup_runs: u32,
+    /// Width and height of each warmup generation
+    #[serde(default = "default_warmup_size")]
+    pub warmup_size: i32,
+    #[serde(default = "default_warmup_steps")]
+    pub warmup_steps: i32,
+    #[serde(default = "default_model_id")]
+    pub default_model: String,
+    #[serde(default)]
+    pub models: Vec<ModelDefinition>,
+    #[serde(default = "default_max_concurrent_loads")]
+    pub max_concurrent_loads: usize,
+    #[serde(default)]
+    pub unload_after_idle_seconds: Option<u64>,
+    /// Skip loading weights and the GPU entirely, generating deterministic
+    /// placeholder images instead. Everything else (validation, queueing,
+    /// seeds, metadata) runs as normal, so the server can be exercised in CI.
+    #[serde(default)]
+    pub mock_inference: bool,
+    /// Noise schedule for models that don't define their own
+    #[serde(default)]
+    pub noise_schedule: NoiseScheduleConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelDefinition {
+    pub id: String,
+    pub path: PathBuf,
+    /// Scheduler used when a request for this model doesn't name one
+    #[serde(default)]
+    pub default_scheduler: Option<String>,
+    /// Must match the schedule the checkpoint was trained with
+    #[serde(default)]
+    pub noise_schedule: Option<NoiseScheduleConfig>,
+    /// Trigger words or style text added around every prompt for this
+    /// model, unless the request sets `disable_auto_prompt`
+    #[serde(default)]
+    pub prompt_prefix: Option<String>,
+    #[serde(default)]
+    pub prompt_suffix: Option<String>,
+    #[serde(default)]
+    pub negative_prompt_prefix: Option<String>,
+    #[serde(default)]
+    pub negative_prompt_suffix: Option<String>,
+    /// Representative prompt and size for this model's warmup, so kernels
+    /// are compiled for the shapes it actually serves
+    #[serde(default)]
+    pub warmup_prompt: Option<String>,
+    #[serde(default)]
+    pub warmup_size: Option<i32>,
+    /// Step range and default for this model, overriding the `[inference]`
+    /// ones (e.g. 1-4 steps for turbo models)
+    #[serde(default)]
+    pub min_steps: Option<i32>,
+    #[serde(default)]
+    pub max_steps: Option<i32>,
+    #[serde(default)]
+    pub default_steps: Option<i32>,
+    /// Hidden size of the text encoder's output, which `prompt_embeds`
+    /// must match (768 when unset; 1024 for SD 2.x, 2048 for SDXL)
+    #[serde(default)]
+    pub encoder_dim: Option<u64>,
+}
+
+/// Beta schedule the scheduler is constructed with
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NoiseScheduleConfig {
+    #[serde(default = "default_beta_start")]
+    pub beta_start: f64,
+    #[serde(default = "default_beta_end")]
+    pub beta_end: f64,
+    /// "linear", "scaled_linear" or "squaredcos"
+    #[serde(default = "default_beta_schedule")]
+    pub beta_schedule: String,
+}
+
+impl Default for NoiseScheduleConfig {
+    fn default() -> Self {
+        Self {
+            beta_start: default_beta_start(),
+            beta_end: default_beta_end(),
+            beta_schedule: default_beta_schedule(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -37,7 +197,233 @@ pub struct InferenceConfig {
     pub max_width: i32,
     pub max_height: i32,
     pub max_steps: i32,
+    /// Largest side of a decoded (and upscaled) image, independent of
+    /// `max_width` / `max_height`
+    #[serde(default = "default_max_decode_dimension")]
+    pub max_decode_dimension: u32,
     pub safety_checker: bool,
+    #[serde(default = "default_max_batch_size")]
+    pub max_batch_size: i32,
+    /// Combined prompt + negative prompt tokens allowed across a whole
+    /// batch, so it fits one forward pass. Unlimited when unset.
+    #[serde(default)]
+    pub max_batch_tokens: Option<usize>,
+    /// Largest `images x steps x megapixels x upscale` a request may ask
+    /// for, catching combinations that each pass their own limit. Unlimited
+    /// when unset.
+    #[serde(default)]
+    pub max_compute_budget: Option<f64>,
+    #[serde(default = "default_scheduler")]
+    pub default_scheduler: String,
+    #[serde(default)]
+    pub moderation: ModerationConfig,
+    /// Pixels cropped from each edge of the output before encoding
+    #[serde(default)]
+    pub edge_crop_pixels: u32,
+    /// Upper bound on the number of cells in a seed search grid
+    #[serde(default = "default_max_seed_search_cells")]
+    pub max_seed_search_cells: i32,
+    /// Width and height each seed search cell is generated at
+    #[serde(default = "default_seed_search_cell_size")]
+    pub seed_search_cell_size: i32,
+    #[serde(default)]
+    pub adaptive_steps: AdaptiveStepsConfig,
+    /// Remove EXIF and text chunks from encoded outputs
+    #[serde(default = "default_true")]
+    pub strip_metadata: bool,
+    /// Snap requested sizes to the closest of `resolution_buckets`
+    #[serde(default)]
+    pub snap_to_bucket: bool,
+    /// Resolutions the model was trained on, as [width, height] pairs
+    #[serde(default)]
+    pub resolution_buckets: Vec<(u32, u32)>,
+    #[serde(default)]
+    pub face_restore: FaceRestoreConfig,
+    #[serde(default)]
+    pub degenerate_output: DegenerateOutputConfig,
+    /// Format used when neither the request nor its transport picks one
+    #[serde(default = "default_output_format")]
+    pub output_format: String,
+    /// Generate from `fallback_prompt` instead of rejecting empty prompts
+    #[serde(default)]
+    pub allow_empty_prompt_fallback: bool,
+    #[serde(default)]
+    pub fallback_prompt: String,
+    /// Luminance (0-1) at or above which `transparent_background` treats a
+    /// pixel as background
+    #[serde(default = "default_transparency_threshold")]
+    pub transparency_threshold: f64,
+    /// Report a DCT perceptual hash of every image
+    #[serde(default)]
+    pub include_phash: bool,
+    /// Downscale init images larger than the generation size instead of
+    /// rejecting them
+    #[serde(default)]
+    pub auto_resize_init: bool,
+    /// Batch images run through the VAE decode stage together; also the
+    /// number decoding at once across all generations
+    #[serde(default = "default_vae_decode_batch")]
+    pub vae_decode_batch: usize,
+    /// Uploaded images decoded at once on the blocking thread pool
+    #[serde(default = "default_max_concurrent_decodes")]
+    pub max_concurrent_decodes: usize,
+    /// Ceiling on image bytes held by in-flight generations. Unlimited when
+    /// unset.
+    #[serde(default)]
+    pub max_inflight_bytes: Option<usize>,
+    /// How long a generation waits for room under `max_inflight_bytes`
+    /// before it is rejected (0 rejects immediately)
+    #[serde(default = "default_inflight_wait_seconds")]
+    pub inflight_wait_seconds: u64,
+    /// Allow requests to ask for the final latent tensor (large responses)
+    #[serde(default)]
+    pub allow_return_latents: bool,
+    /// Allow requests to set `debug`, which logs their prompts and
+    /// parameters at trace level
+    #[serde(default)]
+    pub allow_debug_requests: bool,
+    /// Upper bound on the serialized latent size per image
+    #[serde(default = "default_max_latent_bytes")]
+    pub max_latent_bytes: usize,
+    /// Per-step time assumed by /v1/estimate until real generations have run
+    #[serde(default = "default_estimate_step_seconds")]
+    pub estimate_step_seconds: f64,
+    /// Compute price per second of generation, reported by /v1/estimate
+    #[serde(default)]
+    pub cost_per_second: Option<f64>,
+    /// File keeping the lifetime generation count across restarts
+    #[serde(default)]
+    pub generation_counter_path: Option<PathBuf>,
+    /// Directory of daily `seeds-YYYY-MM-DD.jsonl` files recording every
+    /// generation's resolved parameters and seeds
+    #[serde(default)]
+    pub seed_log_dir: Option<PathBuf>,
+    /// Prompt conditionings kept for reuse by later requests; 0 disables
+    #[serde(default = "default_conditioning_cache_size")]
+    pub conditioning_cache_size: usize,
+    /// Guidance range per scheduler name, replacing the built-in range of
+    /// that scheduler
+    #[serde(default)]
+    pub scheduler_guidance: BTreeMap<String, GuidanceRangeConfig>,
+}
+
+/// Inclusive guidance scale bounds
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct GuidanceRangeConfig {
+    pub min: f64,
+    pub max: f64,
+}
+
+/// Face restoration (GFPGAN / CodeFormer) run after generation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FaceRestoreConfig {
+    /// Restore faces unless the request says otherwise
+    #[serde(default)]
+    pub enabled: bool,
+    /// Blend between the original (0.0) and fully restored (1.0) face
+    #[serde(default = "default_face_restore_weight")]
+    pub weight: f64,
+}
+
+impl Default for FaceRestoreConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            weight: default_face_restore_weight(),
+        }
+    }
+}
+
+/// What to do when an image decodes to NaN / Inf or a flat black or white
+/// frame
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DegenerateOutputConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    #[serde(default = "default_degenerate_action")]
+    pub action: DegenerateAction,
+    /// Reseeded attempts per image before giving up (action = "retry")
+    #[serde(default = "default_degenerate_max_retries")]
+    pub max_retries: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DegenerateAction {
+    Retry,
+    Fail,
+}
+
+impl Default for DegenerateOutputConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            action: default_degenerate_action(),
+            max_retries: default_degenerate_max_retries(),
+        }
+    }
+}
+
+/// Trades quality for throughput under load by lowering step counts
+/// while the queue is backed up
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdaptiveStepsConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Steps are never reduced below this
+    #[serde(default = "default_adaptive_min_steps")]
+    pub min_steps: i32,
+    /// Queue depth at which reduction starts. Steps reach `min_steps` at
+    /// twice this depth.
+    #[serde(default = "default_adaptive_queue_threshold")]
+    pub queue_threshold: usize,
+}
+
+impl Default for AdaptiveStepsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min_steps: default_adaptive_min_steps(),
+            queue_threshold: default_adaptive_queue_threshold(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModerationConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_moderation_action")]
+    pub action: ModerationAction,
+    /// Terms matched case-insensitively on word boundaries
+    #[serde(default)]
+    pub blocklist: Vec<String>,
+    /// Regular expressions matched against the raw prompt
+    #[serde(default)]
+    pub patterns: Vec<String>,
+    /// Accept `prompt_embeds` while moderation is enabled. Embeddings carry
+    /// no text to check, so they are rejected unless this is set.
+    #[serde(default)]
+    pub allow_prompt_embeds: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ModerationAction {
+    Reject,
+    Strip,
+}
+
+impl Default for ModerationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            action: default_moderation_action(),
+            blocklist: Vec::new(),
+            patterns: Vec::new(),
+            allow_prompt_embeds: false,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -45,6 +431,174 @@ pub struct QueueConfig {
     pub backend: String,
     pub max_queue_size: usize,
     pub worker_threads: usize,
+    /// Fail or re-enqueue processing jobs whose worker hasn't heartbeat
+    /// for this long. Disabled when unset.
+    #[serde(default)]
+    pub stuck_job_timeout_seconds: Option<u64>,
+    #[serde(default = "default_max_reenqueue")]
+    pub max_reenqueue: u32,
+    /// Finished jobs remembered for status, listing and result lookups;
+    /// older ones are forgotten
+    #[serde(default = "default_max_finished_jobs")]
+    pub max_finished_jobs: usize,
+    /// Journal file for queued jobs, re-enqueued on startup after a crash.
+    /// Disabled when unset.
+    #[serde(default)]
+    pub persistence_path: Option<PathBuf>,
+    /// Where jobs go while the queue is full instead of being rejected:
+    /// "memory", or "disk" (also journaled to `overflow_path`). Disabled
+    /// when unset.
+    #[serde(default)]
+    pub overflow_backend: Option<String>,
+    #[serde(default = "default_overflow_max_size")]
+    pub overflow_max_size: usize,
+    #[serde(default)]
+    pub overflow_path: Option<PathBuf>,
+    /// Job events buffered per /v1/events subscriber; slower subscribers
+    /// skip the oldest events
+    #[serde(default = "default_event_buffer")]
+    pub event_buffer: usize,
+    /// Priority class name -> share of dequeues. Empty means plain FIFO.
+    #[serde(default)]
+    pub class_weights: BTreeMap<String, u32>,
+    /// API key (sent as `x-api-key`) -> priority tier for its jobs
+    #[serde(default)]
+    pub key_priorities: BTreeMap<String, KeyPriorityConfig>,
+    /// Tier for requests without a key or with one not in
+    /// `key_priorities`. Unset, their requested class applies uncapped.
+    #[serde(default)]
+    pub default_key_priority: Option<KeyPriorityConfig>,
+    /// API key -> images it may generate per UTC day
+    #[serde(default)]
+    pub key_quotas: BTreeMap<String, u32>,
+    /// Images per UTC day shared by requests without an API key or with
+    /// one not in `key_quotas`. Unset, those requests are unmetered.
+    #[serde(default)]
+    pub default_key_quota: Option<u32>,
+    /// What happens to a batch larger than the key's remaining quota
+    #[serde(default = "default_quota_action")]
+    pub quota_action: QuotaAction,
+    /// Extra enqueue attempts while the queue is full, with doubling
+    /// backoff, before answering "queue full"
+    #[serde(default)]
+    pub enqueue_retries: u32,
+    #[serde(default = "default_enqueue_retry_backoff_ms")]
+    pub enqueue_retry_backoff_ms: u64,
+    /// Overall time spent retrying a full queue
+    #[serde(default = "default_enqueue_retry_budget_ms")]
+    pub enqueue_retry_budget_ms: u64,
+    /// Pin each model's jobs to one worker (hash of the model id) instead of
+    /// handing them to any idle worker. Only applies with several models.
+    #[serde(default)]
+    pub affinity: bool,
+    /// How long a pinned job waits for its worker before any worker may
+    /// take it
+    #[serde(default = "default_affinity_fallback_ms")]
+    pub affinity_fallback_ms: u64,
+    /// Longest a worker waits after taking a job for compatible jobs (same
+    /// model, size, steps and scheduler) to run with it. Scaled by queue
+    /// depth; 0 disables batching.
+    #[serde(default)]
+    pub batch_window_ms: u64,
+    /// Queue depth at which the full `batch_window_ms` applies
+    #[serde(default = "default_batch_window_depth")]
+    pub batch_window_depth: usize,
+    /// Most jobs a worker runs as one batch
+    #[serde(default = "default_max_batch_jobs")]
+    pub max_batch_jobs: usize,
+    /// While other jobs are queued, wait at least this long for jobs with
+    /// the first job's prompt (other seeds), which are batched ahead of
+    /// other compatible jobs and share one prompt encoding. 0 disables
+    /// coalescing.
+    #[serde(default)]
+    pub coalesce_window_ms: u64,
+    /// Order in which queued jobs are dequeued
+    #[serde(default = "default_queue_scheduling")]
+    pub scheduling: QueueScheduling,
+    /// Deadline for jobs that don't set `deadline_ms`, under EDF scheduling.
+    /// Without one such jobs never expire and go after every deadline.
+    #[serde(default)]
+    pub default_deadline_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QueueScheduling {
+    /// Arrival order, shared out by `class_weights` when set
+    Fifo,
+    /// Earliest deadline first, ignoring `class_weights`. Jobs whose
+    /// deadline passes while queued are failed.
+    Edf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QuotaAction {
+    /// Refuse the whole batch
+    Reject,
+    /// Generate as many images as the quota has left
+    Clamp,
+}
+
+/// Queue priority of one API key's tier
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyPriorityConfig {
+    /// Class used when the request doesn't pick one
+    pub priority: String,
+    /// Highest class (by weight) a request may pick itself. Without it the
+    /// tier's class always applies.
+    #[serde(default)]
+    pub max_priority: Option<String>,
+}
+
+/// Thresholds at which health checks stop reporting "healthy"
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HealthConfig {
+    /// Degraded once the queue is this full (0.0 - 1.0)
+    #[serde(default = "default_degraded_queue_fraction")]
+    pub degraded_queue_fraction: f64,
+    #[serde(default = "default_degraded_failure_rate")]
+    pub degraded_failure_rate: f64,
+    #[serde(default = "default_unhealthy_failure_rate")]
+    pub unhealthy_failure_rate: f64,
+    /// Number of recent generations the failure rate is computed over
+    #[serde(default = "default_failure_window")]
+    pub failure_window: usize,
+    /// The failure rate is ignored until this many generations have run
+    #[serde(default = "default_failure_min_samples")]
+    pub failure_min_samples: usize,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            degraded_queue_fraction: default_degraded_queue_fraction(),
+            degraded_failure_rate: default_degraded_failure_rate(),
+            unhealthy_failure_rate: default_unhealthy_failure_rate(),
+            failure_window: default_failure_window(),
+            failure_min_samples: default_failure_min_samples(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoggingConfig {
+    /// Share of fast, successful REST requests written to the access log
+    /// (0.0 - 1.0). Errors and slow requests are always logged.
+    #[serde(default = "default_access_sample_rate")]
+    pub access_sample_rate: f64,
+    /// Requests taking at least this long count as slow
+    #[serde(default = "default_access_slow_ms")]
+    pub access_slow_ms: u64,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            access_sample_rate: default_access_sample_rate(),
+            access_slow_ms: default_access_slow_ms(),
+        }
+    }
 }
 
 impl Config {
@@ -66,6 +620,21 @@ impl Config {
                 rest_port: 8080,
                 max_concurrent_requests: 10,
                 request_timeout_seconds: 300,
+                admin_key: None,
+                max_upload_bytes: default_max_upload_bytes(),
+                upload_ttl_seconds: default_upload_ttl_seconds(),
+                strict_request_fields: false,
+                etag_enabled: true,
+                rest_enabled: true,
+                grpc_enabled: true,
+                rest_routes: None,
+                max_metadata_entries: default_max_metadata_entries(),
+                max_metadata_bytes: default_max_metadata_bytes(),
+                maintenance: MaintenanceConfig::default(),
+                recipes_dir: None,
+                download_filename: default_download_filename(),
+                rest_output_format: None,
+                grpc_output_format: None,
             },
             model: ModelConfig {
                 model_path: PathBuf::from("./models/stable-diffusion-v1-5"),
@@ -73,6 +642,15 @@ impl Config {
                 precision: "fp16".to_string(),
                 cache_dir: PathBuf::from("./cache"),
                 warmup_on_start: false,
+                warmup_runs: default_warmup_runs(),
+                warmup_size: default_warmup_size(),
+                warmup_steps: default_warmup_steps(),
+                default_model: default_model_id(),
+                models: Vec::new(),
+                max_concurrent_loads: default_max_concurrent_loads(),
+                unload_after_idle_seconds: None,
+                mock_inference: false,
+                noise_schedule: NoiseScheduleConfig::default(),
             },
             inference: InferenceConfig {
                 default_steps: 50,
@@ -82,13 +660,127 @@ impl Config {
                 max_width: 1024,
                 max_height: 1024,
                 max_steps: 150,
+                max_decode_dimension: default_max_decode_dimension(),
                 safety_checker: false,
+                max_batch_size: default_max_batch_size(),
+                max_batch_tokens: None,
+                max_compute_budget: None,
+                default_scheduler: default_scheduler(),
+                moderation: ModerationConfig::default(),
+                edge_crop_pixels: 0,
+                max_seed_search_cells: default_max_seed_search_cells(),
+                seed_search_cell_size: default_seed_search_cell_size(),
+                adaptive_steps: AdaptiveStepsConfig::default(),
+                strip_metadata: true,
+                snap_to_bucket: false,
+                resolution_buckets: Vec::new(),
+                face_restore: FaceRestoreConfig::default(),
+                degenerate_output: DegenerateOutputConfig::default(),
+                output_format: default_output_format(),
+                allow_empty_prompt_fallback: false,
+                fallback_prompt: String::new(),
+                transparency_threshold: default_transparency_threshold(),
+                include_phash: false,
+                auto_resize_init: false,
+                vae_decode_batch: default_vae_decode_batch(),
+                max_concurrent_decodes: default_max_concurrent_decodes(),
+                max_inflight_bytes: None,
+                inflight_wait_seconds: default_inflight_wait_seconds(),
+                allow_return_latents: false,
+                allow_debug_requests: false,
+                max_latent_bytes: default_max_latent_bytes(),
+                estimate_step_seconds: default_estimate_step_seconds(),
+                cost_per_second: None,
+                generation_counter_path: None,
+                seed_log_dir: None,
+                conditioning_cache_size: default_conditioning_cache_size(),
+                scheduler_guidance: BTreeMap::new(),
             },
             queue: QueueConfig {
                 backend: "memory".to_string(),
                 max_queue_size: 1000,
                 worker_threads: 2,
+                stuck_job_timeout_seconds: None,
+                max_reenqueue: default_max_reenqueue(),
+                max_finished_jobs: default_max_finished_jobs(),
+                persistence_path: None,
+                overflow_backend: None,
+                overflow_max_size: default_overflow_max_size(),
+                overflow_path: None,
+                event_buffer: default_event_buffer(),
+                class_weights: BTreeMap::new(),
+                key_priorities: BTreeMap::new(),
+                default_key_priority: None,
+                key_quotas: BTreeMap::new(),
+                default_key_quota: None,
+                quota_action: default_quota_action(),
+                enqueue_retries: 0,
+                enqueue_retry_backoff_ms: default_enqueue_retry_backoff_ms(),
+                enqueue_retry_budget_ms: default_enqueue_retry_budget_ms(),
+                affinity: false,
+                affinity_fallback_ms: default_affinity_fallback_ms(),
+                batch_window_ms: 0,
+                batch_window_depth: default_batch_window_depth(),
+                max_batch_jobs: default_max_batch_jobs(),
+                coalesce_window_ms: 0,
+                scheduling: default_queue_scheduling(),
+                default_deadline_ms: None,
             },
+            health: HealthConfig::default(),
+            logging: LoggingConfig::default(),
         }
     }
 }
+
+fn default_true() -> bool { true }
+fn default_download_filename() -> String { "{prompt}_{seed}".to_string() }
+fn default_maintenance_message() -> String { "The server is down for scheduled maintenance".to_string() }
+fn default_max_upload_bytes() -> usize { 20 * 1024 * 1024 }
+fn default_upload_ttl_seconds() -> u64 { 600 }
+fn default_max_metadata_entries() -> usize { 16 }
+fn default_max_metadata_bytes() -> usize { 4096 }
+fn default_model_id() -> String { "stable-diffusion-v1-5".to_string() }
+fn default_beta_start() -> f64 { 0.00085 }
+fn default_beta_end() -> f64 { 0.012 }
+fn default_beta_schedule() -> String { "scaled_linear".to_string() }
+fn default_max_concurrent_loads() -> usize { 1 }
+fn default_warmup_runs() -> u32 { 2 }
+fn default_warmup_size() -> i32 { 256 }
+fn default_warmup_steps() -> i32 { 5 }
+fn default_max_batch_size() -> i32 { 4 }
+fn default_max_seed_search_cells() -> i32 { 16 }
+fn default_seed_search_cell_size() -> i32 { 256 }
+fn default_adaptive_min_steps() -> i32 { 20 }
+fn default_adaptive_queue_threshold() -> usize { 10 }
+fn default_face_restore_weight() -> f64 { 0.5 }
+fn default_degenerate_action() -> DegenerateAction { DegenerateAction::Retry }
+fn default_moderation_action() -> ModerationAction { ModerationAction::Reject }
+fn default_degenerate_max_retries() -> u32 { 2 }
+fn default_max_concurrent_decodes() -> usize { 2 }
+fn default_vae_decode_batch() -> usize { 2 }
+fn default_max_decode_dimension() -> u32 { 2048 }
+fn default_transparency_threshold() -> f64 { 0.95 }
+fn default_output_format() -> String { "png".to_string() }
+fn default_inflight_wait_seconds() -> u64 { 30 }
+fn default_max_latent_bytes() -> usize { 4 * 1024 * 1024 }
+fn default_estimate_step_seconds() -> f64 { 0.1 }
+fn default_conditioning_cache_size() -> usize { 128 }
+fn default_max_reenqueue() -> u32 { 1 }
+fn default_max_finished_jobs() -> usize { 10_000 }
+fn default_enqueue_retry_backoff_ms() -> u64 { 100 }
+fn default_enqueue_retry_budget_ms() -> u64 { 5000 }
+fn default_event_buffer() -> usize { 256 }
+fn default_quota_action() -> QuotaAction { QuotaAction::Reject }
+fn default_queue_scheduling() -> QueueScheduling { QueueScheduling::Fifo }
+fn default_affinity_fallback_ms() -> u64 { 5000 }
+fn default_batch_window_depth() -> usize { 8 }
+fn default_max_batch_jobs() -> usize { 4 }
+fn default_overflow_max_size() -> usize { 10000 }
+fn default_scheduler() -> String { "pndm".to_string() }
+fn default_degraded_queue_fraction() -> f64 { 0.8 }
+fn default_degraded_failure_rate() -> f64 { 0.2 }
+fn default_unhealthy_failure_rate() -> f64 { 0.5 }
+fn default_failure_window() -> usize { 50 }
+fn default_failure_min_samples() -> usize { 10 }
+fn default_access_sample_rate() -> f64 { 1.0 }
+fn default_access_slow_ms() -> u64 { 5000 }
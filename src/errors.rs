@@ -14,20 +14,104 @@ pub enum DiffusionError {
     #[error("Queue full")]
     QueueFull,
     
+    #[error("Overloaded: {0}")]
+    Overloaded(String),
+    
     #[error("Job not found: {0}")]
     JobNotFound(String),
     
+    #[error("Job {0} is not queued")]
+    JobNotQueued(String),
+    
     #[error("Invalid parameters: {0}")]
     InvalidParameters(String),
     
+    /// Every failed check of a request
+    #[error("Invalid parameters: {}", .0.join("; "))]
+    Validation(Vec<String>),
+    
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+    
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+    
     #[error("Storage error: {0}")]
     Storage(String),
     
+    #[error("Daily image quota exceeded ({remaining} images remaining today)")]
+    QuotaExceeded { remaining: u32 },
+    
     #[error("Timeout")]
     Timeout,
     
+    #[error("Deadline passed before the job started")]
+    DeadlineExceeded,
+    
     #[error("Internal error: {0}")]
     Internal(String),
+    
+    /// The same request would fail the same way again, e.g. its output
+    /// can't fit `max_response_bytes`
+    #[error("Unrecoverable: {0}")]
+    Unrecoverable(String),
+    
+    /// `source` was the outcome of the last of `attempts` tries at a job
+    #[error("{source} (after {attempts} attempts)")]
+    Exhausted {
+        source: Box<DiffusionError>,
+        attempts: u32,
+    },
 }
 
 pub type Result<T> = std::result::Result<T, DiffusionError>;
+
+/// Suggested client backoff starts here and doubles per attempt made
+const RETRY_BASE_SECONDS: u64 = 2;
+/// Model loads take a while; retrying sooner only queues behind the load
+const MODEL_LOAD_RETRY_SECONDS: u64 = 30;
+const MAX_RETRY_AFTER_SECONDS: u64 = 300;
+
+/// Whether, and when, a client should retry a failed request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryInfo {
+    pub retryable: bool,
+    pub attempts_made: u32,
+    pub retry_after_seconds: Option<u64>,
+}
+
+impl DiffusionError {
+    /// Capacity and transient failures may succeed on a retry; rejected
+    /// requests and server misconfiguration won't
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            DiffusionError::QueueFull
+            | DiffusionError::Overloaded(_)
+            | DiffusionError::Timeout
+            | DiffusionError::ModelLoad(_)
+            | DiffusionError::Inference(_)
+            | DiffusionError::Storage(_) => true,
+            DiffusionError::Exhausted { source, .. } => source.is_retryable(),
+            _ => false,
+        }
+    }
+    
+    pub fn retry_info(&self) -> RetryInfo {
+        let (error, attempts_made) = match self {
+            DiffusionError::Exhausted { source, attempts } => (source.as_ref(), (*attempts).max(1)),
+            error => (error, 1),
+        };
+        let retryable = error.is_retryable();
+        let base = match error {
+            DiffusionError::ModelLoad(_) => MODEL_LOAD_RETRY_SECONDS,
+            _ => RETRY_BASE_SECONDS,
+        };
+        let backoff = base.saturating_mul(1 << (attempts_made - 1).min(16));
+        
+        RetryInfo {
+            retryable,
+            attempts_made,
+            retry_after_seconds: retryable.then(|| backoff.min(MAX_RETRY_AFTER_SECONDS)),
+        }
+    }
+}
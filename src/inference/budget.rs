@@ -0,0 +1,82 @@
+use crate::errors::{DiffusionError, Result};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Bytes are accounted in KiB so large ceilings fit the semaphore's permits
+const UNIT: usize = 1024;
+
+/// Global ceiling on the bytes held by generations in flight
+#[derive(Debug)]
+pub struct ByteBudget {
+    permits: Arc<Semaphore>,
+    capacity: usize,
+    wait: Duration,
+}
+
+/// Bytes held until the generation's result is dropped
+#[derive(Debug)]
+pub struct Reservation {
+    _permit: OwnedSemaphorePermit,
+}
+
+impl ByteBudget {
+    /// Callers wait up to `wait` for room before being turned away
+    pub fn new(max_bytes: usize, wait: Duration) -> Self {
+        let capacity = (max_bytes / UNIT).max(1);
+        Self {
+            permits: Arc::new(Semaphore::new(capacity)),
+            capacity,
+            wait,
+        }
+    }
+
+    pub async fn reserve(&self, bytes: usize) -> Result<Reservation> {
+        let units = bytes / UNIT + usize::from(bytes % UNIT != 0);
+        if units > self.capacity {
+            return Err(DiffusionError::InvalidParameters(format!(
+                "Generation needs about {} bytes, more than the {} byte in-flight limit",
+                bytes,
+                self.capacity * UNIT
+            )));
+        }
+
+        let acquire = Arc::clone(&self.permits).acquire_many_owned(units as u32);
+        match tokio::time::timeout(self.wait, acquire).await {
+            Ok(Ok(permit)) => Ok(Reservation { _permit: permit }),
+            Ok(Err(e)) => Err(DiffusionError::Internal(format!("Byte budget closed: {}", e))),
+            Err(_) => Err(DiffusionError::Overloaded(
+                "Too many large results in flight, try again shortly".to_string()
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn reservations_past_the_ceiling_wait_until_bytes_are_freed() {
+        let budget = Arc::new(ByteBudget::new(8 * UNIT, Duration::from_secs(5)));
+        let held = budget.reserve(6 * UNIT).await.unwrap();
+
+        let waiting = {
+            let budget = Arc::clone(&budget);
+            tokio::spawn(async move { budget.reserve(4 * UNIT).await })
+        };
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!waiting.is_finished());
+
+        drop(held);
+        assert!(waiting.await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn reservations_time_out_or_are_rejected() {
+        let budget = ByteBudget::new(8 * UNIT, Duration::from_millis(20));
+        let _held = budget.reserve(6 * UNIT).await.unwrap();
+        assert!(matches!(budget.reserve(4 * UNIT).await, Err(DiffusionError::Overloaded(_))));
+        assert!(matches!(budget.reserve(9 * UNIT).await, Err(DiffusionError::InvalidParameters(_))));
+    }
+}
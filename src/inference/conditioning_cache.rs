@@ -0,0 +1,203 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use crate::inference::precision::Precision;
+
+/// Text encoder outputs for one request's prompts
+#[derive(Debug)]
+pub struct Conditioning {
+    pub conditioning: u64,
+    /// `(fraction of steps, conditioning)` per scheduled prompt
+    pub schedule: Vec<(f64, u64)>,
+    /// `(conditioning, weight)` per negative prompt
+    pub unconditioning: Vec<(u64, f64)>,
+}
+
+/// Everything the text encoder output depends on. Floats are compared by
+/// their bits.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    model: String,
+    prompt: String,
+    schedule: Vec<(u64, String)>,
+    negatives: Vec<(String, u64)>,
+    clip_skip: i32,
+    precision: Precision,
+}
+
+impl CacheKey {
+    pub fn new(
+        model: &str,
+        prompt: &str,
+        schedule: &[(f64, String)],
+        negatives: &[(&str, f64)],
+        clip_skip: i32,
+        precision: Precision,
+    ) -> Self {
+        Self {
+            model: model.to_string(),
+            prompt: prompt.to_string(),
+            schedule: schedule
+                .iter()
+                .map(|(fraction, prompt)| (fraction.to_bits(), prompt.clone()))
+                .collect(),
+            negatives: negatives
+                .iter()
+                .map(|(negative, weight)| (negative.to_string(), weight.to_bits()))
+                .collect(),
+            clip_skip,
+            precision,
+        }
+    }
+}
+
+struct Entries {
+    map: HashMap<CacheKey, Arc<Conditioning>>,
+    /// Least recently used first
+    order: VecDeque<CacheKey>,
+}
+
+/// Recently used prompt conditionings, so sweeps over seeds with the same
+/// prompt skip the text encoder. Holds at most `capacity` entries, evicting
+/// the least recently used; a capacity of 0 disables caching.
+pub struct ConditioningCache {
+    entries: Mutex<Entries>,
+    capacity: usize,
+}
+
+impl ConditioningCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: Mutex::new(Entries {
+                map: HashMap::new(),
+                order: VecDeque::new(),
+            }),
+            capacity,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    /// The cached conditioning for `key`, or the result of `encode`, which
+    /// is then cached. The flag is true on a cache hit. `encode` runs
+    /// without the lock held, so concurrent misses may both encode.
+    pub fn get_or_encode(&self, key: CacheKey, encode: impl FnOnce() -> Conditioning) -> (Arc<Conditioning>, bool) {
+        if self.capacity == 0 {
+            return (Arc::new(encode()), false);
+        }
+
+        if let Some(cached) = self.lookup(&key) {
+            return (cached, true);
+        }
+
+        (self.encode_fresh(key, encode), false)
+    }
+
+    /// Runs `encode` without looking in the cache, then caches its result,
+    /// replacing any entry for `key`
+    pub fn encode_fresh(&self, key: CacheKey, encode: impl FnOnce() -> Conditioning) -> Arc<Conditioning> {
+        let conditioning = Arc::new(encode());
+        if self.capacity == 0 {
+            return conditioning;
+        }
+
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        if entries.map.insert(key.clone(), Arc::clone(&conditioning)).is_none() {
+            entries.order.push_back(key);
+            if entries.order.len() > self.capacity {
+                if let Some(evicted) = entries.order.pop_front() {
+                    entries.map.remove(&evicted);
+                }
+            }
+        }
+        conditioning
+    }
+
+    /// Drops every entry, for when model weights change under the cached
+    /// encodings
+    pub fn clear(&self) {
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        entries.map.clear();
+        entries.order.clear();
+    }
+
+    fn lookup(&self, key: &CacheKey) -> Option<Arc<Conditioning>> {
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        let cached = Arc::clone(entries.map.get(key)?);
+        if let Some(position) = entries.order.iter().position(|k| k == key) {
+            if let Some(key) = entries.order.remove(position) {
+                entries.order.push_back(key);
+            }
+        }
+        Some(cached)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    fn key(prompt: &str, precision: Precision) -> CacheKey {
+        CacheKey::new("sd-1.5", prompt, &[], &[("blurry", 1.0)], 1, precision)
+    }
+
+    fn encoder(calls: &Cell<usize>) -> impl Fn() -> Conditioning + '_ {
+        move || {
+            calls.set(calls.get() + 1);
+            Conditioning { conditioning: 7, schedule: Vec::new(), unconditioning: Vec::new() }
+        }
+    }
+
+    #[test]
+    fn encoder_runs_once_for_repeated_prompts() {
+        let cache = ConditioningCache::new(4);
+        let calls = Cell::new(0);
+
+        let (_, hit) = cache.get_or_encode(key("a lighthouse", Precision::Fp32), encoder(&calls));
+        assert!(!hit);
+        for _ in 0..3 {
+            let (conditioning, hit) = cache.get_or_encode(key("a lighthouse", Precision::Fp32), encoder(&calls));
+            assert!(hit);
+            assert_eq!(conditioning.conditioning, 7);
+        }
+        assert_eq!(calls.get(), 1);
+    }
+
+    #[test]
+    fn precision_is_part_of_the_key() {
+        let cache = ConditioningCache::new(4);
+        let calls = Cell::new(0);
+
+        cache.get_or_encode(key("a lighthouse", Precision::Fp32), encoder(&calls));
+        let (_, hit) = cache.get_or_encode(key("a lighthouse", Precision::Fp16), encoder(&calls));
+        assert!(!hit);
+        assert_eq!(calls.get(), 2);
+    }
+
+    #[test]
+    fn clear_forces_a_fresh_encode() {
+        let cache = ConditioningCache::new(4);
+        let calls = Cell::new(0);
+
+        cache.get_or_encode(key("a lighthouse", Precision::Fp32), encoder(&calls));
+        cache.clear();
+        let (_, hit) = cache.get_or_encode(key("a lighthouse", Precision::Fp32), encoder(&calls));
+        assert!(!hit);
+        assert_eq!(calls.get(), 2);
+    }
+
+    #[test]
+    fn zero_capacity_always_encodes() {
+        let cache = ConditioningCache::new(0);
+        let calls = Cell::new(0);
+
+        for _ in 0..2 {
+            let (_, hit) = cache.get_or_encode(key("a lighthouse", Precision::Fp32), encoder(&calls));
+            assert!(!hit);
+        }
+        assert_eq!(calls.get(), 2);
+    }
+}
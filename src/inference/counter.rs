@@ -0,0 +1,112 @@
+use crate::errors::{DiffusionError, Result};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::Mutex;
+use tracing::warn;
+
+/// Lifetime count of successful generations, persisted to a small file so
+/// it survives restarts
+#[derive(Debug)]
+pub struct GenerationCounter {
+    count: AtomicU64,
+    path: Option<PathBuf>,
+    /// Serializes writes, so an older value never overwrites a newer one
+    write: Mutex<()>,
+}
+
+impl GenerationCounter {
+    /// Starts from the value stored at `path`, or 0 when there is none.
+    /// Without a path the count only lives as long as the process.
+    pub fn load(path: Option<PathBuf>) -> Result<Self> {
+        let count = match &path {
+            Some(path) => match std::fs::read_to_string(path) {
+                Ok(contents) => contents.trim().parse().map_err(|e| {
+                    DiffusionError::Storage(format!("Invalid generation counter in {}: {}", path.display(), e))
+                })?,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+                Err(e) => {
+                    return Err(DiffusionError::Storage(format!("Failed to read generation counter: {}", e)));
+                }
+            },
+            None => 0,
+        };
+
+        Ok(Self {
+            count: AtomicU64::new(count),
+            path,
+            write: Mutex::new(()),
+        })
+    }
+
+    pub fn get(&self) -> u64 {
+        self.count.load(Ordering::SeqCst)
+    }
+
+    /// Counts one generation and persists the new total. A failed write is
+    /// logged; the in-memory count stays correct.
+    pub async fn increment(&self) -> u64 {
+        let count = self.count.fetch_add(1, Ordering::SeqCst) + 1;
+
+        if let Some(path) = &self.path {
+            let _guard = self.write.lock().await;
+            // Written under the lock, so the latest total always lands last
+            let latest = self.get().to_string();
+            let tmp = path.with_extension("tmp");
+            let result = async {
+                tokio::fs::write(&tmp, latest).await?;
+                tokio::fs::rename(&tmp, path).await
+            }
+            .await;
+            if let Err(e) = result {
+                warn!("Failed to persist generation counter: {}", e);
+            }
+        }
+
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn counter_increments_and_restores_after_restart() {
+        let path = std::env::temp_dir().join(format!("generations-{}", uuid::Uuid::new_v4()));
+
+        let counter = Arc::new(GenerationCounter::load(Some(path.clone())).unwrap());
+        assert_eq!(counter.get(), 0);
+        assert_eq!(counter.increment().await, 1);
+
+        // Concurrent increments are all counted and the last write wins
+        let tasks: Vec<_> = (0..8)
+            .map(|_| {
+                let counter = counter.clone();
+                tokio::spawn(async move { counter.increment().await })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(counter.get(), 9);
+        drop(counter);
+
+        // A simulated restart picks up where the last process stopped
+        let restarted = GenerationCounter::load(Some(path.clone())).unwrap();
+        assert_eq!(restarted.get(), 9);
+        assert_eq!(restarted.increment().await, 10);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn corrupt_counter_file_fails_to_load() {
+        let path = std::env::temp_dir().join(format!("generations-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "not a number").unwrap();
+
+        assert!(matches!(GenerationCounter::load(Some(path.clone())), Err(DiffusionError::Storage(_))));
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
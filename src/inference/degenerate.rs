@@ -0,0 +1,90 @@
+use image::DynamicImage;
+use std::fmt;
+
+/// Channel values within this distance of 0.0 / 1.0 count as black / white
+const FLAT_TOLERANCE: f32 = 1.0 / 255.0;
+
+/// Ways a decoded image can be unusable even though decoding succeeded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Degeneracy {
+    /// The buffer holds NaN or infinite samples
+    NonFinite,
+    AllBlack,
+    AllWhite,
+}
+
+impl Degeneracy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Degeneracy::NonFinite => "non-finite values",
+            Degeneracy::AllBlack => "all black",
+            Degeneracy::AllWhite => "all white",
+        }
+    }
+}
+
+impl fmt::Display for Degeneracy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Checks raw decoder output (normalized to 0.0..=1.0)
+pub fn detect_samples(samples: &[f32]) -> Option<Degeneracy> {
+    if samples.is_empty() {
+        return None;
+    }
+    if samples.iter().any(|v| !v.is_finite()) {
+        return Some(Degeneracy::NonFinite);
+    }
+    if samples.iter().all(|&v| v <= FLAT_TOLERANCE) {
+        return Some(Degeneracy::AllBlack);
+    }
+    if samples.iter().all(|&v| v >= 1.0 - FLAT_TOLERANCE) {
+        return Some(Degeneracy::AllWhite);
+    }
+    None
+}
+
+/// Checks a decoded image. Float images keep any NaN / Inf the decoder
+/// produced; 8-bit images can only be flat, so check the latent as well.
+pub fn detect(image: &DynamicImage) -> Option<Degeneracy> {
+    detect_samples(image.to_rgb32f().as_raw())
+}
+
+/// Checks the final latent, where NaN / Inf from the UNet show up before
+/// decoding clamps them away. Flatness means nothing in latent space.
+pub fn detect_latent(latent: &[f32]) -> Option<Degeneracy> {
+    latent.iter().any(|v| !v.is_finite()).then_some(Degeneracy::NonFinite)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn synthetic_degenerate_buffers_are_detected() {
+        assert_eq!(detect_samples(&[0.5, f32::NAN, 0.5]), Some(Degeneracy::NonFinite));
+        assert_eq!(detect_samples(&[0.0, f32::INFINITY]), Some(Degeneracy::NonFinite));
+        assert_eq!(detect_samples(&[0.0, 0.001, 0.0]), Some(Degeneracy::AllBlack));
+        assert_eq!(detect_samples(&[1.0, 0.999, 1.0]), Some(Degeneracy::AllWhite));
+        assert_eq!(detect_samples(&[0.0, 0.5, 1.0]), None);
+        assert_eq!(detect_samples(&[]), None);
+
+        assert_eq!(detect_latent(&[0.1, -2.0, f32::NEG_INFINITY]), Some(Degeneracy::NonFinite));
+        assert_eq!(detect_latent(&[0.0; 16]), None);
+    }
+
+    #[test]
+    fn flat_8_bit_images_are_detected() {
+        let flat = |value: u8| DynamicImage::ImageRgb8(RgbImage::from_pixel(8, 8, Rgb([value; 3])));
+        assert_eq!(detect(&flat(0)), Some(Degeneracy::AllBlack));
+        assert_eq!(detect(&flat(255)), Some(Degeneracy::AllWhite));
+        assert_eq!(detect(&flat(128)), None);
+
+        let mut speck = RgbImage::from_pixel(8, 8, Rgb([0; 3]));
+        speck.put_pixel(3, 3, Rgb([200, 10, 10]));
+        assert_eq!(detect(&DynamicImage::ImageRgb8(speck)), None);
+    }
+}
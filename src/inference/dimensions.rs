@@ -0,0 +1,116 @@
+use crate::errors::{DiffusionError, Result};
+
+/// Named aspect ratios accepted in place of explicit width/height
+pub const ASPECT_RATIO_PRESETS: &[(&str, u32, u32)] = &[
+    ("1:1", 1, 1),
+    ("16:9", 16, 9),
+    ("9:16", 9, 16),
+    ("4:3", 4, 3),
+    ("3:4", 3, 4),
+];
+
+/// Pixels in a megapixel, for `megapixels` requests
+pub const PIXELS_PER_MEGAPIXEL: f64 = 1_000_000.0;
+
+/// Rounds to the nearest multiple of 8, as required by the latent space
+pub fn snap_to_multiple_of_8(value: f64) -> i32 {
+    (((value / 8.0).round() as i32) * 8).max(8)
+}
+
+/// Rounds down to a multiple of 8, so a pixel budget is never exceeded
+fn floor_to_multiple_of_8(value: f64) -> i32 {
+    (((value / 8.0).floor() as i32) * 8).max(8)
+}
+
+/// Width / height ratio of an aspect ratio preset
+pub fn aspect_ratio(preset: &str) -> Result<f64> {
+    let (_, ratio_w, ratio_h) = ASPECT_RATIO_PRESETS
+        .iter()
+        .find(|(name, _, _)| *name == preset)
+        .ok_or_else(|| {
+            let names: Vec<&str> = ASPECT_RATIO_PRESETS.iter().map(|(name, _, _)| *name).collect();
+            DiffusionError::InvalidParameters(format!(
+                "Unknown aspect_ratio '{}' (expected one of {})",
+                preset,
+                names.join(", ")
+            ))
+        })?;
+
+    Ok(*ratio_w as f64 / *ratio_h as f64)
+}
+
+/// Resolves an aspect ratio preset to dimensions with roughly the same pixel
+/// area as the model's native resolution.
+pub fn aspect_ratio_dimensions(preset: &str, native_width: i32, native_height: i32) -> Result<(i32, i32)> {
+    let area = native_width as f64 * native_height as f64;
+    let ratio = aspect_ratio(preset)?;
+
+    let width = snap_to_multiple_of_8((area * ratio).sqrt());
+    let height = snap_to_multiple_of_8((area / ratio).sqrt());
+
+    Ok((width, height))
+}
+
+/// Largest multiple-of-8 dimensions at `ratio` (width / height) within a
+/// budget of `megapixels`; 1.0 at 16:9 gives 1328x744
+pub fn megapixel_dimensions(megapixels: f64, ratio: f64) -> (i32, i32) {
+    let area = megapixels * PIXELS_PER_MEGAPIXEL;
+    let width = floor_to_multiple_of_8((area * ratio).sqrt());
+    let height = floor_to_multiple_of_8((area / ratio).sqrt());
+
+    (width, height)
+}
+
+/// Picks the bucket closest to the requested size. Distance is the sum of
+/// the log differences in aspect ratio and in area, so an exact match is
+/// always chosen when present.
+pub fn nearest_bucket(width: i32, height: i32, buckets: &[(u32, u32)]) -> Option<(i32, i32)> {
+    let ratio = width as f64 / height as f64;
+    let area = width as f64 * height as f64;
+
+    let distance = |&(w, h): &(u32, u32)| {
+        let bucket_ratio = w as f64 / h as f64;
+        let bucket_area = w as f64 * h as f64;
+        (ratio / bucket_ratio).ln().abs() + (area / bucket_area).ln().abs()
+    };
+
+    buckets
+        .iter()
+        .filter(|(w, h)| *w > 0 && *h > 0)
+        .min_by(|a, b| distance(a).total_cmp(&distance(b)))
+        .map(|&(w, h)| (w as i32, h as i32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_keep_the_native_area() {
+        assert_eq!(aspect_ratio_dimensions("1:1", 512, 512).unwrap(), (512, 512));
+        assert_eq!(aspect_ratio_dimensions("16:9", 512, 512).unwrap(), (680, 384));
+        assert_eq!(aspect_ratio_dimensions("9:16", 512, 512).unwrap(), (384, 680));
+    }
+
+    #[test]
+    fn unknown_preset_is_rejected() {
+        for preset in ["21:9", "16x9", ""] {
+            assert!(matches!(
+                aspect_ratio_dimensions(preset, 512, 512),
+                Err(DiffusionError::InvalidParameters(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn megapixels_snap_under_the_budget() {
+        assert_eq!(megapixel_dimensions(1.0, 16.0 / 9.0), (1328, 744));
+        assert_eq!(megapixel_dimensions(1.0, 9.0 / 16.0), (744, 1328));
+        assert_eq!(megapixel_dimensions(0.25, 1.0), (496, 496));
+        for (megapixels, ratio) in [(1.0, 16.0 / 9.0), (0.5, 4.0 / 3.0), (2.0, 1.0)] {
+            let (width, height) = megapixel_dimensions(megapixels, ratio);
+            assert_eq!((width % 8, height % 8), (0, 0));
+            assert!((width * height) as f64 <= megapixels * PIXELS_PER_MEGAPIXEL);
+        }
+    }
+}
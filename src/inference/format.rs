@@ -0,0 +1,87 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Most encodings one request may ask for (every format, once)
+pub const MAX_OUTPUT_FORMATS: usize = 2;
+
+/// Encoding of returned images
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Png,
+    Jpeg,
+}
+
+impl OutputFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Jpeg => "jpeg",
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "image/png",
+            OutputFormat::Jpeg => "image/jpeg",
+        }
+    }
+
+    /// Whether the encoding keeps an alpha channel
+    pub fn supports_alpha(&self) -> bool {
+        matches!(self, OutputFormat::Png)
+    }
+
+    /// Whether decoding gives back exactly the pixels that were encoded
+    pub fn is_lossless(&self) -> bool {
+        matches!(self, OutputFormat::Png)
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Jpeg => "jpg",
+        }
+    }
+}
+
+/// Parses `output_formats`: known names, no repeats, at most
+/// `MAX_OUTPUT_FORMATS`
+pub fn parse_list(formats: &[String]) -> Result<Vec<OutputFormat>, String> {
+    if formats.len() > MAX_OUTPUT_FORMATS {
+        return Err(format!(
+            "At most {} output_formats are allowed, got {}",
+            MAX_OUTPUT_FORMATS,
+            formats.len()
+        ));
+    }
+    let mut parsed: Vec<OutputFormat> = Vec::with_capacity(formats.len());
+    for format in formats {
+        let format = format.parse()?;
+        if parsed.contains(&format) {
+            return Err(format!("output_formats lists {} twice", format));
+        }
+        parsed.push(format);
+    }
+    Ok(parsed)
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "png" => Ok(OutputFormat::Png),
+            "jpeg" | "jpg" => Ok(OutputFormat::Jpeg),
+            other => Err(format!(
+                "Unknown format '{}' (expected png or jpeg)",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
@@ -0,0 +1,52 @@
+//! Final latents returned to clients that ask for them
+
+/// Channels of the VAE latent space
+pub const LATENT_CHANNELS: usize = 4;
+
+/// Pixels per latent cell along each axis
+pub const VAE_SCALE_FACTOR: usize = 8;
+
+/// Tensor name the latent is stored under in the safetensors payload
+pub const LATENT_TENSOR_NAME: &str = "latent";
+
+/// NCHW shape of the latent for one image of the given size
+pub fn latent_shape(width: u32, height: u32) -> [usize; 4] {
+    [
+        1,
+        LATENT_CHANNELS,
+        height as usize / VAE_SCALE_FACTOR,
+        width as usize / VAE_SCALE_FACTOR,
+    ]
+}
+
+/// Size of the serialized f32 tensor data, excluding the safetensors header
+pub fn latent_bytes(width: u32, height: u32) -> usize {
+    latent_shape(width, height).iter().product::<usize>() * std::mem::size_of::<f32>()
+}
+
+/// Serializes a single f32 tensor in the safetensors format: an 8-byte
+/// little-endian header length, a JSON header, then the raw data
+pub fn to_safetensors(shape: &[usize], data: &[f32]) -> Vec<u8> {
+    let data_len = data.len() * std::mem::size_of::<f32>();
+    let shape: Vec<String> = shape.iter().map(|dim| dim.to_string()).collect();
+    let mut header = format!(
+        "{{\"{}\":{{\"dtype\":\"F32\",\"shape\":[{}],\"data_offsets\":[0,{}]}}}}",
+        LATENT_TENSOR_NAME,
+        shape.join(","),
+        data_len
+    );
+
+    // Pad so the data starts 8-byte aligned
+    while (header.len() + 8) % 8 != 0 {
+        header.push(' ');
+    }
+
+    let mut bytes = Vec::with_capacity(8 + header.len() + data_len);
+    bytes.extend_from_slice(&(header.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(header.as_bytes());
+    for value in data {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    bytes
+}
@@ -1,3 +1,35 @@
+pub mod budget;
+pub mod conditioning_cache;
+pub mod counter;
+pub mod degenerate;
+pub mod dimensions;
+pub mod format;
+pub mod latents;
+pub mod models;
+pub mod moderation;
+pub mod negative_prompts;
+pub mod noise;
+pub mod outcomes;
+pub mod palette;
+pub mod phash;
 pub mod pipeline;
+pub mod postprocess;
+pub mod precision;
+pub mod prompt_embeds;
+pub mod prompt_schedule;
+pub mod quality;
+pub mod references;
+pub mod scheduler;
+pub mod seed_log;
+pub mod strength;
+pub mod tokens;
+pub mod vary_region;
 
+pub use models::{ModelRegistry, ModelState};
 pub use pipeline::{InferencePipeline, GenerationParams, GenerationResult};
+pub use precision::Precision;
+pub use prompt_schedule::PromptSchedule;
+pub use quality::QualityPreset;
+pub use scheduler::{NoiseSchedule, SchedulerKind};
+pub use format::OutputFormat;
+pub use strength::StrengthPreset;
@@ -0,0 +1,468 @@
+use crate::config::{ModelConfig, ModelDefinition};
+use crate::errors::{DiffusionError, Result};
+use crate::inference::scheduler::NoiseSchedule;
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+use tracing::{info, warn};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModelState {
+    Unloaded,
+    Loading,
+    Loaded,
+    /// The last load attempt failed; the next request retries it
+    Failed,
+}
+
+impl ModelState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ModelState::Unloaded => "unloaded",
+            ModelState::Loading => "loading",
+            ModelState::Loaded => "loaded",
+            ModelState::Failed => "failed",
+        }
+    }
+}
+
+struct ModelEntry {
+    state: ModelState,
+    last_used: Instant,
+    in_use: usize,
+    /// Jobs admitted and not yet answered, queued or running
+    admitted: usize,
+    /// New jobs are refused while set, see `drain`
+    draining: bool,
+}
+
+/// Tracks the configured models and lazily loads them on first use,
+/// bounding how many loads may run at the same time.
+pub struct ModelRegistry {
+    definitions: HashMap<String, ModelDefinition>,
+    noise_schedules: HashMap<String, NoiseSchedule>,
+    entries: Mutex<HashMap<String, ModelEntry>>,
+    load_permits: Semaphore,
+    default_model: String,
+    mock: bool,
+}
+
+/// Marks a model as in use for the duration of a generation so the idle
+/// unloader leaves it alone.
+pub struct ModelLease<'a> {
+    registry: &'a ModelRegistry,
+    model_id: String,
+    /// Seconds spent loading the model for this request, if it wasn't resident
+    pub load_time: Option<f64>,
+}
+
+impl Drop for ModelLease<'_> {
+    fn drop(&mut self) {
+        let mut entries = self.registry.entries();
+        if let Some(entry) = entries.get_mut(&self.model_id) {
+            entry.in_use = entry.in_use.saturating_sub(1);
+            entry.last_used = Instant::now();
+        }
+    }
+}
+
+/// Counts an accepted job against its model until dropped, so a drain
+/// waits for it
+pub struct ModelAdmission<'a> {
+    registry: &'a ModelRegistry,
+    model_id: String,
+}
+
+impl Drop for ModelAdmission<'_> {
+    fn drop(&mut self) {
+        if let Some(entry) = self.registry.entries().get_mut(&self.model_id) {
+            entry.admitted = entry.admitted.saturating_sub(1);
+        }
+    }
+}
+
+/// How often `drain` checks whether the model's jobs have finished
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+impl ModelRegistry {
+    pub fn new(config: &ModelConfig) -> Result<Self> {
+        let mut definitions: HashMap<String, ModelDefinition> = config
+            .models
+            .iter()
+            .map(|m| (m.id.clone(), m.clone()))
+            .collect();
+
+        // The top-level model_path always backs the default model
+        definitions
+            .entry(config.default_model.clone())
+            .or_insert_with(|| ModelDefinition {
+                id: config.default_model.clone(),
+                path: config.model_path.clone(),
+                default_scheduler: None,
+                noise_schedule: None,
+                prompt_prefix: None,
+                prompt_suffix: None,
+                negative_prompt_prefix: None,
+                negative_prompt_suffix: None,
+                warmup_prompt: None,
+                warmup_size: None,
+                min_steps: None,
+                max_steps: None,
+                default_steps: None,
+                encoder_dim: None,
+            });
+
+        // Validated up front: a wrong schedule silently produces bad images
+        let noise_schedules = definitions
+            .values()
+            .map(|m| {
+                let schedule = m.noise_schedule.as_ref().unwrap_or(&config.noise_schedule);
+                NoiseSchedule::from_config(schedule)
+                    .map(|schedule| (m.id.clone(), schedule))
+                    .map_err(|e| DiffusionError::Config(format!("Model {}: {}", m.id, e)))
+            })
+            .collect::<Result<HashMap<_, _>>>()?;
+
+        let entries = definitions
+            .keys()
+            .map(|id| {
+                (id.clone(), ModelEntry {
+                    state: ModelState::Unloaded,
+                    last_used: Instant::now(),
+                    in_use: 0,
+                    admitted: 0,
+                    draining: false,
+                })
+            })
+            .collect();
+
+        Ok(Self {
+            definitions,
+            noise_schedules,
+            entries: Mutex::new(entries),
+            load_permits: Semaphore::new(config.max_concurrent_loads.max(1)),
+            default_model: config.default_model.clone(),
+            mock: config.mock_inference,
+        })
+    }
+
+    fn entries(&self) -> MutexGuard<'_, HashMap<String, ModelEntry>> {
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn definition(&self, model_id: &str) -> Option<&ModelDefinition> {
+        self.definitions.get(model_id)
+    }
+
+    pub fn noise_schedule(&self, model_id: &str) -> Option<NoiseSchedule> {
+        self.noise_schedules.get(model_id).copied()
+    }
+
+    /// Resolves an optional requested model id to a configured model
+    pub fn resolve(&self, model_id: Option<&str>) -> Result<String> {
+        let id = match model_id {
+            Some(id) if !id.is_empty() => id,
+            _ => self.default_model.as_str(),
+        };
+
+        if !self.definitions.contains_key(id) {
+            return Err(DiffusionError::InvalidParameters(
+                format!("Unknown model: {}", id)
+            ));
+        }
+
+        Ok(id.to_string())
+    }
+
+    /// Loads the model if needed and marks it in use until the lease is dropped
+    pub async fn acquire(&self, model_id: &str) -> Result<ModelLease<'_>> {
+        let mut load_time = None;
+
+        loop {
+            let start = Instant::now();
+            if self.ensure_loaded(model_id).await? {
+                load_time = Some(load_time.unwrap_or(0.0) + start.elapsed().as_secs_f64());
+            }
+
+            let mut entries = self.entries();
+            if let Some(entry) = entries.get_mut(model_id) {
+                // The idle unloader may have run between loading and here
+                if entry.state == ModelState::Loaded {
+                    entry.in_use += 1;
+                    entry.last_used = Instant::now();
+                    break;
+                }
+            }
+        }
+
+        Ok(ModelLease {
+            registry: self,
+            model_id: model_id.to_string(),
+            load_time,
+        })
+    }
+
+    /// Loads the model if it isn't resident yet, returning whether a load
+    /// happened. Loads beyond `max_concurrent_loads` wait for a permit.
+    pub async fn ensure_loaded(&self, model_id: &str) -> Result<bool> {
+        if self.state(model_id) == Some(ModelState::Loaded) {
+            return Ok(false);
+        }
+
+        let _permit = self.load_permits
+            .acquire()
+            .await
+            .map_err(|e| DiffusionError::Internal(format!("Load semaphore closed: {}", e)))?;
+
+        {
+            let mut entries = self.entries();
+            match entries.get_mut(model_id) {
+                None => {
+                    return Err(DiffusionError::InvalidParameters(
+                        format!("Unknown model: {}", model_id)
+                    ));
+                }
+                // Another request finished loading while we waited
+                Some(entry) if entry.state == ModelState::Loaded => return Ok(false),
+                Some(entry) => entry.state = ModelState::Loading,
+            }
+        }
+
+        let result = self.load_model(model_id).await;
+
+        let mut entries = self.entries();
+        if let Some(entry) = entries.get_mut(model_id) {
+            match &result {
+                Ok(()) => {
+                    entry.state = ModelState::Loaded;
+                    entry.last_used = Instant::now();
+                }
+                Err(e) => {
+                    warn!("Failed to load model {}: {}", model_id, e);
+                    entry.state = ModelState::Failed;
+                }
+            }
+        }
+
+        result.map(|_| true)
+    }
+
+    async fn load_model(&self, model_id: &str) -> Result<()> {
+        let definition = self.definitions
+            .get(model_id)
+            .ok_or_else(|| DiffusionError::ModelLoad(format!("Unknown model: {}", model_id)))?;
+
+        if self.mock {
+            info!("Mock inference: skipping weights for model {}", model_id);
+            return Ok(());
+        }
+        
+        info!("Loading model {} from {}", model_id, definition.path.display());
+
+        // TODO: Load the UNet / VAE / text encoder weights onto the device
+
+        info!("✓ Model {} loaded", model_id);
+        Ok(())
+    }
+
+    /// Unloads loaded models that have not been used for `idle`, skipping
+    /// any with a generation in flight. Returns the unloaded model ids.
+    pub fn unload_idle(&self, idle: Duration) -> Vec<String> {
+        let mut entries = self.entries();
+        let mut unloaded = Vec::new();
+
+        for (id, entry) in entries.iter_mut() {
+            if entry.state == ModelState::Loaded
+                && entry.in_use == 0
+                && entry.last_used.elapsed() >= idle
+            {
+                // TODO: Drop the model weights to release device memory
+                entry.state = ModelState::Unloaded;
+                info!("Unloaded idle model {}", id);
+                unloaded.push(id.clone());
+            }
+        }
+
+        unloaded
+    }
+
+    /// Accepts a job for the (resolved) model, unless the model is draining
+    pub fn admit(&self, model_id: Option<&str>) -> Result<ModelAdmission<'_>> {
+        let model_id = self.resolve(model_id)?;
+        let mut entries = self.entries();
+        let entry = entries
+            .get_mut(&model_id)
+            .ok_or_else(|| DiffusionError::InvalidParameters(format!("Unknown model: {}", model_id)))?;
+
+        if entry.draining {
+            return Err(DiffusionError::Overloaded(format!(
+                "Model {} is draining and not accepting new jobs",
+                model_id
+            )));
+        }
+        entry.admitted += 1;
+        drop(entries);
+
+        Ok(ModelAdmission {
+            registry: self,
+            model_id,
+        })
+    }
+
+    /// Stops admitting jobs for `model_id`, then waits up to `timeout` for
+    /// its admitted and running jobs to finish. Returns whether it drained;
+    /// the model keeps refusing jobs either way until `resume`.
+    pub async fn drain(&self, model_id: &str, timeout: Duration) -> Result<bool> {
+        match self.entries().get_mut(model_id) {
+            Some(entry) => entry.draining = true,
+            None => return Err(DiffusionError::InvalidParameters(format!("Unknown model: {}", model_id))),
+        }
+        info!("Draining model {}", model_id);
+
+        let start = Instant::now();
+        loop {
+            let drained = self
+                .entries()
+                .get(model_id)
+                .map_or(true, |entry| entry.admitted == 0 && entry.in_use == 0);
+            if drained {
+                info!("Model {} drained", model_id);
+                return Ok(true);
+            }
+            if start.elapsed() >= timeout {
+                warn!("Model {} still has jobs after {:?} of draining", model_id, timeout);
+                return Ok(false);
+            }
+            tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Admits jobs for a drained model again
+    pub fn resume(&self, model_id: &str) -> Result<()> {
+        match self.entries().get_mut(model_id) {
+            Some(entry) => {
+                entry.draining = false;
+                info!("Model {} accepting jobs again", model_id);
+                Ok(())
+            }
+            None => Err(DiffusionError::InvalidParameters(format!("Unknown model: {}", model_id))),
+        }
+    }
+
+    pub fn state(&self, model_id: &str) -> Option<ModelState> {
+        self.entries().get(model_id).map(|e| e.state)
+    }
+
+    /// Every configured model id, sorted
+    pub fn ids(&self) -> Vec<String> {
+        let mut ids: Vec<String> = self.definitions.keys().cloned().collect();
+        ids.sort();
+        ids
+    }
+
+    pub fn states(&self) -> HashMap<String, ModelState> {
+        self.entries()
+            .iter()
+            .map(|(id, e)| (id.clone(), e.state))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inference::pipeline::tests::{mock_config, model};
+    use std::sync::Arc;
+
+    fn registry(max_concurrent_loads: usize) -> Arc<ModelRegistry> {
+        let mut config = mock_config().model;
+        config.models = vec![model("a"), model("b")];
+        config.max_concurrent_loads = max_concurrent_loads;
+        Arc::new(ModelRegistry::new(&config).unwrap())
+    }
+
+    #[tokio::test]
+    async fn loads_beyond_the_limit_wait_their_turn() {
+        let registry = registry(1);
+
+        // Model "a" is mid-load, holding the only permit
+        let loading_a = registry.load_permits.acquire().await.unwrap();
+        let load_b = tokio::spawn({
+            let registry = Arc::clone(&registry);
+            async move { registry.ensure_loaded("b").await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!load_b.is_finished());
+        assert_eq!(registry.state("b"), Some(ModelState::Unloaded));
+
+        drop(loading_a);
+        assert!(load_b.await.unwrap().unwrap());
+        assert_eq!(registry.state("b"), Some(ModelState::Loaded));
+    }
+
+    #[tokio::test]
+    async fn concurrent_first_requests_each_load_once() {
+        let registry = registry(1);
+
+        let (a, b) = tokio::join!(registry.ensure_loaded("a"), registry.ensure_loaded("b"));
+        assert!(a.unwrap() && b.unwrap());
+        assert!(!registry.ensure_loaded("a").await.unwrap());
+        assert_eq!(registry.state("a"), Some(ModelState::Loaded));
+        assert_eq!(registry.state("b"), Some(ModelState::Loaded));
+    }
+
+    #[tokio::test]
+    async fn idle_models_unload_and_reload_on_next_use() {
+        let registry = registry(1);
+
+        let lease = registry.acquire("a").await.unwrap();
+        assert!(lease.load_time.is_some());
+        // Never mid-generation
+        assert!(registry.unload_idle(Duration::ZERO).is_empty());
+        drop(lease);
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(registry.unload_idle(Duration::from_secs(60)).is_empty());
+        assert_eq!(registry.unload_idle(Duration::from_millis(10)), ["a"]);
+        assert_eq!(registry.state("a"), Some(ModelState::Unloaded));
+
+        let lease = registry.acquire("a").await.unwrap();
+        assert!(lease.load_time.is_some());
+        assert_eq!(registry.state("a"), Some(ModelState::Loaded));
+    }
+
+    #[tokio::test]
+    async fn drain_blocks_new_jobs_and_waits_for_existing_ones() {
+        let registry = registry(1);
+        let existing = registry.admit(Some("a")).unwrap();
+
+        let drain = tokio::spawn({
+            let registry = Arc::clone(&registry);
+            async move { registry.drain("a", Duration::from_secs(5)).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // New "a" jobs are refused while the existing one runs; "b" flows
+        assert!(matches!(registry.admit(Some("a")), Err(DiffusionError::Overloaded(_))));
+        let other = registry.admit(Some("b")).unwrap();
+        assert!(!drain.is_finished());
+
+        drop(existing);
+        assert!(drain.await.unwrap().unwrap());
+        // Drained, but still closed until resumed; "b" jobs never counted
+        drop(other);
+        assert!(registry.admit(Some("a")).is_err());
+        registry.resume("a").unwrap();
+        assert!(registry.admit(Some("a")).is_ok());
+    }
+
+    #[tokio::test]
+    async fn drain_reports_jobs_still_running_at_the_timeout() {
+        let registry = registry(1);
+        let _running = registry.admit(Some("a")).unwrap();
+
+        assert!(!registry.drain("a", Duration::from_millis(10)).await.unwrap());
+        assert!(registry.drain("missing", Duration::ZERO).await.is_err());
+    }
+}
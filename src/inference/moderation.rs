@@ -0,0 +1,145 @@
+use crate::config::{ModerationAction, ModerationConfig};
+use crate::errors::{DiffusionError, Result};
+use regex::Regex;
+
+/// What moderation did to a prompt, reported back in the response metadata
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModerationOutcome {
+    Disabled,
+    Passed,
+    Stripped(Vec<String>),
+}
+
+impl ModerationOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ModerationOutcome::Disabled => "disabled",
+            ModerationOutcome::Passed => "passed",
+            ModerationOutcome::Stripped(_) => "stripped",
+        }
+    }
+
+    pub fn matched_terms(&self) -> &[String] {
+        match self {
+            ModerationOutcome::Stripped(terms) => terms,
+            _ => &[],
+        }
+    }
+}
+
+/// Prompt-level moderation pass run before generation, driven by the
+/// configured blocklist and regex patterns.
+pub struct PromptModerator {
+    enabled: bool,
+    action: ModerationAction,
+    rules: Vec<Regex>,
+}
+
+impl PromptModerator {
+    pub fn new(config: &ModerationConfig) -> Result<Self> {
+        let mut rules = Vec::with_capacity(config.blocklist.len() + config.patterns.len());
+
+        for term in &config.blocklist {
+            let pattern = format!(r"(?i)\b{}\b", regex::escape(term));
+            rules.push(Regex::new(&pattern).map_err(|e| {
+                DiffusionError::Config(format!("Invalid moderation term '{}': {}", term, e))
+            })?);
+        }
+
+        for pattern in &config.patterns {
+            rules.push(Regex::new(pattern).map_err(|e| {
+                DiffusionError::Config(format!("Invalid moderation pattern '{}': {}", pattern, e))
+            })?);
+        }
+
+        Ok(Self {
+            enabled: config.enabled,
+            action: config.action,
+            rules,
+        })
+    }
+
+    /// Checks the prompt, returning the (possibly stripped) prompt to generate
+    /// from. Rejections surface as `InvalidParameters`.
+    pub fn moderate(&self, prompt: &str) -> Result<(String, ModerationOutcome)> {
+        if !self.enabled {
+            return Ok((prompt.to_string(), ModerationOutcome::Disabled));
+        }
+
+        // TODO: Consult an external moderation API here when one is configured
+        let matched: Vec<String> = self.rules
+            .iter()
+            .flat_map(|rule| rule.find_iter(prompt).map(|m| m.as_str().to_string()))
+            .collect();
+
+        if matched.is_empty() {
+            return Ok((prompt.to_string(), ModerationOutcome::Passed));
+        }
+
+        match self.action {
+            ModerationAction::Reject => Err(DiffusionError::InvalidParameters(
+                format!("Prompt rejected by moderation (matched: {})", matched.join(", "))
+            )),
+            ModerationAction::Strip => {
+                let mut stripped = prompt.to_string();
+                for rule in &self.rules {
+                    stripped = rule.replace_all(&stripped, "").into_owned();
+                }
+                let stripped = stripped.split_whitespace().collect::<Vec<_>>().join(" ");
+
+                if stripped.is_empty() {
+                    return Err(DiffusionError::InvalidParameters(
+                        "Prompt is empty after moderation".to_string()
+                    ));
+                }
+
+                Ok((stripped, ModerationOutcome::Stripped(matched)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn moderator(action: &str) -> PromptModerator {
+        let config: ModerationConfig = serde_json::from_value(serde_json::json!({
+            "enabled": true,
+            "action": action,
+            "blocklist": ["gore"],
+        }))
+        .unwrap();
+        PromptModerator::new(&config).unwrap()
+    }
+
+    #[test]
+    fn blocklisted_prompt_is_rejected() {
+        let result = moderator("reject").moderate("a castle with GORE");
+        assert!(matches!(result, Err(DiffusionError::InvalidParameters(_))));
+    }
+
+    #[test]
+    fn blocklisted_terms_are_stripped() {
+        let (prompt, outcome) = moderator("strip").moderate("a castle with gore at dusk").unwrap();
+        assert_eq!(prompt, "a castle with at dusk");
+        assert_eq!(outcome, ModerationOutcome::Stripped(vec!["gore".to_string()]));
+
+        assert!(moderator("strip").moderate("gore").is_err());
+    }
+
+    #[test]
+    fn clean_prompt_passes() {
+        // Word boundaries: "gorge" isn't "gore"
+        let (prompt, outcome) = moderator("reject").moderate("a gorge at dawn").unwrap();
+        assert_eq!(prompt, "a gorge at dawn");
+        assert_eq!(outcome, ModerationOutcome::Passed);
+    }
+
+    #[test]
+    fn omitted_fields_take_defaults() {
+        let config: ModerationConfig = serde_json::from_value(serde_json::json!({"enabled": true})).unwrap();
+        assert_eq!(config.action, ModerationAction::Reject);
+        assert!(config.blocklist.is_empty() && config.patterns.is_empty());
+    }
+}
@@ -0,0 +1,55 @@
+/// Several negative prompts, each `(prompt, weight)`. Their weighted
+/// conditionings together form the unconditional branch of guidance, so
+/// concepts can be suppressed with different strengths.
+pub type WeightedPrompts = Vec<(String, f64)>;
+
+pub const MAX_NEGATIVE_PROMPTS: usize = 8;
+pub const MAX_WEIGHT: f64 = 2.0;
+
+/// At most `MAX_NEGATIVE_PROMPTS` entries, each a non-empty prompt with a
+/// weight in (0, `MAX_WEIGHT`]
+pub fn validate(prompts: &[(String, f64)]) -> Result<(), String> {
+    if prompts.len() > MAX_NEGATIVE_PROMPTS {
+        return Err(format!(
+            "At most {} negative_prompts are allowed, got {}",
+            MAX_NEGATIVE_PROMPTS,
+            prompts.len()
+        ));
+    }
+    for (prompt, weight) in prompts {
+        if prompt.trim().is_empty() {
+            return Err("negative_prompts entries must not be empty".to_string());
+        }
+        if !(*weight > 0.0 && *weight <= MAX_WEIGHT) {
+            return Err(format!(
+                "negative_prompts weight for '{}' must be in (0, {}], got {}",
+                prompt, MAX_WEIGHT, weight
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prompts(entries: &[(&str, f64)]) -> WeightedPrompts {
+        entries.iter().map(|(prompt, weight)| (prompt.to_string(), *weight)).collect()
+    }
+
+    #[test]
+    fn weights_and_counts_are_validated() {
+        assert!(validate(&[]).is_ok());
+        assert!(validate(&prompts(&[("blurry", 1.0), ("watermark", MAX_WEIGHT)])).is_ok());
+
+        for bad in [0.0, -1.0, MAX_WEIGHT + 0.1, f64::NAN] {
+            let error = validate(&prompts(&[("blurry", bad)])).unwrap_err();
+            assert!(error.contains("blurry"), "{}", error);
+        }
+        assert!(validate(&prompts(&[("  ", 1.0)])).is_err());
+
+        let too_many = vec![("blurry".to_string(), 1.0); MAX_NEGATIVE_PROMPTS + 1];
+        assert!(validate(&too_many).unwrap_err().contains("At most"));
+    }
+}
@@ -0,0 +1,88 @@
+use crate::errors::{DiffusionError, Result};
+use std::collections::VecDeque;
+use std::sync::{Mutex, PoisonError};
+
+/// Rolling record of the most recent generation outcomes, used to derive
+/// the failure rate reported by health checks
+pub struct OutcomeWindow {
+    outcomes: Mutex<VecDeque<bool>>,
+    capacity: usize,
+}
+
+impl OutcomeWindow {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            outcomes: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Records a generation result. Rejections caused by the request
+    /// itself say nothing about server health and are ignored.
+    pub fn record<T>(&self, result: &Result<T>) {
+        let failed = match result {
+            Ok(_) => false,
+            Err(DiffusionError::InvalidParameters(_))
+            | Err(DiffusionError::Validation(_))
+            | Err(DiffusionError::Unauthorized(_))
+            | Err(DiffusionError::Forbidden(_)) => return,
+            Err(_) => true,
+        };
+
+        let mut outcomes = self.outcomes.lock().unwrap_or_else(PoisonError::into_inner);
+        if outcomes.len() == self.capacity {
+            outcomes.pop_front();
+        }
+        outcomes.push_back(failed);
+    }
+
+    /// Fraction of recorded outcomes that failed, or None until at least
+    /// `min_samples` have been recorded
+    pub fn failure_rate(&self, min_samples: usize) -> Option<f64> {
+        let outcomes = self.outcomes.lock().unwrap_or_else(PoisonError::into_inner);
+        if outcomes.is_empty() || outcomes.len() < min_samples {
+            return None;
+        }
+
+        let failures = outcomes.iter().filter(|failed| **failed).count();
+        Some(failures as f64 / outcomes.len() as f64)
+    }
+}
+
+/// Rolling record of recent per-step generation times, used to estimate
+/// how long a request will take
+pub struct StepTimings {
+    samples: Mutex<VecDeque<f64>>,
+    capacity: usize,
+}
+
+impl StepTimings {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            samples: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity: capacity.max(1),
+        }
+    }
+
+    pub fn record(&self, seconds_per_step: f64) {
+        if !seconds_per_step.is_finite() || seconds_per_step <= 0.0 {
+            return;
+        }
+
+        let mut samples = self.samples.lock().unwrap_or_else(PoisonError::into_inner);
+        if samples.len() == self.capacity {
+            samples.pop_front();
+        }
+        samples.push_back(seconds_per_step);
+    }
+
+    /// Average seconds per step and the number of samples it is based on
+    pub fn average(&self) -> Option<(f64, usize)> {
+        let samples = self.samples.lock().unwrap_or_else(PoisonError::into_inner);
+        if samples.is_empty() {
+            return None;
+        }
+
+        Some((samples.iter().sum::<f64>() / samples.len() as f64, samples.len()))
+    }
+}
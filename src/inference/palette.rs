@@ -0,0 +1,90 @@
+use crate::inference::postprocess::parse_hex_color;
+use image::{DynamicImage, Rgb};
+
+pub const MAX_PALETTE_COLORS: usize = 8;
+/// How far each pixel moves toward its nearest palette color. Kept low so
+/// the grade tints rather than posterizes.
+const GRADE_STRENGTH: f32 = 0.25;
+
+/// Parses a palette of "#rrggbb" colors, at most `MAX_PALETTE_COLORS`
+pub fn parse(palette: &[String]) -> Result<Vec<[u8; 3]>, String> {
+    if palette.len() > MAX_PALETTE_COLORS {
+        return Err(format!(
+            "At most {} color_palette entries are allowed, got {}",
+            MAX_PALETTE_COLORS,
+            palette.len()
+        ));
+    }
+    palette
+        .iter()
+        .map(|color| {
+            parse_hex_color(color).ok_or_else(|| format!("color_palette entries must be #rrggbb, got '{}'", color))
+        })
+        .collect()
+}
+
+/// Canonical "#rrggbb" spelling, as reported in the metadata
+pub fn to_hex(color: [u8; 3]) -> String {
+    format!("#{:02x}{:02x}{:02x}", color[0], color[1], color[2])
+}
+
+/// Gentle color grade: every pixel is pulled `GRADE_STRENGTH` of the way
+/// toward the closest palette color. A no-op for an empty palette.
+pub fn grade(image: DynamicImage, palette: &[[u8; 3]]) -> DynamicImage {
+    if palette.is_empty() {
+        return image;
+    }
+
+    let distance = |a: &Rgb<u8>, b: &[u8; 3]| -> u32 {
+        (0..3).map(|c| (a[c] as i32 - b[c] as i32).pow(2) as u32).sum()
+    };
+
+    let mut graded = image.to_rgb8();
+    for pixel in graded.pixels_mut() {
+        let Some(target) = palette.iter().min_by_key(|color| distance(pixel, color)) else {
+            continue;
+        };
+        for c in 0..3 {
+            let shifted = pixel[c] as f32 + (target[c] as f32 - pixel[c] as f32) * GRADE_STRENGTH;
+            pixel[c] = shifted.round() as u8;
+        }
+    }
+
+    DynamicImage::ImageRgb8(graded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbImage;
+
+    #[test]
+    fn hex_colors_are_validated() {
+        let palette = parse(&["#FF8800".to_string(), "1e90ff".to_string()]).unwrap();
+        assert_eq!(palette, vec![[255, 136, 0], [30, 144, 255]]);
+        assert_eq!(to_hex(palette[0]), "#ff8800");
+
+        for bad in ["#ff88", "#gg0000", "red", "#ff88000", "#ffé000"] {
+            let err = parse(&[bad.to_string()]).unwrap_err();
+            assert!(err.contains(bad), "{}", err);
+        }
+        let too_many = vec!["#000000".to_string(); MAX_PALETTE_COLORS + 1];
+        assert!(parse(&too_many).unwrap_err().contains("At most"));
+        assert!(parse(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn grade_pulls_pixels_toward_the_nearest_palette_color() {
+        let image = DynamicImage::ImageRgb8(RgbImage::from_fn(8, 8, |x, _| {
+            if x < 4 { Rgb([200, 40, 40]) } else { Rgb([40, 40, 200]) }
+        }));
+        let palette = [[255, 0, 0], [0, 0, 255]];
+
+        let graded = grade(image.clone(), &palette).to_rgb8();
+        // A quarter of the way to the nearest color, each side to its own
+        assert_eq!(graded.get_pixel(0, 0), &Rgb([214, 30, 30]));
+        assert_eq!(graded.get_pixel(7, 0), &Rgb([30, 30, 214]));
+
+        assert_eq!(grade(image.clone(), &[]).to_rgb8(), image.to_rgb8());
+    }
+}
@@ -0,0 +1,97 @@
+use image::imageops::FilterType;
+use image::DynamicImage;
+use std::f64::consts::PI;
+
+/// Side of the downscaled grayscale image the DCT runs over
+const SAMPLE_SIZE: usize = 32;
+/// Side of the low frequency block kept from the DCT (64 bits)
+const HASH_SIZE: usize = 8;
+
+/// 64-bit DCT perceptual hash. Visually similar images get hashes a small
+/// Hamming distance apart; identical images get identical hashes.
+pub fn phash(image: &DynamicImage) -> u64 {
+    let gray = image
+        .resize_exact(SAMPLE_SIZE as u32, SAMPLE_SIZE as u32, FilterType::Triangle)
+        .to_luma8();
+    let pixels: Vec<f64> = gray.pixels().map(|p| p[0] as f64).collect();
+
+    // Separable DCT-II, keeping only the lowest HASH_SIZE frequencies
+    let mut rows = vec![0.0; SAMPLE_SIZE * HASH_SIZE];
+    for y in 0..SAMPLE_SIZE {
+        for u in 0..HASH_SIZE {
+            rows[y * HASH_SIZE + u] = (0..SAMPLE_SIZE)
+                .map(|x| pixels[y * SAMPLE_SIZE + x] * dct_basis(x, u))
+                .sum();
+        }
+    }
+
+    let mut coefficients = [0.0; HASH_SIZE * HASH_SIZE];
+    for v in 0..HASH_SIZE {
+        for u in 0..HASH_SIZE {
+            coefficients[v * HASH_SIZE + u] = (0..SAMPLE_SIZE)
+                .map(|y| rows[y * HASH_SIZE + u] * dct_basis(y, v))
+                .sum();
+        }
+    }
+
+    // The DC term only reflects overall brightness, so it is left out of
+    // the median the bits are thresholded against
+    let mut ac: Vec<f64> = coefficients[1..].to_vec();
+    ac.sort_by(f64::total_cmp);
+    let median = (ac[ac.len() / 2 - 1] + ac[ac.len() / 2]) / 2.0;
+
+    coefficients
+        .iter()
+        .enumerate()
+        .filter(|&(_, &c)| c > median)
+        .fold(0u64, |hash, (bit, _)| hash | (1 << bit))
+}
+
+fn dct_basis(n: usize, k: usize) -> f64 {
+    (PI / SAMPLE_SIZE as f64 * (n as f64 + 0.5) * k as f64).cos()
+}
+
+/// Number of differing bits between two hashes
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    fn pattern() -> RgbImage {
+        RgbImage::from_fn(128, 128, |x, y| {
+            let value = ((x as f64 / 9.0).sin() * (y as f64 / 13.0).cos() * 100.0 + 128.0) as u8;
+            Rgb([value, value / 2, 255 - value])
+        })
+    }
+
+    #[test]
+    fn similar_images_have_close_hashes() {
+        let original = DynamicImage::ImageRgb8(pattern());
+        let hash = phash(&original);
+        assert_eq!(phash(&original.clone()), hash);
+
+        let mut touched = pattern();
+        for x in 60..64 {
+            for y in 60..64 {
+                let Rgb([r, g, b]) = *touched.get_pixel(x, y);
+                touched.put_pixel(x, y, Rgb([r.saturating_add(30), g, b]));
+            }
+        }
+        assert!(hamming_distance(hash, phash(&DynamicImage::ImageRgb8(touched))) <= 4);
+
+        let mut inverted = original.clone();
+        inverted.invert();
+        assert!(hamming_distance(hash, phash(&inverted)) > 24);
+    }
+
+    #[test]
+    fn hamming_distance_counts_differing_bits() {
+        assert_eq!(hamming_distance(0, 0), 0);
+        assert_eq!(hamming_distance(0b1011, 0b0001), 2);
+        assert_eq!(hamming_distance(0, u64::MAX), 64);
+    }
+}
@@ -1,47 +1,693 @@
-use crate::config::InferenceConfig;
+use crate::config::{DegenerateAction, HealthConfig, InferenceConfig, ModelConfig, ModelDefinition};
 use crate::errors::{DiffusionError, Result};
-use image::{DynamicImage, ImageBuffer, Rgb};
+use crate::inference::budget::{ByteBudget, Reservation};
+use crate::inference::conditioning_cache::{CacheKey, Conditioning, ConditioningCache};
+use crate::inference::counter::GenerationCounter;
+use crate::inference::degenerate;
+use crate::inference::dimensions;
+use crate::inference::format::{self, OutputFormat};
+use crate::inference::latents;
+use crate::inference::models::ModelRegistry;
+use crate::inference::moderation::{ModerationOutcome, PromptModerator};
+use crate::inference::negative_prompts::{self, WeightedPrompts};
+use crate::inference::noise;
+use crate::inference::outcomes::{OutcomeWindow, StepTimings};
+use crate::inference::palette;
+use crate::inference::phash;
+use crate::inference::postprocess::{self, Degradation};
+use crate::inference::precision::Precision;
+use crate::inference::prompt_embeds;
+use crate::inference::prompt_schedule::{self, PromptSchedule};
+use crate::inference::references::{self, AppliedReference, ReferenceImages};
+use crate::inference::seed_log::SeedLog;
+use crate::inference::scheduler::{NoiseSchedule, SchedulerConfig, SchedulerKind};
+use crate::inference::strength::StrengthPreset;
+use crate::inference::tokens;
+use crate::inference::vary_region::{self, AppliedVaryRegion, VaryRegion};
+use image::imageops::FilterType;
+use image::{DynamicImage, GrayImage, ImageBuffer, Rgb};
+use serde::Serialize;
 use tch::Device;
-use tracing::{info, warn};
-use std::time::Instant;
+use tracing::{info, info_span, trace, warn, Instrument};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+
+/// Smallest width or height accepted
+pub const MIN_DIMENSION: i32 = 64;
+pub const DEFAULT_CLIP_SKIP: i32 = 1;
+pub const MAX_CLIP_SKIP: i32 = 12;
+pub const DEFAULT_STRENGTH: f64 = 0.75;
+pub const DEFAULT_TEMPERATURE: f64 = 1.0;
+pub const MAX_TEMPERATURE: f64 = 2.0;
+pub const MAX_DPI: u32 = 2400;
+pub const MAX_UPSCALE: u32 = 4;
+/// Step animations keep at most this many frames, sampling every k steps
+pub const MAX_ANIMATION_FRAMES: usize = 32;
+/// Longer side of step animation frames
+const ANIMATION_MAX_DIMENSION: u32 = 256;
+const ANIMATION_FRAME_DELAY_MS: u32 = 100;
+/// White
+pub const DEFAULT_BORDER_COLOR: [u8; 3] = [255, 255, 255];
+/// Smallest `max_response_bytes` accepted; below this nothing useful fits
+pub const MIN_RESPONSE_BYTES: u64 = 4096;
+
+/// Schema version of the generation metadata in every response. Bump it
+/// whenever the metadata shape changes; clients should still ignore fields
+/// they don't know.
+pub const METADATA_VERSION: u32 = 1;
+
+const WARMUP_PROMPT: &str = "a photograph of a mountain landscape";
 
 pub struct InferencePipeline {
     config: InferenceConfig,
     device: Device,
+    models: Arc<ModelRegistry>,
+    moderator: Arc<PromptModerator>,
+    outcomes: Arc<OutcomeWindow>,
+    step_timings: Arc<StepTimings>,
+    generations: Arc<GenerationCounter>,
+    seed_log: Arc<SeedLog>,
+    conditioning_cache: Arc<ConditioningCache>,
+    /// Bounds the blocking threads busy decoding uploaded images
+    decode_permits: Arc<Semaphore>,
+    /// Images in the VAE decode stage at once, across all generations
+    vae_permits: Arc<Semaphore>,
+    byte_budget: Option<Arc<ByteBudget>>,
+    default_precision: Precision,
+    mock: bool,
+    /// Batch indexes `check_image` fails, standing in for a flagged image
+    #[cfg(test)]
+    failing_images: Vec<usize>,
+    /// Batch indexes `check_degenerate` always sees as all black
+    #[cfg(test)]
+    degenerate_images: Vec<usize>,
+    /// Decodes running on the blocking pool, and the most seen at once
+    #[cfg(test)]
+    decode_gauge: Arc<DecodeGauge>,
+    /// `(model, prompt, size)` of each warmup generation run
+    #[cfg(test)]
+    warmup_log: Arc<std::sync::Mutex<Vec<(String, String, i32)>>>,
+    /// Size of each sub-batch run through the VAE
+    #[cfg(test)]
+    vae_batches: Arc<std::sync::Mutex<Vec<usize>>>,
+    /// Text encoder runs
+    #[cfg(test)]
+    prompt_encodes: Arc<std::sync::atomic::AtomicUsize>,
+}
+
+#[cfg(test)]
+#[derive(Default)]
+struct DecodeGauge {
+    current: std::sync::atomic::AtomicUsize,
+    peak: std::sync::atomic::AtomicUsize,
+}
+
+#[cfg(test)]
+impl DecodeGauge {
+    fn enter(&self) {
+        use std::sync::atomic::Ordering;
+        let current = self.current.fetch_add(1, Ordering::SeqCst) + 1;
+        self.peak.fetch_max(current, Ordering::SeqCst);
+        // Hold the slot long enough for concurrent decodes to overlap
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    
+    fn exit(&self) {
+        self.current.fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct GenerationParams {
     pub prompt: String,
+    /// Serialized text encoder output used as the conditioning instead of
+    /// `prompt`, which must then be empty; see `prompt_embeds::parse`
+    pub prompt_embeds: Option<Vec<u8>>,
+    /// Prompts switched to partway through denoising; parsed from inline
+    /// `[from:to:at]` edits in `prompt` when empty
+    pub prompt_schedule: PromptSchedule,
     pub negative_prompt: Option<String>,
+    /// Weighted alternative to `negative_prompt`; empty means unused
+    pub negative_prompts: WeightedPrompts,
+    /// 0 takes the selected model's default
     pub num_inference_steps: i32,
     pub guidance_scale: f64,
-    pub width: i32,
-    pub height: i32,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub aspect_ratio: Option<String>,
+    /// Target pixel count in millions, at `aspect_ratio` (or the default
+    /// size's ratio); instead of width/height
+    pub megapixels: Option<f64>,
+    /// Encoded init image for img2img
+    pub init_image: Option<Vec<u8>>,
+    pub strength: Option<f64>,
+    /// Style / content references, each with its weight
+    pub reference_images: ReferenceImages,
+    /// Prior output whose masked region is regenerated with `seed`, the
+    /// rest kept; the output takes its size
+    pub vary_region: Option<VaryRegion>,
+    /// Named alternative to `strength`
+    pub strength_preset: Option<String>,
     pub seed: Option<i64>,
+    /// Variation seed whose noise is blended in by `subseed_strength`
+    pub subseed: Option<i64>,
+    pub subseed_strength: Option<f64>,
+    /// Scales the noise ancestral samplers add each step (0-2, default 1)
+    pub temperature: Option<f64>,
+    pub clip_skip: Option<i32>,
+    pub model_id: Option<String>,
+    pub num_images: i32,
+    pub precision: Option<String>,
+    pub scheduler: Option<String>,
+    /// Skip the model's configured prompt prefix / suffix
+    pub disable_auto_prompt: bool,
+    /// Encode the prompt afresh instead of taking the cached conditioning;
+    /// the result still populates the cache
+    pub no_cache: bool,
+    /// Include each image's final latent (safetensors) in the result
+    pub return_latents: bool,
+    /// Report the per-step sigmas the scheduler ran with
+    pub return_schedule: bool,
+    /// Also return an animated GIF of the denoising steps
+    pub return_steps_animation: bool,
+    /// Emit trace-level detail for this request only
+    pub debug: bool,
+    /// Also return a JPEG thumbnail with this longer side
+    pub thumbnail: Option<u32>,
+    /// Print resolution written into the output metadata
+    pub dpi: Option<u32>,
+    /// Output encoding; the server default when unset
+    pub format: Option<String>,
+    /// Extra encodings of each image, returned keyed by format
+    pub output_formats: Vec<String>,
+    /// Cut out the background, returning RGBA (alpha-capable formats only)
+    pub transparent_background: bool,
+    /// Upscale factor applied after generation (1-4)
+    pub upscale: Option<u32>,
+    /// Re-encode (lower JPEG quality, then smaller) any image larger than this
+    pub max_response_bytes: Option<u64>,
+    /// Solid frame this many pixels wide around each image
+    pub border_width: Option<u32>,
+    /// "#rrggbb"; white by default
+    pub border_color: Option<String>,
+    /// "#rrggbb" colors the output is steered and graded toward; empty
+    /// means unused
+    pub color_palette: Vec<String>,
+    /// Overrides whether face restoration runs
+    pub face_restore: Option<bool>,
+    pub face_restore_weight: Option<f64>,
+    /// Quality preset the steps / guidance / scheduler were expanded from
+    pub quality: Option<String>,
+    /// Jobs waiting when this request started, used for adaptive step reduction
+    #[serde(skip)]
+    pub queue_depth: usize,
+    /// Job or request this generation runs for, keying its seed log entry
+    #[serde(skip)]
+    pub job_id: Option<String>,
+}
+
+/// Outcome of a single image within a batch
+#[derive(Debug)]
+pub struct ImageResult {
+    pub index: usize,
+    pub seed: i64,
+    pub outcome: std::result::Result<Vec<u8>, String>,  // PNG bytes or failure reason
+    /// Final latent as safetensors bytes, when requested
+    pub latent: Option<Vec<u8>>,
+    /// Perceptual hash of the returned image, when `include_phash` is set
+    pub phash: Option<u64>,
+    /// Downscaled JPEG, when requested
+    pub thumbnail: Option<Vec<u8>>,
+    /// GIF of the denoising steps, when requested
+    pub animation: Option<Vec<u8>>,
+    /// The image in each of the request's `output_formats`
+    pub encodings: Vec<(OutputFormat, Vec<u8>)>,
+    /// Set when the image was re-encoded to fit `max_response_bytes`
+    pub degraded: Option<Degradation>,
 }
 
 #[derive(Debug)]
 pub struct GenerationResult {
-    pub images: Vec<Vec<u8>>,  // PNG bytes
+    pub results: Vec<ImageResult>,
     pub generation_time: f64,
     pub seed: i64,
+    pub subseed: Option<i64>,
+    pub subseed_strength: Option<f64>,
+    pub temperature: f64,
     pub steps_taken: i32,
+    /// Originally requested steps, when adaptive reduction lowered them
+    pub requested_steps: Option<i32>,
+    /// Images regenerated with a new seed after a degenerate output
+    pub degenerate_retries: u32,
+    pub guidance_scale: f64,
+    pub quality: Option<String>,
+    pub width: i32,
+    pub height: i32,
+    /// Size asked for, when it was snapped to a resolution bucket
+    pub requested_dimensions: Option<(i32, i32)>,
+    pub strength: Option<f64>,
+    /// Original size of an init image that was downscaled to fit
+    pub init_image_resized: Option<(u32, u32)>,
+    /// Reference images the generation was conditioned on
+    pub references: Vec<AppliedReference>,
+    /// Regenerated and preserved regions, for vary-region requests
+    pub vary_region: Option<AppliedVaryRegion>,
+    /// Weight face restoration ran with; None when it was skipped
+    pub face_restore_weight: Option<f64>,
+    pub clip_skip: i32,
+    pub model_used: String,
+    /// Prompts as sent to the model, after moderation and prefix / suffix
+    pub final_prompt: String,
+    pub final_negative_prompt: Option<String>,
+    /// Weighted negative prompts as sent to the model
+    pub negative_prompts: WeightedPrompts,
+    /// Palette applied, as "#rrggbb"
+    pub color_palette: Vec<String>,
+    /// The request's prompt was empty and `fallback_prompt` was used
+    pub prompt_fallback: bool,
+    /// Scheduled prompts as sent to the model
+    pub prompt_schedule: PromptSchedule,
+    /// The prompt conditioning came from the cache, skipping the text encoder
+    pub conditioning_cache_hit: bool,
+    /// The request's precomputed `prompt_embeds` were used as the conditioning
+    pub prompt_embeds: bool,
+    pub model_load_time: Option<f64>,
+    pub precision: Precision,
+    pub format: OutputFormat,
+    pub scheduler: SchedulerKind,
+    pub noise_schedule: NoiseSchedule,
+    /// Noise level at each step, when `return_schedule` was set
+    pub sigmas: Option<Vec<f64>>,
+    pub moderation: ModerationOutcome,
+    /// Share of `max_inflight_bytes` held until this result is dropped
+    pub reservation: Option<Reservation>,
+}
+
+/// A single cell of a seed search grid
+#[derive(Debug)]
+pub struct SeedCell {
+    pub index: usize,
+    pub seed: i64,
+    pub row: u32,
+    pub column: u32,
+    /// Failure reason when this cell could not be generated
+    pub error: Option<String>,
+}
+
+/// Low resolution previews of consecutive seeds, tiled into one PNG
+#[derive(Debug)]
+pub struct SeedGrid {
+    pub image: Vec<u8>,
+    pub columns: u32,
+    pub rows: u32,
+    pub cell_width: u32,
+    pub cell_height: u32,
+    pub cells: Vec<SeedCell>,
+    pub generation_time: f64,
+}
+
+/// Expected duration of a generation, from recent per-step timings
+#[derive(Debug)]
+pub struct GenerationEstimate {
+    pub seconds: f64,
+    pub seconds_per_step: f64,
+    pub steps: i32,
+    pub num_images: i32,
+    /// Generations the per-step time is averaged over; 0 when it is the
+    /// configured fallback
+    pub samples: usize,
+    pub cost: Option<f64>,
+}
+
+impl ImageResult {
+    /// Encoding of this image: `requested`, unless fitting it to
+    /// `max_response_bytes` switched it
+    pub fn format(&self, requested: OutputFormat) -> OutputFormat {
+        self.degraded.map_or(requested, |degradation| degradation.format)
+    }
+}
+
+impl GenerationResult {
+    /// PNG bytes of every image that generated successfully, in batch order
+    pub fn images(&self) -> impl Iterator<Item = &Vec<u8>> {
+        self.results.iter().filter_map(|r| r.outcome.as_ref().ok())
+    }
+    
+    /// The first image that generated successfully, the one single-image
+    /// responses return
+    pub fn first_image(&self) -> Option<&ImageResult> {
+        self.results.iter().find(|r| r.outcome.is_ok())
+    }
 }
 
 impl InferencePipeline {
-    pub fn new(config: InferenceConfig, device: Device) -> Result<Self> {
-        Ok(Self { config, device })
+    pub fn new(
+        config: InferenceConfig,
+        model_config: &ModelConfig,
+        health_config: &HealthConfig,
+        device: Device,
+    ) -> Result<Self> {
+        let models = Arc::new(ModelRegistry::new(model_config)?);
+        let moderator = Arc::new(PromptModerator::new(&config.moderation)?);
+        if config.allow_empty_prompt_fallback && config.fallback_prompt.is_empty() {
+            return Err(DiffusionError::Config(
+                "allow_empty_prompt_fallback requires a fallback_prompt".to_string()
+            ));
+        }
+        for (name, range) in &config.scheduler_guidance {
+            name.parse::<SchedulerKind>().map_err(DiffusionError::Config)?;
+            if !(range.min > 0.0 && range.min <= range.max) {
+                return Err(DiffusionError::Config(format!(
+                    "scheduler_guidance.{} needs 0 < min <= max", name
+                )));
+            }
+        }
+        if config.max_compute_budget.map_or(false, |budget| !(budget > 0.0)) {
+            return Err(DiffusionError::Config(
+                "max_compute_budget must be positive".to_string()
+            ));
+        }
+        if !(0.0..=1.0).contains(&config.face_restore.weight) {
+            return Err(DiffusionError::Config(
+                "face_restore.weight must be between 0.0 and 1.0".to_string()
+            ));
+        }
+        for model_id in models.ids() {
+            let (min, max, default) = Self::steps_for(&config, models.definition(&model_id));
+            if !(1 <= min && min <= default && default <= max) {
+                return Err(DiffusionError::Config(format!(
+                    "Model {} needs 1 <= min_steps <= default_steps <= max_steps, got {}, {}, {}",
+                    model_id, min, default, max
+                )));
+            }
+        }
+        let outcomes = Arc::new(OutcomeWindow::new(health_config.failure_window));
+        let step_timings = Arc::new(StepTimings::new(health_config.failure_window));
+        let generations = Arc::new(GenerationCounter::load(config.generation_counter_path.clone())?);
+        let seed_log = Arc::new(SeedLog::new(config.seed_log_dir.clone()));
+        let conditioning_cache = Arc::new(ConditioningCache::new(config.conditioning_cache_size));
+        let decode_permits = Arc::new(Semaphore::new(config.max_concurrent_decodes.max(1)));
+        let vae_permits = Arc::new(Semaphore::new(config.vae_decode_batch.max(1)));
+        let byte_budget = config.max_inflight_bytes.map(|max_bytes| {
+            Arc::new(ByteBudget::new(max_bytes, Duration::from_secs(config.inflight_wait_seconds)))
+        });
+        let mock = model_config.mock_inference;
+        let default_precision = Self::default_precision(&model_config.precision, device, mock)?;
+        Ok(Self {
+            config,
+            device,
+            models,
+            moderator,
+            outcomes,
+            step_timings,
+            generations,
+            seed_log,
+            conditioning_cache,
+            decode_permits,
+            vae_permits,
+            byte_budget,
+            default_precision,
+            mock,
+            #[cfg(test)]
+            failing_images: Vec::new(),
+            #[cfg(test)]
+            degenerate_images: Vec::new(),
+            #[cfg(test)]
+            decode_gauge: Arc::default(),
+            #[cfg(test)]
+            warmup_log: Arc::default(),
+            #[cfg(test)]
+            vae_batches: Arc::default(),
+            #[cfg(test)]
+            prompt_encodes: Arc::default(),
+        })
+    }
+    
+    /// Runs `runs` small generations of every configured model so kernels
+    /// are compiled before the first real request. Each model warms up with
+    /// its own `warmup_prompt` / `warmup_size` when set, otherwise a generic
+    /// prompt at `size`. Returns the seconds spent per model. Bypasses
+    /// `generate`, so warmups don't count toward failure rates or step timings.
+    /// Mock pipelines have no kernels to compile, so callers skip it there.
+    pub async fn warmup(&self, runs: u32, size: i32, steps: i32) -> Result<Vec<(String, f64)>> {
+        let start = Instant::now();
+        let mut timings = Vec::new();
+        for model_id in self.models.ids() {
+            let definition = self.models.definition(&model_id);
+            let prompt = definition
+                .and_then(|m| m.warmup_prompt.clone())
+                .unwrap_or_else(|| WARMUP_PROMPT.to_string());
+            let size = definition.and_then(|m| m.warmup_size).unwrap_or(size);
+            let (min_steps, max_steps, _) = self.step_range(Some(&model_id));
+            let steps = steps.clamp(min_steps, max_steps);
+            
+            let model_start = Instant::now();
+            for run in 0..runs {
+                let run_start = Instant::now();
+                self.run_generation(GenerationParams {
+                    prompt: prompt.clone(),
+                    prompt_embeds: None,
+                    prompt_schedule: Vec::new(),
+                    negative_prompt: None,
+                    negative_prompts: Vec::new(),
+                    num_inference_steps: steps,
+                    guidance_scale: self.config.default_guidance_scale,
+                    width: Some(size),
+                    height: Some(size),
+                    aspect_ratio: None,
+                    megapixels: None,
+                    init_image: None,
+                    strength: None,
+                    reference_images: Vec::new(),
+                    vary_region: None,
+                    strength_preset: None,
+                    seed: Some(run as i64),
+                    subseed: None,
+                    subseed_strength: None,
+                    temperature: None,
+                    clip_skip: None,
+                    model_id: Some(model_id.clone()),
+                    num_images: 1,
+                    precision: None,
+                    scheduler: None,
+                    disable_auto_prompt: true,
+                    no_cache: false,
+                    return_latents: false,
+                    return_schedule: false,
+                    return_steps_animation: false,
+                    debug: false,
+                    thumbnail: None,
+                    dpi: None,
+                    format: None,
+                    output_formats: Vec::new(),
+                    transparent_background: false,
+                    upscale: None,
+                    max_response_bytes: None,
+                    border_width: None,
+                    border_color: None,
+                    color_palette: Vec::new(),
+                    face_restore: Some(false),
+                    face_restore_weight: None,
+                    quality: None,
+                    queue_depth: 0,
+                    job_id: None,
+                }, None).await?;
+                #[cfg(test)]
+                self.warmup_log.lock().unwrap().push((model_id.clone(), prompt.clone(), size));
+                trace!(%model_id, run, elapsed = ?run_start.elapsed(), "Warmup run finished");
+            }
+            
+            let seconds = model_start.elapsed().as_secs_f64();
+            info!("Warmed up {} ({}x{}, {} runs) in {:.2}s", model_id, size, size, runs, seconds);
+            timings.push((model_id, seconds));
+        }
+        
+        info!("Warmup finished in {:.2}s", start.elapsed().as_secs_f64());
+        Ok(timings)
+    }
+    
+    /// Text encoder runs so far
+    #[cfg(test)]
+    pub(crate) fn prompt_encodes(&self) -> usize {
+        self.prompt_encodes.load(std::sync::atomic::Ordering::SeqCst)
+    }
+    
+    pub fn models(&self) -> &ModelRegistry {
+        &self.models
+    }
+    
+    /// Successful generations over the server's lifetime, across restarts
+    /// when `generation_counter_path` is set
+    pub fn total_generations(&self) -> u64 {
+        self.generations.get()
+    }
+    
+    /// Recent generation failure rate, once enough generations have run
+    pub fn failure_rate(&self, min_samples: usize) -> Option<f64> {
+        self.outcomes.failure_rate(min_samples)
     }
     
     pub async fn generate(
         &self,
         params: GenerationParams,
+    ) -> Result<GenerationResult> {
+        self.generate_in(params, None).await
+    }
+    
+    /// `generate` for one job of a coalesced batch. Jobs of the batch with
+    /// the same prompt share the conditioning the first of them encodes
+    /// through `shared`, even with the conditioning cache disabled.
+    pub async fn generate_sharing(
+        &self,
+        params: GenerationParams,
+        shared: &ConditioningCache,
+    ) -> Result<GenerationResult> {
+        self.generate_in(params, Some(shared)).await
+    }
+    
+    async fn generate_in(
+        &self,
+        params: GenerationParams,
+        shared: Option<&ConditioningCache>,
+    ) -> Result<GenerationResult> {
+        let params = self.with_default_steps(params);
+        let span = info_span!("generation", debug = params.debug);
+        let logged_params = self.seed_log.is_enabled().then(|| params.clone());
+        let result = self.run_generation(params, shared).instrument(span).await;
+        self.outcomes.record(&result);
+        
+        if let Ok(result) = &result {
+            let compute_time = result.generation_time - result.model_load_time.unwrap_or(0.0);
+            let steps = result.steps_taken as f64 * result.results.len() as f64;
+            self.step_timings.record(compute_time / steps);
+            self.generations.increment().await;
+            if let Some(params) = &logged_params {
+                let job_id = params.job_id
+                    .clone()
+                    .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+                self.seed_log.append(&job_id, params, result).await;
+            }
+        }
+        
+        result
+    }
+    
+    /// Checks `params` the way `generate` does before starting any work, for
+    /// callers that answer without generating (e.g. with a 304)
+    pub fn validate(&self, params: &GenerationParams) -> Result<()> {
+        let params = &self.with_default_steps(params.clone());
+        let (width, height) = self.snap_dimensions(params, self.resolve_dimensions(params)?);
+        self.validate_params(params, width, height)?;
+        self.resolve_precision(params.precision.as_deref())?;
+        self.resolve_format(params.format.as_deref())?;
+        let model_id = self.models.resolve(params.model_id.as_deref())?;
+        if let Some(embeds) = &params.prompt_embeds {
+            prompt_embeds::parse(embeds, self.encoder_dim(Some(&model_id))).map_err(DiffusionError::InvalidParameters)?;
+        }
+        let (prompt, _, _) = self.final_prompts(params, &model_id, params.prompt_embeds.is_some());
+        self.moderator.moderate(&prompt)?;
+        Ok(())
+    }
+    
+    /// Estimates generation time as average per-step time × steps × batch
+    /// size, without running anything
+    pub fn estimate(&self, params: &GenerationParams) -> Result<GenerationEstimate> {
+        let params = &self.with_default_steps(params.clone());
+        let (width, height) = self.snap_dimensions(params, self.resolve_dimensions(params)?);
+        self.validate_params(params, width, height)?;
+        
+        let (seconds_per_step, samples) = self.step_timings
+            .average()
+            .unwrap_or((self.config.estimate_step_seconds, 0));
+        let seconds = seconds_per_step
+            * params.num_inference_steps as f64
+            * params.num_images as f64;
+        
+        Ok(GenerationEstimate {
+            seconds,
+            seconds_per_step,
+            steps: params.num_inference_steps,
+            num_images: params.num_images,
+            samples,
+            cost: self.config.cost_per_second.map(|price| price * seconds),
+        })
+    }
+    
+    async fn run_generation(
+        &self,
+        params: GenerationParams,
+        shared: Option<&ConditioningCache>,
     ) -> Result<GenerationResult> {
         let start = Instant::now();
         
+        let model_id = self.models.resolve(params.model_id.as_deref())?;
+        let embeds = params.prompt_embeds
+            .as_deref()
+            .map(|embeds| prompt_embeds::parse(embeds, self.encoder_dim(Some(&model_id))))
+            .transpose()
+            .map_err(DiffusionError::InvalidParameters)?;
+        let (prompt, negative_prompt, prompt_fallback) = self.final_prompts(&params, &model_id, embeds.is_some());
+        
         // Validate parameters
-        self.validate_params(&params)?;
+        let requested_dimensions = self.resolve_dimensions(&params)?;
+        let (width, height) = self.snap_dimensions(&params, requested_dimensions);
+        if (width, height) != requested_dimensions {
+            info!(
+                "Snapped {}x{} to resolution bucket {}x{}",
+                requested_dimensions.0, requested_dimensions.1, width, height
+            );
+        }
+        self.validate_params(&params, width, height)?;
+        let upscale = params.upscale.unwrap_or(1) as i32;
+        let border = params.border_width.unwrap_or(0);
+        // Bounded by validation, but checked so nothing can wrap
+        let output_size = |size: i32| {
+            let border = i32::try_from(border).ok()?.checked_mul(2)?;
+            size.checked_mul(upscale)?.checked_add(border)
+        };
+        let (Some(output_width), Some(output_height)) = (output_size(width), output_size(height)) else {
+            return Err(DiffusionError::InvalidParameters("Output size overflows".to_string()));
+        };
+        let border_color = params.border_color
+            .as_deref()
+            .and_then(postprocess::parse_hex_color)
+            .unwrap_or(DEFAULT_BORDER_COLOR);
+        let palette = palette::parse(&params.color_palette).map_err(DiffusionError::InvalidParameters)?;
+        let output_formats = format::parse_list(&params.output_formats).map_err(DiffusionError::InvalidParameters)?;
+        let reservation = self
+            .reserve_bytes(&params, output_width, output_height, output_formats.len())
+            .await?;
+        let precision = self.resolve_precision(params.precision.as_deref())?;
+        let format = self.resolve_format(params.format.as_deref())?;
+        if prompt_fallback {
+            info!("Empty prompt, using the configured fallback prompt");
+        }
+        let (prompt, moderation) = self.moderator.moderate(&prompt)?;
+        trace!(
+            ?requested_dimensions, width, height, ?precision, ?moderation,
+            "Resolved dimensions and precision"
+        );
+        
+        let weighted_negatives = self.prepare_negative_prompts(&params, &model_id);
+        // A single negative prompt is the one-entry case at full weight
+        let negatives: Vec<(&str, f64)> = if weighted_negatives.is_empty() {
+            negative_prompt.iter().map(|negative| (negative.as_str(), 1.0)).collect()
+        } else {
+            weighted_negatives.iter().map(|(negative, weight)| (negative.as_str(), *weight)).collect()
+        };
+        self.check_token_budget(&prompt, &negatives, params.num_images)?;
+        let schedule = self.prepare_prompt_schedule(&params, &model_id)?;
+        let scheduler = self.resolve_scheduler(params.scheduler.as_deref(), &model_id)?;
+        trace!(%model_id, ?scheduler, %prompt, ?negative_prompt, "Resolved model and prompts");
+        
+        let stage = Instant::now();
+        let model = self.models.acquire(&model_id).await?;
+        trace!(elapsed = ?stage.elapsed(), load_time = ?model.load_time, "Model acquired");
+        if model.load_time.is_some() {
+            // Fresh weights, so cached encodings may no longer match them
+            self.conditioning_cache.clear();
+        }
         
         // Get or generate seed
         let seed = params.seed.unwrap_or_else(|| {
@@ -54,111 +700,2738 @@ impl InferencePipeline {
         
         info!(
             "Starting generation: prompt='{}', steps={}, guidance={}, size={}x{}",
-            params.prompt,
+            prompt,
             params.num_inference_steps,
             params.guidance_scale,
-            params.width,
-            params.height
+            width,
+            height
         );
         
-        // Generate image (placeholder implementation)
-        // TODO: Replace with actual Stable Diffusion inference
-        let image = self.generate_placeholder_image(
-            params.width as u32,
-            params.height as u32,
-            &params.prompt,
-            seed,
-        )?;
+        let (init_image, init_image_resized) = match &params.init_image {
+            Some(bytes) => {
+                let (image, resized) = self.decode_init_image(bytes, width as u32, height as u32).await?;
+                if let Some((original_width, original_height)) = resized {
+                    info!(
+                        "Downscaled {}x{} init image to {}x{}",
+                        original_width, original_height, width, height
+                    );
+                }
+                (Some(image), resized)
+            }
+            None => (None, None),
+        };
+        let (reference_images, applied_references) = self.decode_references(&params.reference_images).await?;
+        let vary = match &params.vary_region {
+            Some(region) => Some(self.decode_vary_region(region).await?),
+            None => None,
+        };
+        let requested_strength = self.resolve_strength(&params)?;
+        let strength = init_image
+            .as_ref()
+            .map(|_| requested_strength.unwrap_or(DEFAULT_STRENGTH));
+        
+        // Load shedding never goes below what the model can work with
+        let (min_steps, _, _) = self.step_range(Some(&model_id));
+        let steps = self.adaptive_steps(params.num_inference_steps, params.queue_depth).max(min_steps);
+        if steps < params.num_inference_steps {
+            info!(
+                "Queue depth {}: reducing steps from {} to {}",
+                params.queue_depth, params.num_inference_steps, steps
+            );
+        }
+        
+        let scheduler_config = self.scheduler_config(scheduler, &model_id, steps)?;
+        trace!(seed, steps, ?strength, noise_schedule = ?scheduler_config.noise_schedule, "Resolved sampling parameters");
+        
+        let face_restore_weight = params.face_restore
+            .unwrap_or(self.config.face_restore.enabled)
+            .then(|| params.face_restore_weight.unwrap_or(self.config.face_restore.weight));
+        
+        let clip_skip = params.clip_skip.unwrap_or(DEFAULT_CLIP_SKIP);
+        let stage = Instant::now();
+        let encode_negatives = || -> Vec<(u64, f64)> {
+            negatives
+                .iter()
+                .map(|(negative, weight)| (self.encode_prompt(negative, clip_skip), *weight))
+                .collect()
+        };
+        let (encoded, conditioning_cache_hit) = match &embeds {
+            // Cheap to take as they are, so never cached
+            Some(embeds) => (Arc::new(Conditioning {
+                conditioning: self.encode_embeds(embeds),
+                schedule: Vec::new(),
+                unconditioning: encode_negatives(),
+            }), false),
+            None => {
+                let cache_key = CacheKey::new(&model_id, &prompt, &schedule, &negatives, clip_skip, precision);
+                let encode = || Conditioning {
+                    conditioning: self.encode_prompt(&prompt, clip_skip),
+                    schedule: schedule
+                        .iter()
+                        .map(|(fraction, prompt)| (*fraction, self.encode_prompt(prompt, clip_skip)))
+                        .collect(),
+                    unconditioning: encode_negatives(),
+                };
+                // The encode doesn't await, so of a coalesced batch's jobs
+                // (polled together) only the first misses
+                let cache = match shared {
+                    Some(shared) if !self.conditioning_cache.is_enabled() => shared,
+                    _ => self.conditioning_cache.as_ref(),
+                };
+                if params.no_cache {
+                    (cache.encode_fresh(cache_key, encode), false)
+                } else {
+                    cache.get_or_encode(cache_key, encode)
+                }
+            }
+        };
+        let reference_conditioning: Vec<(u64, f64)> = reference_images
+            .iter()
+            .map(|(image, weight)| (self.encode_reference(image), *weight))
+            .collect();
+        trace!(
+            clip_skip, scheduled = schedule.len(), references = reference_conditioning.len(),
+            conditioning_cache_hit, prompt_embeds = embeds.is_some(), elapsed = ?stage.elapsed(),
+            "Prompt encoded"
+        );
+        
+        // Generate each image of the batch independently so that one
+        // failure doesn't discard the others
+        let mut results = Vec::with_capacity(params.num_images as usize);
+        let mut degenerate_retries = 0;
+        // Images still degenerate once retries ran out
+        let mut degenerate_failures = 0;
+        // Images that can't fit `max_response_bytes`, which no retry changes
+        let mut unfit = 0;
+        let subseed = params.subseed.map(|subseed| (subseed, params.subseed_strength.unwrap_or(0.0)));
+        let temperature = params.temperature.unwrap_or(DEFAULT_TEMPERATURE);
+        // Latents go through the VAE in sub-batches of `vae_decode_batch`,
+        // holding that many VAE permits only while they decode, to cap peak
+        // memory
+        let decode_batch = self.config.vae_decode_batch.max(1);
+        let latent_shape = latents::latent_shape(width as u32, height as u32);
+        let num_images = params.num_images as usize;
+        let denoise = |seed: i64, subseed: Option<(i64, f64)>| {
+            self.denoise(
+                &scheduler_config,
+                &latent_shape,
+                encoded.conditioning,
+                &encoded.schedule,
+                &encoded.unconditioning,
+                &reference_conditioning,
+                &palette,
+                seed,
+                subseed,
+                temperature,
+            )
+        };
+        for batch_start in (0..num_images).step_by(decode_batch) {
+            let batch = batch_start..num_images.min(batch_start + decode_batch);
+            
+            let stage = Instant::now();
+            let denoised: Vec<(i64, Vec<f32>)> = batch
+                .clone()
+                .map(|index| {
+                    let image_seed = seed.wrapping_add(index as i64);
+                    // Each image of the batch varies around its own subseed, too
+                    let image_subseed = subseed.map(|(subseed, strength)| (subseed.wrapping_add(index as i64), strength));
+                    (image_seed, denoise(image_seed, image_subseed))
+                })
+                .collect();
+            let decoded = self.vae_decode(&denoised, width as u32, height as u32, encoded.conditioning).await?;
+            trace!(first = batch.start, size = batch.len(), elapsed = ?stage.elapsed(), "Denoised and decoded sub-batch");
+            
+            for ((index, (mut image_seed, mut final_latent)), mut image) in batch.zip(denoised).zip(decoded) {
+                let image_subseed = subseed.map(|(subseed, strength)| (subseed.wrapping_add(index as i64), strength));
+                let mut attempt = 0;
+                // Set when retries run out; fails this image, not the batch
+                let mut degenerate = None;
+                
+                // Retried seeds skip past the rest of the batch so they never
+                // collide with a sibling image's seed
+                loop {
+                    let problem = match self.check_degenerate(index, &final_latent, &image, attempt) {
+                        Ok(None) => break,
+                        Ok(Some(problem)) => problem,
+                        Err(e) => {
+                            degenerate_failures += 1;
+                            degenerate = Some(e.to_string());
+                            break;
+                        }
+                    };
+                    attempt += 1;
+                    degenerate_retries += 1;
+                    let offset = index as i64 + attempt as i64 * params.num_images as i64;
+                    image_seed = seed.wrapping_add(offset);
+                    warn!(
+                        "Image {} of batch came out {}, retrying with seed {}",
+                        index, problem, image_seed
+                    );
+                    let retried = [(image_seed, denoise(image_seed, image_subseed))];
+                    image = self.vae_decode(&retried, width as u32, height as u32, encoded.conditioning).await?.remove(0);
+                    let [(_, latent)] = retried;
+                    final_latent = latent;
+                }
+                
+                let latent = params.return_latents.then(|| latents::to_safetensors(&latent_shape, &final_latent));
+                
+                if let (Some(init), Some(strength)) = (&init_image, strength) {
+                    image = self.apply_init_image(image, init, strength);
+                }
+                
+                let stage = Instant::now();
+                let mut image_phash = None;
+                let mut thumbnail = None;
+                let mut animation = None;
+                let mut encodings = Vec::with_capacity(output_formats.len());
+                let mut degraded = None;
+                let outcome = degenerate
+                    .map_or(Ok(()), Err)
+                    .and_then(|_| self.check_image(index, &image))
+                    .and_then(|_| {
+                        let mut image = self.postprocess(image, face_restore_weight, upscale as u32);
+                        image = palette::grade(image, &palette);
+                        // TODO: Denoise only the masked latents, re-noising the
+                        // prior's latents outside the mask at every step, so the
+                        // region blends with its surroundings. The placeholder
+                        // pastes the masked part of a full new image.
+                        if let Some((prior, mask, _)) = &vary {
+                            image = vary_region::composite(prior, image, mask);
+                        }
+                        if params.transparent_background {
+                            image = postprocess::remove_background(image, self.config.transparency_threshold);
+                        }
+                        image = postprocess::add_border(image, border, border_color).map_err(|e| e.to_string())?;
+                        if self.config.include_phash {
+                            image_phash = Some(phash::phash(&image));
+                        }
+                        if let Some(max_dimension) = params.thumbnail {
+                            let encoded = postprocess::encode_thumbnail(&image, max_dimension, params.dpi);
+                            thumbnail = Some(encoded.map_err(|e| e.to_string())?);
+                        }
+                        if params.return_steps_animation {
+                            let frames = self.step_frames(steps, image_seed, &image);
+                            let encoded = postprocess::encode_gif(&frames, ANIMATION_FRAME_DELAY_MS);
+                            animation = Some(encoded.map_err(|e| e.to_string())?);
+                        }
+                        let encoded = self.encode(&image, format, params.dpi).map_err(|e| e.to_string())?;
+                        for &output_format in &output_formats {
+                            let extra = if output_format == format {
+                                encoded.clone()
+                            } else {
+                                self.encode(&image, output_format, params.dpi).map_err(|e| e.to_string())?
+                            };
+                            encodings.push((output_format, extra));
+                        }
+                        match params.max_response_bytes.map(|max| max as usize) {
+                            Some(max) if encoded.len() > max => {
+                                let (bytes, degradation) =
+                                    postprocess::fit_to_budget(&image, max, params.transparent_background, params.dpi)
+                                        .map_err(|e| e.to_string())?
+                                        .ok_or_else(|| {
+                                            unfit += 1;
+                                            format!("Image does not fit in max_response_bytes ({} bytes)", max)
+                                        })?;
+                                degraded = Some(degradation);
+                                Ok(bytes)
+                            }
+                            _ => Ok(encoded),
+                        }
+                    });
+                trace!(index, elapsed = ?stage.elapsed(), "Post-processed and encoded");
+                
+                if let Err(reason) = &outcome {
+                    warn!("Image {} of batch failed: {}", index, reason);
+                }
+                
+                results.push(ImageResult {
+                    index,
+                    seed: image_seed,
+                    outcome,
+                    latent,
+                    phash: image_phash,
+                    thumbnail,
+                    animation,
+                    encodings,
+                    degraded,
+                });
+            }
+        }
+        
+        if results.iter().all(|r| r.outcome.is_err()) {
+            let message = "All images in the batch failed".to_string();
+            return Err(if unfit == results.len() {
+                DiffusionError::Unrecoverable(format!("{}: none fit in max_response_bytes", message))
+            } else if degenerate_failures == results.len() {
+                DiffusionError::Inference("degenerate output".to_string())
+            } else {
+                DiffusionError::Inference(message)
+            });
+        }
         
         let elapsed = start.elapsed().as_secs_f64();
         
         info!("Generation completed in {:.2}s", elapsed);
         
+        // Report the size of the images actually returned
+        let crop = self.config.edge_crop_pixels as i32;
+        
         Ok(GenerationResult {
-            images: vec![image],
+            results,
             generation_time: elapsed,
             seed,
-            steps_taken: params.num_inference_steps,
+            subseed: params.subseed,
+            subseed_strength: subseed.map(|(_, strength)| strength),
+            temperature,
+            steps_taken: steps,
+            requested_steps: (steps < params.num_inference_steps).then_some(params.num_inference_steps),
+            degenerate_retries,
+            guidance_scale: params.guidance_scale,
+            quality: params.quality,
+            width: output_width - crop * 2 * upscale,
+            height: output_height - crop * 2 * upscale,
+            requested_dimensions: ((width, height) != requested_dimensions).then_some(requested_dimensions),
+            strength,
+            init_image_resized,
+            references: applied_references,
+            vary_region: vary.as_ref().map(|(_, _, applied)| *applied),
+            face_restore_weight,
+            clip_skip,
+            model_used: model_id,
+            final_prompt: prompt,
+            final_negative_prompt: negative_prompt,
+            negative_prompts: weighted_negatives,
+            color_palette: palette.iter().copied().map(palette::to_hex).collect(),
+            prompt_fallback,
+            prompt_schedule: schedule,
+            conditioning_cache_hit,
+            prompt_embeds: embeds.is_some(),
+            model_load_time: model.load_time,
+            precision,
+            format,
+            scheduler,
+            noise_schedule: scheduler_config.noise_schedule,
+            sigmas: params.return_schedule.then(|| scheduler_config.sigmas()),
+            moderation,
+            reservation,
         })
     }
     
-    fn validate_params(&self, params: &GenerationParams) -> Result<()> {
-        if params.prompt.is_empty() {
+    /// Generates `count` previews at consecutive seeds starting from
+    /// `params.seed` at reduced resolution and tiles them into a grid
+    pub async fn seed_search(
+        &self,
+        params: GenerationParams,
+        count: i32,
+    ) -> Result<SeedGrid> {
+        let start = Instant::now();
+        let start_seed = params.seed.unwrap_or_else(|| {
+            use std::time::{SystemTime, UNIX_EPOCH};
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs() as i64
+        });
+        
+        if count < 1 || count > self.config.max_seed_search_cells {
             return Err(DiffusionError::InvalidParameters(
-                "Prompt cannot be empty".to_string()
+                format!("count must be between 1 and {}", self.config.max_seed_search_cells)
             ));
         }
         
-        if params.width < 64 || params.width > self.config.max_width {
-            return Err(DiffusionError::InvalidParameters(
-                format!("Width must be between 64 and {}", self.config.max_width)
-            ));
+        let cell_size = self.config.seed_search_cell_size;
+        let mut cells = Vec::with_capacity(count as usize);
+        let mut images = Vec::with_capacity(count as usize);
+        let mut cell_dimensions = None;
+        
+        // Reuse the batch path, one batch at a time; images within a batch
+        // already get consecutive seeds
+        let mut offset = 0;
+        while offset < count {
+            let batch = (count - offset).min(self.config.max_batch_size.max(1));
+            let result = self.generate(GenerationParams {
+                width: Some(cell_size),
+                height: Some(cell_size),
+                aspect_ratio: None,
+                megapixels: None,
+                seed: Some(start_seed.wrapping_add(offset as i64)),
+                num_images: batch,
+                ..params.clone()
+            }).await;
+            
+            match result {
+                Ok(result) => {
+                    cell_dimensions.get_or_insert((result.width as u32, result.height as u32));
+                    for image in result.results {
+                        let max_dimension = self.config.max_decode_dimension;
+                        let decoded = image.outcome.and_then(|bytes| decode_bounded(&bytes, max_dimension));
+                        cells.push((image.seed, decoded.as_ref().err().cloned()));
+                        images.push(decoded.ok());
+                    }
+                }
+                // Invalid parameters fail the same way for every batch
+                Err(e @ (DiffusionError::InvalidParameters(_) | DiffusionError::Validation(_))) => return Err(e),
+                Err(e) => {
+                    for index in 0..batch {
+                        cells.push((start_seed.wrapping_add((offset + index) as i64), Some(e.to_string())));
+                        images.push(None);
+                    }
+                }
+            }
+            
+            offset += batch;
         }
         
-        if params.height < 64 || params.height > self.config.max_height {
-            return Err(DiffusionError::InvalidParameters(
-                format!("Height must be between 64 and {}", self.config.max_height)
-            ));
+        let (cell_width, cell_height) = cell_dimensions.ok_or_else(|| {
+            DiffusionError::Inference("Every cell of the seed search failed".to_string())
+        })?;
+        
+        let columns = (count as f64).sqrt().ceil() as u32;
+        let rows = (count as u32).div_ceil(columns);
+        let grid = postprocess::compose_grid(&images, columns, cell_width, cell_height);
+        
+        let cells = cells
+            .into_iter()
+            .enumerate()
+            .map(|(index, (seed, error))| SeedCell {
+                index,
+                seed,
+                row: index as u32 / columns,
+                column: index as u32 % columns,
+                error,
+            })
+            .collect();
+        
+        Ok(SeedGrid {
+            image: self.encode(&grid, self.resolve_format(params.format.as_deref())?, params.dpi)?,
+            columns,
+            rows,
+            cell_width,
+            cell_height,
+            cells,
+            generation_time: start.elapsed().as_secs_f64(),
+        })
+    }
+    
+    /// Steps to actually run: unchanged below the queue threshold, then
+    /// reduced linearly to `min_steps` at twice the threshold
+    fn adaptive_steps(&self, requested: i32, queue_depth: usize) -> i32 {
+        let adaptive = &self.config.adaptive_steps;
+        if !adaptive.enabled || queue_depth < adaptive.queue_threshold || requested <= adaptive.min_steps {
+            return requested;
         }
         
-        if params.num_inference_steps < 1 || params.num_inference_steps > self.config.max_steps {
-            return Err(DiffusionError::InvalidParameters(
-                format!("Steps must be between 1 and {}", self.config.max_steps)
-            ));
+        let threshold = adaptive.queue_threshold.max(1) as f64;
+        let overload = ((queue_depth as f64 - threshold + 1.0) / threshold).min(1.0);
+        let reduction = ((requested - adaptive.min_steps) as f64 * overload).round() as i32;
+        
+        requested - reduction
+    }
+    
+    /// Resolves the output size from a vary-region image, a megapixel
+    /// target, an aspect ratio preset, explicit dimensions, or the
+    /// configured defaults
+    fn resolve_dimensions(&self, params: &GenerationParams) -> Result<(i32, i32)> {
+        if let Some(region) = &params.vary_region {
+            if params.width.is_some() || params.height.is_some() || params.aspect_ratio.is_some() || params.megapixels.is_some() {
+                return Err(DiffusionError::InvalidParameters(
+                    "vary_region takes the size of its image; width, height, aspect_ratio and megapixels must be unset".to_string()
+                ));
+            }
+            let (width, height) = vary_region::dimensions(&region.image).map_err(DiffusionError::InvalidParameters)?;
+            return Ok((width as i32, height as i32));
         }
         
-        if params.guidance_scale < 1.0 || params.guidance_scale > 20.0 {
-            return Err(DiffusionError::InvalidParameters(
-                "Guidance scale must be between 1.0 and 20.0".to_string()
-            ));
+        if let Some(megapixels) = params.megapixels {
+            if params.width.is_some() || params.height.is_some() {
+                return Err(DiffusionError::InvalidParameters(
+                    "megapixels cannot be combined with explicit width/height".to_string()
+                ));
+            }
+            if !(megapixels.is_finite() && megapixels > 0.0) {
+                return Err(DiffusionError::InvalidParameters(
+                    format!("megapixels must be positive, got {}", megapixels)
+                ));
+            }
+            let ratio = match &params.aspect_ratio {
+                Some(preset) => dimensions::aspect_ratio(preset)?,
+                None => self.config.default_width as f64 / self.config.default_height as f64,
+            };
+            return Ok(dimensions::megapixel_dimensions(megapixels, ratio));
         }
         
-        Ok(())
+        match &params.aspect_ratio {
+            Some(preset) => {
+                if params.width.is_some() || params.height.is_some() {
+                    return Err(DiffusionError::InvalidParameters(
+                        "aspect_ratio cannot be combined with explicit width/height".to_string()
+                    ));
+                }
+                dimensions::aspect_ratio_dimensions(
+                    preset,
+                    self.config.default_width,
+                    self.config.default_height,
+                )
+            }
+            None => Ok((
+                params.width.unwrap_or(self.config.default_width),
+                params.height.unwrap_or(self.config.default_height),
+            )),
+        }
     }
     
-    fn generate_placeholder_image(
+    /// Holds room for each image of the batch at its output size: the
+    /// decoded image plus its encoded and base64 copies (roughly twice the
+    /// raw RGB size), one raw size per extra `output_formats` encoding, and
+    /// the thumbnail and step animation when requested
+    async fn reserve_bytes(
         &self,
-        width: u32,
-        height: u32,
-        prompt: &str,
-        seed: i64,
-    ) -> Result<Vec<u8>> {
-        use image::ImageOutputFormat;
-        use std::io::Cursor;
+        params: &GenerationParams,
+        width: i32,
+        height: i32,
+        extra_encodings: usize,
+    ) -> Result<Option<Reservation>> {
+        let budget = match &self.byte_budget {
+            Some(budget) => budget,
+            None => return Ok(None),
+        };
         
-        // Create a colorful gradient based on prompt and seed
-        let hash = self.simple_hash(prompt) ^ (seed as u64);
-        let mut img = ImageBuffer::new(width, height);
+        // Saturating, so an absurd request asks for more than any budget
+        // instead of wrapping to a small reservation
+        let rgb = |width: usize, height: usize| width.saturating_mul(height).saturating_mul(3);
+        let (width, height) = (width.max(0) as usize, height.max(0) as usize);
+        let mut per_image = rgb(width, height).saturating_mul(2 + extra_encodings);
+        if let Some(thumbnail) = params.thumbnail {
+            per_image = per_image.saturating_add(rgb(thumbnail as usize, thumbnail as usize).saturating_mul(2));
+        }
+        if params.return_steps_animation {
+            let frame = ANIMATION_MAX_DIMENSION as usize;
+            per_image = per_image.saturating_add(rgb(width.min(frame), height.min(frame)) * MAX_ANIMATION_FRAMES);
+        }
+        let bytes = per_image.saturating_mul(params.num_images.max(1) as usize);
+        budget.reserve(bytes).await.map(Some)
+    }
+    
+    /// Snaps to the nearest resolution bucket when enabled
+    fn snap_dimensions(&self, params: &GenerationParams, (width, height): (i32, i32)) -> (i32, i32) {
+        // A vary-region output is composited onto its image, so keeps its size
+        if !self.config.snap_to_bucket || width <= 0 || height <= 0 || params.vary_region.is_some() {
+            return (width, height);
+        }
         
-        for (x, y, pixel) in img.enumerate_pixels_mut() {
-            let r = ((x as f32 / width as f32) * 255.0) as u8;
-            let g = ((y as f32 / height as f32) * 255.0) as u8;
-            let b = ((hash % 256) as f32) as u8;
-            *pixel = Rgb([r, g, b]);
+        dimensions::nearest_bucket(width, height, &self.config.resolution_buckets)
+            .unwrap_or((width, height))
+    }
+    
+    fn validate_params(&self, params: &GenerationParams, width: i32, height: i32) -> Result<()> {
+        // Every problem is reported at once, not just the first
+        let mut errors = Vec::new();
+        
+        if params.prompt_embeds.is_some() {
+            if !params.prompt.is_empty() || !params.prompt_schedule.is_empty() {
+                errors.push("prompt_embeds replaces the prompt; prompt and prompt_schedule must be empty".to_string());
+            }
+            let moderation = &self.config.moderation;
+            if moderation.enabled && !moderation.allow_prompt_embeds {
+                errors.push("prompt_embeds can't be moderated, so it is unavailable while moderation is enabled".to_string());
+            }
+        } else if params.prompt.is_empty() && !self.config.allow_empty_prompt_fallback {
+            errors.push("Prompt cannot be empty".to_string());
         }
         
-        // Add some text to show it's a placeholder
-        // (In real implementation, this would be the diffusion output)
+        if width < MIN_DIMENSION || width > self.config.max_width {
+            errors.push(format!("Width must be between {} and {}", MIN_DIMENSION, self.config.max_width));
+        }
         
-        // Encode to PNG
-        let mut buffer = Vec::new();
-        DynamicImage::ImageRgb8(img)
-            .write_to(&mut Cursor::new(&mut buffer), ImageOutputFormat::Png)
-            .map_err(|e| DiffusionError::Internal(format!("PNG encoding failed: {}", e)))?;
+        if height < MIN_DIMENSION || height > self.config.max_height {
+            errors.push(format!("Height must be between {} and {}", MIN_DIMENSION, self.config.max_height));
+        }
+        
+        let crop = self.config.edge_crop_pixels as i32;
+        if width - crop * 2 < 64 || height - crop * 2 < 64 {
+            errors.push(format!("Edge crop of {}px would reduce the output below 64px", crop));
+        }
+        
+        let (min_steps, max_steps, _) = self.step_range(params.model_id.as_deref());
+        if params.num_inference_steps < min_steps || params.num_inference_steps > max_steps {
+            errors.push(format!("Steps must be between {} and {} for this model", min_steps, max_steps));
+        }
+        
+        // An unknown model or scheduler is reported when it is resolved
+        let scheduler = self.models
+            .resolve(params.model_id.as_deref())
+            .and_then(|model_id| self.resolve_scheduler(params.scheduler.as_deref(), &model_id));
+        if let Ok(scheduler) = scheduler {
+            let (min, max) = self.guidance_range(scheduler);
+            if !(min..=max).contains(&params.guidance_scale) {
+                errors.push(format!(
+                    "Guidance scale must be between {} and {} for the {} scheduler",
+                    min, max, scheduler
+                ));
+            }
+        }
+        
+        if params.num_images < 1 || params.num_images > self.config.max_batch_size {
+            errors.push(format!("num_images must be between 1 and {}", self.config.max_batch_size));
+        }
+        
+        if params.strength.is_some() && params.strength_preset.is_some() {
+            errors.push("strength and strength_preset are mutually exclusive".to_string());
+        }
+        
+        if params.strength_preset.is_some() && params.init_image.is_none() {
+            errors.push("strength_preset requires an init image".to_string());
+        }
+        
+        // Preserved pixels must come out exactly as they went in
+        if params.vary_region.is_some() {
+            if params.init_image.is_some() {
+                errors.push("vary_region and init_image are mutually exclusive".to_string());
+            }
+            if params.upscale.unwrap_or(1) > 1 || params.border_width.unwrap_or(0) > 0 || params.transparent_background {
+                errors.push("vary_region cannot be combined with upscale, border_width or transparent_background".to_string());
+            }
+            if self.config.edge_crop_pixels > 0 {
+                errors.push("vary_region is unavailable while edge_crop_pixels is set".to_string());
+            }
+            if params.max_response_bytes.is_some() {
+                errors.push("vary_region cannot be combined with max_response_bytes".to_string());
+            }
+            let lossy = self.resolve_format(params.format.as_deref())
+                .into_iter()
+                .chain(format::parse_list(&params.output_formats).unwrap_or_default())
+                .find(|format| !format.is_lossless());
+            if let Some(format) = lossy {
+                errors.push(format!("vary_region needs a lossless output format, not {}", format));
+            }
+        }
+        
+        if let Some(strength) = params.strength {
+            if params.init_image.is_none() {
+                errors.push("strength requires an init image".to_string());
+            }
+            if !(0.0..=1.0).contains(&strength) {
+                errors.push("Strength must be between 0.0 and 1.0".to_string());
+            }
+        }
+        
+        if let Some(strength) = params.subseed_strength {
+            if params.subseed.is_none() {
+                errors.push("subseed_strength requires a subseed".to_string());
+            }
+            if !(0.0..=1.0).contains(&strength) {
+                errors.push("subseed_strength must be between 0.0 and 1.0".to_string());
+            }
+        }
+        
+        if let Some(temperature) = params.temperature {
+            if !(0.0..=MAX_TEMPERATURE).contains(&temperature) {
+                errors.push(format!("temperature must be between 0.0 and {}", MAX_TEMPERATURE));
+            }
+        }
+        
+        match format::parse_list(&params.output_formats) {
+            Ok(output_formats) if params.transparent_background => {
+                if let Some(format) = output_formats.iter().find(|format| !format.supports_alpha()) {
+                    errors.push(format!("transparent_background needs alpha-capable output_formats, not {}", format));
+                }
+            }
+            Ok(_) => {}
+            Err(e) => errors.push(e),
+        }
+        if params.transparent_background {
+            if let Ok(format) = self.resolve_format(params.format.as_deref()) {
+                if !format.supports_alpha() {
+                    errors.push(format!("transparent_background needs an alpha-capable format, not {}", format));
+                }
+            }
+        }
+        
+        // Decoding, upscaling and framing allocate at the final size, so
+        // that is capped separately from the generation size
+        if let Some(factor) = params.upscale {
+            if !(1..=MAX_UPSCALE).contains(&factor) {
+                errors.push(format!("upscale must be between 1 and {}", MAX_UPSCALE));
+            }
+        }
+        let max_decode = self.config.max_decode_dimension;
+        let border = params.border_width.unwrap_or(0);
+        if border > max_decode {
+            errors.push(format!("border_width must be at most {}", max_decode));
+        }
+        // Checked, so absurd values are reported instead of wrapping
+        let decoded = (width.max(height).max(0) as u32)
+            .checked_mul(params.upscale.unwrap_or(1))
+            .and_then(|size| size.checked_add(border.checked_mul(2)?));
+        match decoded {
+            Some(decoded) if decoded <= max_decode => {}
+            Some(decoded) => errors.push(format!(
+                "Output of {}px would exceed the {}px decode limit",
+                decoded, max_decode
+            )),
+            None => errors.push(format!("Output would exceed the {}px decode limit", max_decode)),
+        }
+        
+        if let Some(budget) = self.config.max_compute_budget {
+            let cost = Self::compute_cost(params, width, height);
+            if cost > budget {
+                errors.push(format!(
+                    "Estimated compute of {:.1} (images x steps x megapixels x upscale) exceeds the budget of {:.1}",
+                    cost, budget
+                ));
+            }
+        }
+        
+        if let Some(color) = &params.border_color {
+            if postprocess::parse_hex_color(color).is_none() {
+                errors.push(format!("border_color must be #rrggbb, got '{}'", color));
+            }
+        }
         
-        Ok(buffer)
+        if let Err(e) = palette::parse(&params.color_palette) {
+            errors.push(e);
+        }
+        
+        if let Some(max) = params.max_response_bytes {
+            if max < MIN_RESPONSE_BYTES {
+                errors.push(format!("max_response_bytes must be at least {}", MIN_RESPONSE_BYTES));
+            }
+            // The extra encodings would go over the budget it sets
+            if !params.output_formats.is_empty() {
+                errors.push("max_response_bytes can't be combined with output_formats".to_string());
+            }
+        }
+        
+        if let Some(dpi) = params.dpi {
+            if !(1..=MAX_DPI).contains(&dpi) {
+                errors.push(format!("dpi must be between 1 and {}, got {}", MAX_DPI, dpi));
+            }
+        }
+        
+        if let Some(thumbnail) = params.thumbnail {
+            let crop = self.config.edge_crop_pixels as i32 * 2;
+            let output_size = (width - crop).max(height - crop);
+            if thumbnail == 0 || thumbnail >= output_size.max(0) as u32 {
+                errors.push(format!(
+                    "thumbnail must be between 1 and {} (smaller than the image)",
+                    output_size - 1
+                ));
+            }
+        }
+        
+        if params.debug && !self.config.allow_debug_requests {
+            errors.push("debug is disabled on this server".to_string());
+        }
+        
+        if params.return_latents {
+            if !self.config.allow_return_latents {
+                errors.push("return_latents is disabled on this server".to_string());
+            }
+            let size = latents::latent_bytes(width as u32, height as u32);
+            if size > self.config.max_latent_bytes {
+                errors.push(format!(
+                    "Latent of {} bytes exceeds the {} byte limit",
+                    size, self.config.max_latent_bytes
+                ));
+            }
+        }
+        
+        if let Some(weight) = params.face_restore_weight {
+            if !(0.0..=1.0).contains(&weight) {
+                errors.push("face_restore_weight must be between 0.0 and 1.0".to_string());
+            }
+        }
+        
+        if let Some(clip_skip) = params.clip_skip {
+            if clip_skip < 1 || clip_skip > MAX_CLIP_SKIP {
+                errors.push(format!("clip_skip must be between 1 and {}", MAX_CLIP_SKIP));
+            }
+        }
+        
+        if let Err(e) = prompt_schedule::validate(&self.resolve_prompt_schedule(params)) {
+            errors.push(e);
+        }
+        
+        if !params.negative_prompts.is_empty() && params.negative_prompt.is_some() {
+            errors.push("negative_prompt and negative_prompts are mutually exclusive".to_string());
+        }
+        if let Err(e) = negative_prompts::validate(&params.negative_prompts) {
+            errors.push(e);
+        }
+        if let Err(e) = references::validate(&params.reference_images) {
+            errors.push(e);
+        }
+        
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(DiffusionError::Validation(errors))
+        }
     }
     
-    fn simple_hash(&self, s: &str) -> u64 {
-        s.bytes().fold(0u64, |acc, b| acc.wrapping_mul(31).wrapping_add(b as u64))
+    /// Explicit strength, or the value of the named preset
+    fn resolve_strength(&self, params: &GenerationParams) -> Result<Option<f64>> {
+        match &params.strength_preset {
+            Some(preset) => preset
+                .parse::<StrengthPreset>()
+                .map(|preset| Some(preset.strength()))
+                .map_err(DiffusionError::InvalidParameters),
+            None => Ok(params.strength),
+        }
     }
-}
-
-impl Clone for InferencePipeline {
-    fn clone(&self) -> Self {
-        Self {
-            config: self.config.clone(),
-            device: self.device,
+    
+    /// Every image of the batch encodes both prompts, so the budget applies
+    /// to their tokens times the batch size
+    fn check_token_budget(&self, prompt: &str, negative_prompts: &[(&str, f64)], num_images: i32) -> Result<()> {
+        let budget = match self.config.max_batch_tokens {
+            Some(budget) => budget,
+            None => return Ok(()),
+        };
+        
+        let per_image = tokens::estimate_tokens(prompt)
+            + negative_prompts.iter().map(|(negative, _)| tokens::estimate_tokens(negative)).sum::<usize>();
+        let total = per_image * num_images.max(1) as usize;
+        
+        if total > budget {
+            return Err(DiffusionError::InvalidParameters(format!(
+                "Batch needs {} prompt tokens ({} per image x {} images), over the budget of {}",
+                total, per_image, num_images, budget
+            )));
+        }
+        
+        Ok(())
+    }
+    
+    /// The prompt and negative prompt as encoded: the fallback prompt for an
+    /// empty one, wrapped in the model's prefix / suffix. Built before
+    /// validation so moderation and the token budget see the final text.
+    /// Also says whether the fallback was used.
+    fn final_prompts(&self, params: &GenerationParams, model_id: &str, has_embeds: bool) -> (String, Option<String>, bool) {
+        let prompt_fallback = params.prompt.is_empty() && !has_embeds;
+        let prompt = if prompt_fallback {
+            self.config.fallback_prompt.clone()
+        } else {
+            params.prompt.clone()
+        };
+        // Inline `[from:to:at]` edits become the schedule; the prompt itself
+        // is their starting text
+        let prompt = if params.prompt_schedule.is_empty() {
+            prompt_schedule::strip_inline(&prompt)
+        } else {
+            prompt
+        };
+        
+        // Trigger words can't be added to precomputed embeddings
+        let (prompt, negative_prompt) = self.apply_auto_prompt(
+            model_id,
+            prompt,
+            params.negative_prompt.clone(),
+            params.disable_auto_prompt || has_embeds,
+        );
+        (prompt, negative_prompt, prompt_fallback)
+    }
+    
+    /// Wraps the prompts in the model's configured prefix / suffix
+    fn apply_auto_prompt(
+        &self,
+        model_id: &str,
+        prompt: String,
+        negative_prompt: Option<String>,
+        disabled: bool,
+    ) -> (String, Option<String>) {
+        let model = match self.models.definition(model_id) {
+            Some(model) if !disabled => model,
+            _ => return (prompt, negative_prompt),
+        };
+        
+        let join = |prefix: &Option<String>, text: &str, suffix: &Option<String>| {
+            [prefix.as_deref(), Some(text), suffix.as_deref()]
+                .into_iter()
+                .flatten()
+                .filter(|part| !part.is_empty())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        
+        let prompt = join(&model.prompt_prefix, &prompt, &model.prompt_suffix);
+        let has_negative_affixes = model.negative_prompt_prefix.is_some()
+            || model.negative_prompt_suffix.is_some();
+        let negative_prompt = match negative_prompt {
+            Some(negative) => Some(negative),
+            None if has_negative_affixes => Some(String::new()),
+            None => None,
         }
+        .map(|negative| {
+            join(&model.negative_prompt_prefix, &negative, &model.negative_prompt_suffix)
+        });
+        
+        (prompt, negative_prompt)
+    }
+    
+    /// Explicit schedule, or the one expanded from inline edits in the prompt
+    fn resolve_prompt_schedule(&self, params: &GenerationParams) -> PromptSchedule {
+        if !params.prompt_schedule.is_empty() {
+            return params.prompt_schedule.clone();
+        }
+        prompt_schedule::parse_inline(&params.prompt).unwrap_or_default()
+    }
+    
+    /// Moderates each scheduled prompt and applies the model's prefix /
+    /// suffix, like the main prompt
+    fn prepare_prompt_schedule(&self, params: &GenerationParams, model_id: &str) -> Result<PromptSchedule> {
+        self.resolve_prompt_schedule(params)
+            .into_iter()
+            .map(|(fraction, prompt)| {
+                let (prompt, _) = self.moderator.moderate(&prompt)?;
+                let (prompt, _) = self.apply_auto_prompt(model_id, prompt, None, params.disable_auto_prompt);
+                Ok((fraction, prompt))
+            })
+            .collect()
+    }
+    
+    /// Weighted negative prompts wrapped in the model's negative prefix /
+    /// suffix
+    fn prepare_negative_prompts(&self, params: &GenerationParams, model_id: &str) -> WeightedPrompts {
+        params.negative_prompts
+            .iter()
+            .map(|(negative, weight)| {
+                let (_, wrapped) = self.apply_auto_prompt(
+                    model_id,
+                    String::new(),
+                    Some(negative.clone()),
+                    params.disable_auto_prompt,
+                );
+                (wrapped.unwrap_or_else(|| negative.clone()), *weight)
+            })
+            .collect()
+    }
+    
+    /// Text-encoding stage. `clip_skip` selects how many of the final CLIP
+    /// layers are skipped when taking the hidden states.
+    fn encode_prompt(&self, prompt: &str, clip_skip: i32) -> u64 {
+        #[cfg(test)]
+        self.prompt_encodes.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        // TODO: Run the CLIP text encoder and take hidden states from layer -clip_skip
+        self.simple_hash(prompt).wrapping_add((clip_skip - DEFAULT_CLIP_SKIP) as u64)
+    }
+    
+    fn encode_embeds(&self, embeds: &[f32]) -> u64 {
+        // TODO: Pass the embeddings to the UNet as its encoder hidden states
+        embeds.iter().fold(0u64, |acc, value| acc.wrapping_mul(31).wrapping_add(value.to_bits() as u64))
+    }
+    
+    fn encode_reference(&self, image: &DynamicImage) -> u64 {
+        // TODO: Run the CLIP image encoder and project through the IP-Adapter
+        image.as_bytes().iter().fold(0u64, |acc, &b| acc.wrapping_mul(31).wrapping_add(b as u64))
+    }
+    
+    /// Request format overrides the configured default
+    fn resolve_format(&self, requested: Option<&str>) -> Result<OutputFormat> {
+        requested
+            .unwrap_or(&self.config.output_format)
+            .parse()
+            .map_err(DiffusionError::InvalidParameters)
+    }
+    
+    /// `model.precision`, checked against the device at startup. Unknown
+    /// names and precisions the device can't run are configuration errors
+    /// (only warned about in mock mode, which always runs on the CPU).
+    fn default_precision(configured: &str, device: Device, mock: bool) -> Result<Precision> {
+        let precision: Precision = configured
+            .parse()
+            .map_err(|e| DiffusionError::Config(format!("model.precision: {}", e)))?;
+        
+        if !precision.is_supported_on(device) {
+            let message = format!("model.precision {} is not supported on {:?}", precision, device);
+            if !mock {
+                return Err(DiffusionError::Config(message));
+            }
+            warn!("{}; requests must pick another precision", message);
+        } else if let (Some(warning), false) = (precision.device_warning(device), mock) {
+            warn!("model.precision {}", warning);
+        }
+        
+        Ok(precision)
+    }
+    
+    /// Request precision overrides the model default, provided the device supports it
+    fn resolve_precision(&self, requested: Option<&str>) -> Result<Precision> {
+        let precision = match requested {
+            Some(p) => p.parse::<Precision>().map_err(DiffusionError::InvalidParameters)?,
+            None => self.default_precision,
+        };
+        
+        if !precision.is_supported_on(self.device) {
+            return Err(DiffusionError::InvalidParameters(
+                format!("Precision {} is not supported on {:?}", precision, self.device)
+            ));
+        }
+        
+        Ok(precision)
+    }
+    
+    /// Scheduler resolution: request > model default > global default
+    pub fn resolve_scheduler(&self, requested: Option<&str>, model_id: &str) -> Result<SchedulerKind> {
+        let model_default = self.models
+            .definition(model_id)
+            .and_then(|m| m.default_scheduler.as_deref());
+        
+        let name = requested
+            .or(model_default)
+            .unwrap_or(&self.config.default_scheduler);
+        
+        name.parse::<SchedulerKind>().map_err(DiffusionError::InvalidParameters)
+    }
+    
+    /// Relative compute of a request, `images x steps x megapixels x
+    /// upscale`, as checked against `max_compute_budget`
+    fn compute_cost(params: &GenerationParams, width: i32, height: i32) -> f64 {
+        let megapixels = width as f64 * height as f64 / dimensions::PIXELS_PER_MEGAPIXEL;
+        params.num_images as f64
+            * params.num_inference_steps as f64
+            * megapixels
+            * params.upscale.unwrap_or(1) as f64
+    }
+    
+    /// `(min, max, default)` steps of a model: its own overrides, else the
+    /// `[inference]` ones. An unknown model gets the global range; it is
+    /// rejected when resolved.
+    pub fn step_range(&self, model_id: Option<&str>) -> (i32, i32, i32) {
+        let definition = self.models
+            .resolve(model_id)
+            .ok()
+            .and_then(|id| self.models.definition(&id));
+        Self::steps_for(&self.config, definition)
+    }
+    
+    fn steps_for(config: &InferenceConfig, definition: Option<&ModelDefinition>) -> (i32, i32, i32) {
+        (
+            definition.and_then(|m| m.min_steps).unwrap_or(1),
+            definition.and_then(|m| m.max_steps).unwrap_or(config.max_steps),
+            definition.and_then(|m| m.default_steps).unwrap_or(config.default_steps),
+        )
+    }
+    
+    /// Hidden size `prompt_embeds` must have for a model
+    pub fn encoder_dim(&self, model_id: Option<&str>) -> u64 {
+        self.models
+            .resolve(model_id)
+            .ok()
+            .and_then(|id| self.models.definition(&id))
+            .and_then(|m| m.encoder_dim)
+            .unwrap_or(prompt_embeds::DEFAULT_EMBEDS_DIM)
+    }
+    
+    /// Fills in the selected model's default when no step count was given
+    fn with_default_steps(&self, mut params: GenerationParams) -> GenerationParams {
+        if params.num_inference_steps == 0 {
+            params.num_inference_steps = self.step_range(params.model_id.as_deref()).2;
+        }
+        params
+    }
+    
+    /// Configured guidance range of a scheduler, or its built-in one
+    pub fn guidance_range(&self, kind: SchedulerKind) -> (f64, f64) {
+        guidance_range(&self.config, kind)
+    }
+    
+    /// Scheduler construction hook: combines the sampler with the model's
+    /// noise schedule
+    fn scheduler_config(&self, kind: SchedulerKind, model_id: &str, steps: i32) -> Result<SchedulerConfig> {
+        let noise_schedule = self.models.noise_schedule(model_id).ok_or_else(|| {
+            DiffusionError::Internal(format!("No noise schedule for model {}", model_id))
+        })?;
+        
+        Ok(SchedulerConfig {
+            kind,
+            noise_schedule,
+            num_inference_steps: steps,
+        })
+    }
+    
+    /// Per-image post-generation check. A failure marks only this image
+    /// of the batch as failed.
+    fn check_image(&self, index: usize, _image: &DynamicImage) -> std::result::Result<(), String> {
+        #[cfg(test)]
+        if self.failing_images.contains(&index) {
+            return Err(format!("Image {} flagged", index));
+        }
+        if self.config.safety_checker {
+            // TODO: Run the safety checker and reject flagged images
+            trace!(index, "Safety check");
+        }
+        Ok(())
+    }
+    
+    /// Returns the problem when `latent` or `image` is degenerate and
+    /// another attempt is allowed, or fails image `index` once retries are
+    /// exhausted
+    fn check_degenerate(
+        &self,
+        index: usize,
+        latent: &[f32],
+        image: &DynamicImage,
+        attempt: u32,
+    ) -> Result<Option<degenerate::Degeneracy>> {
+        let config = &self.config.degenerate_output;
+        if !config.enabled {
+            return Ok(None);
+        }
+        #[cfg(test)]
+        let black;
+        #[cfg(test)]
+        let image = if self.degenerate_images.contains(&index) {
+            black = DynamicImage::new_rgb8(image.width(), image.height());
+            &black
+        } else {
+            image
+        };
+        
+        match degenerate::detect_latent(latent).or_else(|| degenerate::detect(image)) {
+            None => Ok(None),
+            Some(problem) if config.action == DegenerateAction::Retry && attempt < config.max_retries => {
+                Ok(Some(problem))
+            }
+            Some(problem) => {
+                warn!("Image {} still degenerate ({}) after {} retries", index, problem, attempt);
+                Err(DiffusionError::Inference("degenerate output".to_string()))
+            }
+        }
+    }
+    
+    /// Decodes the init image and resizes it to the generation size. Images
+    /// larger than that are rejected unless `auto_resize_init` is set, in
+    /// which case they're downscaled and their original size is returned.
+    ///
+    /// Decoding is CPU heavy, so it runs on the blocking pool, at most
+    /// `max_concurrent_decodes` at a time, to keep the async workers free.
+    async fn decode_init_image(
+        &self,
+        bytes: &[u8],
+        width: u32,
+        height: u32,
+    ) -> Result<(DynamicImage, Option<(u32, u32)>)> {
+        let _permit = self.decode_permits
+            .acquire()
+            .await
+            .map_err(|e| DiffusionError::Internal(format!("Decode semaphore closed: {}", e)))?;
+        
+        let bytes = bytes.to_vec();
+        let auto_resize = self.config.auto_resize_init;
+        let max_dimension = self.config.max_decode_dimension;
+        #[cfg(test)]
+        let gauge = Arc::clone(&self.decode_gauge);
+        tokio::task::spawn_blocking(move || {
+            #[cfg(test)]
+            gauge.enter();
+            let decoded = decode_bounded(&bytes, max_dimension);
+            #[cfg(test)]
+            gauge.exit();
+            let image = decoded.map_err(|e| {
+                DiffusionError::InvalidParameters(format!("Invalid init image: {}", e))
+            })?;
+            
+            let original = (image.width(), image.height());
+            if original == (width, height) {
+                return Ok((image, None));
+            }
+            if original.0 > width || original.1 > height {
+                if !auto_resize {
+                    return Err(DiffusionError::InvalidParameters(format!(
+                        "Init image is {}x{}, larger than the requested {}x{}",
+                        original.0, original.1, width, height
+                    )));
+                }
+                // Scale to cover the target and crop the overhang, so the
+                // aspect ratio is kept
+                let resized = image.resize_to_fill(width, height, FilterType::Lanczos3);
+                return Ok((resized, Some(original)));
+            }
+            Ok((image.resize_exact(width, height, FilterType::Lanczos3), None))
+        })
+        .await
+        .map_err(|e| DiffusionError::Internal(format!("Init image decode task failed: {}", e)))?
+    }
+    
+    /// Decodes and checks each reference image on the blocking pool, sharing
+    /// the init image's decode permits
+    async fn decode_references(
+        &self,
+        references: &[(Vec<u8>, f64)],
+    ) -> Result<(Vec<(DynamicImage, f64)>, Vec<AppliedReference>)> {
+        let mut decoded = Vec::with_capacity(references.len());
+        let mut applied = Vec::with_capacity(references.len());
+        
+        for (index, (bytes, weight)) in references.iter().enumerate() {
+            let _permit = self.decode_permits
+                .acquire()
+                .await
+                .map_err(|e| DiffusionError::Internal(format!("Decode semaphore closed: {}", e)))?;
+            
+            let bytes = bytes.clone();
+            let (image, (width, height)) = tokio::task::spawn_blocking(move || references::decode(index, &bytes))
+                .await
+                .map_err(|e| DiffusionError::Internal(format!("Reference image decode task failed: {}", e)))?
+                .map_err(DiffusionError::InvalidParameters)?;
+            
+            decoded.push((image, *weight));
+            applied.push(AppliedReference { index, width, height, weight: *weight });
+        }
+        
+        Ok((decoded, applied))
+    }
+    
+    async fn decode_vary_region(&self, region: &VaryRegion) -> Result<(DynamicImage, GrayImage, AppliedVaryRegion)> {
+        let _permit = self.decode_permits
+            .acquire()
+            .await
+            .map_err(|e| DiffusionError::Internal(format!("Decode semaphore closed: {}", e)))?;
+        
+        let region = region.clone();
+        tokio::task::spawn_blocking(move || vary_region::decode(&region))
+            .await
+            .map_err(|e| DiffusionError::Internal(format!("Vary region decode task failed: {}", e)))?
+            .map_err(DiffusionError::InvalidParameters)
+    }
+    
+    /// img2img stage. `strength` is how far the output departs from the init image.
+    fn apply_init_image(&self, generated: DynamicImage, init: &DynamicImage, strength: f64) -> DynamicImage {
+        // TODO: Encode the init image to latents and noise them to `strength`.
+        // The placeholder blends the init image into the output instead.
+        let mut output = generated.to_rgb8();
+        let init = init.to_rgb8();
+        
+        for (out, src) in output.pixels_mut().zip(init.pixels()) {
+            for c in 0..3 {
+                let blended = src[c] as f64 * (1.0 - strength) + out[c] as f64 * strength;
+                out[c] = blended.round() as u8;
+            }
+        }
+        
+        DynamicImage::ImageRgb8(output)
+    }
+    
+    /// Frames of the step animation: the image after every
+    /// `ceil(steps / MAX_ANIMATION_FRAMES)` steps, ending with
+    /// `final_image`, downscaled to `ANIMATION_MAX_DIMENSION`
+    fn step_frames(&self, steps: i32, seed: i64, final_image: &DynamicImage) -> Vec<DynamicImage> {
+        let final_frame = final_image
+            .thumbnail(ANIMATION_MAX_DIMENSION, ANIMATION_MAX_DIMENSION)
+            .to_rgb8();
+        let (width, height) = final_frame.dimensions();
+        let steps = steps.max(1) as usize;
+        let stride = steps.div_ceil(MAX_ANIMATION_FRAMES);
+        
+        // TODO: Decode the latent every `stride` steps inside `denoise`
+        // (a linear latent-to-RGB projection is enough at this size). The
+        // placeholder fades from the seed's initial noise to the final image.
+        let noise = noise::initial_noise(seed, (width * height * 3) as usize);
+        (1..=steps.div_ceil(stride))
+            .map(|frame| {
+                let progress = (frame * stride).min(steps) as f32 / steps as f32;
+                let mut pixels = final_frame.clone();
+                for (value, noise) in pixels.iter_mut().zip(&noise) {
+                    let noise = (noise * 0.5 + 0.5).clamp(0.0, 1.0) * 255.0;
+                    *value = (noise * (1.0 - progress) + *value as f32 * progress).round() as u8;
+                }
+                DynamicImage::ImageRgb8(pixels)
+            })
+            .collect()
+    }
+    
+    /// Post-processing applied to each image before encoding
+    fn postprocess(&self, image: DynamicImage, face_restore_weight: Option<f64>, upscale: u32) -> DynamicImage {
+        let image = match face_restore_weight {
+            Some(weight) => postprocess::restore_faces(image, weight),
+            None => image,
+        };
+        let image = postprocess::crop_edges(image, self.config.edge_crop_pixels);
+        postprocess::upscale(image, upscale)
+    }
+    
+    /// Diffusion stage: produces the final latent for one seed of the
+    /// batch, before VAE decode. `conditioning_schedule` holds (fraction of
+    /// steps, conditioning) pairs to switch to as denoising progresses.
+    fn denoise(
+        &self,
+        _scheduler: &SchedulerConfig,
+        shape: &[usize],
+        _conditioning: u64,
+        _conditioning_schedule: &[(f64, u64)],
+        _unconditioning: &[(u64, f64)],
+        _references: &[(u64, f64)],
+        _palette: &[[u8; 3]],
+        seed: i64,
+        subseed: Option<(i64, f64)>,
+        _temperature: f64,
+    ) -> Vec<f32> {
+        // TODO: Unless `self.mock`, which keeps the placeholder, run actual
+        // Stable Diffusion inference here, constructing the scheduler from
+        // `_scheduler` (betas via `noise_schedule.betas`) and switching to the last `_conditioning_schedule` entry whose
+        // fraction step / num_inference_steps has reached. The starting
+        // latent is `initial_latent(seed, subseed)`. The unconditional
+        // branch is the weight-normalized sum of `_unconditioning` (an empty
+        // prompt when it is empty). `_references` are image embeddings fed
+        // to the IP-Adapter cross-attention, each scaled by its weight.
+        // `_palette` is a color hint (e.g. appended as palette tokens or a
+        // color-histogram adapter input).
+        // Ancestral samplers scale the noise they add back each step by
+        // `_temperature`.
+        // The placeholder is the initial latent.
+        self.initial_latent(shape.iter().product(), seed, subseed)
+    }
+    
+    /// VAE-decodes a sub-batch of `(seed, latent)` pairs, holding one VAE
+    /// permit per latent for the duration of the decode
+    async fn vae_decode(
+        &self,
+        latents: &[(i64, Vec<f32>)],
+        width: u32,
+        height: u32,
+        conditioning: u64,
+    ) -> Result<Vec<DynamicImage>> {
+        let _permit = self.vae_permits.acquire_many(latents.len() as u32).await.map_err(|e| {
+            DiffusionError::Internal(format!("VAE semaphore closed: {}", e))
+        })?;
+        #[cfg(test)]
+        self.vae_batches.lock().unwrap().push(latents.len());
+        
+        // TODO: Unless `self.mock`, run the VAE decoder over the stacked
+        // latents. The placeholder draws an image from the seed.
+        Ok(latents
+            .iter()
+            .map(|(seed, _latent)| self.generate_placeholder_image(width, height, conditioning, *seed))
+            .collect())
+    }
+    
+    fn encode(&self, image: &DynamicImage, format: OutputFormat, dpi: Option<u32>) -> Result<Vec<u8>> {
+        if format == OutputFormat::Jpeg {
+            return postprocess::encode_jpeg(image, dpi);
+        }
+        
+        let mut png = postprocess::encode_png(image)?;
+        if self.config.strip_metadata {
+            png = postprocess::strip_png_metadata(&png)?;
+        }
+        // pHYs is layout information, not identifying, so it survives stripping
+        if let Some(dpi) = dpi {
+            png = postprocess::set_png_dpi(&png, dpi)?;
+        }
+        Ok(png)
+    }
+    
+    /// Starting noise for `seed`, blended toward the noise of a
+    /// `(subseed, strength)` variation seed
+    fn initial_latent(&self, len: usize, seed: i64, subseed: Option<(i64, f64)>) -> Vec<f32> {
+        let base = noise::initial_noise(seed, len);
+        match subseed {
+            Some((subseed, strength)) if strength > 0.0 => {
+                noise::blend(&base, &noise::initial_noise(subseed, len), strength)
+            }
+            _ => base,
+        }
+    }
+    
+    fn generate_placeholder_image(
+        &self,
+        width: u32,
+        height: u32,
+        conditioning: u64,
+        seed: i64,
+    ) -> DynamicImage {
+        // Create a colorful gradient based on prompt and seed
+        let hash = conditioning ^ (seed as u64);
+        let mut img = ImageBuffer::new(width, height);
+        
+        for (x, y, pixel) in img.enumerate_pixels_mut() {
+            let r = ((x as f32 / width as f32) * 255.0) as u8;
+            let g = ((y as f32 / height as f32) * 255.0) as u8;
+            let b = ((hash % 256) as f32) as u8;
+            *pixel = Rgb([r, g, b]);
+        }
+        
+        // Add some text to show it's a placeholder
+        // (In real implementation, this would be the diffusion output)
+        
+        DynamicImage::ImageRgb8(img)
+    }
+    
+    fn simple_hash(&self, s: &str) -> u64 {
+        s.bytes().fold(0u64, |acc, b| acc.wrapping_mul(31).wrapping_add(b as u64))
+    }
+}
+
+/// Guidance range of `kind`: its `scheduler_guidance` entry, or the
+/// sampler's built-in range
+pub fn guidance_range(config: &InferenceConfig, kind: SchedulerKind) -> (f64, f64) {
+    config.scheduler_guidance
+        .get(kind.as_str())
+        .map(|range| (range.min, range.max))
+        .unwrap_or_else(|| kind.guidance_range())
+}
+
+/// Decodes an encoded image, reading its size from the header first so one
+/// over `max_dimension` is rejected before its pixels are allocated
+fn decode_bounded(bytes: &[u8], max_dimension: u32) -> std::result::Result<DynamicImage, String> {
+    let reader = || {
+        image::io::Reader::new(std::io::Cursor::new(bytes))
+            .with_guessed_format()
+            .map_err(|e| e.to_string())
+    };
+    let (width, height) = reader()?.into_dimensions().map_err(|e| e.to_string())?;
+    if width > max_dimension || height > max_dimension {
+        return Err(format!(
+            "{}x{} exceeds the {}px decode limit",
+            width, height, max_dimension
+        ));
+    }
+    reader()?.decode().map_err(|e| e.to_string())
+}
+
+impl Clone for InferencePipeline {
+    fn clone(&self) -> Self {
+        Self {
+            config: self.config.clone(),
+            device: self.device,
+            models: Arc::clone(&self.models),
+            moderator: Arc::clone(&self.moderator),
+            outcomes: Arc::clone(&self.outcomes),
+            step_timings: Arc::clone(&self.step_timings),
+            generations: Arc::clone(&self.generations),
+            seed_log: Arc::clone(&self.seed_log),
+            conditioning_cache: Arc::clone(&self.conditioning_cache),
+            decode_permits: Arc::clone(&self.decode_permits),
+            vae_permits: Arc::clone(&self.vae_permits),
+            byte_budget: self.byte_budget.clone(),
+            default_precision: self.default_precision,
+            mock: self.mock,
+            #[cfg(test)]
+            failing_images: self.failing_images.clone(),
+            #[cfg(test)]
+            degenerate_images: self.degenerate_images.clone(),
+            #[cfg(test)]
+            decode_gauge: Arc::clone(&self.decode_gauge),
+            #[cfg(test)]
+            warmup_log: Arc::clone(&self.warmup_log),
+            #[cfg(test)]
+            vae_batches: Arc::clone(&self.vae_batches),
+            #[cfg(test)]
+            prompt_encodes: Arc::clone(&self.prompt_encodes),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::config::Config;
+    
+    /// Config for a CPU pipeline that generates placeholder images
+    pub(crate) fn mock_config() -> Config {
+        let mut config = Config::default();
+        config.model.mock_inference = true;
+        config.model.device = "cpu".to_string();
+        config.model.precision = "fp32".to_string();
+        config
+    }
+    
+    /// A model entry with only the required fields set
+    pub(crate) fn model(id: &str) -> ModelDefinition {
+        serde_json::from_value(serde_json::json!({"id": id, "path": format!("./models/{}", id)})).unwrap()
+    }
+    
+    pub(crate) fn mock_pipeline(config: &Config) -> InferencePipeline {
+        InferencePipeline::new(config.inference.clone(), &config.model, &config.health, Device::Cpu).unwrap()
+    }
+    
+    /// A small, seeded, single-image request for `prompt`
+    pub(crate) fn params(prompt: &str) -> GenerationParams {
+        GenerationParams {
+            prompt: prompt.to_string(),
+            prompt_embeds: None,
+            prompt_schedule: Vec::new(),
+            negative_prompt: None,
+            negative_prompts: Vec::new(),
+            num_inference_steps: 4,
+            guidance_scale: 7.5,
+            width: Some(64),
+            height: Some(64),
+            aspect_ratio: None,
+            megapixels: None,
+            init_image: None,
+            strength: None,
+            reference_images: Vec::new(),
+            vary_region: None,
+            strength_preset: None,
+            seed: Some(42),
+            subseed: None,
+            subseed_strength: None,
+            temperature: None,
+            clip_skip: None,
+            model_id: None,
+            num_images: 1,
+            precision: None,
+            scheduler: None,
+            disable_auto_prompt: false,
+            no_cache: false,
+            return_latents: false,
+            return_schedule: false,
+            return_steps_animation: false,
+            debug: false,
+            thumbnail: None,
+            dpi: None,
+            format: None,
+            output_formats: Vec::new(),
+            transparent_background: false,
+            upscale: None,
+            max_response_bytes: None,
+            border_width: None,
+            border_color: None,
+            color_palette: Vec::new(),
+            face_restore: None,
+            face_restore_weight: None,
+            quality: None,
+            queue_depth: 0,
+            job_id: None,
+        }
+    }
+    
+    #[tokio::test]
+    async fn clip_skip_defaults_overrides_and_bounds() {
+        let pipeline = mock_pipeline(&mock_config());
+        
+        let result = pipeline.generate(params("a lighthouse")).await.unwrap();
+        assert_eq!(result.clip_skip, DEFAULT_CLIP_SKIP);
+        
+        let result = pipeline.generate(GenerationParams { clip_skip: Some(2), ..params("a lighthouse") }).await.unwrap();
+        assert_eq!(result.clip_skip, 2);
+        
+        for clip_skip in [0, MAX_CLIP_SKIP + 1] {
+            let params = GenerationParams { clip_skip: Some(clip_skip), ..params("a lighthouse") };
+            assert!(matches!(pipeline.generate(params).await, Err(DiffusionError::Validation(_))));
+        }
+    }
+    
+    #[tokio::test]
+    async fn failed_image_does_not_fail_the_batch() {
+        let mut pipeline = mock_pipeline(&mock_config());
+        pipeline.failing_images = vec![1];
+        
+        let result = pipeline.generate(GenerationParams { num_images: 3, ..params("a lighthouse") }).await.unwrap();
+        let succeeded: Vec<bool> = result.results.iter().map(|r| r.outcome.is_ok()).collect();
+        assert_eq!(succeeded, [true, false, true]);
+        assert_eq!(result.images().count(), 2);
+        assert_eq!(result.first_image().unwrap().index, 0);
+        
+        pipeline.failing_images = vec![0, 1, 2];
+        let params = GenerationParams { num_images: 3, ..params("a lighthouse") };
+        assert!(pipeline.generate(params).await.is_err());
+    }
+    
+    #[test]
+    fn configured_precision_is_checked_against_the_device() {
+        let precision = |name: &str, mock: bool| InferencePipeline::default_precision(name, Device::Cpu, mock);
+        
+        assert_eq!(precision("fp16", false).unwrap(), Precision::Fp16);
+        assert_eq!(precision("fp32", false).unwrap(), Precision::Fp32);
+        assert!(matches!(precision("bf16", false), Err(DiffusionError::Config(_))));
+        // Mock mode always runs on the CPU, so it only warns
+        assert_eq!(precision("bf16", true).unwrap(), Precision::Bf16);
+        assert!(matches!(precision("fp8", true), Err(DiffusionError::Config(_))));
+    }
+    
+    #[tokio::test]
+    async fn precision_overrides_the_model_default() {
+        let mut config = mock_config();
+        config.model.precision = "fp16".to_string();
+        let pipeline = mock_pipeline(&config);
+        
+        let result = pipeline.generate(params("a lighthouse")).await.unwrap();
+        assert_eq!(result.precision, Precision::Fp16);
+        
+        let params_fp32 = GenerationParams { precision: Some("fp32".to_string()), ..params("a lighthouse") };
+        let result = pipeline.generate(params_fp32).await.unwrap();
+        assert_eq!(result.precision, Precision::Fp32);
+        
+        // The CPU has no bf16 kernels
+        let params_bf16 = GenerationParams { precision: Some("bf16".to_string()), ..params("a lighthouse") };
+        assert!(matches!(
+            pipeline.generate(params_bf16).await,
+            Err(DiffusionError::InvalidParameters(_))
+        ));
+    }
+    
+    #[tokio::test]
+    async fn edge_crop_shrinks_the_output() {
+        let mut config = mock_config();
+        config.inference.edge_crop_pixels = 16;
+        let pipeline = mock_pipeline(&config);
+        
+        let params = GenerationParams { width: Some(256), height: Some(256), ..params("a lighthouse") };
+        let result = pipeline.generate(params).await.unwrap();
+        assert_eq!((result.width, result.height), (224, 224));
+        let image = image::load_from_memory(result.images().next().unwrap()).unwrap();
+        assert_eq!((image.width(), image.height()), (224, 224));
+    }
+    
+    #[tokio::test]
+    async fn scheduler_falls_back_from_request_to_model_to_global() {
+        let mut config = mock_config();
+        config.model.models = vec![ModelDefinition { default_scheduler: Some("euler".to_string()), ..model("anime") }];
+        let pipeline = mock_pipeline(&config);
+        let anime = || GenerationParams { model_id: Some("anime".to_string()), ..params("a lighthouse") };
+        
+        let result = pipeline.generate(anime()).await.unwrap();
+        assert_eq!(result.scheduler, SchedulerKind::Euler);
+        
+        let result = pipeline.generate(GenerationParams { scheduler: Some("ddim".to_string()), ..anime() }).await.unwrap();
+        assert_eq!(result.scheduler, SchedulerKind::Ddim);
+        
+        let result = pipeline.generate(params("a lighthouse")).await.unwrap();
+        assert_eq!(result.scheduler, SchedulerKind::Pndm);
+    }
+    
+    #[tokio::test]
+    async fn turbo_model_takes_two_steps_where_a_standard_one_defaults_higher() {
+        let mut config = mock_config();
+        config.model.models = vec![
+            ModelDefinition { min_steps: Some(1), max_steps: Some(4), default_steps: Some(2), ..model("turbo") },
+            model("standard"),
+        ];
+        let pipeline = mock_pipeline(&config);
+        let request = |model_id: &str, steps: i32| GenerationParams {
+            model_id: Some(model_id.to_string()),
+            num_inference_steps: steps,
+            ..params("a lighthouse")
+        };
+        
+        assert_eq!(pipeline.step_range(Some("turbo")), (1, 4, 2));
+        assert_eq!(pipeline.generate(request("turbo", 2)).await.unwrap().steps_taken, 2);
+        assert_eq!(pipeline.generate(request("turbo", 0)).await.unwrap().steps_taken, 2);
+        assert!(matches!(pipeline.generate(request("turbo", 8)).await, Err(DiffusionError::Validation(_))));
+        
+        // The standard model follows the global range
+        let (_, max_steps, default_steps) = pipeline.step_range(Some("standard"));
+        assert_eq!((max_steps, default_steps), (config.inference.max_steps, config.inference.default_steps));
+        assert_eq!(pipeline.generate(request("standard", 0)).await.unwrap().steps_taken, default_steps);
+        assert_eq!(pipeline.generate(request("standard", 8)).await.unwrap().steps_taken, 8);
+    }
+    
+    #[test]
+    fn one_megapixel_at_16_9_resolves_to_snapped_dimensions() {
+        let pipeline = mock_pipeline(&mock_config());
+        let request = GenerationParams {
+            width: None,
+            height: None,
+            megapixels: Some(1.0),
+            aspect_ratio: Some("16:9".to_string()),
+            ..params("a lighthouse")
+        };
+        assert_eq!(pipeline.resolve_dimensions(&request).unwrap(), (1328, 744));
+        
+        let explicit = GenerationParams { width: Some(512), ..request };
+        assert!(matches!(pipeline.resolve_dimensions(&explicit), Err(DiffusionError::InvalidParameters(_))));
+    }
+    
+    #[tokio::test]
+    async fn seed_search_cells_have_sequential_seeds() {
+        let mut config = mock_config();
+        config.inference.seed_search_cell_size = 64;
+        let pipeline = mock_pipeline(&config);
+        
+        // More cells than one batch holds
+        let grid = pipeline.seed_search(GenerationParams { seed: Some(100), ..params("a lighthouse") }, 5).await.unwrap();
+        let seeds: Vec<i64> = grid.cells.iter().map(|cell| cell.seed).collect();
+        assert_eq!(seeds, [100, 101, 102, 103, 104]);
+        assert!(grid.cells.iter().all(|cell| cell.error.is_none()));
+        assert_eq!((grid.columns, grid.rows), (3, 2));
+        
+        let image = image::load_from_memory(&grid.image).unwrap();
+        assert_eq!((image.width(), image.height()), (3 * 64, 2 * 64));
+    }
+    
+    #[tokio::test]
+    async fn mock_generation_is_deterministic() {
+        let pipeline = mock_pipeline(&mock_config());
+        let image = |result: GenerationResult| result.images().next().unwrap().clone();
+        
+        let first = image(pipeline.generate(params("a lighthouse")).await.unwrap());
+        let again = image(pipeline.generate(params("a lighthouse")).await.unwrap());
+        let other_seed = image(pipeline.generate(GenerationParams { seed: Some(43), ..params("a lighthouse") }).await.unwrap());
+        assert_eq!(first, again);
+        assert_ne!(first, other_seed);
+    }
+    
+    #[tokio::test]
+    async fn deep_queue_reduces_steps() {
+        let mut config = mock_config();
+        config.inference.adaptive_steps.enabled = true;
+        config.inference.adaptive_steps.min_steps = 20;
+        config.inference.adaptive_steps.queue_threshold = 10;
+        let pipeline = mock_pipeline(&config);
+        
+        let result = pipeline.generate(GenerationParams { num_inference_steps: 50, ..params("a lighthouse") }).await.unwrap();
+        assert_eq!((result.steps_taken, result.requested_steps), (50, None));
+        
+        let busy = GenerationParams { num_inference_steps: 50, queue_depth: 20, ..params("a lighthouse") };
+        let result = pipeline.generate(busy).await.unwrap();
+        assert_eq!((result.steps_taken, result.requested_steps), (20, Some(50)));
+    }
+    
+    /// Chunk types of an encoded PNG, in order
+    fn png_chunks(png: &[u8]) -> Vec<[u8; 4]> {
+        let mut chunks = Vec::new();
+        let mut offset = 8;
+        while offset + 8 <= png.len() {
+            let length = u32::from_be_bytes(png[offset..offset + 4].try_into().unwrap()) as usize;
+            chunks.push(png[offset + 4..offset + 8].try_into().unwrap());
+            offset += 12 + length;
+        }
+        chunks
+    }
+    
+    #[tokio::test]
+    async fn init_image_exif_is_stripped() {
+        // A camera photo: PNG with an eXIf chunk carrying GPS tags after IHDR
+        let source = postprocess::encode_png(&DynamicImage::new_rgb8(64, 64)).unwrap();
+        let exif = b"Exif\0\0MM\0*GPSLatitude 51.5N GPSLongitude 0.1W Camera X100";
+        let mut chunk = Vec::new();
+        chunk.extend_from_slice(&(exif.len() as u32).to_be_bytes());
+        chunk.extend_from_slice(b"eXIf");
+        chunk.extend_from_slice(exif);
+        let crc = postprocess::crc32(&chunk[4..]);
+        chunk.extend_from_slice(&crc.to_be_bytes());
+        let ihdr_end = 8 + 12 + 13;
+        let init = [&source[..ihdr_end], &chunk, &source[ihdr_end..]].concat();
+        assert!(png_chunks(&init).contains(b"eXIf"));
+        
+        let mut config = mock_config();
+        config.inference.strip_metadata = true;
+        let pipeline = mock_pipeline(&config);
+        let request = GenerationParams { init_image: Some(init), strength: Some(0.5), ..params("a lighthouse") };
+        let result = pipeline.generate(request).await.unwrap();
+        
+        let output = result.images().next().unwrap();
+        let chunks = png_chunks(output);
+        assert!(chunks.iter().all(|kind| !postprocess::METADATA_CHUNKS.contains(&kind)));
+        assert!(!output.windows(3).any(|bytes| bytes == b"GPS"));
+        assert!(image::load_from_memory(output).is_ok());
+    }
+    
+    #[test]
+    fn estimate_scales_with_steps_and_batch_size() {
+        let mut config = mock_config();
+        config.inference.cost_per_second = Some(0.01);
+        let pipeline = mock_pipeline(&config);
+        pipeline.step_timings.record(0.5);
+        pipeline.step_timings.record(0.5);
+        
+        let estimate = |steps: i32, num_images: i32| {
+            let params = GenerationParams { num_inference_steps: steps, num_images, ..params("a lighthouse") };
+            pipeline.estimate(&params).unwrap()
+        };
+        
+        let base = estimate(10, 1);
+        assert_eq!((base.seconds_per_step, base.samples), (0.5, 2));
+        assert!((base.seconds - 5.0).abs() < 1e-9);
+        assert!((estimate(20, 1).seconds - 2.0 * base.seconds).abs() < 1e-9);
+        assert!((estimate(20, 2).seconds - 4.0 * base.seconds).abs() < 1e-9);
+        assert!((base.cost.unwrap() - 0.05).abs() < 1e-9);
+    }
+    
+    #[tokio::test]
+    async fn off_bucket_sizes_snap_to_the_closest_bucket() {
+        let mut config = mock_config();
+        config.inference.snap_to_bucket = true;
+        config.inference.resolution_buckets = vec![(64, 64), (96, 64), (64, 96)];
+        let pipeline = mock_pipeline(&config);
+        let sized = |width: i32, height: i32| GenerationParams { width: Some(width), height: Some(height), ..params("a lighthouse") };
+        
+        let result = pipeline.generate(sized(104, 64)).await.unwrap();
+        assert_eq!((result.width, result.height), (96, 64));
+        assert_eq!(result.requested_dimensions, Some((104, 64)));
+        let image = image::load_from_memory(result.images().next().unwrap()).unwrap();
+        assert_eq!((image.width(), image.height()), (96, 64));
+        
+        let result = pipeline.generate(sized(64, 96)).await.unwrap();
+        assert_eq!((result.width, result.height), (64, 96));
+        assert_eq!(result.requested_dimensions, None);
+    }
+    
+    #[tokio::test]
+    async fn noise_schedule_propagates_into_the_scheduler_config() {
+        use crate::config::NoiseScheduleConfig;
+        use crate::inference::scheduler::BetaSchedule;
+        
+        let linear = NoiseScheduleConfig { beta_start: 0.0001, beta_end: 0.02, beta_schedule: "linear".to_string() };
+        let mut config = mock_config();
+        config.model.noise_schedule = linear;
+        config.model.models = vec![ModelDefinition {
+            noise_schedule: Some(NoiseScheduleConfig { beta_schedule: "squaredcos".to_string(), ..Default::default() }),
+            ..model("cosine")
+        }];
+        let pipeline = mock_pipeline(&config);
+        
+        let result = pipeline.generate(params("a lighthouse")).await.unwrap();
+        assert_eq!(result.noise_schedule, NoiseSchedule { beta_start: 0.0001, beta_end: 0.02, beta_schedule: BetaSchedule::Linear });
+        
+        let cosine = GenerationParams { model_id: Some("cosine".to_string()), ..params("a lighthouse") };
+        let result = pipeline.generate(cosine).await.unwrap();
+        assert_eq!(result.noise_schedule.beta_schedule, BetaSchedule::SquaredCos);
+        
+        let mut config = mock_config();
+        config.model.noise_schedule.beta_schedule = "quadratic".to_string();
+        assert!(matches!(
+            InferencePipeline::new(config.inference.clone(), &config.model, &config.health, Device::Cpu),
+            Err(DiffusionError::Config(_))
+        ));
+    }
+    
+    #[tokio::test]
+    async fn model_prefix_and_suffix_wrap_the_prompt() {
+        let mut config = mock_config();
+        config.model.models = vec![ModelDefinition {
+            prompt_prefix: Some("sks style".to_string()),
+            prompt_suffix: Some("masterpiece".to_string()),
+            negative_prompt_prefix: Some("lowres".to_string()),
+            ..model("tuned")
+        }];
+        let pipeline = mock_pipeline(&config);
+        let tuned = || GenerationParams {
+            model_id: Some("tuned".to_string()),
+            negative_prompt: Some("blurry".to_string()),
+            ..params("a lighthouse")
+        };
+        
+        let result = pipeline.generate(tuned()).await.unwrap();
+        assert_eq!(result.final_prompt, "sks style, a lighthouse, masterpiece");
+        assert_eq!(result.final_negative_prompt.as_deref(), Some("lowres, blurry"));
+        
+        let result = pipeline.generate(GenerationParams { disable_auto_prompt: true, ..tuned() }).await.unwrap();
+        assert_eq!(result.final_prompt, "a lighthouse");
+        assert_eq!(result.final_negative_prompt.as_deref(), Some("blurry"));
+    }
+    
+    #[tokio::test]
+    async fn moderation_and_token_budget_see_the_wrapped_prompt() {
+        let mut config = mock_config();
+        config.inference.moderation.enabled = true;
+        config.inference.moderation.blocklist = vec!["gore".to_string()];
+        config.model.models = vec![ModelDefinition { prompt_suffix: Some("gore".to_string()), ..model("grim") }];
+        let pipeline = mock_pipeline(&config);
+        let grim = GenerationParams { model_id: Some("grim".to_string()), ..params("a lighthouse") };
+        assert!(pipeline.generate(grim.clone()).await.is_err());
+        assert!(pipeline.validate(&grim).is_err());
+        assert!(pipeline.generate(GenerationParams { disable_auto_prompt: true, ..grim }).await.is_ok());
+        
+        let mut config = mock_config();
+        config.model.models = vec![ModelDefinition { prompt_prefix: Some("word ".repeat(40)), ..model("long") }];
+        let lighthouse = tokens::estimate_tokens("a lighthouse");
+        config.inference.max_batch_tokens = Some(lighthouse);
+        let pipeline = mock_pipeline(&config);
+        assert!(pipeline.generate(params("a lighthouse")).await.is_ok());
+        let long = GenerationParams { model_id: Some("long".to_string()), ..params("a lighthouse") };
+        assert!(matches!(pipeline.generate(long).await, Err(DiffusionError::InvalidParameters(_))));
+    }
+    
+    #[tokio::test]
+    async fn prompt_embeds_are_checked_against_the_model_and_moderation() {
+        let embeds = |dim: usize| latents::to_safetensors(&[1, 77, dim], &vec![0.5; 77 * dim]);
+        let request = |embeds: Vec<u8>, model_id: Option<&str>| GenerationParams {
+            prompt_embeds: Some(embeds),
+            model_id: model_id.map(str::to_string),
+            ..params("")
+        };
+        let mut config = mock_config();
+        config.model.models = vec![ModelDefinition { encoder_dim: Some(1024), ..model("sd2") }];
+        let pipeline = mock_pipeline(&config);
+        
+        let result = pipeline.generate(request(embeds(768), None)).await.unwrap();
+        assert!(result.prompt_embeds);
+        assert!(pipeline.generate(request(embeds(1024), Some("sd2"))).await.unwrap().prompt_embeds);
+        assert!(pipeline.validate(&request(embeds(1024), Some("sd2"))).is_ok());
+        
+        let mut nan = embeds(768);
+        let last = nan.len() - 4;
+        nan[last..].copy_from_slice(&f32::NAN.to_le_bytes());
+        for malformed in [
+            request(embeds(768), Some("sd2")),
+            request(embeds(1024), None),
+            request(nan, None),
+            request(b"not safetensors".to_vec(), None),
+        ] {
+            assert!(pipeline.validate(&malformed).is_err());
+            assert!(matches!(pipeline.generate(malformed).await, Err(DiffusionError::InvalidParameters(_))));
+        }
+        
+        // Embeddings have no text for the moderator to check
+        let mut config = mock_config();
+        config.inference.moderation.enabled = true;
+        let pipeline = mock_pipeline(&config);
+        assert!(matches!(pipeline.generate(request(embeds(768), None)).await, Err(DiffusionError::Validation(_))));
+        config.inference.moderation.allow_prompt_embeds = true;
+        let pipeline = mock_pipeline(&config);
+        assert!(pipeline.generate(request(embeds(768), None)).await.unwrap().prompt_embeds);
+    }
+    
+    #[tokio::test]
+    async fn compute_budget_rejects_just_over_and_accepts_just_under() {
+        let request = GenerationParams {
+            num_images: 4,
+            num_inference_steps: 20,
+            width: Some(512),
+            height: Some(512),
+            ..params("a lighthouse")
+        };
+        let cost = InferencePipeline::compute_cost(&request, 512, 512);
+        assert!((cost - 4.0 * 20.0 * 512.0 * 512.0 / dimensions::PIXELS_PER_MEGAPIXEL).abs() < 1e-9);
+        
+        let mut config = mock_config();
+        config.inference.max_compute_budget = Some(cost * 1.01);
+        assert!(mock_pipeline(&config).validate(&request).is_ok());
+        
+        config.inference.max_compute_budget = Some(cost * 0.99);
+        match mock_pipeline(&config).generate(request).await {
+            Err(DiffusionError::Validation(errors)) => {
+                let message = errors.iter().find(|e| e.contains("budget")).expect("a budget error");
+                assert!(message.contains(&format!("{:.1}", cost)), "{}", message);
+                assert!(message.contains(&format!("{:.1}", cost * 0.99)), "{}", message);
+            }
+            other => panic!("expected the budget to be exceeded, got {:?}", other.map(|r| r.seed)),
+        }
+        
+        for budget in [0.0, -1.0, f64::NAN] {
+            config.inference.max_compute_budget = Some(budget);
+            assert!(matches!(
+                InferencePipeline::new(config.inference.clone(), &config.model, &config.health, Device::Cpu),
+                Err(DiffusionError::Config(_))
+            ));
+        }
+    }
+    
+    #[tokio::test]
+    async fn requested_latents_deserialize_to_the_latent_shape() {
+        let request = GenerationParams { width: Some(64), height: Some(96), return_latents: true, ..params("a lighthouse") };
+        let pipeline = mock_pipeline(&mock_config());
+        assert!(matches!(pipeline.generate(request.clone()).await, Err(DiffusionError::Validation(_))));
+        
+        let mut config = mock_config();
+        config.inference.allow_return_latents = true;
+        let pipeline = mock_pipeline(&config);
+        let result = pipeline.generate(request).await.unwrap();
+        let latent = result.results[0].latent.as_ref().unwrap();
+        
+        let header_len = u64::from_le_bytes(latent[..8].try_into().unwrap()) as usize;
+        let header: serde_json::Value = serde_json::from_slice(&latent[8..8 + header_len]).unwrap();
+        let tensor = &header[latents::LATENT_TENSOR_NAME];
+        assert_eq!(tensor["dtype"], "F32");
+        assert_eq!(tensor["shape"], serde_json::json!([1, 4, 12, 8]));
+        let data = &latent[8 + header_len..];
+        assert_eq!(data.len(), latents::latent_bytes(64, 96));
+        assert_eq!(tensor["data_offsets"], serde_json::json!([0, data.len()]));
+        
+        let result = pipeline.generate(params("a lighthouse")).await.unwrap();
+        assert!(result.results[0].latent.is_none());
+    }
+    
+    #[tokio::test]
+    async fn face_restore_runs_only_when_enabled() {
+        let mut config = mock_config();
+        config.inference.face_restore.weight = 0.7;
+        let pipeline = mock_pipeline(&config);
+        let restore = |face_restore: Option<bool>, face_restore_weight: Option<f64>| GenerationParams {
+            face_restore,
+            face_restore_weight,
+            ..params("a portrait")
+        };
+        
+        let plain = pipeline.generate(restore(None, None)).await.unwrap();
+        assert_eq!(plain.face_restore_weight, None);
+        let skipped = pipeline.generate(restore(Some(false), Some(0.3))).await.unwrap();
+        assert_eq!(skipped.face_restore_weight, None);
+        
+        let restored = pipeline.generate(restore(Some(true), Some(0.3))).await.unwrap();
+        assert_eq!(restored.face_restore_weight, Some(0.3));
+        let defaulted = pipeline.generate(restore(Some(true), None)).await.unwrap();
+        assert_eq!(defaulted.face_restore_weight, Some(0.7));
+        // The stage is a pass-through until a model is wired in
+        assert_eq!(restored.images().next(), plain.images().next());
+        
+        assert!(matches!(pipeline.generate(restore(Some(true), Some(1.5))).await, Err(DiffusionError::Validation(_))));
+        
+        config.inference.face_restore.enabled = true;
+        let pipeline = mock_pipeline(&config);
+        assert_eq!(pipeline.generate(restore(None, None)).await.unwrap().face_restore_weight, Some(0.7));
+    }
+    
+    #[tokio::test]
+    async fn batch_over_the_token_budget_is_rejected() {
+        let mut config = mock_config();
+        config.inference.max_batch_tokens = Some(20);
+        let pipeline = mock_pipeline(&config);
+        // 5 words + start / end tokens
+        let batch = |num_images: i32| GenerationParams { num_images, ..params("a red lighthouse at dusk") };
+        
+        assert!(pipeline.generate(batch(1)).await.is_ok());
+        assert!(pipeline.generate(batch(2)).await.is_ok());
+        match pipeline.generate(batch(3)).await {
+            Err(DiffusionError::InvalidParameters(message)) => assert!(message.contains("21"), "{}", message),
+            other => panic!("expected the budget to be exceeded, got {:?}", other.map(|r| r.seed)),
+        }
+        
+        // Negative prompts are encoded for every image too
+        let with_negatives = GenerationParams {
+            negative_prompts: vec![("blurry".to_string(), 1.0), ("lowres".to_string(), 0.5)],
+            ..batch(2)
+        };
+        assert!(pipeline.generate(with_negatives).await.is_err());
+    }
+    
+    #[tokio::test]
+    async fn strength_presets_resolve_to_their_values() {
+        let pipeline = mock_pipeline(&mock_config());
+        let init = postprocess::encode_png(&DynamicImage::new_rgb8(64, 64)).unwrap();
+        let img2img = |strength_preset: Option<&str>, strength: Option<f64>| GenerationParams {
+            init_image: Some(init.clone()),
+            strength,
+            strength_preset: strength_preset.map(str::to_string),
+            ..params("a lighthouse")
+        };
+        
+        for (name, value) in [("subtle", 0.25), ("balanced", 0.5), ("creative", 0.8)] {
+            let result = pipeline.generate(img2img(Some(name), None)).await.unwrap();
+            assert_eq!(result.strength, Some(value), "{}", name);
+        }
+        assert_eq!(pipeline.generate(img2img(None, Some(0.4))).await.unwrap().strength, Some(0.4));
+        
+        assert!(matches!(
+            pipeline.generate(img2img(Some("balanced"), Some(0.4))).await,
+            Err(DiffusionError::Validation(errors)) if errors.iter().any(|e| e.contains("mutually exclusive"))
+        ));
+        assert!(pipeline.generate(img2img(Some("wild"), None)).await.is_err());
+    }
+    
+    #[test]
+    fn degenerate_outputs_retry_then_fail() {
+        let mut config = mock_config();
+        config.inference.degenerate_output.max_retries = 2;
+        let pipeline = mock_pipeline(&config);
+        let black = DynamicImage::new_rgb8(8, 8);
+        let noise = DynamicImage::ImageRgb8(image::RgbImage::from_fn(8, 8, |x, y| Rgb([(x * 30) as u8, (y * 30) as u8, 90])));
+        let finite = [0.5f32; 16];
+        let nan = [0.5, f32::NAN, 0.5, 0.5];
+        
+        assert_eq!(pipeline.check_degenerate(0, &finite, &noise, 0).unwrap(), None);
+        assert_eq!(pipeline.check_degenerate(0, &finite, &black, 0).unwrap(), Some(degenerate::Degeneracy::AllBlack));
+        // NaN in the latent is caught even though the 8-bit image looks fine
+        assert_eq!(pipeline.check_degenerate(0, &nan, &noise, 1).unwrap(), Some(degenerate::Degeneracy::NonFinite));
+        assert!(matches!(
+            pipeline.check_degenerate(0, &finite, &black, 2),
+            Err(DiffusionError::Inference(message)) if message == "degenerate output"
+        ));
+        // A client retry gets a new seed, which may well come out fine
+        assert!(pipeline.check_degenerate(0, &finite, &black, 2).unwrap_err().is_retryable());
+        
+        config.inference.degenerate_output.action = DegenerateAction::Fail;
+        let pipeline = mock_pipeline(&config);
+        assert!(pipeline.check_degenerate(0, &nan, &noise, 0).is_err());
+        
+        config.inference.degenerate_output.enabled = false;
+        let pipeline = mock_pipeline(&config);
+        assert_eq!(pipeline.check_degenerate(0, &nan, &black, 5).unwrap(), None);
+    }
+    
+    #[tokio::test]
+    async fn degenerate_image_fails_alone_and_keeps_its_siblings() {
+        let mut config = mock_config();
+        config.inference.degenerate_output.max_retries = 2;
+        let mut pipeline = mock_pipeline(&config);
+        pipeline.degenerate_images = vec![1];
+        
+        let result = pipeline.generate(GenerationParams { num_images: 3, ..params("a lighthouse") }).await.unwrap();
+        let succeeded: Vec<bool> = result.results.iter().map(|r| r.outcome.is_ok()).collect();
+        assert_eq!(succeeded, [true, false, true]);
+        assert!(result.results[1].outcome.as_ref().unwrap_err().contains("degenerate output"));
+        assert_eq!(result.degenerate_retries, 2);
+        
+        // With nothing left, the request fails the way a lone image does
+        pipeline.degenerate_images = vec![0];
+        assert!(matches!(
+            pipeline.generate(params("a lighthouse")).await,
+            Err(DiffusionError::Inference(message)) if message == "degenerate output"
+        ));
+    }
+    
+    #[tokio::test]
+    async fn init_image_decodes_never_exceed_the_limit() {
+        let mut config = mock_config();
+        config.inference.max_concurrent_decodes = 2;
+        let pipeline = mock_pipeline(&config);
+        let init = postprocess::encode_png(&DynamicImage::new_rgb8(64, 64)).unwrap();
+        
+        let decodes = (0..6).map(|_| pipeline.decode_init_image(&init, 64, 64));
+        for decoded in futures::future::join_all(decodes).await {
+            assert!(decoded.is_ok());
+        }
+        
+        let peak = pipeline.decode_gauge.peak.load(std::sync::atomic::Ordering::SeqCst);
+        assert_eq!(peak, 2);
+        assert_eq!(pipeline.decode_gauge.current.load(std::sync::atomic::Ordering::SeqCst), 0);
+    }
+    
+    #[tokio::test]
+    async fn oversized_init_image_is_rejected_from_its_header() {
+        let mut config = mock_config();
+        config.inference.max_decode_dimension = 32;
+        let pipeline = mock_pipeline(&config);
+        let init = postprocess::encode_png(&DynamicImage::new_rgb8(64, 64)).unwrap();
+        
+        match pipeline.decode_init_image(&init, 64, 64).await {
+            Err(DiffusionError::InvalidParameters(message)) => assert!(message.contains("decode limit"), "{}", message),
+            other => panic!("expected the init image to be rejected, got {:?}", other.map(|(_, size)| size)),
+        }
+    }
+    
+    #[tokio::test]
+    async fn warmup_runs_each_model_the_configured_number_of_times() {
+        let mut config = mock_config();
+        config.model.models = vec![model("base"), model("anime")];
+        let pipeline = mock_pipeline(&config);
+        
+        let timings = pipeline.warmup(3, 64, 4).await.unwrap();
+        let models: Vec<_> = timings.iter().map(|(id, _)| id.as_str()).collect();
+        assert_eq!(models.len(), 2);
+        assert!(models.contains(&"base") && models.contains(&"anime"));
+        
+        let log = pipeline.warmup_log.lock().unwrap();
+        assert_eq!(log.len(), 6);
+        for id in ["base", "anime"] {
+            assert_eq!(log.iter().filter(|(model, _, size)| model == id && *size == 64).count(), 3);
+        }
+        // Warmups stay out of the lifetime count and the outcome window
+        assert_eq!(pipeline.total_generations(), 0);
+    }
+    
+    #[tokio::test]
+    async fn byte_reservation_covers_extra_outputs() {
+        let mut config = mock_config();
+        // A 64x64 image and its encoded copy take 24 KiB
+        config.inference.max_inflight_bytes = Some(26 * 1024);
+        let pipeline = mock_pipeline(&config);
+        assert!(pipeline.generate(params("a lighthouse")).await.is_ok());
+        
+        let with_thumbnail = GenerationParams { thumbnail: Some(32), ..params("a lighthouse") };
+        let with_encodings = GenerationParams {
+            output_formats: vec!["jpeg".to_string(), "png".to_string()],
+            ..params("a lighthouse")
+        };
+        let with_animation = GenerationParams { return_steps_animation: true, ..params("a lighthouse") };
+        for request in [with_thumbnail, with_encodings, with_animation] {
+            match pipeline.generate(request).await {
+                Err(DiffusionError::InvalidParameters(message)) => assert!(message.contains("in-flight limit"), "{}", message),
+                other => panic!("expected the byte ceiling to be exceeded, got {:?}", other.map(|r| r.seed)),
+            }
+        }
+    }
+    
+    #[tokio::test]
+    async fn step_animation_is_a_gif_with_bounded_frames() {
+        use image::AnimationDecoder;
+        
+        let pipeline = mock_pipeline(&mock_config());
+        let frames = |steps: i32| {
+            let request = GenerationParams {
+                num_inference_steps: steps,
+                return_steps_animation: true,
+                ..params("a lighthouse")
+            };
+            let pipeline = &pipeline;
+            async move {
+                let result = pipeline.generate(request).await.unwrap();
+                assert!(result.results[0].animation.is_some());
+                let gif = result.results[0].animation.clone().unwrap();
+                assert_eq!(image::guess_format(&gif).unwrap(), image::ImageFormat::Gif);
+                let decoder = image::codecs::gif::GifDecoder::new(std::io::Cursor::new(gif)).unwrap();
+                decoder.into_frames().collect_frames().unwrap().len()
+            }
+        };
+        
+        // One frame per step, until sampling every k steps caps them
+        assert_eq!(frames(4).await, 4);
+        assert_eq!(frames(100).await, 25);
+        assert!(frames(150).await <= MAX_ANIMATION_FRAMES);
+        
+        let plain = pipeline.generate(params("a lighthouse")).await.unwrap();
+        assert!(plain.results[0].animation.is_none());
+    }
+    
+    #[tokio::test]
+    async fn thumbnail_is_a_downscaled_jpeg() {
+        let pipeline = mock_pipeline(&mock_config());
+        let request = GenerationParams { width: Some(96), thumbnail: Some(32), ..params("a lighthouse") };
+        let result = pipeline.generate(request).await.unwrap();
+        
+        let thumbnail = result.results[0].thumbnail.as_deref().unwrap();
+        assert_eq!(image::guess_format(thumbnail).unwrap(), image::ImageFormat::Jpeg);
+        let decoded = image::load_from_memory(thumbnail).unwrap();
+        assert_eq!(decoded.width().max(decoded.height()), 32);
+        assert!(decoded.width() > decoded.height());
+        
+        for size in [0, 96, 200, i32::MAX as u32 + 1, u32::MAX] {
+            let request = GenerationParams { width: Some(96), thumbnail: Some(size), ..params("a lighthouse") };
+            assert!(matches!(pipeline.generate(request).await, Err(DiffusionError::Validation(_))), "{}", size);
+        }
+        assert!(pipeline.generate(params("a lighthouse")).await.unwrap().results[0].thumbnail.is_none());
+        
+        // Estimated without wrapping, so it's over any in-flight limit
+        let mut config = mock_config();
+        config.inference.max_inflight_bytes = Some(64 << 20);
+        let pipeline = mock_pipeline(&config);
+        let huge = GenerationParams { thumbnail: Some(u32::MAX), ..params("a lighthouse") };
+        assert!(matches!(pipeline.reserve_bytes(&huge, 64, 64, 0).await, Err(DiffusionError::InvalidParameters(_))));
+    }
+    
+    #[tokio::test]
+    async fn dpi_is_read_back_from_png_and_jpeg() {
+        let pipeline = mock_pipeline(&mock_config());
+        
+        let plain = pipeline.generate(params("a lighthouse")).await.unwrap();
+        let png = pipeline.generate(GenerationParams { dpi: Some(300), ..params("a lighthouse") }).await.unwrap();
+        let png = png.images().next().unwrap();
+        let start = png.windows(4).position(|bytes| bytes == b"pHYs").unwrap() + 4;
+        let x = u32::from_be_bytes(png[start..start + 4].try_into().unwrap());
+        let y = u32::from_be_bytes(png[start + 4..start + 8].try_into().unwrap());
+        // Pixels per metre, unit 1 = metre
+        assert_eq!((x, y, png[start + 8]), (11811, 11811, 1));
+        assert_eq!(
+            image::load_from_memory(png).unwrap().to_rgb8(),
+            image::load_from_memory(plain.images().next().unwrap()).unwrap().to_rgb8()
+        );
+        
+        let request = GenerationParams { dpi: Some(600), format: Some("jpeg".to_string()), ..params("a lighthouse") };
+        let jpeg = pipeline.generate(request).await.unwrap();
+        let jpeg = jpeg.images().next().unwrap();
+        let jfif = jpeg.windows(5).position(|bytes| bytes == b"JFIF\0").unwrap();
+        // Version, then unit 1 = dots per inch and the X / Y densities
+        let density = &jpeg[jfif + 7..jfif + 12];
+        assert_eq!(density[0], 1);
+        assert_eq!(u16::from_be_bytes([density[1], density[2]]), 600);
+        assert_eq!(u16::from_be_bytes([density[3], density[4]]), 600);
+        
+        for dpi in [0, MAX_DPI + 1] {
+            let request = GenerationParams { dpi: Some(dpi), ..params("a lighthouse") };
+            assert!(matches!(pipeline.generate(request).await, Err(DiffusionError::Validation(_))), "{}", dpi);
+        }
+    }
+    
+    #[tokio::test]
+    async fn empty_prompt_uses_the_fallback_only_when_enabled() {
+        let strict = mock_pipeline(&mock_config());
+        assert!(matches!(
+            strict.generate(params("")).await,
+            Err(DiffusionError::Validation(errors)) if errors.iter().any(|e| e.contains("empty"))
+        ));
+        
+        let mut config = mock_config();
+        config.inference.allow_empty_prompt_fallback = true;
+        config.inference.fallback_prompt = "a quiet mountain lake".to_string();
+        let lenient = mock_pipeline(&config);
+        let result = lenient.generate(params("")).await.unwrap();
+        assert!(result.prompt_fallback);
+        assert!(result.final_prompt.contains("a quiet mountain lake"), "{}", result.final_prompt);
+        
+        let result = lenient.generate(params("a lighthouse")).await.unwrap();
+        assert!(!result.prompt_fallback);
+        assert!(!result.final_prompt.contains("mountain lake"));
+        
+        config.inference.fallback_prompt = String::new();
+        assert!(InferencePipeline::new(config.inference.clone(), &config.model, &config.health, Device::Cpu).is_err());
+    }
+    
+    #[tokio::test]
+    async fn no_cache_encodes_afresh_and_refreshes_the_cache() {
+        let mut config = mock_config();
+        config.inference.conditioning_cache_size = 8;
+        let pipeline = mock_pipeline(&config);
+        
+        let fresh = || GenerationParams { no_cache: true, ..params("a lighthouse") };
+        assert!(!pipeline.generate(fresh()).await.unwrap().conditioning_cache_hit);
+        assert!(!pipeline.generate(fresh()).await.unwrap().conditioning_cache_hit);
+        
+        // The fresh encodings were still cached
+        assert!(pipeline.generate(params("a lighthouse")).await.unwrap().conditioning_cache_hit);
+        assert!(pipeline.generate(params("a lighthouse")).await.unwrap().conditioning_cache_hit);
+    }
+    
+    #[tokio::test]
+    async fn reloading_the_model_clears_the_conditioning_cache() {
+        let mut config = mock_config();
+        config.inference.conditioning_cache_size = 8;
+        let pipeline = mock_pipeline(&config);
+        
+        assert!(!pipeline.generate(params("a lighthouse")).await.unwrap().conditioning_cache_hit);
+        assert!(pipeline.generate(params("a lighthouse")).await.unwrap().conditioning_cache_hit);
+        
+        assert!(!pipeline.models().unload_idle(Duration::ZERO).is_empty());
+        let result = pipeline.generate(params("a lighthouse")).await.unwrap();
+        assert!(result.model_load_time.is_some());
+        assert!(!result.conditioning_cache_hit);
+        assert!(pipeline.generate(params("a lighthouse")).await.unwrap().conditioning_cache_hit);
+    }
+    
+    #[tokio::test]
+    async fn every_validation_error_is_reported() {
+        let pipeline = mock_pipeline(&mock_config());
+        let request = GenerationParams {
+            width: Some(100_000),
+            num_inference_steps: 9999,
+            num_images: 0,
+            guidance_scale: 99.0,
+            ..params("a lighthouse")
+        };
+        
+        let errors = match pipeline.generate(request).await {
+            Err(DiffusionError::Validation(errors)) => errors,
+            other => panic!("expected validation errors, got {:?}", other.map(|r| r.seed)),
+        };
+        for expected in ["Width", "Steps", "num_images", "Guidance scale"] {
+            assert!(errors.iter().any(|e| e.starts_with(expected)), "{} missing from {:?}", expected, errors);
+        }
+    }
+    
+    #[tokio::test]
+    async fn guidance_below_a_scheduler_floor_is_rejected() {
+        let pipeline = mock_pipeline(&mock_config());
+        let request = |scheduler: &str| GenerationParams {
+            scheduler: Some(scheduler.to_string()),
+            guidance_scale: 1.5,
+            ..params("a lighthouse")
+        };
+        
+        assert!(pipeline.generate(request("ddim")).await.is_ok());
+        match pipeline.generate(request("euler_ancestral")).await {
+            Err(DiffusionError::Validation(errors)) => {
+                assert!(errors.iter().any(|e| e.contains("euler_ancestral scheduler")), "{:?}", errors);
+            }
+            other => panic!("expected the guidance to be rejected, got {:?}", other.map(|r| r.seed)),
+        }
+    }
+    
+    #[tokio::test]
+    async fn inline_edits_are_stripped_from_the_encoded_prompt() {
+        let pipeline = mock_pipeline(&mock_config());
+        let result = pipeline.generate(params("a [cat:dog:0.5] on a sofa")).await.unwrap();
+        
+        assert!(result.final_prompt.contains("a cat on a sofa"), "{}", result.final_prompt);
+        assert!(!result.final_prompt.contains('['));
+        let schedule: Vec<_> = result.prompt_schedule.iter().map(|(fraction, _)| *fraction).collect();
+        assert_eq!(schedule, vec![0.0, 0.5]);
+        assert!(result.prompt_schedule[1].1.contains("a dog on a sofa"));
+    }
+    
+    #[tokio::test]
+    async fn each_model_warms_up_with_its_own_prompt_and_size() {
+        let mut config = mock_config();
+        config.model.models = vec![
+            model("base"),
+            ModelDefinition {
+                warmup_prompt: Some("an anime portrait".to_string()),
+                warmup_size: Some(128),
+                ..model("anime")
+            },
+        ];
+        let pipeline = mock_pipeline(&config);
+        
+        let timings = pipeline.warmup(1, 64, 4).await.unwrap();
+        assert_eq!(timings.len(), 2);
+        assert!(timings.iter().all(|(_, seconds)| *seconds >= 0.0));
+        
+        let log = pipeline.warmup_log.lock().unwrap();
+        assert!(log.contains(&("anime".to_string(), "an anime portrait".to_string(), 128)), "{:?}", log);
+        assert!(log.contains(&("base".to_string(), WARMUP_PROMPT.to_string(), 64)), "{:?}", log);
+    }
+    
+    #[tokio::test]
+    async fn oversized_init_image_is_downscaled_only_when_enabled() {
+        let init = postprocess::encode_png(&DynamicImage::new_rgb8(256, 128)).unwrap();
+        let request = || GenerationParams { init_image: Some(init.clone()), strength: Some(0.5), ..params("a lighthouse") };
+        
+        let strict = mock_pipeline(&mock_config());
+        match strict.generate(request()).await {
+            Err(DiffusionError::InvalidParameters(message)) => assert!(message.contains("larger"), "{}", message),
+            other => panic!("expected the init image to be rejected, got {:?}", other.map(|r| r.seed)),
+        }
+        
+        let mut config = mock_config();
+        config.inference.auto_resize_init = true;
+        let lenient = mock_pipeline(&config);
+        let result = lenient.generate(request()).await.unwrap();
+        assert_eq!(result.init_image_resized, Some((256, 128)));
+        assert_eq!((result.width, result.height), (64, 64));
+        
+        let (resized, original) = lenient.decode_init_image(&init, 64, 64).await.unwrap();
+        assert_eq!((resized.width(), resized.height()), (64, 64));
+        assert_eq!(original, Some((256, 128)));
+    }
+    
+    #[tokio::test]
+    async fn subseed_strength_is_validated_and_reported() {
+        let pipeline = mock_pipeline(&mock_config());
+        let variation = |subseed: Option<i64>, strength: f64| GenerationParams {
+            subseed,
+            subseed_strength: Some(strength),
+            ..params("a lighthouse")
+        };
+        
+        for request in [variation(Some(7), 1.5), variation(Some(7), -0.1), variation(None, 0.5)] {
+            assert!(matches!(pipeline.generate(request).await, Err(DiffusionError::Validation(_))));
+        }
+        
+        let result = pipeline.generate(variation(Some(7), 0.25)).await.unwrap();
+        assert_eq!((result.seed, result.subseed, result.subseed_strength), (42, Some(7), Some(0.25)));
+        
+        // The blend's endpoints are the base and subseed noise
+        let len = 4 * 8 * 8;
+        let base = noise::initial_noise(42, len);
+        assert_eq!(pipeline.initial_latent(len, 42, Some((7, 0.0))), base);
+        assert_eq!(pipeline.initial_latent(len, 42, Some((7, 1.0))), noise::initial_noise(7, len));
+        assert_eq!(pipeline.initial_latent(len, 42, None), base);
+    }
+    
+    #[tokio::test]
+    async fn schedule_is_returned_only_when_requested() {
+        let pipeline = mock_pipeline(&mock_config());
+        
+        let result = pipeline.generate(params("a lighthouse")).await.unwrap();
+        assert!(result.sigmas.is_none());
+        
+        let result = pipeline
+            .generate(GenerationParams { return_schedule: true, ..params("a lighthouse") })
+            .await
+            .unwrap();
+        let sigmas = result.sigmas.unwrap();
+        assert_eq!(sigmas.len(), params("a lighthouse").num_inference_steps as usize);
+        assert!(sigmas.windows(2).all(|pair| pair[0] > pair[1]), "{:?}", sigmas);
+    }
+    
+    #[tokio::test]
+    async fn batches_decode_in_vae_sub_batches() {
+        let mut config = mock_config();
+        config.inference.vae_decode_batch = 2;
+        config.inference.max_batch_size = 5;
+        let pipeline = mock_pipeline(&config);
+        
+        let result = pipeline
+            .generate(GenerationParams { num_images: 5, ..params("a lighthouse") })
+            .await
+            .unwrap();
+        
+        assert_eq!(result.results.len(), 5);
+        assert_eq!(*pipeline.vae_batches.lock().unwrap(), vec![2, 2, 1]);
+        // Every permit is back once the decodes finish
+        assert_eq!(pipeline.vae_permits.available_permits(), 2);
+    }
+    
+    #[test]
+    fn luminance_matte_clears_bright_pixels() {
+        let mut image = image::RgbImage::from_pixel(2, 1, Rgb([255, 255, 255]));
+        image.put_pixel(1, 0, Rgb([20, 40, 60]));
+        
+        let matted = postprocess::remove_background(DynamicImage::ImageRgb8(image), 0.95).to_rgba8();
+        assert_eq!(matted.get_pixel(0, 0).0, [255, 255, 255, 0]);
+        assert_eq!(matted.get_pixel(1, 0).0, [20, 40, 60, 255]);
+    }
+    
+    #[tokio::test]
+    async fn png_and_jpeg_come_from_one_generation() {
+        let pipeline = mock_pipeline(&mock_config());
+        let request = GenerationParams {
+            format: Some("png".to_string()),
+            output_formats: vec!["png".to_string(), "jpeg".to_string()],
+            ..params("a lighthouse")
+        };
+        let result = pipeline.generate(request).await.unwrap();
+        assert_eq!(pipeline.total_generations(), 1);
+        
+        let image = &result.results[0];
+        let primary = image.outcome.as_ref().unwrap();
+        let formats: Vec<OutputFormat> = image.encodings.iter().map(|(format, _)| *format).collect();
+        assert_eq!(formats, [OutputFormat::Png, OutputFormat::Jpeg]);
+        for (format, bytes) in &image.encodings {
+            let expected = match format {
+                OutputFormat::Png => image::ImageFormat::Png,
+                _ => image::ImageFormat::Jpeg,
+            };
+            assert_eq!(image::guess_format(bytes).unwrap(), expected);
+            let decoded = image::load_from_memory_with_format(bytes, expected).unwrap();
+            assert_eq!((decoded.width(), decoded.height()), (64, 64));
+        }
+        // The primary format's encoding is reused
+        assert_eq!(&image.encodings[0].1, primary);
+        
+        let budgeted = GenerationParams {
+            output_formats: vec!["jpeg".to_string()],
+            max_response_bytes: Some(1 << 20),
+            ..params("a lighthouse")
+        };
+        match pipeline.generate(budgeted).await {
+            Err(DiffusionError::Validation(errors)) => {
+                assert!(errors.iter().any(|e| e.contains("output_formats")), "{:?}", errors);
+            }
+            other => panic!("expected the combination to be rejected, got {:?}", other.map(|r| r.seed)),
+        }
+    }
+    
+    #[tokio::test]
+    async fn vary_region_keeps_pixels_outside_the_mask() {
+        use image::GenericImageView;
+        
+        let pipeline = mock_pipeline(&mock_config());
+        let png = |image: DynamicImage| postprocess::encode_png(&image).unwrap();
+        // Left half selected
+        let mask = GrayImage::from_fn(64, 64, |x, _| image::Luma([if x < 32 { 255 } else { 0 }]));
+        let region = |image: DynamicImage| VaryRegion { image: png(image), mask: png(DynamicImage::ImageLuma8(mask.clone())) };
+        let request = |region: VaryRegion| GenerationParams {
+            width: None,
+            height: None,
+            vary_region: Some(region),
+            ..params("a lighthouse")
+        };
+        
+        let priors = [
+            DynamicImage::ImageRgba8(image::RgbaImage::from_fn(64, 64, |x, y| image::Rgba([x as u8, y as u8, 7, 100]))),
+            DynamicImage::ImageRgb16(ImageBuffer::from_fn(64, 64, |x, y| Rgb([x as u16 * 1000 + 3, y as u16 * 1000 + 5, 40_000]))),
+        ];
+        for prior in priors {
+            let result = pipeline.generate(request(region(prior.clone()))).await.unwrap();
+            let output = image::load_from_memory(result.first_image().unwrap().outcome.as_ref().unwrap()).unwrap();
+            assert_eq!(output.color(), prior.color());
+            assert_eq!((output.width(), output.height()), (64, 64));
+            for (x, y) in (32..64).flat_map(|x| (0..64).map(move |y| (x, y))) {
+                assert_eq!(output.get_pixel(x, y), prior.get_pixel(x, y), "pixel {},{}", x, y);
+            }
+            assert_ne!(output.crop_imm(0, 0, 32, 64).to_rgba16(), prior.crop_imm(0, 0, 32, 64).to_rgba16());
+        }
+        
+        let prior = DynamicImage::ImageRgb8(image::RgbImage::new(64, 64));
+        for lossy in [
+            GenerationParams { format: Some("jpeg".to_string()), ..request(region(prior.clone())) },
+            GenerationParams { output_formats: vec!["png".to_string(), "jpeg".to_string()], ..request(region(prior.clone())) },
+            GenerationParams { max_response_bytes: Some(1 << 20), ..request(region(prior.clone())) },
+        ] {
+            assert!(matches!(pipeline.generate(lossy).await, Err(DiffusionError::Validation(_))));
+        }
+        
+        // A mask whose header claims a different size is turned away before it's decoded
+        let mut mask = png(DynamicImage::ImageLuma8(mask.clone()));
+        mask[16..20].copy_from_slice(&5000u32.to_be_bytes());
+        let crc = postprocess::crc32(&mask[12..29]);
+        mask[29..33].copy_from_slice(&crc.to_be_bytes());
+        let oversized = VaryRegion { image: png(prior), mask };
+        match pipeline.generate(request(oversized)).await {
+            Err(DiffusionError::InvalidParameters(message)) => assert!(message.contains("5000x64"), "{}", message),
+            other => panic!("expected the mask to be rejected, got {:?}", other.map(|r| r.seed)),
+        }
+    }
+    
+    #[tokio::test]
+    async fn transparent_background_outputs_rgba() {
+        let mut config = mock_config();
+        // Every pixel is at or above a zero cutoff, so the whole image clears
+        config.inference.transparency_threshold = 0.0;
+        let pipeline = mock_pipeline(&config);
+        let transparent = |format: &str| GenerationParams {
+            transparent_background: true,
+            format: Some(format.to_string()),
+            ..params("a logo")
+        };
+        
+        let result = pipeline.generate(transparent("png")).await.unwrap();
+        let bytes = result.results[0].outcome.as_ref().unwrap();
+        let image = image::load_from_memory(bytes).unwrap();
+        assert_eq!(image.color(), image::ColorType::Rgba8);
+        assert!(image.to_rgba8().pixels().all(|pixel| pixel[3] == 0));
+        
+        assert!(matches!(pipeline.generate(transparent("jpeg")).await, Err(DiffusionError::Validation(_))));
+    }
+    
+    #[tokio::test]
+    async fn upscale_past_the_decode_cap_is_rejected() {
+        let mut config = mock_config();
+        config.inference.max_decode_dimension = 200;
+        let pipeline = mock_pipeline(&config);
+        let upscaled = |factor| GenerationParams { upscale: Some(factor), ..params("a lighthouse") };
+        
+        // 64px is well inside the generation limits, but 4x decodes 256px
+        match pipeline.generate(upscaled(4)).await {
+            Err(DiffusionError::Validation(errors)) => {
+                assert!(errors.iter().any(|e| e.contains("256px") && e.contains("200px decode limit")), "{:?}", errors);
+            }
+            other => panic!("expected a validation error, got {:?}", other.map(|_| ())),
+        }
+        
+        let result = pipeline.generate(upscaled(3)).await.unwrap();
+        let image = image::load_from_memory(result.results[0].outcome.as_ref().unwrap()).unwrap();
+        assert_eq!((image.width(), image.height()), (192, 192));
+    }
+    
+    #[tokio::test]
+    async fn large_image_is_re_encoded_to_fit_the_byte_budget() {
+        // Noise barely compresses, so the 256px PNG is far over budget
+        let mut state = 42u32;
+        let noise = image::RgbImage::from_fn(256, 256, |_, _| {
+            state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+            let [r, g, b, _] = state.to_le_bytes();
+            Rgb([r, g, b])
+        });
+        let image = DynamicImage::ImageRgb8(noise);
+        let max = 32 * 1024;
+        assert!(postprocess::encode_png(&image).unwrap().len() > max);
+        
+        let (bytes, degradation) = postprocess::fit_to_budget(&image, max, false, None).unwrap().unwrap();
+        assert!(bytes.len() <= max, "{} bytes", bytes.len());
+        assert_eq!(degradation.format, OutputFormat::Jpeg);
+        let decoded = image::load_from_memory(&bytes).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (degradation.width, degradation.height));
+        
+        // Nothing fits in a few bytes
+        assert!(postprocess::fit_to_budget(&image, 16, false, None).unwrap().is_none());
+        
+        // Through the pipeline, an image already under the hint is untouched
+        let pipeline = mock_pipeline(&mock_config());
+        let result = pipeline
+            .generate(GenerationParams { max_response_bytes: Some(u32::MAX as u64), ..params("a lighthouse") })
+            .await
+            .unwrap();
+        assert!(result.results[0].degraded.is_none());
+    }
+    
+    #[tokio::test]
+    async fn temperature_is_validated_and_defaults_to_unchanged_output() {
+        let pipeline = mock_pipeline(&mock_config());
+        let heated = |temperature| GenerationParams { temperature, ..params("a lighthouse") };
+        
+        for temperature in [-0.5, MAX_TEMPERATURE + 0.1] {
+            assert!(matches!(
+                pipeline.generate(heated(Some(temperature))).await,
+                Err(DiffusionError::Validation(_))
+            ));
+        }
+        
+        let unset = pipeline.generate(heated(None)).await.unwrap();
+        let default = pipeline.generate(heated(Some(DEFAULT_TEMPERATURE))).await.unwrap();
+        assert_eq!((unset.temperature, default.temperature), (1.0, 1.0));
+        assert_eq!(unset.results[0].outcome, default.results[0].outcome);
+        
+        let hot = pipeline.generate(heated(Some(MAX_TEMPERATURE))).await.unwrap();
+        assert_eq!(hot.temperature, MAX_TEMPERATURE);
+    }
+    
+    #[tokio::test]
+    async fn unfittable_byte_budget_fails_without_retry_advice() {
+        let pipeline = mock_pipeline(&mock_config());
+        let err = pipeline
+            .generate(GenerationParams { max_response_bytes: Some(16), ..params("a lighthouse") })
+            .await
+            .unwrap_err();
+        
+        assert!(matches!(err, DiffusionError::Unrecoverable(_)), "{:?}", err);
+        assert_eq!(err.retry_info(), crate::errors::RetryInfo {
+            retryable: false,
+            attempts_made: 1,
+            retry_after_seconds: None,
+        });
+    }
+    
+    #[tokio::test]
+    async fn border_grows_the_output_and_huge_borders_are_rejected() {
+        let pipeline = mock_pipeline(&mock_config());
+        let framed = |border_width| GenerationParams {
+            width: Some(256),
+            height: Some(256),
+            border_width: Some(border_width),
+            border_color: Some("#ff0000".to_string()),
+            ..params("a lighthouse")
+        };
+        
+        let result = pipeline.generate(framed(10)).await.unwrap();
+        assert_eq!((result.width, result.height), (276, 276));
+        let image = image::load_from_memory(result.results[0].outcome.as_ref().unwrap()).unwrap().to_rgb8();
+        assert_eq!(image.dimensions(), (276, 276));
+        assert_eq!(image.get_pixel(0, 0).0, [255, 0, 0]);
+        
+        // Values that would overflow the size arithmetic are plain errors
+        for border_width in [u32::MAX, u32::MAX / 2 + 1, 4096] {
+            match pipeline.generate(framed(border_width)).await {
+                Err(DiffusionError::Validation(errors)) => {
+                    assert!(errors.iter().any(|e| e.contains("border_width must be at most")), "{:?}", errors);
+                }
+                other => panic!("expected a validation error, got {:?}", other.map(|_| ())),
+            }
+        }
+        
+        assert!(postprocess::add_border(DynamicImage::new_rgb8(4, 4), u32::MAX, [0, 0, 0]).is_err());
+    }
+    
+    #[tokio::test]
+    async fn empty_negative_prompts_match_no_negative_prompt() {
+        let pipeline = mock_pipeline(&mock_config());
+        let negatives = |negative_prompts: &[(&str, f64)]| GenerationParams {
+            negative_prompts: negative_prompts.iter().map(|(prompt, weight)| (prompt.to_string(), *weight)).collect(),
+            disable_auto_prompt: true,
+            ..params("a lighthouse")
+        };
+        
+        let plain = pipeline
+            .generate(GenerationParams { disable_auto_prompt: true, ..params("a lighthouse") })
+            .await
+            .unwrap();
+        let empty = pipeline.generate(negatives(&[])).await.unwrap();
+        assert!(empty.negative_prompts.is_empty());
+        assert_eq!(plain.results[0].outcome, empty.results[0].outcome);
+        
+        let weighted = pipeline.generate(negatives(&[("blurry", 1.5), ("watermark", 0.5)])).await.unwrap();
+        assert_eq!(weighted.negative_prompts, vec![("blurry".to_string(), 1.5), ("watermark".to_string(), 0.5)]);
+        
+        let invalid = pipeline.generate(negatives(&[("blurry", 0.0)])).await;
+        assert!(matches!(invalid, Err(DiffusionError::Validation(_))));
+    }
+    
+    #[tokio::test]
+    async fn generation_appends_one_complete_seed_log_line() {
+        let dir = std::env::temp_dir().join(format!("seed-log-{}", uuid::Uuid::new_v4()));
+        let mut config = mock_config();
+        config.inference.seed_log_dir = Some(dir.clone());
+        let pipeline = mock_pipeline(&config);
+        
+        let result = pipeline
+            .generate(GenerationParams {
+                seed: Some(1234),
+                num_images: 2,
+                job_id: Some("job-7".to_string()),
+                ..params("a lighthouse")
+            })
+            .await
+            .unwrap();
+        
+        let files: Vec<_> = std::fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().path()).collect();
+        assert_eq!(files.len(), 1);
+        let name = files[0].file_name().unwrap().to_str().unwrap().to_string();
+        assert!(name.starts_with("seeds-") && name.ends_with(".jsonl"), "{}", name);
+        
+        let contents = std::fs::read_to_string(&files[0]).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 1);
+        let line: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(line["job_id"], "job-7");
+        assert_eq!(line["seed"], 1234);
+        assert_eq!(line["image_seeds"], serde_json::json!([1234, 1235]));
+        assert_eq!(line["prompt"], result.final_prompt.as_str());
+        assert_eq!(line["steps"], result.steps_taken);
+        assert_eq!(line["guidance_scale"], result.guidance_scale);
+        assert_eq!((line["width"].clone(), line["height"].clone()), (serde_json::json!(64), serde_json::json!(64)));
+        assert_eq!(line["scheduler"], result.scheduler.as_str());
+        
+        let _ = std::fs::remove_dir_all(&dir);
     }
 }
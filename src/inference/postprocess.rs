@@ -0,0 +1,325 @@
+//! Image operations applied between generation and encoding
+
+use crate::errors::{DiffusionError, Result};
+use crate::inference::format::OutputFormat;
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::codecs::jpeg::{JpegEncoder, PixelDensity};
+use image::imageops::FilterType;
+use image::{Delay, DynamicImage, Frame, GenericImage, ImageOutputFormat, Rgb, RgbImage, Rgba, RgbaImage};
+use std::io::Cursor;
+
+const THUMBNAIL_QUALITY: u8 = 85;
+const JPEG_QUALITY: u8 = 92;
+/// JPEG qualities tried, in order, when fitting an image to a byte budget
+const FIT_QUALITIES: [u8; 4] = [80, 65, 50, 35];
+/// Fitting stops halving once the shorter side would drop below this
+const MIN_FIT_DIMENSION: u32 = 64;
+
+/// Crops `pixels` from every edge, removing border artifacts common in
+/// diffusion outputs
+pub fn crop_edges(image: DynamicImage, pixels: u32) -> DynamicImage {
+    if pixels == 0 {
+        return image;
+    }
+
+    let width = image.width().saturating_sub(pixels * 2);
+    let height = image.height().saturating_sub(pixels * 2);
+    image.crop_imm(pixels, pixels, width, height)
+}
+
+/// Tiles equally sized cells row-major into a single image. Missing cells
+/// are left black.
+pub fn compose_grid(
+    cells: &[Option<DynamicImage>],
+    columns: u32,
+    cell_width: u32,
+    cell_height: u32,
+) -> DynamicImage {
+    let rows = (cells.len() as u32).div_ceil(columns);
+    let mut grid = DynamicImage::ImageRgb8(RgbImage::new(columns * cell_width, rows * cell_height));
+
+    for (index, cell) in cells.iter().enumerate() {
+        if let Some(cell) = cell {
+            let x = (index as u32 % columns) * cell_width;
+            let y = (index as u32 / columns) * cell_height;
+            // Cells all come from the same generation size, so they always fit
+            let _ = grid.copy_from(cell, x, y);
+        }
+    }
+
+    grid
+}
+
+/// PNG chunks that can carry identifying information: EXIF (GPS, camera),
+/// free-form text and timestamps
+pub(crate) const METADATA_CHUNKS: [&[u8; 4]; 5] = [b"eXIf", b"tEXt", b"zTXt", b"iTXt", b"tIME"];
+
+const PNG_SIGNATURE: &[u8; 8] = b"\x89PNG\r\n\x1a\n";
+
+/// Drops metadata chunks from an encoded PNG, keeping everything needed to
+/// render it
+pub fn strip_png_metadata(png: &[u8]) -> Result<Vec<u8>> {
+    let malformed = || DiffusionError::Internal("Malformed PNG while stripping metadata".to_string());
+
+    if !png.starts_with(PNG_SIGNATURE) {
+        return Err(malformed());
+    }
+
+    let mut output = Vec::with_capacity(png.len());
+    output.extend_from_slice(PNG_SIGNATURE);
+
+    // Each chunk is length (4) + type (4) + data (length) + CRC (4)
+    let mut offset = PNG_SIGNATURE.len();
+    while offset < png.len() {
+        let header = png.get(offset..offset + 8).ok_or_else(malformed)?;
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let end = offset + 12 + length;
+        let chunk = png.get(offset..end).ok_or_else(malformed)?;
+
+        if !METADATA_CHUNKS.iter().any(|kind| &header[4..8] == *kind) {
+            output.extend_from_slice(chunk);
+        }
+        offset = end;
+    }
+
+    Ok(output)
+}
+
+/// Writes a `pHYs` chunk recording `dpi`, right after `IHDR`. Pixels are
+/// untouched; any existing `pHYs` chunk is replaced.
+pub fn set_png_dpi(png: &[u8], dpi: u32) -> Result<Vec<u8>> {
+    let malformed = || DiffusionError::Internal("Malformed PNG while setting DPI".to_string());
+
+    if !png.starts_with(PNG_SIGNATURE) {
+        return Err(malformed());
+    }
+
+    // pHYs stores pixels per metre
+    let pixels_per_metre = (dpi as f64 / METRES_PER_INCH).round() as u32;
+    let mut phys = Vec::with_capacity(21);
+    phys.extend_from_slice(&9u32.to_be_bytes());
+    phys.extend_from_slice(b"pHYs");
+    phys.extend_from_slice(&pixels_per_metre.to_be_bytes());
+    phys.extend_from_slice(&pixels_per_metre.to_be_bytes());
+    phys.push(1);
+    let crc = crc32(&phys[4..]);
+    phys.extend_from_slice(&crc.to_be_bytes());
+
+    let mut output = Vec::with_capacity(png.len() + phys.len());
+    output.extend_from_slice(PNG_SIGNATURE);
+
+    let mut offset = PNG_SIGNATURE.len();
+    while offset < png.len() {
+        let header = png.get(offset..offset + 8).ok_or_else(malformed)?;
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let end = offset + 12 + length;
+        let chunk = png.get(offset..end).ok_or_else(malformed)?;
+
+        if &header[4..8] != b"pHYs" {
+            output.extend_from_slice(chunk);
+        }
+        if &header[4..8] == b"IHDR" {
+            output.extend_from_slice(&phys);
+        }
+        offset = end;
+    }
+
+    Ok(output)
+}
+
+const METRES_PER_INCH: f64 = 0.0254;
+
+/// CRC-32 (ISO-HDLC) as used by PNG chunks
+pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// Face restoration stage (GFPGAN / CodeFormer slot). `weight` blends
+/// between the original and the restored faces.
+pub fn restore_faces(image: DynamicImage, _weight: f64) -> DynamicImage {
+    // TODO: Detect faces and run the restoration model, blending by weight
+    image
+}
+
+/// Upscaling stage (ESRGAN slot). Multiplies both dimensions by `factor`.
+pub fn upscale(image: DynamicImage, factor: u32) -> DynamicImage {
+    if factor <= 1 {
+        return image;
+    }
+    // TODO: Run the super-resolution model; Lanczos stands in until then
+    let (width, height) = (image.width() * factor, image.height() * factor);
+    image.resize_exact(width, height, FilterType::Lanczos3)
+}
+
+/// Parses "#rrggbb" (the '#' is optional)
+pub fn parse_hex_color(color: &str) -> Option<[u8; 3]> {
+    let hex = color.strip_prefix('#').unwrap_or(color);
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+/// Frames the image with a solid border `width` pixels wide on every side,
+/// growing it by `2 * width` in each dimension. The border is opaque even
+/// on RGBA images.
+pub fn add_border(image: DynamicImage, width: u32, color: [u8; 3]) -> Result<DynamicImage> {
+    if width == 0 {
+        return Ok(image);
+    }
+
+    let grown = |side: u32| width.checked_mul(2).and_then(|border| side.checked_add(border));
+    let (Some(framed_width), Some(framed_height)) = (grown(image.width()), grown(image.height())) else {
+        return Err(DiffusionError::InvalidParameters(format!("A {}px border overflows the image size", width)));
+    };
+    let mut framed = if image.color().has_alpha() {
+        let [r, g, b] = color;
+        DynamicImage::ImageRgba8(RgbaImage::from_pixel(framed_width, framed_height, Rgba([r, g, b, 255])))
+    } else {
+        DynamicImage::ImageRgb8(RgbImage::from_pixel(framed_width, framed_height, Rgb(color)))
+    };
+    // The frame is sized around the image, so it always fits
+    let _ = framed.copy_from(&image, width, width);
+    Ok(framed)
+}
+
+/// Background removal stage. Until a segmentation model is wired in, pixels
+/// whose luminance is at least `threshold` (0-1) are treated as background
+/// and made fully transparent; the output is always RGBA.
+pub fn remove_background(image: DynamicImage, threshold: f64) -> DynamicImage {
+    // TODO: Replace the luminance matte with a segmentation model's mask
+    let cutoff = (threshold.clamp(0.0, 1.0) * 255.0).round() as u8;
+    let luma = image.to_luma8();
+    let mut rgba = image.to_rgba8();
+    for (pixel, value) in rgba.pixels_mut().zip(luma.pixels()) {
+        if value[0] >= cutoff {
+            pixel[3] = 0;
+        }
+    }
+    DynamicImage::ImageRgba8(rgba)
+}
+
+/// JPEG downscaled so its longer side is `max_dimension`, keeping the
+/// aspect ratio, with `dpi` in its JFIF header when given
+pub fn encode_thumbnail(image: &DynamicImage, max_dimension: u32, dpi: Option<u32>) -> Result<Vec<u8>> {
+    let thumbnail = image.thumbnail(max_dimension, max_dimension).to_rgb8();
+    let mut buffer = Vec::new();
+    let mut encoder = JpegEncoder::new_with_quality(&mut buffer, THUMBNAIL_QUALITY);
+    if let Some(dpi) = dpi {
+        encoder.set_pixel_density(PixelDensity::dpi(dpi as u16));
+    }
+    encoder
+        .encode_image(&thumbnail)
+        .map_err(|e| DiffusionError::Internal(format!("Thumbnail encoding failed: {}", e)))?;
+
+    Ok(buffer)
+}
+
+/// Looping GIF showing `frames` in order, `delay_ms` apart
+pub fn encode_gif(frames: &[DynamicImage], delay_ms: u32) -> Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    {
+        let mut encoder = GifEncoder::new(&mut buffer);
+        encoder
+            .set_repeat(Repeat::Infinite)
+            .map_err(|e| DiffusionError::Internal(format!("GIF encoding failed: {}", e)))?;
+        for frame in frames {
+            let frame = Frame::from_parts(frame.to_rgba8(), 0, 0, Delay::from_numer_denom_ms(delay_ms, 1));
+            encoder
+                .encode_frame(frame)
+                .map_err(|e| DiffusionError::Internal(format!("GIF encoding failed: {}", e)))?;
+        }
+    }
+
+    Ok(buffer)
+}
+
+pub fn encode_jpeg(image: &DynamicImage, dpi: Option<u32>) -> Result<Vec<u8>> {
+    encode_jpeg_with_quality(image, JPEG_QUALITY, dpi)
+}
+
+fn encode_jpeg_with_quality(image: &DynamicImage, quality: u8, dpi: Option<u32>) -> Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    let mut encoder = JpegEncoder::new_with_quality(&mut buffer, quality);
+    if let Some(dpi) = dpi {
+        encoder.set_pixel_density(PixelDensity::dpi(dpi as u16));
+    }
+    encoder
+        .encode_image(&image.to_rgb8())
+        .map_err(|e| DiffusionError::Internal(format!("JPEG encoding failed: {}", e)))?;
+
+    Ok(buffer)
+}
+
+pub fn encode_png(image: &DynamicImage) -> Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut buffer), ImageOutputFormat::Png)
+        .map_err(|e| DiffusionError::Internal(format!("PNG encoding failed: {}", e)))?;
+
+    Ok(buffer)
+}
+
+/// How an image was re-encoded to fit `max_response_bytes`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Degradation {
+    pub format: OutputFormat,
+    /// JPEG quality used; `None` for PNG
+    pub quality: Option<u8>,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Re-encodes `image` to at most `max_bytes`: first as JPEG at falling
+/// quality, then at halved sizes. With `keep_alpha` only PNG is used, so
+/// only the size drops. `None` when even the smallest attempt is too big.
+pub fn fit_to_budget(
+    image: &DynamicImage,
+    max_bytes: usize,
+    keep_alpha: bool,
+    dpi: Option<u32>,
+) -> Result<Option<(Vec<u8>, Degradation)>> {
+    let encode = |image: &DynamicImage, quality: Option<u8>| match quality {
+        Some(quality) => encode_jpeg_with_quality(image, quality, dpi),
+        None => encode_png(image),
+    };
+    let degradation = |image: &DynamicImage, quality: Option<u8>| Degradation {
+        format: if quality.is_some() { OutputFormat::Jpeg } else { OutputFormat::Png },
+        quality,
+        width: image.width(),
+        height: image.height(),
+    };
+
+    let qualities: Vec<Option<u8>> = if keep_alpha {
+        vec![None]
+    } else {
+        FIT_QUALITIES.iter().copied().map(Some).collect()
+    };
+    let lowest = *qualities.last().unwrap_or(&None);
+
+    for &quality in &qualities {
+        let encoded = encode(image, quality)?;
+        if encoded.len() <= max_bytes {
+            return Ok(Some((encoded, degradation(image, quality))));
+        }
+    }
+
+    let mut resized = image.clone();
+    while resized.width().min(resized.height()) / 2 >= MIN_FIT_DIMENSION {
+        resized = resized.resize_exact(resized.width() / 2, resized.height() / 2, FilterType::Lanczos3);
+        let encoded = encode(&resized, lowest)?;
+        if encoded.len() <= max_bytes {
+            return Ok(Some((encoded, degradation(&resized, lowest))));
+        }
+    }
+
+    Ok(None)
+}
@@ -0,0 +1,88 @@
+use std::fmt;
+use std::str::FromStr;
+use tch::Device;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Precision {
+    Fp32,
+    Fp16,
+    Bf16,
+}
+
+impl Precision {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Precision::Fp32 => "fp32",
+            Precision::Fp16 => "fp16",
+            Precision::Bf16 => "bf16",
+        }
+    }
+
+    pub fn kind(&self) -> tch::Kind {
+        match self {
+            Precision::Fp32 => tch::Kind::Float,
+            Precision::Fp16 => tch::Kind::Half,
+            Precision::Bf16 => tch::Kind::BFloat16,
+        }
+    }
+
+    /// Whether the device can run inference at this precision
+    pub fn is_supported_on(&self, device: Device) -> bool {
+        match self {
+            Precision::Fp32 | Precision::Fp16 => true,
+            Precision::Bf16 => matches!(device, Device::Cuda(_)),
+        }
+    }
+
+    /// Why this supported combination is still a poor choice, if it is
+    pub fn device_warning(&self, device: Device) -> Option<&'static str> {
+        match (self, device) {
+            (Precision::Fp16, Device::Cpu) => Some("fp16 on the CPU is slow and loses accuracy; fp32 is recommended"),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for Precision {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "fp32" => Ok(Precision::Fp32),
+            "fp16" => Ok(Precision::Fp16),
+            "bf16" => Ok(Precision::Bf16),
+            other => Err(format!(
+                "Unknown precision '{}' (expected fp32, fp16 or bf16)",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Precision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_parse_case_insensitively() {
+        for (name, precision) in [("fp16", Precision::Fp16), ("fp32", Precision::Fp32), ("BF16", Precision::Bf16)] {
+            assert_eq!(name.parse::<Precision>().unwrap(), precision);
+            assert_eq!(precision.to_string(), name.to_ascii_lowercase());
+        }
+        assert!("fp8".parse::<Precision>().unwrap_err().contains("fp8"));
+    }
+
+    #[test]
+    fn fp16_on_the_cpu_is_warned_about() {
+        assert!(Precision::Fp16.device_warning(Device::Cpu).is_some());
+        assert!(Precision::Fp16.device_warning(Device::Cuda(0)).is_none());
+        assert!(Precision::Fp32.device_warning(Device::Cpu).is_none());
+        assert!(!Precision::Bf16.is_supported_on(Device::Cpu));
+    }
+}
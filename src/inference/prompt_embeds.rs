@@ -0,0 +1,71 @@
+//! Precomputed text encoder output sent in place of a prompt
+
+use serde_json::Value;
+
+/// Token positions in the text encoder's output
+pub const EMBEDS_TOKENS: u64 = 77;
+/// Hidden size of the text encoder for models that don't set
+/// `encoder_dim` (CLIP ViT-L/14, as in Stable Diffusion 1.x)
+pub const DEFAULT_EMBEDS_DIM: u64 = 768;
+
+/// Parses a safetensors payload holding a single F32 tensor of shape
+/// `[1, EMBEDS_TOKENS, dim]`, the layout `latents::to_safetensors` writes,
+/// where `dim` is the selected model's encoder hidden size
+pub fn parse(bytes: &[u8], dim: u64) -> Result<Vec<f32>, String> {
+    let mut header_len = [0u8; 8];
+    header_len.copy_from_slice(
+        bytes.get(..8).ok_or_else(|| "prompt_embeds is not a safetensors payload".to_string())?,
+    );
+    let data_start = usize::try_from(u64::from_le_bytes(header_len))
+        .ok()
+        .and_then(|len| len.checked_add(8))
+        .filter(|&start| start <= bytes.len())
+        .ok_or_else(|| "prompt_embeds header is truncated".to_string())?;
+    let header: serde_json::Map<String, Value> = serde_json::from_slice(&bytes[8..data_start])
+        .map_err(|e| format!("Invalid prompt_embeds header: {}", e))?;
+
+    let tensors: Vec<&Value> = header
+        .iter()
+        .filter(|(name, _)| *name != "__metadata__")
+        .map(|(_, tensor)| tensor)
+        .collect();
+    let [tensor] = tensors.as_slice() else {
+        return Err(format!("prompt_embeds must hold exactly one tensor, got {}", tensors.len()));
+    };
+
+    if tensor["dtype"].as_str() != Some("F32") {
+        return Err(format!("prompt_embeds dtype must be F32, got {}", tensor["dtype"]));
+    }
+    let expected = [1, EMBEDS_TOKENS, dim];
+    let shape: Option<Vec<u64>> = tensor["shape"]
+        .as_array()
+        .map(|dims| dims.iter().filter_map(Value::as_u64).collect());
+    if shape.as_deref() != Some(expected.as_slice()) {
+        return Err(format!(
+            "prompt_embeds shape must be {:?}, got {}",
+            expected, tensor["shape"]
+        ));
+    }
+
+    let offsets: Option<Vec<usize>> = tensor["data_offsets"]
+        .as_array()
+        .map(|offsets| offsets.iter().filter_map(|o| o.as_u64()?.try_into().ok()).collect());
+    let data = match offsets.as_deref() {
+        Some(&[start, end]) => bytes[data_start..].get(start..end),
+        _ => None,
+    };
+    let expected_len = (EMBEDS_TOKENS * dim) as usize * std::mem::size_of::<f32>();
+    let data = data
+        .filter(|data| data.len() == expected_len)
+        .ok_or_else(|| "prompt_embeds data_offsets don't match its shape".to_string())?;
+
+    let values: Vec<f32> = data
+        .chunks_exact(4)
+        .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+        .collect();
+    if values.iter().any(|value| !value.is_finite()) {
+        return Err("prompt_embeds contains NaN or infinite values".to_string());
+    }
+
+    Ok(values)
+}
@@ -0,0 +1,153 @@
+/// Prompt editing over the denoising process. Each entry is
+/// `(fraction_of_steps, prompt)`: from that fraction of the steps on, the
+/// conditioning switches to `prompt`. Before the first entry the request's
+/// own prompt is used.
+pub type PromptSchedule = Vec<(f64, String)>;
+
+enum Segment<'a> {
+    Text(&'a str),
+    /// `[from:to:at]`
+    Edit { from: &'a str, to: &'a str, at: f64 },
+}
+
+/// Expands inline `[from:to:at]` edits into a schedule, one entry per
+/// distinct switch point (plus 0.0). Text around the brackets is shared by
+/// every entry. Brackets that aren't a well-formed edit are kept as text.
+/// `None` when the prompt contains no edits.
+pub fn parse_inline(prompt: &str) -> Option<PromptSchedule> {
+    let segments = segments(prompt);
+
+    let mut fractions = vec![0.0];
+    for segment in &segments {
+        if let Segment::Edit { at, .. } = segment {
+            fractions.push(*at);
+        }
+    }
+    if fractions.len() == 1 {
+        return None;
+    }
+    fractions.sort_by(f64::total_cmp);
+    fractions.dedup();
+
+    let schedule = fractions
+        .into_iter()
+        .map(|fraction| {
+            let prompt: String = segments
+                .iter()
+                .map(|segment| match segment {
+                    Segment::Text(text) => *text,
+                    Segment::Edit { from, to, at } => if fraction >= *at { to } else { from },
+                })
+                .collect();
+            (fraction, prompt)
+        })
+        .collect();
+
+    Some(schedule)
+}
+
+/// The prompt with each inline `[from:to:at]` edit replaced by its `from`
+/// text, as encoded before the first switch
+pub fn strip_inline(prompt: &str) -> String {
+    segments(prompt)
+        .into_iter()
+        .map(|segment| match segment {
+            Segment::Text(text) => text,
+            Segment::Edit { from, .. } => from,
+        })
+        .collect()
+}
+
+fn segments(prompt: &str) -> Vec<Segment<'_>> {
+    let mut segments = Vec::new();
+    let mut rest = prompt;
+
+    while let Some(open) = rest.find('[') {
+        let Some(close) = rest[open..].find(']').map(|i| open + i) else {
+            break;
+        };
+        match parse_edit(&rest[open + 1..close]) {
+            Some(edit) => {
+                segments.push(Segment::Text(&rest[..open]));
+                segments.push(edit);
+            }
+            None => segments.push(Segment::Text(&rest[..=close])),
+        }
+        rest = &rest[close + 1..];
+    }
+    segments.push(Segment::Text(rest));
+
+    segments
+}
+
+fn parse_edit(inner: &str) -> Option<Segment<'_>> {
+    let mut parts = inner.split(':');
+    let (from, to, at) = (parts.next()?, parts.next()?, parts.next()?);
+    if parts.next().is_some() {
+        return None;
+    }
+    let at = at.trim().parse().ok()?;
+    Some(Segment::Edit { from, to, at })
+}
+
+/// Fractions must be within [0, 1] and strictly increasing, and every
+/// prompt non-empty
+pub fn validate(schedule: &[(f64, String)]) -> Result<(), String> {
+    let mut previous: Option<f64> = None;
+    for (fraction, prompt) in schedule {
+        if !(0.0..=1.0).contains(fraction) {
+            return Err(format!("prompt_schedule fraction {} is outside [0, 1]", fraction));
+        }
+        if previous.map_or(false, |previous| *fraction <= previous) {
+            return Err("prompt_schedule fractions must be sorted and distinct".to_string());
+        }
+        if prompt.trim().is_empty() {
+            return Err(format!("prompt_schedule prompt at {} is empty", fraction));
+        }
+        previous = Some(*fraction);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inline_edits_expand_into_a_schedule() {
+        let schedule = parse_inline("a [cat:dog:0.5] on a [sofa:bench:0.25]").unwrap();
+        assert_eq!(schedule, vec![
+            (0.0, "a cat on a sofa".to_string()),
+            (0.25, "a cat on a bench".to_string()),
+            (0.5, "a dog on a bench".to_string()),
+        ]);
+        assert!(validate(&schedule).is_ok());
+
+        assert_eq!(parse_inline("a lighthouse"), None);
+        // Malformed brackets stay as text
+        assert_eq!(parse_inline("a [cat:dog] at [dusk]"), None);
+        assert_eq!(parse_inline("[a:b:c]"), None);
+    }
+
+    #[test]
+    fn stripped_prompt_keeps_the_starting_text() {
+        assert_eq!(strip_inline("a [cat:dog:0.5] on a sofa"), "a cat on a sofa");
+        assert_eq!(strip_inline("a [cat:dog] [x:y:0.1]"), "a [cat:dog] x");
+        assert_eq!(strip_inline("a lighthouse"), "a lighthouse");
+    }
+
+    #[test]
+    fn schedule_must_be_sorted_and_in_range() {
+        let schedule = |entries: &[(f64, &str)]| -> PromptSchedule {
+            entries.iter().map(|(fraction, prompt)| (*fraction, prompt.to_string())).collect()
+        };
+        assert!(validate(&schedule(&[(0.0, "cat"), (0.5, "dog")])).is_ok());
+        assert!(validate(&[]).is_ok());
+
+        assert!(validate(&schedule(&[(0.5, "dog"), (0.2, "cat")])).unwrap_err().contains("sorted"));
+        assert!(validate(&schedule(&[(0.5, "dog"), (0.5, "cat")])).unwrap_err().contains("sorted"));
+        assert!(validate(&schedule(&[(1.5, "dog")])).unwrap_err().contains("outside"));
+        assert!(validate(&schedule(&[(-0.1, "dog")])).unwrap_err().contains("outside"));
+        assert!(validate(&schedule(&[(0.5, " ")])).unwrap_err().contains("empty"));
+    }
+}
@@ -0,0 +1,71 @@
+use crate::inference::scheduler::SchedulerKind;
+use std::fmt;
+use std::str::FromStr;
+
+/// Bundles of steps / guidance / scheduler for clients that don't want to
+/// tune each knob. Explicitly set fields take precedence over the preset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QualityPreset {
+    Draft,
+    Standard,
+    High,
+}
+
+impl QualityPreset {
+    pub const ALL: [QualityPreset; 3] = [
+        QualityPreset::Draft,
+        QualityPreset::Standard,
+        QualityPreset::High,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QualityPreset::Draft => "draft",
+            QualityPreset::Standard => "standard",
+            QualityPreset::High => "high",
+        }
+    }
+
+    pub fn steps(&self) -> i32 {
+        match self {
+            QualityPreset::Draft => 15,
+            QualityPreset::Standard => 30,
+            QualityPreset::High => 75,
+        }
+    }
+
+    pub fn guidance_scale(&self) -> f64 {
+        match self {
+            QualityPreset::Draft => 6.0,
+            QualityPreset::Standard => 7.5,
+            QualityPreset::High => 8.0,
+        }
+    }
+
+    pub fn scheduler(&self) -> SchedulerKind {
+        match self {
+            QualityPreset::Draft => SchedulerKind::EulerAncestral,
+            QualityPreset::Standard => SchedulerKind::DpmSolver,
+            QualityPreset::High => SchedulerKind::Ddim,
+        }
+    }
+}
+
+impl FromStr for QualityPreset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.to_ascii_lowercase();
+        QualityPreset::ALL
+            .iter()
+            .find(|preset| preset.as_str() == name)
+            .copied()
+            .ok_or_else(|| format!("Unknown quality '{}' (expected draft, standard or high)", s))
+    }
+}
+
+impl fmt::Display for QualityPreset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
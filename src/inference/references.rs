@@ -0,0 +1,128 @@
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat};
+use std::io::Cursor;
+
+/// Style / content reference images, each `(encoded image, weight)`. Their
+/// image embeddings condition generation alongside the prompt
+/// (IP-Adapter style), scaled by weight.
+pub type ReferenceImages = Vec<(Vec<u8>, f64)>;
+
+pub const MAX_REFERENCE_IMAGES: usize = 4;
+pub const MAX_WEIGHT: f64 = 1.0;
+/// Largest width or height of a reference image
+pub const MAX_REFERENCE_DIMENSION: u32 = 4096;
+/// Input size of the CLIP image encoder
+pub const ENCODER_SIZE: u32 = 224;
+
+/// A reference the generation was conditioned on, as reported in the metadata
+#[derive(Debug, Clone, PartialEq)]
+pub struct AppliedReference {
+    pub index: usize,
+    /// Size of the image as uploaded
+    pub width: u32,
+    pub height: u32,
+    pub weight: f64,
+}
+
+/// At most `MAX_REFERENCE_IMAGES` non-empty entries, each with a weight in
+/// (0, `MAX_WEIGHT`]. The images themselves are checked by `decode`.
+pub fn validate<B: AsRef<[u8]>>(references: &[(B, f64)]) -> Result<(), String> {
+    if references.len() > MAX_REFERENCE_IMAGES {
+        return Err(format!(
+            "At most {} reference_images are allowed, got {}",
+            MAX_REFERENCE_IMAGES,
+            references.len()
+        ));
+    }
+    for (index, (bytes, weight)) in references.iter().enumerate() {
+        if bytes.as_ref().is_empty() {
+            return Err(format!("reference_images[{}] is empty", index));
+        }
+        if !(*weight > 0.0 && *weight <= MAX_WEIGHT) {
+            return Err(format!(
+                "reference_images[{}] weight must be in (0, {}], got {}",
+                index, MAX_WEIGHT, weight
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Decodes a PNG, JPEG or WebP reference and resizes it to the image
+/// encoder's input, keeping the aspect ratio by center cropping
+pub fn decode(index: usize, bytes: &[u8]) -> Result<(DynamicImage, (u32, u32)), String> {
+    let format = image::guess_format(bytes)
+        .map_err(|e| format!("reference_images[{}]: {}", index, e))?;
+    if !matches!(format, ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::WebP) {
+        return Err(format!(
+            "reference_images[{}] is {:?}; expected PNG, JPEG or WebP",
+            index, format
+        ));
+    }
+
+    // The size comes from the header, so an oversized image is rejected
+    // before its pixels are allocated
+    let reader = || image::io::Reader::with_format(Cursor::new(bytes), format);
+    let size = reader()
+        .into_dimensions()
+        .map_err(|e| format!("Invalid reference_images[{}]: {}", index, e))?;
+    if size.0 > MAX_REFERENCE_DIMENSION || size.1 > MAX_REFERENCE_DIMENSION {
+        return Err(format!(
+            "reference_images[{}] is {}x{}; at most {}x{} is allowed",
+            index, size.0, size.1, MAX_REFERENCE_DIMENSION, MAX_REFERENCE_DIMENSION
+        ));
+    }
+    let image = reader()
+        .decode()
+        .map_err(|e| format!("Invalid reference_images[{}]: {}", index, e))?;
+
+    Ok((image.resize_to_fill(ENCODER_SIZE, ENCODER_SIZE, FilterType::Lanczos3), size))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encoded(width: u32, height: u32, format: image::ImageOutputFormat) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        DynamicImage::new_rgb8(width, height)
+            .write_to(&mut Cursor::new(&mut bytes), format)
+            .unwrap();
+        bytes
+    }
+
+    #[test]
+    fn references_decode_to_the_encoder_size() {
+        let png = encoded(640, 320, image::ImageOutputFormat::Png);
+        let (image, size) = decode(0, &png).unwrap();
+        assert_eq!(size, (640, 320));
+        assert_eq!((image.width(), image.height()), (ENCODER_SIZE, ENCODER_SIZE));
+
+        let gif = encoded(8, 8, image::ImageOutputFormat::Gif);
+        assert!(decode(1, &gif).unwrap_err().contains("expected PNG, JPEG or WebP"));
+        assert!(decode(2, b"not an image").is_err());
+    }
+
+    #[test]
+    fn oversized_reference_is_rejected_from_its_header() {
+        // A header claiming 5000x10 with no pixel data behind it: only the
+        // header is read, so the size check fails rather than the decode
+        let mut png = encoded(1, 1, image::ImageOutputFormat::Png);
+        png[16..20].copy_from_slice(&5000u32.to_be_bytes());
+        png[20..24].copy_from_slice(&10u32.to_be_bytes());
+        let crc = crate::inference::postprocess::crc32(&png[12..29]);
+        png[29..33].copy_from_slice(&crc.to_be_bytes());
+        let error = decode(3, &png).unwrap_err();
+        assert!(error.contains("5000x10"), "{}", error);
+    }
+
+    #[test]
+    fn counts_and_weights_are_validated() {
+        let image = vec![1u8];
+        assert!(validate(&[(&image, 0.5)]).is_ok());
+        assert!(validate(&[(&image, 0.0)]).is_err());
+        assert!(validate(&[(&image, MAX_WEIGHT + 0.1)]).is_err());
+        assert!(validate(&[(Vec::<u8>::new(), 0.5)]).is_err());
+        assert!(validate(&vec![(&image, 0.5); MAX_REFERENCE_IMAGES + 1]).is_err());
+    }
+}
@@ -0,0 +1,259 @@
+use crate::config::NoiseScheduleConfig;
+use std::fmt;
+use std::str::FromStr;
+
+/// Timesteps the Stable Diffusion checkpoints were trained with
+pub const TRAIN_TIMESTEPS: usize = 1000;
+
+/// Noise schedulers (samplers) the pipeline can run with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchedulerKind {
+    Ddim,
+    Pndm,
+    Lms,
+    Euler,
+    EulerAncestral,
+    DpmSolver,
+}
+
+impl SchedulerKind {
+    pub const ALL: [SchedulerKind; 6] = [
+        SchedulerKind::Ddim,
+        SchedulerKind::Pndm,
+        SchedulerKind::Lms,
+        SchedulerKind::Euler,
+        SchedulerKind::EulerAncestral,
+        SchedulerKind::DpmSolver,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SchedulerKind::Ddim => "ddim",
+            SchedulerKind::Pndm => "pndm",
+            SchedulerKind::Lms => "lms",
+            SchedulerKind::Euler => "euler",
+            SchedulerKind::EulerAncestral => "euler_ancestral",
+            SchedulerKind::DpmSolver => "dpm_solver",
+        }
+    }
+
+    /// Guidance scales the sampler gives usable results with. Ancestral and
+    /// multistep samplers wash out at low guidance.
+    pub fn guidance_range(&self) -> (f64, f64) {
+        match self {
+            SchedulerKind::EulerAncestral => (2.0, 20.0),
+            SchedulerKind::DpmSolver => (1.5, 20.0),
+            _ => (1.0, 20.0),
+        }
+    }
+}
+
+impl FromStr for SchedulerKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.to_ascii_lowercase();
+        SchedulerKind::ALL
+            .iter()
+            .find(|kind| kind.as_str() == name)
+            .copied()
+            .ok_or_else(|| {
+                let names: Vec<&str> = SchedulerKind::ALL.iter().map(|k| k.as_str()).collect();
+                format!("Unknown scheduler '{}' (expected one of {})", s, names.join(", "))
+            })
+    }
+}
+
+impl fmt::Display for SchedulerKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// How betas are spaced between `beta_start` and `beta_end`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BetaSchedule {
+    Linear,
+    ScaledLinear,
+    /// Cosine schedule ("squaredcos_cap_v2"); ignores beta_start / beta_end
+    SquaredCos,
+}
+
+impl BetaSchedule {
+    pub const ALL: [BetaSchedule; 3] = [
+        BetaSchedule::Linear,
+        BetaSchedule::ScaledLinear,
+        BetaSchedule::SquaredCos,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BetaSchedule::Linear => "linear",
+            BetaSchedule::ScaledLinear => "scaled_linear",
+            BetaSchedule::SquaredCos => "squaredcos",
+        }
+    }
+}
+
+impl FromStr for BetaSchedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.to_ascii_lowercase();
+        BetaSchedule::ALL
+            .iter()
+            .find(|schedule| schedule.as_str() == name)
+            .copied()
+            .ok_or_else(|| {
+                let names: Vec<&str> = BetaSchedule::ALL.iter().map(|s| s.as_str()).collect();
+                format!("Unknown beta_schedule '{}' (expected one of {})", s, names.join(", "))
+            })
+    }
+}
+
+impl fmt::Display for BetaSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Validated noise schedule parameters
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NoiseSchedule {
+    pub beta_start: f64,
+    pub beta_end: f64,
+    pub beta_schedule: BetaSchedule,
+}
+
+impl NoiseSchedule {
+    pub fn from_config(config: &NoiseScheduleConfig) -> Result<Self, String> {
+        let beta_schedule = config.beta_schedule.parse::<BetaSchedule>()?;
+
+        if !(config.beta_start > 0.0 && config.beta_start < config.beta_end && config.beta_end < 1.0) {
+            return Err(format!(
+                "Noise schedule needs 0 < beta_start < beta_end < 1, got {} and {}",
+                config.beta_start, config.beta_end
+            ));
+        }
+
+        Ok(Self {
+            beta_start: config.beta_start,
+            beta_end: config.beta_end,
+            beta_schedule,
+        })
+    }
+
+    /// Per-timestep betas over the training timesteps
+    pub fn betas(&self, train_timesteps: usize) -> Vec<f64> {
+        let n = train_timesteps.max(1);
+        let fraction = |i: usize| if n > 1 { i as f64 / (n - 1) as f64 } else { 0.0 };
+
+        match self.beta_schedule {
+            BetaSchedule::Linear => (0..n)
+                .map(|i| self.beta_start + (self.beta_end - self.beta_start) * fraction(i))
+                .collect(),
+            BetaSchedule::ScaledLinear => {
+                let (start, end) = (self.beta_start.sqrt(), self.beta_end.sqrt());
+                (0..n)
+                    .map(|i| (start + (end - start) * fraction(i)).powi(2))
+                    .collect()
+            }
+            BetaSchedule::SquaredCos => {
+                let alpha_bar = |t: f64| ((t + 0.008) / 1.008 * std::f64::consts::FRAC_PI_2).cos().powi(2);
+                (0..n)
+                    .map(|i| {
+                        let (t1, t2) = (i as f64 / n as f64, (i + 1) as f64 / n as f64);
+                        (1.0 - alpha_bar(t2) / alpha_bar(t1)).min(0.999)
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+/// Everything needed to construct a scheduler for one generation
+#[derive(Debug, Clone)]
+pub struct SchedulerConfig {
+    pub kind: SchedulerKind,
+    pub noise_schedule: NoiseSchedule,
+    pub num_inference_steps: i32,
+}
+
+impl SchedulerConfig {
+    /// Noise level (sigma) at each inference step, highest first. Steps are
+    /// spread evenly over the training timesteps, ending at timestep 0.
+    pub fn sigmas(&self) -> Vec<f64> {
+        let mut alpha_cumprod = 1.0;
+        let sigmas: Vec<f64> = self.noise_schedule
+            .betas(TRAIN_TIMESTEPS)
+            .into_iter()
+            .map(|beta| {
+                alpha_cumprod *= 1.0 - beta;
+                ((1.0 - alpha_cumprod) / alpha_cumprod).sqrt()
+            })
+            .collect();
+
+        let steps = self.num_inference_steps.max(1) as usize;
+        let stride = TRAIN_TIMESTEPS as f64 / steps as f64;
+        (0..steps)
+            .rev()
+            .map(|step| sigmas[((step as f64 * stride) as usize).min(TRAIN_TIMESTEPS - 1)])
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedule(beta_start: f64, beta_end: f64, beta_schedule: &str) -> Result<NoiseSchedule, String> {
+        NoiseSchedule::from_config(&NoiseScheduleConfig {
+            beta_start,
+            beta_end,
+            beta_schedule: beta_schedule.to_string(),
+        })
+    }
+
+    #[test]
+    fn schedule_names_and_values_are_validated() {
+        for (name, kind) in [
+            ("linear", BetaSchedule::Linear),
+            ("scaled_linear", BetaSchedule::ScaledLinear),
+            ("squaredcos", BetaSchedule::SquaredCos),
+        ] {
+            assert_eq!(schedule(0.00085, 0.012, name).unwrap().beta_schedule, kind);
+        }
+
+        let error = schedule(0.00085, 0.012, "quadratic").unwrap_err();
+        assert!(error.contains("linear, scaled_linear, squaredcos"), "{}", error);
+        assert!(schedule(0.012, 0.00085, "linear").is_err());
+        assert!(schedule(0.0, 0.012, "linear").is_err());
+        assert!(schedule(0.00085, 1.0, "linear").is_err());
+    }
+
+    #[test]
+    fn betas_span_start_to_end() {
+        let betas = schedule(0.0001, 0.02, "linear").unwrap().betas(1000);
+        assert_eq!(betas.len(), 1000);
+        assert!((betas[0] - 0.0001).abs() < 1e-12 && (betas[999] - 0.02).abs() < 1e-12);
+        assert!(betas.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn sigmas_have_one_entry_per_step_and_decrease() {
+        for name in ["linear", "scaled_linear", "squaredcos"] {
+            for steps in [1, 4, 30, 150] {
+                let sigmas = SchedulerConfig {
+                    kind: SchedulerKind::Euler,
+                    noise_schedule: schedule(0.00085, 0.012, name).unwrap(),
+                    num_inference_steps: steps,
+                }
+                .sigmas();
+
+                assert_eq!(sigmas.len(), steps as usize);
+                assert!(sigmas.iter().all(|sigma| *sigma > 0.0));
+                assert!(sigmas.windows(2).all(|pair| pair[0] > pair[1]), "{} at {} steps: {:?}", name, steps, sigmas);
+            }
+        }
+    }
+}
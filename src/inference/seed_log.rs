@@ -0,0 +1,110 @@
+use crate::inference::pipeline::{GenerationParams, GenerationResult};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tracing::warn;
+
+/// Reproducibility ledger: one JSON line per successful generation with
+/// every resolved parameter and seed, appended to `seeds-YYYY-MM-DD.jsonl`
+/// (UTC) under the configured directory. Unlike logs it is never sampled or
+/// redacted, so any image can be regenerated from its line.
+#[derive(Debug)]
+pub struct SeedLog {
+    dir: Option<PathBuf>,
+    /// Keeps concurrent lines from interleaving
+    write: Mutex<()>,
+}
+
+impl SeedLog {
+    /// Without a directory nothing is written
+    pub fn new(dir: Option<PathBuf>) -> Self {
+        Self { dir, write: Mutex::new(()) }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.dir.is_some()
+    }
+
+    /// Appends the entry for `job_id`. A failed write is logged, never
+    /// failing the generation.
+    pub async fn append(&self, job_id: &str, params: &GenerationParams, result: &GenerationResult) {
+        let Some(dir) = &self.dir else {
+            return;
+        };
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let mut line = entry(job_id, now.as_millis() as u64, params, result).to_string();
+        line.push('\n');
+        let path = dir.join(format!("seeds-{}.jsonl", utc_date(now.as_secs())));
+
+        let _guard = self.write.lock().await;
+        let result = async {
+            tokio::fs::create_dir_all(dir).await?;
+            let mut file = tokio::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .await?;
+            file.write_all(line.as_bytes()).await
+        }
+        .await;
+        if let Err(e) = result {
+            warn!("Failed to append to seed log {}: {}", path.display(), e);
+        }
+    }
+}
+
+fn entry(job_id: &str, timestamp: u64, params: &GenerationParams, result: &GenerationResult) -> serde_json::Value {
+    serde_json::json!({
+        "job_id": job_id,
+        "timestamp": timestamp,
+        "model": result.model_used,
+        "seed": result.seed,
+        "image_seeds": result.results.iter().map(|r| r.seed).collect::<Vec<_>>(),
+        "subseed": result.subseed,
+        "subseed_strength": result.subseed_strength,
+        "prompt": result.final_prompt,
+        "prompt_embeds": result.prompt_embeds,
+        "negative_prompt": result.final_negative_prompt,
+        "negative_prompts": result.negative_prompts,
+        "prompt_schedule": result.prompt_schedule,
+        "steps": result.steps_taken,
+        "guidance_scale": result.guidance_scale,
+        "width": result.width,
+        "height": result.height,
+        "strength": result.strength,
+        "temperature": result.temperature,
+        "clip_skip": result.clip_skip,
+        "scheduler": result.scheduler.as_str(),
+        "beta_start": result.noise_schedule.beta_start,
+        "beta_end": result.noise_schedule.beta_end,
+        "beta_schedule": result.noise_schedule.beta_schedule.as_str(),
+        "precision": result.precision.to_string(),
+        "format": result.format.to_string(),
+        "face_restore_weight": result.face_restore_weight,
+        "color_palette": result.color_palette,
+        "reference_weights": result.references.iter().map(|r| r.weight).collect::<Vec<_>>(),
+        "init_image": params.init_image.is_some(),
+        "vary_region": result.vary_region.map(|region| region.bounds),
+        "upscale": params.upscale,
+        "border_width": params.border_width,
+        "border_color": params.border_color,
+        "transparent_background": params.transparent_background,
+    })
+}
+
+/// `YYYY-MM-DD` for seconds since the Unix epoch
+fn utc_date(secs: u64) -> String {
+    // Civil-from-days, after Howard Hinnant
+    let days = (secs / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
@@ -0,0 +1,53 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Named img2img strengths, selectable instead of a numeric `strength`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StrengthPreset {
+    Subtle,
+    Balanced,
+    Creative,
+}
+
+impl StrengthPreset {
+    pub const ALL: [StrengthPreset; 3] = [
+        StrengthPreset::Subtle,
+        StrengthPreset::Balanced,
+        StrengthPreset::Creative,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StrengthPreset::Subtle => "subtle",
+            StrengthPreset::Balanced => "balanced",
+            StrengthPreset::Creative => "creative",
+        }
+    }
+
+    pub fn strength(&self) -> f64 {
+        match self {
+            StrengthPreset::Subtle => 0.25,
+            StrengthPreset::Balanced => 0.5,
+            StrengthPreset::Creative => 0.8,
+        }
+    }
+}
+
+impl FromStr for StrengthPreset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.to_ascii_lowercase();
+        StrengthPreset::ALL
+            .iter()
+            .find(|preset| preset.as_str() == name)
+            .copied()
+            .ok_or_else(|| format!("Unknown strength_preset '{}' (expected subtle, balanced or creative)", s))
+    }
+}
+
+impl fmt::Display for StrengthPreset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
@@ -0,0 +1,20 @@
+//! Prompt token counting for budget checks
+
+/// Start and end-of-text tokens the text encoder adds to every prompt
+const SPECIAL_TOKENS: usize = 2;
+
+/// Approximates the CLIP token count of a prompt: one token per word and
+/// per punctuation character, plus the start / end tokens.
+// TODO: Count with the model's actual BPE tokenizer
+pub fn estimate_tokens(text: &str) -> usize {
+    let words = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .count();
+    let punctuation = text
+        .chars()
+        .filter(|c| !c.is_alphanumeric() && !c.is_whitespace())
+        .count();
+
+    words + punctuation + SPECIAL_TOKENS
+}
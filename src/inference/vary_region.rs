@@ -0,0 +1,121 @@
+use image::{DynamicImage, GrayImage, ImageBuffer, Pixel};
+use serde::Serialize;
+use std::io::Cursor;
+
+/// Mask values at or above this are regenerated
+const MASK_THRESHOLD: u8 = 128;
+
+/// A prior output and a mask of the region to regenerate. White mask pixels
+/// are regenerated with the request's seed; everything else is kept.
+#[derive(Debug, Clone, Serialize)]
+pub struct VaryRegion {
+    /// Encoded prior image; the output has its size
+    pub image: Vec<u8>,
+    /// Encoded mask of the same size
+    pub mask: Vec<u8>,
+}
+
+/// Regions of a vary-region generation, as reported in the metadata
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AppliedVaryRegion {
+    /// Bounding box `(x, y, width, height)` of the regenerated pixels
+    pub bounds: (u32, u32, u32, u32),
+    pub regenerated_pixels: u64,
+    pub preserved_pixels: u64,
+}
+
+/// Size of the prior image, read from its header only
+pub fn dimensions(image: &[u8]) -> Result<(u32, u32), String> {
+    header_dimensions(image).map_err(|e| format!("Invalid vary_region image: {}", e))
+}
+
+fn header_dimensions(bytes: &[u8]) -> image::ImageResult<(u32, u32)> {
+    image::io::Reader::new(Cursor::new(bytes))
+        .with_guessed_format()?
+        .into_dimensions()
+}
+
+/// Decodes the prior image and its mask, which must be the same size and
+/// select at least one pixel. The mask's size is checked from its header
+/// before decoding, since only the prior's is checked against the decode
+/// limit.
+pub fn decode(region: &VaryRegion) -> Result<(DynamicImage, GrayImage, AppliedVaryRegion), String> {
+    let (width, height) = dimensions(&region.image)?;
+    let (mask_width, mask_height) = header_dimensions(&region.mask)
+        .map_err(|e| format!("Invalid vary_region mask: {}", e))?;
+    if (mask_width, mask_height) != (width, height) {
+        return Err(format!(
+            "vary_region mask is {}x{}, but the image is {}x{}",
+            mask_width, mask_height, width, height
+        ));
+    }
+
+    let image = image::load_from_memory(&region.image)
+        .map_err(|e| format!("Invalid vary_region image: {}", e))?;
+    // Kept as they are, so they must be something PNG can hold
+    if matches!(image, DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_)) {
+        return Err("vary_region image must have 8 or 16 bits per channel".to_string());
+    }
+    let mask = image::load_from_memory(&region.mask)
+        .map_err(|e| format!("Invalid vary_region mask: {}", e))?
+        .to_luma8();
+
+    let applied = measure(&mask).ok_or_else(|| "vary_region mask selects no pixels".to_string())?;
+    Ok((image, mask, applied))
+}
+
+/// Bounding box and pixel counts of the masked region; `None` when empty
+fn measure(mask: &GrayImage) -> Option<AppliedVaryRegion> {
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (u32::MAX, u32::MAX, 0, 0);
+    let mut regenerated = 0u64;
+    for (x, y, pixel) in mask.enumerate_pixels() {
+        if pixel[0] >= MASK_THRESHOLD {
+            regenerated += 1;
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+        }
+    }
+    if regenerated == 0 {
+        return None;
+    }
+
+    let total = mask.width() as u64 * mask.height() as u64;
+    Some(AppliedVaryRegion {
+        bounds: (min_x, min_y, max_x - min_x + 1, max_y - min_y + 1),
+        regenerated_pixels: regenerated,
+        preserved_pixels: total - regenerated,
+    })
+}
+
+/// `generated` inside the mask, `prior` outside it, in the prior's color
+/// type. Outside pixels are copied, not blended, so they are identical to
+/// the prior's, alpha and bit depth included.
+pub fn composite(prior: &DynamicImage, generated: DynamicImage, mask: &GrayImage) -> DynamicImage {
+    match prior {
+        DynamicImage::ImageLuma8(prior) => DynamicImage::ImageLuma8(paste(prior, &generated.to_luma8(), mask)),
+        DynamicImage::ImageLumaA8(prior) => DynamicImage::ImageLumaA8(paste(prior, &generated.to_luma_alpha8(), mask)),
+        DynamicImage::ImageRgba8(prior) => DynamicImage::ImageRgba8(paste(prior, &generated.to_rgba8(), mask)),
+        DynamicImage::ImageLuma16(prior) => DynamicImage::ImageLuma16(paste(prior, &generated.to_luma16(), mask)),
+        DynamicImage::ImageLumaA16(prior) => DynamicImage::ImageLumaA16(paste(prior, &generated.to_luma_alpha16(), mask)),
+        DynamicImage::ImageRgb16(prior) => DynamicImage::ImageRgb16(paste(prior, &generated.to_rgb16(), mask)),
+        DynamicImage::ImageRgba16(prior) => DynamicImage::ImageRgba16(paste(prior, &generated.to_rgba16(), mask)),
+        // 8-bit RGB, and anything `decode` turns away
+        prior => DynamicImage::ImageRgb8(paste(&prior.to_rgb8(), &generated.to_rgb8(), mask)),
+    }
+}
+
+fn paste<P: Pixel>(
+    prior: &ImageBuffer<P, Vec<P::Subpixel>>,
+    generated: &ImageBuffer<P, Vec<P::Subpixel>>,
+    mask: &GrayImage,
+) -> ImageBuffer<P, Vec<P::Subpixel>> {
+    let mut output = prior.clone();
+    for ((out, new), selected) in output.pixels_mut().zip(generated.pixels()).zip(mask.pixels()) {
+        if selected[0] >= MASK_THRESHOLD {
+            *out = *new;
+        }
+    }
+    output
+}
@@ -0,0 +1,119 @@
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id};
+use tracing::{Metadata, Subscriber};
+use tracing_subscriber::filter::{dynamic_filter_fn, EnvFilter, FilterExt};
+use tracing_subscriber::layer::{Context, Filter, Layer, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::fmt;
+
+const DEFAULT_FILTER: &str = "diffusion_server=debug,info";
+
+/// Span field that opts a request into verbose logging
+pub const DEBUG_FIELD: &str = "debug";
+
+/// Marker stored on spans created with `debug = true`
+struct DebugRequest;
+
+/// Tags spans whose `debug` field is true, so the filter below can find them
+struct DebugRequestLayer;
+
+impl<S> Layer<S> for DebugRequestLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut visitor = DebugFieldVisitor(false);
+        attrs.record(&mut visitor);
+        if visitor.0 {
+            if let Some(span) = ctx.span(id) {
+                span.extensions_mut().insert(DebugRequest);
+            }
+        }
+    }
+}
+
+struct DebugFieldVisitor(bool);
+
+impl Visit for DebugFieldVisitor {
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        if field.name() == DEBUG_FIELD {
+            self.0 = value;
+        }
+    }
+
+    fn record_debug(&mut self, _field: &Field, _value: &dyn std::fmt::Debug) {}
+}
+
+/// True for this crate's events emitted inside a debug-flagged span
+fn in_debug_request<S>(meta: &Metadata<'_>, cx: &Context<'_, S>) -> bool
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    meta.target().starts_with(env!("CARGO_CRATE_NAME"))
+        && cx.lookup_current().map_or(false, |span| {
+            span.scope().any(|span| span.extensions().get::<DebugRequest>().is_some())
+        })
+}
+
+/// `DEFAULT_FILTER`, widened to trace inside debug-flagged spans
+fn filter<S>() -> impl Filter<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    EnvFilter::new(DEFAULT_FILTER).or(dynamic_filter_fn(in_debug_request))
+}
+
+/// Installs the global subscriber. Output follows `DEFAULT_FILTER`, except
+/// that everything down to trace is let through inside spans created with
+/// `debug = true`, so a single request can be inspected without raising the
+/// global level.
+pub fn init() {
+    tracing_subscriber::registry()
+        .with(DebugRequestLayer)
+        .with(fmt::layer().with_filter(filter()))
+        .init();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inference::pipeline::tests::{mock_config, mock_pipeline, params};
+    use crate::inference::GenerationParams;
+    use std::sync::{Arc, Mutex};
+    use tracing::{Event, Level};
+
+    /// Records the level of every event that gets through its filter
+    struct Capture(Arc<Mutex<Vec<Level>>>);
+
+    impl<S: Subscriber> Layer<S> for Capture {
+        fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+            self.0.lock().unwrap().push(*event.metadata().level());
+        }
+    }
+
+    #[tokio::test]
+    async fn debug_requests_emit_trace_events() {
+        let levels = Arc::new(Mutex::new(Vec::new()));
+        let subscriber = tracing_subscriber::registry()
+            .with(DebugRequestLayer)
+            .with(Capture(Arc::clone(&levels)).with_filter(filter()));
+        let _default = tracing::subscriber::set_default(subscriber);
+
+        let mut config = mock_config();
+        let pipeline = mock_pipeline(&config);
+        let debug = GenerationParams { debug: true, ..params("a lighthouse") };
+        assert!(pipeline.generate(debug.clone()).await.is_err());
+
+        config.inference.allow_debug_requests = true;
+        let pipeline = mock_pipeline(&config);
+        let traces = || levels.lock().unwrap().iter().filter(|level| **level == Level::TRACE).count();
+
+        pipeline.generate(params("a lighthouse")).await.unwrap();
+        assert_eq!(traces(), 0);
+        assert!(!levels.lock().unwrap().is_empty());
+
+        pipeline.generate(debug).await.unwrap();
+        assert!(traces() > 0);
+    }
+}
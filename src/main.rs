@@ -1,29 +1,44 @@
 use anyhow::Result;
+use prost::Message;
 
 use tracing::{info, error};
-use tracing_subscriber;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
 mod config;
 mod errors;
 mod inference;
+mod logging;
 mod queue;
 mod server;
 
-use config::Config;
-use inference::pipeline::{InferencePipeline, GenerationParams};
+use config::{Config, QueueScheduling};
+use inference::conditioning_cache::ConditioningCache;
+use inference::pipeline::{InferencePipeline, GenerationParams, METADATA_VERSION};
+use inference::QualityPreset;
+use inference::vary_region::VaryRegion;
+use queue::batching::BatchWindow;
 use tch::Device;
 
 // Use the gRPC proto types directly to avoid type mismatch
 use server::grpc::proto as grpc_proto;
+use server::quota::QuotaTracker;
+
+type WorkerQueue = queue::memory::MemoryQueue<
+    grpc_proto::GenerateImageRequest,
+    grpc_proto::GenerateImageResponse,
+>;
+
+/// How often finished jobs beyond `queue.max_finished_jobs` are forgotten
+const FINISHED_JOB_SWEEP_INTERVAL: tokio::time::Duration = tokio::time::Duration::from_secs(60);
+/// How often queued jobs past their deadline are failed under EDF, so
+/// clients hear back even while every worker is busy
+const DEADLINE_SWEEP_INTERVAL: tokio::time::Duration = tokio::time::Duration::from_millis(500);
 
 #[tokio::main]
 async fn main() -> Result<()> {
     // Initialize logging
-    tracing_subscriber::fmt()
-        .with_env_filter("diffusion_server=debug,info")
-        .init();
+    logging::init();
 
     info!("🚀 Starting Diffusion Server");
 
@@ -41,6 +56,10 @@ async fn main() -> Result<()> {
 
     // Initialize device
     let device = match config.model.device.as_str() {
+        _ if config.model.mock_inference => {
+            info!("⚠ Mock inference enabled, generating placeholder images on CPU");
+            Device::Cpu
+        }
         "cpu" => {
             info!("Using CPU device");
             Device::Cpu
@@ -61,43 +80,209 @@ async fn main() -> Result<()> {
     };
 
     // Initialize inference pipeline
-    let pipeline = InferencePipeline::new(config.inference.clone(), device)?;
+    let pipeline = InferencePipeline::new(
+        config.inference.clone(),
+        &config.model,
+        &config.health,
+        device,
+    )?;
+    if config.model.warmup_on_start && config.model.mock_inference {
+        info!("Skipping warmup in mock mode");
+    } else if config.model.warmup_on_start {
+        info!("Warming up with {} generations per model", config.model.warmup_runs);
+        pipeline
+            .warmup(config.model.warmup_runs, config.model.warmup_size, config.model.warmup_steps)
+            .await?;
+    }
     let pipeline = Arc::new(pipeline);
 
     // Initialize job queue with gRPC proto types
-    let queue: queue::memory::MemoryQueue<
-        grpc_proto::GenerateImageRequest,
-        grpc_proto::GenerateImageResponse,
-    > = queue::memory::MemoryQueue::new(config.queue.max_queue_size);
+    let queue: WorkerQueue = queue::memory::MemoryQueue::new(config.queue.max_queue_size)
+        .with_event_buffer(config.queue.event_buffer)
+        .with_class_weights(
+            &config.queue.class_weights,
+            |req: &grpc_proto::GenerateImageRequest| req.priority.as_deref(),
+        );
+    let queue = match config.queue.scheduling {
+        QueueScheduling::Edf => queue.with_deadlines(
+            |req: &grpc_proto::GenerateImageRequest| {
+                req.deadline_ms.map(|ms| tokio::time::Duration::from_millis(ms.into()))
+            },
+            config.queue.default_deadline_ms.map(tokio::time::Duration::from_millis),
+        ),
+        QueueScheduling::Fifo => queue,
+    };
+    // With a single model every job would pin to one worker
+    let queue = if config.queue.affinity && config.model.models.len() > 1 {
+        queue.with_affinity(
+            |req: &grpc_proto::GenerateImageRequest| req.model_id.as_str(),
+            tokio::time::Duration::from_millis(config.queue.affinity_fallback_ms),
+        )
+    } else {
+        if config.queue.affinity {
+            info!("Ignoring queue.affinity with a single model");
+        }
+        queue
+    };
+    let queue = match &config.queue.persistence_path {
+        Some(path) => {
+            let queue = queue.with_journal(queue::QueueJournal::new(
+                path,
+                |req: &grpc_proto::GenerateImageRequest| req.encode_to_vec(),
+                |bytes| grpc_proto::GenerateImageRequest::decode(bytes).ok(),
+            ));
+            let recovered = queue.recover().await?;
+            if recovered > 0 {
+                info!("Recovered {} queued jobs from {}", recovered, path.display());
+            }
+            queue
+        }
+        None => queue,
+    };
+    let queue = match config.queue.overflow_backend.as_deref() {
+        None => queue,
+        Some("memory") => queue.with_overflow(config.queue.overflow_max_size, None),
+        Some("disk") => {
+            let path = config.queue.overflow_path
+                .as_ref()
+                .ok_or_else(|| anyhow::anyhow!("overflow_backend \"disk\" requires overflow_path"))?;
+            let queue = queue.with_overflow(
+                config.queue.overflow_max_size,
+                Some(queue::QueueJournal::new(
+                    path,
+                    |req: &grpc_proto::GenerateImageRequest| req.encode_to_vec(),
+                    |bytes| grpc_proto::GenerateImageRequest::decode(bytes).ok(),
+                )),
+            );
+            let recovered = queue.recover_overflow().await?;
+            if recovered > 0 {
+                info!("Recovered {} overflow jobs from {}", recovered, path.display());
+            }
+            queue
+        }
+        Some(other) => anyhow::bail!("Unknown overflow_backend '{}' (expected memory or disk)", other),
+    };
     let queue = Arc::new(queue);
 
     // Start worker threads
     info!("Starting {} worker threads", config.queue.worker_threads);
-    for worker_id in 0..config.queue.worker_threads {
+    let heartbeat_interval = config.queue.stuck_job_timeout_seconds
+        .map(|secs| tokio::time::Duration::from_secs((secs / 3).max(1)))
+        .unwrap_or(tokio::time::Duration::from_secs(5));
+    let workers = config.queue.worker_threads;
+    let batching = BatchWindow::from_config(&config.queue);
+    for worker_id in 0..workers {
         let pipeline = Arc::clone(&pipeline);
         let queue = Arc::clone(&queue);
 
         tokio::spawn(async move {
-            worker_loop(worker_id, pipeline, queue).await;
+            worker_loop(worker_id, pipeline, queue, heartbeat_interval, workers, batching).await;
         });
     }
 
+    // Watchdog for jobs whose worker panicked or hung
+    if let Some(timeout_secs) = config.queue.stuck_job_timeout_seconds {
+        let queue = Arc::clone(&queue);
+        let timeout = tokio::time::Duration::from_secs(timeout_secs);
+        let max_reenqueue = config.queue.max_reenqueue;
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(heartbeat_interval);
+            loop {
+                ticker.tick().await;
+                queue.reap_stuck(timeout, max_reenqueue).await;
+            }
+        });
+    }
+
+    if config.queue.scheduling == QueueScheduling::Edf {
+        let queue = Arc::clone(&queue);
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(DEADLINE_SWEEP_INTERVAL);
+            loop {
+                ticker.tick().await;
+                queue.expire_overdue().await;
+            }
+        });
+    }
+
+    // Forget old finished jobs so the job map doesn't grow without bound
+    {
+        let queue = Arc::clone(&queue);
+        let keep = config.queue.max_finished_jobs;
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(FINISHED_JOB_SWEEP_INTERVAL);
+            loop {
+                ticker.tick().await;
+                let evicted = queue.evict_finished(keep).await;
+                if evicted > 0 {
+                    info!("Evicted {} finished jobs", evicted);
+                }
+            }
+        });
+    }
+
+    // Unload models that sit idle so their memory can serve other workloads
+    if let Some(idle_secs) = config.model.unload_after_idle_seconds {
+        let pipeline = Arc::clone(&pipeline);
+        let idle = tokio::time::Duration::from_secs(idle_secs);
+        let check_interval = (idle / 4).max(tokio::time::Duration::from_secs(1));
+        info!("Unloading models after {}s idle", idle_secs);
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(check_interval);
+            loop {
+                ticker.tick().await;
+                pipeline.models().unload_idle(idle);
+            }
+        });
+    }
+
+    if !config.server.rest_enabled && !config.server.grpc_enabled {
+        anyhow::bail!("Both the REST and gRPC servers are disabled");
+    }
+    if let Some(routes) = &config.server.rest_routes {
+        server::rest::check_routes(routes)?;
+    }
+
+    // One daily quota per key across both servers
+    let quotas = Arc::new(QuotaTracker::new(
+        config.queue.key_quotas.clone(),
+        config.queue.default_key_quota,
+        config.queue.quota_action,
+    ));
+
     // Start REST API server in background
-    let rest_config = config.clone();
-    let rest_pipeline = (*pipeline).clone();
-    actix_web::rt::spawn(async move {
-        if let Err(e) = server::start_rest_server(rest_config, rest_pipeline).await {
-            error!("REST server error: {}", e);
-        }
-    });
+    if config.server.rest_enabled {
+        let rest_config = config.clone();
+        let rest_pipeline = (*pipeline).clone();
+        let rest_queue = (*queue).clone();
+        let rest_quotas = Arc::clone(&quotas);
+        actix_web::rt::spawn(async move {
+            if let Err(e) = server::start_rest_server(rest_config, rest_pipeline, rest_queue, rest_quotas).await {
+                error!("REST server error: {}", e);
+            }
+        });
+    } else {
+        info!("REST server disabled");
+    }
 
-    // Start gRPC server (blocking)
     info!("✓ Server initialization complete");
-    server::start_grpc_server(
-        config,
-        (*pipeline).clone(),
-        (*queue).clone(),
-    ).await?;
+
+    // Start gRPC server (blocking)
+    if config.server.grpc_enabled {
+        server::start_grpc_server(
+            config,
+            (*pipeline).clone(),
+            (*queue).clone(),
+            quotas,
+        ).await?;
+    } else {
+        info!("gRPC server disabled");
+        tokio::signal::ctrl_c().await?;
+    }
 
     Ok(())
 }
@@ -106,84 +291,506 @@ async fn main() -> Result<()> {
 async fn worker_loop(
     worker_id: usize,
     pipeline: Arc<InferencePipeline>,
-    queue: Arc<queue::memory::MemoryQueue<
-        grpc_proto::GenerateImageRequest,
-        grpc_proto::GenerateImageResponse,
-    >>,
+    queue: Arc<WorkerQueue>,
+    heartbeat_interval: tokio::time::Duration,
+    workers: usize,
+    batching: BatchWindow,
 ) {
     info!("Worker {} started", worker_id);
 
     loop {
-        let job = queue.dequeue().await;
+        let job = queue.dequeue_for(worker_id, workers).await;
 
         if let Some(job) = job {
-            info!("Worker {} processing job {}", worker_id, job.id);
-
-            // Convert request to generation params
-            let params = GenerationParams {
-                prompt: job.request.prompt.clone(),
-                negative_prompt: if job.request.negative_prompt.is_empty() {
-                    None
-                } else {
-                    Some(job.request.negative_prompt.clone())
-                },
-                num_inference_steps: if job.request.num_inference_steps > 0 {
-                    job.request.num_inference_steps
-                } else {
-                    50
-                },
-                guidance_scale: if job.request.guidance_scale > 0.0 {
-                    job.request.guidance_scale
-                } else {
-                    7.5
-                },
-                width: if job.request.width > 0 {
-                    job.request.width
-                } else {
-                    512
-                },
-                height: if job.request.height > 0 {
-                    job.request.height
-                } else {
-                    512
-                },
-                seed: job.request.seed,
-            };
+            let batch = collect_batch(worker_id, &queue, workers, &batching, job).await;
+            run_batch(worker_id, &pipeline, &queue, heartbeat_interval, batch).await;
+        } else {
+            tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+        }
+    }
+}
 
-            // Generate image
-            let result = pipeline.generate(params).await;
-
-            match result {
-                Ok(generation_result) => {
-                    info!(
-                        "✓ Worker {} completed job {} in {:.2}s",
-                        worker_id, job.id, generation_result.generation_time
-                    );
-
-                    let response = grpc_proto::GenerateImageResponse {
-                        job_id: job.id.clone(),
-                        images: generation_result.images,
-                        status: "completed".to_string(),
-                        metadata: Some(grpc_proto::GenerationMetadata {
-                            generation_time_seconds: generation_result.generation_time,
-                            model_used: "stable-diffusion-v1-5".to_string(),
-                            seed: generation_result.seed,
-                            actual_steps: generation_result.steps_taken,
-                        }),
-                    };
-
-                    queue.update_status(&job.id, queue::memory::JobStatus::Completed).await;
-                    let _ = job.response_tx.send(Ok(response));
-                }
-                Err(e) => {
-                    error!("✗ Worker {} failed job {}: {}", worker_id, job.id, e);
+/// Takes compatible jobs to run with `first`, after waiting the batching
+/// window for the current queue depth. A lone job comes back at once.
+async fn collect_batch(
+    worker_id: usize,
+    queue: &WorkerQueue,
+    workers: usize,
+    batching: &BatchWindow,
+    first: queue::memory::Job<grpc_proto::GenerateImageRequest, grpc_proto::GenerateImageResponse>,
+) -> Vec<queue::memory::Job<grpc_proto::GenerateImageRequest, grpc_proto::GenerateImageResponse>> {
+    let mut batch = vec![first];
 
-                    queue.update_status(&job.id, queue::memory::JobStatus::Failed).await;
-                    let _ = job.response_tx.send(Err(e));
+    // Give compatible jobs a moment to arrive when the queue is busy
+    let wait = batching.wait(queue.queue_length().await);
+    if wait.is_zero() {
+        return batch;
+    }
+
+    tokio::time::sleep(wait).await;
+    let first = batch[0].request.clone();
+    while batch.len() < batching.max_jobs {
+        // Same-prompt jobs first, so the batch reuses one conditioning
+        let mut next = None;
+        if batching.coalesces_prompts() {
+            next = queue
+                .dequeue_compatible_for(worker_id, workers, |req| {
+                    batch_compatible(&first, req) && same_conditioning(&first, req)
+                })
+                .await;
+        }
+        if next.is_none() {
+            next = queue
+                .dequeue_compatible_for(worker_id, workers, |req| batch_compatible(&first, req))
+                .await;
+        }
+        match next {
+            Some(job) => batch.push(job),
+            None => break,
+        }
+    }
+    if batch.len() > 1 {
+        info!("Worker {} batched {} jobs after waiting {:?}", worker_id, batch.len(), wait);
+    }
+    batch
+}
+
+/// Runs a batch's jobs together on the loaded model rather than queueing
+/// them behind each other, so none waits out the others' denoising. Jobs
+/// with the same prompt encode it once between them.
+async fn run_batch(
+    worker_id: usize,
+    pipeline: &InferencePipeline,
+    queue: &WorkerQueue,
+    heartbeat_interval: tokio::time::Duration,
+    batch: Vec<queue::memory::Job<grpc_proto::GenerateImageRequest, grpc_proto::GenerateImageResponse>>,
+) {
+    // TODO: Stack the batch's latents into one tensor batch
+    let shared = ConditioningCache::new(batch.len());
+    let batch_ids: Vec<String> = batch.iter().map(|job| job.id.clone()).collect();
+    let runs = batch
+        .into_iter()
+        .map(|job| run_job(worker_id, pipeline, queue, heartbeat_interval, job, &batch_ids, &shared));
+    futures::future::join_all(runs).await;
+}
+
+/// Jobs that can share a batch: same model, output size, steps and scheduler
+fn batch_compatible(a: &grpc_proto::GenerateImageRequest, b: &grpc_proto::GenerateImageRequest) -> bool {
+    a.model_id == b.model_id
+        && a.width == b.width
+        && a.height == b.height
+        && a.num_inference_steps == b.num_inference_steps
+        && a.scheduler == b.scheduler
+}
+
+/// Jobs whose prompts encode to the same conditioning; only their seeds
+/// may differ
+fn same_conditioning(a: &grpc_proto::GenerateImageRequest, b: &grpc_proto::GenerateImageRequest) -> bool {
+    a.prompt == b.prompt
+        && a.prompt_embeds == b.prompt_embeds
+        && a.negative_prompt == b.negative_prompt
+        && a.negative_prompts == b.negative_prompts
+        && a.prompt_schedule == b.prompt_schedule
+        && a.clip_skip == b.clip_skip
+        && a.disable_auto_prompt == b.disable_auto_prompt
+}
+
+/// Aborts the task when dropped, including while unwinding from a panic
+struct AbortOnDrop(tokio::task::JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Generates one job and delivers its result
+async fn run_job(
+    worker_id: usize,
+    pipeline: &InferencePipeline,
+    queue: &WorkerQueue,
+    heartbeat_interval: tokio::time::Duration,
+    job: queue::memory::Job<grpc_proto::GenerateImageRequest, grpc_proto::GenerateImageResponse>,
+    batch_ids: &[String],
+    shared: &ConditioningCache,
+) {
+    info!("Worker {} processing job {}", worker_id, job.id);
+
+    // Validated at the gRPC boundary
+    let quality = job.request.quality
+        .as_deref()
+        .and_then(|q| q.parse::<QualityPreset>().ok());
+
+    // Convert request to generation params
+    let params = GenerationParams {
+        prompt: job.request.prompt.clone(),
+        prompt_embeds: if job.request.prompt_embeds.is_empty() {
+            None
+        } else {
+            Some(job.request.prompt_embeds.clone())
+        },
+        prompt_schedule: job.request.prompt_schedule
+            .iter()
+            .map(|entry| (entry.fraction, entry.prompt.clone()))
+            .collect(),
+        negative_prompt: if job.request.negative_prompt.is_empty() {
+            None
+        } else {
+            Some(job.request.negative_prompt.clone())
+        },
+        negative_prompts: job.request.negative_prompts
+            .iter()
+            .map(|entry| (entry.prompt.clone(), entry.weight))
+            .collect(),
+        num_inference_steps: if job.request.num_inference_steps > 0 {
+            job.request.num_inference_steps
+        } else {
+            // 0 takes the model's default
+            quality.map_or(0, |q| q.steps())
+        },
+        guidance_scale: if job.request.guidance_scale > 0.0 {
+            job.request.guidance_scale
+        } else {
+            quality.map_or(7.5, |q| q.guidance_scale())
+        },
+        width: if job.request.width > 0 {
+            Some(job.request.width)
+        } else {
+            None
+        },
+        height: if job.request.height > 0 {
+            Some(job.request.height)
+        } else {
+            None
+        },
+        aspect_ratio: job.request.aspect_ratio.clone(),
+        megapixels: job.request.megapixels,
+        init_image: if job.request.init_image.is_empty() {
+            None
+        } else {
+            Some(job.request.init_image.clone())
+        },
+        strength: job.request.strength,
+        reference_images: job.request.reference_images
+            .iter()
+            .map(|reference| (reference.image.clone(), reference.weight))
+            .collect(),
+        vary_region: job.request.vary_region.as_ref().map(|region| VaryRegion {
+            image: region.image.clone(),
+            mask: region.mask.clone(),
+        }),
+        strength_preset: job.request.strength_preset.clone(),
+        seed: job.request.seed,
+        subseed: job.request.subseed,
+        subseed_strength: job.request.subseed_strength,
+        temperature: job.request.temperature,
+        clip_skip: job.request.clip_skip,
+        model_id: if job.request.model_id.is_empty() {
+            None
+        } else {
+            Some(job.request.model_id.clone())
+        },
+        num_images: if job.request.num_images > 0 {
+            job.request.num_images
+        } else {
+            1
+        },
+        precision: job.request.precision.clone(),
+        scheduler: job.request.scheduler
+            .clone()
+            .or_else(|| quality.map(|q| q.scheduler().to_string())),
+        disable_auto_prompt: job.request.disable_auto_prompt,
+        no_cache: job.request.no_cache,
+        return_latents: job.request.return_latents,
+        return_schedule: job.request.return_schedule,
+        return_steps_animation: job.request.return_steps_animation,
+        debug: job.request.debug,
+        thumbnail: job.request.thumbnail,
+        dpi: job.request.dpi,
+        format: job.request.format.clone(),
+        output_formats: job.request.output_formats.clone(),
+        transparent_background: job.request.transparent_background,
+        upscale: job.request.upscale,
+        max_response_bytes: job.request.max_response_bytes,
+        border_width: job.request.border_width,
+        border_color: job.request.border_color.clone(),
+        color_palette: job.request.color_palette.clone(),
+        face_restore: job.request.face_restore,
+        face_restore_weight: job.request.face_restore_weight,
+        quality: quality.map(|q| q.to_string()),
+        queue_depth: queue.queue_length().await,
+        job_id: Some(job.id.clone()),
+    };
+
+    // Heartbeat from a task of its own, since denoising blocks this one
+    // between awaits. It is aborted once the job finishes, or when this task
+    // panics, so a dead worker stops heartbeating.
+    let heartbeat = AbortOnDrop(tokio::spawn({
+        let queue = queue.clone();
+        // Jobs later in the batch are already marked processing
+        let batch_ids = batch_ids.to_vec();
+        async move {
+            let mut ticker = tokio::time::interval(heartbeat_interval);
+            loop {
+                ticker.tick().await;
+                for id in &batch_ids {
+                    queue.heartbeat(id).await;
                 }
             }
-        } else {
-            tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
         }
+    }));
+    let result = pipeline.generate_sharing(params, shared).await;
+    drop(heartbeat);
+
+    match result {
+        Ok(generation_result) => {
+            info!(
+                "✓ Worker {} completed job {} in {:.2}s",
+                worker_id, job.id, generation_result.generation_time
+            );
+
+            let images = generation_result.images().cloned().collect();
+            let results = generation_result.results
+                .into_iter()
+                .map(|r| grpc_proto::ImageResult {
+                    index: r.index as i32,
+                    seed: r.seed,
+                    latent: r.latent,
+                    phash: r.phash,
+                    thumbnail: r.thumbnail,
+                    animation: r.animation,
+                    encodings: r.encodings
+                        .into_iter()
+                        .map(|(format, bytes)| (format.to_string(), bytes))
+                        .collect(),
+                    degraded: r.degraded.map(|d| grpc_proto::Degradation {
+                        format: d.format.to_string(),
+                        quality: d.quality.map(u32::from),
+                        width: d.width,
+                        height: d.height,
+                    }),
+                    result_id: server::result_id::encode(&job.id, r.seed),
+                    outcome: Some(match r.outcome {
+                        Ok(bytes) => grpc_proto::image_result::Outcome::Image(bytes),
+                        Err(reason) => grpc_proto::image_result::Outcome::Error(reason),
+                    }),
+                })
+                .collect();
+
+            let response = grpc_proto::GenerateImageResponse {
+                job_id: job.id.clone(),
+                images,
+                status: "completed".to_string(),
+                metadata: Some(grpc_proto::GenerationMetadata {
+                    metadata_version: METADATA_VERSION,
+                    generation_time_seconds: generation_result.generation_time,
+                    model_used: generation_result.model_used,
+                    final_prompt: generation_result.final_prompt,
+                    final_negative_prompt: generation_result.final_negative_prompt,
+                    prompt_fallback: generation_result.prompt_fallback,
+                    conditioning_cache_hit: generation_result.conditioning_cache_hit,
+                    prompt_embeds: generation_result.prompt_embeds,
+                    color_palette: generation_result.color_palette,
+                    prompt_schedule: generation_result.prompt_schedule
+                        .into_iter()
+                        .map(|(fraction, prompt)| grpc_proto::PromptScheduleEntry { fraction, prompt })
+                        .collect(),
+                    negative_prompts: generation_result.negative_prompts
+                        .into_iter()
+                        .map(|(prompt, weight)| grpc_proto::WeightedPrompt { prompt, weight })
+                        .collect(),
+                    seed: generation_result.seed,
+                    subseed: generation_result.subseed,
+                    subseed_strength: generation_result.subseed_strength,
+                    temperature: generation_result.temperature,
+                    actual_steps: generation_result.steps_taken,
+                    requested_steps: generation_result.requested_steps,
+                    degenerate_retries: generation_result.degenerate_retries as i32,
+                    guidance_scale: generation_result.guidance_scale,
+                    quality: generation_result.quality,
+                    width: generation_result.width,
+                    height: generation_result.height,
+                    requested_width: generation_result.requested_dimensions.map(|(w, _)| w),
+                    requested_height: generation_result.requested_dimensions.map(|(_, h)| h),
+                    strength: generation_result.strength,
+                    init_image_original_width: generation_result.init_image_resized.map(|(w, _)| w as i32),
+                    init_image_original_height: generation_result.init_image_resized.map(|(_, h)| h as i32),
+                    references: generation_result.references
+                        .into_iter()
+                        .map(|reference| grpc_proto::AppliedReference {
+                            index: reference.index as u32,
+                            width: reference.width,
+                            height: reference.height,
+                            weight: reference.weight,
+                        })
+                        .collect(),
+                    vary_region: generation_result.vary_region.map(|region| grpc_proto::AppliedVaryRegion {
+                        x: region.bounds.0,
+                        y: region.bounds.1,
+                        width: region.bounds.2,
+                        height: region.bounds.3,
+                        regenerated_pixels: region.regenerated_pixels,
+                        preserved_pixels: region.preserved_pixels,
+                    }),
+                    face_restore_weight: generation_result.face_restore_weight,
+                    scheduler: generation_result.scheduler.to_string(),
+                    beta_start: generation_result.noise_schedule.beta_start,
+                    beta_end: generation_result.noise_schedule.beta_end,
+                    beta_schedule: generation_result.noise_schedule.beta_schedule.to_string(),
+                    sigmas: generation_result.sigmas.unwrap_or_default(),
+                    clip_skip: generation_result.clip_skip,
+                    precision: generation_result.precision.to_string(),
+                    format: generation_result.format.to_string(),
+                    model_load_time_seconds: generation_result.model_load_time,
+                    prompt_moderation: generation_result.moderation.as_str().to_string(),
+                    moderated_terms: generation_result.moderation.matched_terms().to_vec(),
+                }),
+                results,
+                client_metadata: job.request.metadata.clone(),
+            };
+
+            queue.update_status(&job.id, queue::memory::JobStatus::Completed).await;
+            if job.is_orphaned() {
+                queue.store_result(&job.id, Ok(response)).await;
+            } else {
+                job.respond(Ok(response));
+            }
+        }
+        Err(e) => {
+            error!("✗ Worker {} failed job {}: {}", worker_id, job.id, e);
+            // Re-enqueued jobs report how many tries they got
+            let e = match job.attempts {
+                0 => e,
+                reenqueued => errors::DiffusionError::Exhausted {
+                    source: Box::new(e),
+                    attempts: reenqueued + 1,
+                },
+            };
+
+            queue.update_status(&job.id, queue::memory::JobStatus::Failed).await;
+            if job.is_orphaned() {
+                queue.store_result(&job.id, Err(e.to_string())).await;
+            } else {
+                job.respond(Err(e));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use inference::pipeline::tests::{mock_config, mock_pipeline};
+    use std::collections::HashMap;
+    use tokio::time::Duration;
+
+    fn request(prompt: &str) -> grpc_proto::GenerateImageRequest {
+        grpc_proto::GenerateImageRequest {
+            prompt: prompt.to_string(),
+            width: 64,
+            height: 64,
+            num_inference_steps: 4,
+            seed: Some(42),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn queued_job_echoes_client_metadata() {
+        let pipeline = mock_pipeline(&mock_config());
+        let queue = WorkerQueue::new(10);
+        let metadata = HashMap::from([("user".to_string(), "u-17".to_string())]);
+
+        let request = grpc_proto::GenerateImageRequest { metadata: metadata.clone(), ..request("a lighthouse") };
+        let (_, rx) = queue.enqueue(request).await.unwrap();
+        let job = queue.dequeue().await.unwrap();
+        run_batch(0, &pipeline, &queue, tokio::time::Duration::from_secs(1), vec![job]).await;
+
+        let response = rx.await.unwrap().unwrap();
+        assert_eq!(response.client_metadata, metadata);
+    }
+
+    #[tokio::test]
+    async fn completed_job_reports_the_metadata_version() {
+        let pipeline = mock_pipeline(&mock_config());
+        let queue = WorkerQueue::new(10);
+
+        let (_, rx) = queue.enqueue(request("a lighthouse")).await.unwrap();
+        let job = queue.dequeue().await.unwrap();
+        run_batch(0, &pipeline, &queue, Duration::from_secs(1), vec![job]).await;
+
+        let response = rx.await.unwrap().unwrap();
+        assert_eq!(response.metadata.unwrap().metadata_version, METADATA_VERSION);
+    }
+
+    #[tokio::test]
+    async fn deep_queue_waits_and_batches_while_a_lone_job_starts_at_once() {
+        let batching = BatchWindow {
+            max: Duration::from_millis(200),
+            full_depth: 2,
+            max_jobs: 4,
+            coalesce: Duration::ZERO,
+        };
+        let queue = WorkerQueue::new(10);
+
+        queue.enqueue(request("a lighthouse")).await.unwrap();
+        let lone = queue.dequeue().await.unwrap();
+        let start = std::time::Instant::now();
+        let batch = collect_batch(0, &queue, 1, &batching, lone).await;
+        assert_eq!(batch.len(), 1);
+        assert!(start.elapsed() < Duration::from_millis(100), "{:?}", start.elapsed());
+
+        for prompt in ["a lighthouse", "a harbor", "a cliff"] {
+            queue.enqueue(request(prompt)).await.unwrap();
+        }
+        // A job of another size can't join
+        queue.enqueue(grpc_proto::GenerateImageRequest { width: 128, ..request("a field") }).await.unwrap();
+        let first = queue.dequeue().await.unwrap();
+        let start = std::time::Instant::now();
+        let batch = collect_batch(0, &queue, 1, &batching, first).await;
+        assert!(start.elapsed() >= Duration::from_millis(200), "{:?}", start.elapsed());
+        let prompts: Vec<&str> = batch.iter().map(|job| job.request.prompt.as_str()).collect();
+        assert_eq!(prompts, ["a lighthouse", "a harbor", "a cliff"]);
+        assert_eq!(queue.queue_length().await, 1);
+    }
+
+    #[tokio::test]
+    async fn coalesced_seed_sweep_encodes_the_prompt_once() {
+        let mut config = mock_config();
+        config.inference.conditioning_cache_size = 0;
+        let pipeline = mock_pipeline(&config);
+        let queue = WorkerQueue::new(10);
+        let batching = BatchWindow {
+            max: Duration::ZERO,
+            full_depth: 1,
+            max_jobs: 4,
+            coalesce: Duration::from_millis(10),
+        };
+
+        // Prompt plus any negative prompts, encoded for one job on its own
+        let (_, rx) = queue.enqueue(request("a lighthouse")).await.unwrap();
+        run_batch(0, &pipeline, &queue, Duration::from_secs(1), vec![queue.dequeue().await.unwrap()]).await;
+        rx.await.unwrap().unwrap();
+        let encodes_per_job = pipeline.prompt_encodes();
+
+        let mut receivers = Vec::new();
+        for seed in [1, 2, 3] {
+            let (_, rx) = queue.enqueue(grpc_proto::GenerateImageRequest { seed: Some(seed), ..request("a lighthouse") }).await.unwrap();
+            receivers.push(rx);
+        }
+        let first = queue.dequeue().await.unwrap();
+        let batch = collect_batch(0, &queue, 1, &batching, first).await;
+        assert_eq!(batch.len(), 3);
+        run_batch(0, &pipeline, &queue, Duration::from_secs(1), batch).await;
+
+        assert_eq!(pipeline.prompt_encodes(), encodes_per_job * 2);
+        let mut images = Vec::new();
+        for rx in receivers {
+            let response = rx.await.unwrap().unwrap();
+            images.push(response.images[0].clone());
+        }
+        assert_ne!(images[0], images[1]);
+        assert_ne!(images[1], images[2]);
+        assert_ne!(images[0], images[2]);
     }
 }
@@ -0,0 +1,78 @@
+//! Micro-batching: after taking a job, a worker may wait briefly so
+//! compatible jobs arrive and run back-to-back on the same loaded model.
+//! With prompt coalescing, jobs sharing the first job's prompt are taken
+//! first, and the batch encodes that prompt once between them.
+
+use crate::config::QueueConfig;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy)]
+pub struct BatchWindow {
+    /// Longest wait, reached at `full_depth`
+    pub max: Duration,
+    pub full_depth: usize,
+    /// Most jobs in one batch, including the first
+    pub max_jobs: usize,
+    /// Minimum wait for same-prompt jobs while others are queued; zero
+    /// disables prompt coalescing
+    pub coalesce: Duration,
+}
+
+impl BatchWindow {
+    pub fn from_config(config: &QueueConfig) -> Self {
+        Self {
+            max: Duration::from_millis(config.batch_window_ms),
+            full_depth: config.batch_window_depth.max(1),
+            max_jobs: config.max_batch_jobs.max(1),
+            coalesce: Duration::from_millis(config.coalesce_window_ms),
+        }
+    }
+
+    /// How long to wait for compatible jobs, given `depth` jobs still
+    /// queued. Zero when nothing else is waiting, so a lone job starts
+    /// immediately; otherwise grows linearly to `max` at `full_depth`,
+    /// never shorter than the coalescing window.
+    pub fn wait(&self, depth: usize) -> Duration {
+        if self.max_jobs == 1 || depth == 0 {
+            return Duration::ZERO;
+        }
+        let adaptive = self.max.mul_f64(depth.min(self.full_depth) as f64 / self.full_depth as f64);
+        adaptive.max(self.coalesce)
+    }
+
+    pub fn coalesces_prompts(&self) -> bool {
+        !self.coalesce.is_zero()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(coalesce_secs: u64) -> BatchWindow {
+        BatchWindow {
+            max: Duration::from_secs(8),
+            full_depth: 4,
+            max_jobs: 4,
+            coalesce: Duration::from_secs(coalesce_secs),
+        }
+    }
+
+    #[test]
+    fn wait_grows_with_depth_and_is_zero_for_a_lone_job() {
+        let batching = window(0);
+        assert_eq!(batching.wait(0), Duration::ZERO);
+        assert_eq!(batching.wait(1), Duration::from_secs(2));
+        assert_eq!(batching.wait(4), Duration::from_secs(8));
+        assert_eq!(batching.wait(100), Duration::from_secs(8));
+    }
+
+    #[test]
+    fn coalescing_sets_a_floor_but_never_delays_a_lone_job() {
+        let batching = window(5);
+        assert_eq!(batching.wait(0), Duration::ZERO);
+        assert_eq!(batching.wait(1), Duration::from_secs(5));
+        assert_eq!(batching.wait(4), Duration::from_secs(8));
+        assert_eq!(BatchWindow { max_jobs: 1, ..batching }.wait(4), Duration::ZERO);
+    }
+}
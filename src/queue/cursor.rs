@@ -0,0 +1,12 @@
+/// Opaque pagination cursors for job listings. A cursor names the position
+/// of the last job returned, so a page never shifts when earlier jobs are
+/// added or dropped in between calls.
+pub fn encode(position: u64) -> String {
+    format!("j{:x}", position)
+}
+
+pub fn decode(cursor: &str) -> Option<u64> {
+    cursor
+        .strip_prefix('j')
+        .and_then(|hex| u64::from_str_radix(hex, 16).ok())
+}
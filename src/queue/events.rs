@@ -0,0 +1,68 @@
+use crate::queue::memory::JobStatus;
+use futures::Stream;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// Default number of events buffered per subscriber before it starts
+/// missing events
+pub const DEFAULT_EVENT_BUFFER: usize = 256;
+
+/// A job status transition
+#[derive(Debug, Clone)]
+pub struct JobEvent {
+    pub job_id: String,
+    pub status: JobStatus,
+    /// Milliseconds since the Unix epoch
+    pub timestamp: u64,
+}
+
+impl JobEvent {
+    pub fn new(job_id: &str, status: JobStatus) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+
+        Self {
+            job_id: job_id.to_string(),
+            status,
+            timestamp,
+        }
+    }
+}
+
+/// Fan-out of job events to any number of subscribers. The channel is
+/// bounded: subscribers that fall behind skip the oldest events rather
+/// than slowing the queue down.
+#[derive(Clone)]
+pub struct JobEvents {
+    sender: broadcast::Sender<JobEvent>,
+}
+
+impl JobEvents {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity.max(1));
+        Self { sender }
+    }
+
+    pub fn publish(&self, job_id: &str, status: JobStatus) {
+        // Only fails when nobody is subscribed
+        let _ = self.sender.send(JobEvent::new(job_id, status));
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<JobEvent> {
+        self.sender.subscribe()
+    }
+}
+
+/// Adapts a subscription into a stream that ends when the queue is dropped.
+/// Yields `Err(n)` when the subscriber fell behind and `n` events were skipped.
+pub fn event_stream(receiver: broadcast::Receiver<JobEvent>) -> impl Stream<Item = Result<JobEvent, u64>> {
+    futures::stream::unfold(receiver, |mut receiver| async move {
+        match receiver.recv().await {
+            Ok(event) => Some((Ok(event), receiver)),
+            Err(RecvError::Lagged(skipped)) => Some((Err(skipped), receiver)),
+            Err(RecvError::Closed) => None,
+        }
+    })
+}
@@ -1,7 +1,17 @@
 use crate::errors::{DiffusionError, Result};
-use std::collections::{HashMap, VecDeque};
-use std::sync::Arc;
-use tokio::sync::{Mutex, oneshot};
+use crate::queue::cursor;
+use crate::queue::events::{JobEvent, JobEvents, DEFAULT_EVENT_BUFFER};
+use crate::queue::persistence::QueueJournal;
+use crate::queue::weighted::WeightedQueue;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::ops::Bound;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, PoisonError};
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, Mutex, oneshot};
+use tracing::{info, warn};
 use uuid::Uuid;
 
 #[derive(Debug, Clone)]
@@ -13,35 +23,334 @@ pub enum JobStatus {
     Cancelled,
 }
 
+impl JobStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobStatus::Queued => "queued",
+            JobStatus::Processing => "processing",
+            JobStatus::Completed => "completed",
+            JobStatus::Failed => "failed",
+            JobStatus::Cancelled => "cancelled",
+        }
+    }
+    
+    /// Completed, failed or cancelled: the job won't change again
+    pub fn is_finished(&self) -> bool {
+        matches!(self, JobStatus::Completed | JobStatus::Failed | JobStatus::Cancelled)
+    }
+}
+
+/// Shared between the worker and the queue so the watchdog can still
+/// answer the client when a worker stops responding
+type Responder<Res> = Arc<std::sync::Mutex<Option<oneshot::Sender<Result<Res>>>>>;
+
+fn respond<Res>(responder: &Responder<Res>, result: Result<Res>) -> bool {
+    let tx = responder
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .take();
+    
+    match tx {
+        Some(tx) => tx.send(result).is_ok(),
+        None => false,
+    }
+}
+
 pub struct Job<Req, Res> {
     pub id: String,
     pub request: Req,
-    pub response_tx: oneshot::Sender<Result<Res>>,
     pub status: JobStatus,
+    /// Times this job has been re-enqueued after its worker went silent
+    pub attempts: u32,
+    /// When the job must have started by, under EDF scheduling
+    pub deadline: Option<Instant>,
+    /// When the job last entered the queue
+    pub queued_at: Instant,
+    responder: Responder<Res>,
+}
+
+impl<Req, Res> Job<Req, Res> {
+    /// Delivers the result to the waiting client. Returns false if the job
+    /// was already answered or the client has gone away.
+    pub fn respond(&self, result: Result<Res>) -> bool {
+        respond(&self.responder, result)
+    }
+    
+    /// True when nobody is waiting on this job's result, e.g. after it was
+    /// recovered from the journal following a restart
+    pub fn is_orphaned(&self) -> bool {
+        self.responder
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .as_ref()
+            .map_or(true, |tx| tx.is_closed())
+    }
+}
+
+/// A dequeued job, kept so it can be re-enqueued if its worker stops heartbeating
+struct InFlight<Req, Res> {
+    request: Req,
+    responder: Responder<Res>,
+    attempts: u32,
+    last_heartbeat: Instant,
+}
+
+/// Jobs spilled over while the primary queue is full. They move back into
+/// it in arrival order as workers free up room.
+struct Overflow<Req, Res> {
+    jobs: VecDeque<Job<Req, Res>>,
+    max_size: usize,
 }
 
 pub struct MemoryQueue<Req, Res> {
-    queue: Arc<Mutex<VecDeque<Job<Req, Res>>>>,
+    queue: Arc<Mutex<WeightedQueue<Job<Req, Res>>>>,
+    /// Priority class a request belongs to
+    classify: fn(&Req) -> Option<&str>,
+    /// Key pinning a request to one worker, when affinity is enabled, and
+    /// how long a job waits for that worker before any worker may take it
+    affinity: Option<(fn(&Req) -> &str, Duration)>,
+    /// A request's own deadline, when scheduling earliest deadline first
+    deadline: Option<fn(&Req) -> Option<Duration>>,
+    /// Deadline of requests without their own
+    default_deadline: Option<Duration>,
     jobs: Arc<Mutex<HashMap<String, JobStatus>>>,
+    /// Job ids by submission position, for cursor pagination. Locked
+    /// after `jobs`.
+    listing: Arc<Mutex<BTreeMap<u64, String>>>,
+    next_position: Arc<AtomicU64>,
+    in_flight: Arc<Mutex<HashMap<String, InFlight<Req, Res>>>>,
+    /// Results of orphaned jobs, kept for later fetch by job id
+    results: Arc<Mutex<HashMap<String, std::result::Result<Res, String>>>>,
+    journal: Option<Arc<QueueJournal<Req>>>,
+    /// Locked after `queue`, before `jobs`
+    overflow: Option<Arc<Mutex<Overflow<Req, Res>>>>,
+    overflow_journal: Option<Arc<QueueJournal<Req>>>,
+    events: JobEvents,
     max_size: usize,
 }
 
 impl<Req, Res> MemoryQueue<Req, Res> {
     pub fn new(max_size: usize) -> Self {
         Self {
-            queue: Arc::new(Mutex::new(VecDeque::new())),
+            queue: Arc::new(Mutex::new(WeightedQueue::new(&BTreeMap::new()))),
+            classify: |_| None,
+            affinity: None,
+            deadline: None,
+            default_deadline: None,
             jobs: Arc::new(Mutex::new(HashMap::new())),
+            listing: Arc::new(Mutex::new(BTreeMap::new())),
+            next_position: Arc::new(AtomicU64::new(0)),
+            in_flight: Arc::new(Mutex::new(HashMap::new())),
+            results: Arc::new(Mutex::new(HashMap::new())),
+            journal: None,
+            overflow: None,
+            overflow_journal: None,
+            events: JobEvents::new(DEFAULT_EVENT_BUFFER),
             max_size,
         }
     }
     
+    /// Buffers up to `capacity` events per subscriber
+    pub fn with_event_buffer(mut self, capacity: usize) -> Self {
+        self.events = JobEvents::new(capacity);
+        self
+    }
+    
+    /// Live feed of every job status transition
+    pub fn subscribe(&self) -> broadcast::Receiver<JobEvent> {
+        self.events.subscribe()
+    }
+    
+    /// Serves priority classes in proportion to `weights` instead of plain
+    /// FIFO. `classify` names the class of a request.
+    pub fn with_class_weights(
+        mut self,
+        weights: &BTreeMap<String, u32>,
+        classify: fn(&Req) -> Option<&str>,
+    ) -> Self {
+        self.queue = Arc::new(Mutex::new(WeightedQueue::new(weights)));
+        self.classify = classify;
+        self
+    }
+    
+    /// Routes every request with the same `key` (e.g. its model) to the same
+    /// worker in `dequeue_for`, keeping per-worker caches warm. A job still
+    /// queued `fallback` after it was queued goes to any worker, so a busy
+    /// pinned worker doesn't hold up its jobs while others sit idle.
+    pub fn with_affinity(mut self, key: fn(&Req) -> &str, fallback: Duration) -> Self {
+        self.affinity = Some((key, fallback));
+        self
+    }
+    
+    /// Schedules earliest deadline first instead of by arrival and class
+    /// weights. A job's deadline is `deadline` of its request, else
+    /// `default`, counted from when it is queued; jobs with neither go after
+    /// every deadline. Jobs still queued at their deadline are failed.
+    pub fn with_deadlines(mut self, deadline: fn(&Req) -> Option<Duration>, default: Option<Duration>) -> Self {
+        self.deadline = Some(deadline);
+        self.default_deadline = default;
+        self
+    }
+    
+    /// Persists queued jobs to `journal` so they survive a restart
+    pub fn with_journal(mut self, journal: QueueJournal<Req>) -> Self {
+        self.journal = Some(Arc::new(journal));
+        self
+    }
+    
+    /// Spills up to `max_size` jobs into an overflow queue instead of
+    /// rejecting them while the primary queue is full. With a `journal` the
+    /// spilled jobs are also kept on disk.
+    pub fn with_overflow(mut self, max_size: usize, journal: Option<QueueJournal<Req>>) -> Self {
+        self.overflow = Some(Arc::new(Mutex::new(Overflow {
+            jobs: VecDeque::new(),
+            max_size,
+        })));
+        self.overflow_journal = journal.map(Arc::new);
+        self
+    }
+    
+    /// Restores overflow jobs persisted by a previous run, moving as many as
+    /// fit into the primary queue; like `recover`, their results are kept
+    /// for fetching via `result`
+    pub async fn recover_overflow(&self) -> Result<usize> {
+        let overflow = match &self.overflow {
+            Some(overflow) => overflow,
+            None => return Ok(0),
+        };
+        
+        let recovered = match &self.overflow_journal {
+            Some(journal) => journal.load().await?,
+            None => return Ok(0),
+        };
+        let count = recovered.len();
+        
+        let mut queue = self.queue.lock().await;
+        let mut overflow = overflow.lock().await;
+        let mut jobs = self.jobs.lock().await;
+        let mut listing = self.listing.lock().await;
+        for (id, request) in recovered {
+            let (tx, _) = oneshot::channel();
+            jobs.insert(id.clone(), JobStatus::Queued);
+            listing.insert(self.next_position.fetch_add(1, Ordering::Relaxed), id.clone());
+            self.events.publish(&id, JobStatus::Queued);
+            overflow.jobs.push_back(Job {
+                id,
+                deadline: self.deadline_for(&request),
+                request,
+                status: JobStatus::Queued,
+                attempts: 0,
+                queued_at: Instant::now(),
+                responder: Arc::new(std::sync::Mutex::new(Some(tx))),
+            });
+        }
+        
+        drop(listing);
+        drop(jobs);
+        drop(overflow);
+        self.refill(&mut queue).await;
+        self.persist(&queue);
+        if let Some(overflow) = &self.overflow {
+            self.persist_overflow(&*overflow.lock().await);
+        }
+        drop(queue);
+        self.flush_journal().await;
+        Ok(count)
+    }
+    
+    /// Re-enqueues jobs persisted by a previous run. Their clients are gone,
+    /// so results are kept for fetching via `result` instead.
+    pub async fn recover(&self) -> Result<usize> {
+        let journal = match &self.journal {
+            Some(journal) => journal,
+            None => return Ok(0),
+        };
+        
+        let recovered = journal.load().await?;
+        let count = recovered.len();
+        
+        let mut queue = self.queue.lock().await;
+        let mut jobs = self.jobs.lock().await;
+        let mut listing = self.listing.lock().await;
+        for (id, request) in recovered {
+            // The receiver is dropped straight away: nobody is waiting
+            let (tx, _) = oneshot::channel();
+            jobs.insert(id.clone(), JobStatus::Queued);
+            listing.insert(self.next_position.fetch_add(1, Ordering::Relaxed), id.clone());
+            self.events.publish(&id, JobStatus::Queued);
+            let class = queue.class_index((self.classify)(&request));
+            queue.push_back(class, Job {
+                id,
+                deadline: self.deadline_for(&request),
+                request,
+                status: JobStatus::Queued,
+                attempts: 0,
+                queued_at: Instant::now(),
+                responder: Arc::new(std::sync::Mutex::new(Some(tx))),
+            });
+        }
+        
+        self.persist(&queue);
+        drop(listing);
+        drop(jobs);
+        drop(queue);
+        self.flush_journal().await;
+        Ok(count)
+    }
+    
+    /// When a request queued now must have started by. Deadlines restart
+    /// for jobs recovered after a restart.
+    fn deadline_for(&self, request: &Req) -> Option<Instant> {
+        let deadline = self.deadline?;
+        deadline(request)
+            .or(self.default_deadline)
+            .map(|deadline| Instant::now() + deadline)
+    }
+    
+    /// Stages the current queue contents for the journal. Written by
+    /// `flush_journal` once the queue locks are released.
+    fn persist(&self, queue: &WeightedQueue<Job<Req, Res>>) {
+        if let Some(journal) = &self.journal {
+            journal.stage(queue.iter().map(|job| (job.id.as_str(), &job.request)));
+        }
+    }
+    
+    /// Stages the overflow contents for its journal, like `persist`
+    fn persist_overflow(&self, overflow: &Overflow<Req, Res>) {
+        if let Some(journal) = &self.overflow_journal {
+            journal.stage(overflow.jobs.iter().map(|job| (job.id.as_str(), &job.request)));
+        }
+    }
+    
+    /// Writes the staged journals. Called with no queue locks held, so disk
+    /// I/O never blocks enqueues and dequeues.
+    async fn flush_journal(&self) {
+        for journal in [&self.journal, &self.overflow_journal].into_iter().flatten() {
+            if let Err(e) = journal.flush().await {
+                warn!("{}", e);
+            }
+        }
+    }
+    
+    /// Keeps the outcome of a job nobody was waiting on
+    pub async fn store_result(&self, job_id: &str, result: std::result::Result<Res, String>) {
+        self.results.lock().await.insert(job_id.to_string(), result);
+    }
+    
     pub async fn enqueue(
         &self,
         request: Req,
     ) -> Result<(String, oneshot::Receiver<Result<Res>>)> {
         let mut queue = self.queue.lock().await;
+        let mut overflow = match &self.overflow {
+            Some(overflow) => Some(overflow.lock().await),
+            None => None,
+        };
         
-        if queue.len() >= self.max_size {
+        // Once anything has spilled over, new jobs queue behind it
+        let spill = queue.len() >= self.max_size
+            || overflow.as_ref().map_or(false, |overflow| !overflow.jobs.is_empty());
+        if spill && overflow.as_ref().map_or(true, |overflow| overflow.jobs.len() >= overflow.max_size) {
             return Err(DiffusionError::QueueFull);
         }
         
@@ -50,27 +359,99 @@ impl<Req, Res> MemoryQueue<Req, Res> {
         
         let job = Job {
             id: job_id.clone(),
+            deadline: self.deadline_for(&request),
             request,
-            response_tx: tx,
             status: JobStatus::Queued,
+            attempts: 0,
+            queued_at: Instant::now(),
+            responder: Arc::new(std::sync::Mutex::new(Some(tx))),
         };
         
-        queue.push_back(job);
+        match overflow.as_mut() {
+            Some(overflow) if spill => {
+                overflow.jobs.push_back(job);
+                self.persist_overflow(overflow);
+            }
+            _ => {
+                let class = queue.class_index((self.classify)(&job.request));
+                queue.push_back(class, job);
+                self.persist(&queue);
+            }
+        }
         
         let mut jobs = self.jobs.lock().await;
         jobs.insert(job_id.clone(), JobStatus::Queued);
+        self.listing
+            .lock()
+            .await
+            .insert(self.next_position.fetch_add(1, Ordering::Relaxed), job_id.clone());
+        self.events.publish(&job_id, JobStatus::Queued);
+        drop(jobs);
+        drop(overflow);
+        drop(queue);
+        self.flush_journal().await;
         
         Ok((job_id, rx))
     }
     
-    pub async fn dequeue(&self) -> Option<Job<Req, Res>> {
+    /// Moves a queued job to the front of the queue so it is dequeued next
+    pub async fn promote(&self, job_id: &str) -> Result<()> {
         let mut queue = self.queue.lock().await;
-        let job = queue.pop_front()?;
         
-        let mut jobs = self.jobs.lock().await;
-        jobs.insert(job.id.clone(), JobStatus::Processing);
+        match self.take_queued(&mut queue, job_id).await {
+            Some(job) => {
+                queue.push_front(job);
+                self.persist(&queue);
+                drop(queue);
+                self.flush_journal().await;
+                Ok(())
+            }
+            None => Err(self.not_queued(job_id).await),
+        }
+    }
+    
+    /// Drops a job that hasn't been dequeued yet and marks it cancelled.
+    /// Jobs already processing run to completion.
+    pub async fn cancel(&self, job_id: &str) -> Result<()> {
+        let mut queue = self.queue.lock().await;
         
-        Some(job)
+        match self.take_queued(&mut queue, job_id).await {
+            Some(_) => {
+                self.refill(&mut queue).await;
+                self.persist(&queue);
+                drop(queue);
+                self.flush_journal().await;
+                self.update_status(job_id, JobStatus::Cancelled).await;
+                Ok(())
+            }
+            None => Err(self.not_queued(job_id).await),
+        }
+    }
+    
+    /// Removes a waiting job from the queue or, failing that, the overflow
+    async fn take_queued(
+        &self,
+        queue: &mut WeightedQueue<Job<Req, Res>>,
+        job_id: &str,
+    ) -> Option<Job<Req, Res>> {
+        if let Some(job) = queue.remove_where(|job| job.id == job_id) {
+            return Some(job);
+        }
+        
+        let mut overflow = self.overflow.as_ref()?.lock().await;
+        let position = overflow.jobs.iter().position(|job| job.id == job_id)?;
+        let job = overflow.jobs.remove(position);
+        self.persist_overflow(&overflow);
+        job
+    }
+    
+    async fn not_queued(&self, job_id: &str) -> DiffusionError {
+        let jobs = self.jobs.lock().await;
+        if jobs.contains_key(job_id) {
+            DiffusionError::JobNotQueued(job_id.to_string())
+        } else {
+            DiffusionError::JobNotFound(job_id.to_string())
+        }
     }
     
     pub async fn get_status(&self, job_id: &str) -> Option<JobStatus> {
@@ -80,11 +461,309 @@ impl<Req, Res> MemoryQueue<Req, Res> {
     
     pub async fn update_status(&self, job_id: &str, status: JobStatus) {
         let mut jobs = self.jobs.lock().await;
+        
+        if matches!(status, JobStatus::Completed | JobStatus::Failed | JobStatus::Cancelled) {
+            self.in_flight.lock().await.remove(job_id);
+        }
+        
+        self.events.publish(job_id, status.clone());
         jobs.insert(job_id.to_string(), status);
     }
     
+    /// Records that the worker processing `job_id` is still alive
+    pub async fn heartbeat(&self, job_id: &str) {
+        if let Some(flight) = self.in_flight.lock().await.get_mut(job_id) {
+            flight.last_heartbeat = Instant::now();
+        }
+    }
+    
+    /// Jobs waiting, including any in the overflow queue
     pub async fn queue_length(&self) -> usize {
-        self.queue.lock().await.len()
+        let queued = self.queue.lock().await.len();
+        match &self.overflow {
+            Some(overflow) => queued + overflow.lock().await.jobs.len(),
+            None => queued,
+        }
+    }
+    
+    /// Moves spilled jobs back into the primary queue while it has room
+    async fn refill(&self, queue: &mut WeightedQueue<Job<Req, Res>>) {
+        let overflow = match &self.overflow {
+            Some(overflow) => overflow,
+            None => return,
+        };
+        
+        let mut overflow = overflow.lock().await;
+        let mut moved = false;
+        while queue.len() < self.max_size {
+            let job = match overflow.jobs.pop_front() {
+                Some(job) => job,
+                None => break,
+            };
+            let class = queue.class_index((self.classify)(&job.request));
+            queue.push_back(class, job);
+            moved = true;
+        }
+        
+        if moved {
+            self.persist_overflow(&overflow);
+        }
+    }
+    
+    /// Up to `limit` jobs in submission order after `after` (a cursor from
+    /// a previous page), plus the cursor of the next page when more remain
+    pub async fn list_jobs(
+        &self,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<(Vec<(String, JobStatus)>, Option<String>)> {
+        let start = match after {
+            Some(after) => Bound::Excluded(cursor::decode(after).ok_or_else(|| {
+                DiffusionError::InvalidParameters(format!("Invalid cursor '{}'", after))
+            })?),
+            None => Bound::Unbounded,
+        };
+        
+        let jobs = self.jobs.lock().await;
+        let listing = self.listing.lock().await;
+        
+        // Positions of evicted jobs are skipped, not renumbered
+        let mut live = listing
+            .range((start, Bound::Unbounded))
+            .filter_map(|(&position, id)| jobs.get(id).map(|status| (position, id, status)));
+        let page: Vec<_> = live.by_ref().take(limit).collect();
+        let next = match live.next() {
+            Some(_) => page.last().map(|&(position, _, _)| cursor::encode(position)),
+            None => None,
+        };
+        let page = page
+            .into_iter()
+            .map(|(_, id, status)| (id.clone(), status.clone()))
+            .collect();
+        Ok((page, next))
+    }
+}
+
+impl<Req, Res> MemoryQueue<Req, Res> {
+    /// Forgets the oldest finished jobs beyond the newest `keep`, with any
+    /// stored results, so the job map stays bounded on a long-running
+    /// server. Their listing positions go too; cursors naming them stay
+    /// valid. Returns how many were evicted.
+    pub async fn evict_finished(&self, keep: usize) -> usize {
+        let mut jobs = self.jobs.lock().await;
+        let mut listing = self.listing.lock().await;
+        
+        let finished: Vec<u64> = listing
+            .iter()
+            .filter(|(_, id)| jobs.get(*id).map_or(true, JobStatus::is_finished))
+            .map(|(&position, _)| position)
+            .collect();
+        let excess = finished.len().saturating_sub(keep);
+        if excess == 0 {
+            return 0;
+        }
+        
+        let mut results = self.results.lock().await;
+        for position in &finished[..excess] {
+            if let Some(id) = listing.remove(position) {
+                jobs.remove(&id);
+                results.remove(&id);
+            }
+        }
+        excess
+    }
+}
+
+/// Worker an affinity key is pinned to
+pub fn affinity_worker(key: &str, workers: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    (hasher.finish() % workers.max(1) as u64) as usize
+}
+
+impl<Req: Clone, Res> MemoryQueue<Req, Res> {
+    pub async fn dequeue(&self) -> Option<Job<Req, Res>> {
+        self.dequeue_where(|_| true).await
+    }
+    
+    /// Dequeues for worker `worker_id` of `workers`. With affinity enabled
+    /// only jobs pinned to this worker, or waiting past the fallback, are
+    /// taken; otherwise any job is.
+    pub async fn dequeue_for(&self, worker_id: usize, workers: usize) -> Option<Job<Req, Res>> {
+        self.dequeue_compatible_for(worker_id, workers, |_| true).await
+    }
+    
+    /// `dequeue_for`, limited to jobs whose request satisfies `compatible`.
+    /// Used to gather a batch behind a job already taken.
+    pub async fn dequeue_compatible_for(
+        &self,
+        worker_id: usize,
+        workers: usize,
+        compatible: impl Fn(&Req) -> bool,
+    ) -> Option<Job<Req, Res>> {
+        match self.affinity {
+            Some((key, fallback)) => {
+                self.dequeue_where(|job| {
+                    let pinned = affinity_worker(key(&job.request), workers) == worker_id
+                        || job.queued_at.elapsed() >= fallback;
+                    pinned && compatible(&job.request)
+                })
+                .await
+            }
+            None => self.dequeue_where(|job| compatible(&job.request)).await,
+        }
+    }
+    
+    /// `enqueue`, retried with doubling backoff while the queue is full, up
+    /// to `retries` extra attempts or until `budget` has elapsed
+    pub async fn enqueue_with_retry(
+        &self,
+        request: Req,
+        retries: u32,
+        initial_backoff: Duration,
+        budget: Duration,
+    ) -> Result<(String, oneshot::Receiver<Result<Res>>)> {
+        let start = Instant::now();
+        let mut backoff = initial_backoff;
+        let mut attempt = 0;
+        
+        loop {
+            match self.enqueue(request.clone()).await {
+                Err(DiffusionError::QueueFull) if attempt < retries => {
+                    let remaining = budget.saturating_sub(start.elapsed());
+                    if remaining.is_zero() {
+                        return Err(DiffusionError::QueueFull);
+                    }
+                    tokio::time::sleep(backoff.min(remaining)).await;
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+    
+    async fn dequeue_where(&self, predicate: impl Fn(&Job<Req, Res>) -> bool) -> Option<Job<Req, Res>> {
+        let mut queue = self.queue.lock().await;
+        let job = match self.deadline {
+            Some(_) => {
+                self.drop_expired(&mut queue).await;
+                queue.pop_min_where(predicate, |job| (job.deadline.is_none(), job.deadline))?
+            }
+            None => queue.pop_front_where(predicate)?,
+        };
+        self.refill(&mut queue).await;
+        self.persist(&queue);
+        
+        let mut jobs = self.jobs.lock().await;
+        jobs.insert(job.id.clone(), JobStatus::Processing);
+        self.events.publish(&job.id, JobStatus::Processing);
+        
+        self.in_flight.lock().await.insert(job.id.clone(), InFlight {
+            request: job.request.clone(),
+            responder: Arc::clone(&job.responder),
+            attempts: job.attempts,
+            last_heartbeat: Instant::now(),
+        });
+        drop(jobs);
+        drop(queue);
+        self.flush_journal().await;
+        
+        Some(job)
+    }
+    
+    /// Fails every queued job whose deadline has passed, returning how many.
+    /// Dequeues do this too, but while every worker is busy nothing
+    /// dequeues, so this runs periodically as well.
+    pub async fn expire_overdue(&self) -> usize {
+        let mut queue = self.queue.lock().await;
+        self.drop_expired(&mut queue).await
+    }
+    
+    /// Fails every queued job whose deadline has passed
+    async fn drop_expired(&self, queue: &mut WeightedQueue<Job<Req, Res>>) -> usize {
+        let now = Instant::now();
+        let expired = queue.remove_all_where(|job| job.deadline.map_or(false, |deadline| deadline <= now));
+        if expired.is_empty() {
+            return 0;
+        }
+        let count = expired.len();
+        
+        let mut jobs = self.jobs.lock().await;
+        for job in expired {
+            warn!("Job {} missed its deadline before starting, dropping it", job.id);
+            job.respond(Err(DiffusionError::DeadlineExceeded));
+            self.events.publish(&job.id, JobStatus::Failed);
+            jobs.insert(job.id, JobStatus::Failed);
+        }
+        drop(jobs);
+        
+        self.refill(queue).await;
+        self.persist(queue);
+        count
+    }
+    
+    /// Watchdog pass: jobs whose worker hasn't heartbeat within `timeout` are
+    /// re-enqueued at the front up to `max_reenqueue` times, then failed.
+    /// A re-enqueued job started in time, so its deadline starts over.
+    pub async fn reap_stuck(&self, timeout: Duration, max_reenqueue: u32) {
+        let mut queue = self.queue.lock().await;
+        let mut jobs = self.jobs.lock().await;
+        let mut in_flight = self.in_flight.lock().await;
+        
+        let stuck: Vec<String> = in_flight
+            .iter()
+            .filter(|(_, flight)| flight.last_heartbeat.elapsed() >= timeout)
+            .map(|(id, _)| id.clone())
+            .collect();
+        let mut requeued = false;
+        
+        for job_id in stuck {
+            let flight = match in_flight.remove(&job_id) {
+                Some(flight) => flight,
+                None => continue,
+            };
+            
+            if flight.attempts < max_reenqueue {
+                warn!("Job {} missed its heartbeat, re-enqueueing", job_id);
+                let deadline = self.deadline_for(&flight.request);
+                queue.push_front(Job {
+                    id: job_id.clone(),
+                    request: flight.request,
+                    status: JobStatus::Queued,
+                    attempts: flight.attempts + 1,
+                    deadline,
+                    queued_at: Instant::now(),
+                    responder: flight.responder,
+                });
+                self.events.publish(&job_id, JobStatus::Queued);
+                jobs.insert(job_id, JobStatus::Queued);
+                requeued = true;
+            } else {
+                warn!("Job {} missed its heartbeat, marking failed", job_id);
+                respond(&flight.responder, Err(DiffusionError::Exhausted {
+                    source: Box::new(DiffusionError::Timeout),
+                    attempts: flight.attempts + 1,
+                }));
+                self.events.publish(&job_id, JobStatus::Failed);
+                jobs.insert(job_id, JobStatus::Failed);
+            }
+        }
+        
+        if requeued {
+            self.persist(&queue);
+            drop(in_flight);
+            drop(jobs);
+            drop(queue);
+            self.flush_journal().await;
+        }
+    }
+}
+
+impl<Req, Res: Clone> MemoryQueue<Req, Res> {
+    /// Stored outcome of an orphaned job
+    pub async fn result(&self, job_id: &str) -> Option<std::result::Result<Res, String>> {
+        self.results.lock().await.get(job_id).cloned()
     }
 }
 
@@ -92,8 +771,419 @@ impl<Req, Res> Clone for MemoryQueue<Req, Res> {
     fn clone(&self) -> Self {
         Self {
             queue: Arc::clone(&self.queue),
+            classify: self.classify,
+            affinity: self.affinity,
+            deadline: self.deadline,
+            default_deadline: self.default_deadline,
             jobs: Arc::clone(&self.jobs),
+            listing: Arc::clone(&self.listing),
+            next_position: Arc::clone(&self.next_position),
+            in_flight: Arc::clone(&self.in_flight),
+            results: Arc::clone(&self.results),
+            journal: self.journal.clone(),
+            overflow: self.overflow.clone(),
+            overflow_journal: self.overflow_journal.clone(),
+            events: self.events.clone(),
             max_size: self.max_size,
         }
     }
 }
+
+/// Cancels its job if dropped while armed. Held by a request handler across
+/// the wait for the result, so a client that disconnects (dropping the
+/// handler's future) doesn't leave its job occupying the queue.
+pub struct CancelGuard<Req: Send + Sync + 'static, Res: Send + Sync + 'static> {
+    queue: MemoryQueue<Req, Res>,
+    job_id: Option<String>,
+    on_cancel: Option<Box<dyn FnOnce() + Send>>,
+}
+
+impl<Req: Send + Sync + 'static, Res: Send + Sync + 'static> CancelGuard<Req, Res> {
+    pub fn new(queue: &MemoryQueue<Req, Res>, job_id: &str) -> Self {
+        Self {
+            queue: queue.clone(),
+            job_id: Some(job_id.to_string()),
+            on_cancel: None,
+        }
+    }
+    
+    /// Runs `f` if the guard does take the job out of the queue, e.g. to
+    /// refund what the job was charged
+    pub fn on_cancel(mut self, f: impl FnOnce() + Send + 'static) -> Self {
+        self.on_cancel = Some(Box::new(f));
+        self
+    }
+    
+    /// Call once the result has been delivered
+    pub fn disarm(mut self) {
+        self.job_id = None;
+    }
+}
+
+impl<Req: Send + Sync + 'static, Res: Send + Sync + 'static> Drop for CancelGuard<Req, Res> {
+    fn drop(&mut self) {
+        let Some(job_id) = self.job_id.take() else {
+            return;
+        };
+        let queue = self.queue.clone();
+        let on_cancel = self.on_cancel.take();
+        tokio::spawn(async move {
+            // Not queued any more means a worker has it; it finishes and its
+            // result is kept for get_job_status
+            if queue.cancel(&job_id).await.is_ok() {
+                info!("Cancelled job {}: client went away", job_id);
+                if let Some(on_cancel) = on_cancel {
+                    on_cancel();
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    type TestQueue = MemoryQueue<String, ()>;
+    
+    async fn enqueue_all(queue: &TestQueue, requests: &[&str]) -> Vec<String> {
+        let mut ids = Vec::new();
+        for request in requests {
+            ids.push(queue.enqueue(request.to_string()).await.unwrap().0);
+        }
+        ids
+    }
+    
+    #[tokio::test]
+    async fn promoted_job_dequeues_next() {
+        let queue = TestQueue::new(10);
+        let ids = enqueue_all(&queue, &["a", "b", "c"]).await;
+        
+        queue.promote(&ids[2]).await.unwrap();
+        assert_eq!(queue.dequeue().await.unwrap().request, "c");
+        assert_eq!(queue.dequeue().await.unwrap().request, "a");
+        
+        assert!(matches!(queue.promote(&ids[0]).await, Err(DiffusionError::JobNotQueued(_))));
+        assert!(matches!(queue.promote("missing").await, Err(DiffusionError::JobNotFound(_))));
+    }
+    
+    #[tokio::test]
+    async fn silent_job_is_reenqueued_then_failed() {
+        let queue = TestQueue::new(10);
+        let (id, rx) = queue.enqueue("a".to_string()).await.unwrap();
+        let timeout = Duration::from_millis(20);
+        
+        queue.dequeue().await.unwrap();
+        queue.reap_stuck(timeout, 1).await;
+        assert!(matches!(queue.get_status(&id).await, Some(JobStatus::Processing)));
+        
+        // Heartbeats keep it in flight; silence re-enqueues it once
+        tokio::time::sleep(timeout).await;
+        queue.heartbeat(&id).await;
+        queue.reap_stuck(timeout, 1).await;
+        assert!(matches!(queue.get_status(&id).await, Some(JobStatus::Processing)));
+        tokio::time::sleep(timeout).await;
+        queue.reap_stuck(timeout, 1).await;
+        assert!(matches!(queue.get_status(&id).await, Some(JobStatus::Queued)));
+        
+        let job = queue.dequeue().await.unwrap();
+        assert_eq!((job.id.as_str(), job.attempts), (id.as_str(), 1));
+        tokio::time::sleep(timeout).await;
+        queue.reap_stuck(timeout, 1).await;
+        assert!(matches!(queue.get_status(&id).await, Some(JobStatus::Failed)));
+        assert!(matches!(rx.await.unwrap(), Err(DiffusionError::Exhausted { attempts: 2, .. })));
+    }
+    
+    /// EDF queue where a request "name:ms" has a deadline `ms` from now
+    fn deadline_queue() -> TestQueue {
+        TestQueue::new(10).with_deadlines(
+            |req: &String| req.split_once(':').and_then(|(_, ms)| ms.parse().ok()).map(Duration::from_millis),
+            None,
+        )
+    }
+    
+    #[tokio::test]
+    async fn nearest_deadline_is_served_first_and_expired_jobs_are_dropped() {
+        let queue = deadline_queue();
+        let mut receivers = Vec::new();
+        for request in ["late:5000", "soon:1000", "expired:1", "none"] {
+            receivers.push(queue.enqueue(request.to_string()).await.unwrap());
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        
+        for expected in ["soon:1000", "late:5000", "none"] {
+            assert_eq!(queue.dequeue().await.unwrap().request, expected);
+        }
+        let (expired_id, expired_rx) = receivers.remove(2);
+        assert!(matches!(queue.get_status(&expired_id).await, Some(JobStatus::Failed)));
+        assert!(matches!(expired_rx.await.unwrap(), Err(DiffusionError::DeadlineExceeded)));
+    }
+    
+    #[tokio::test]
+    async fn sweep_expires_jobs_without_a_dequeue() {
+        let queue = deadline_queue();
+        let (_, rx) = queue.enqueue("expired:1".to_string()).await.unwrap();
+        queue.enqueue("later:5000".to_string()).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        
+        assert_eq!(queue.expire_overdue().await, 1);
+        assert!(matches!(rx.await.unwrap(), Err(DiffusionError::DeadlineExceeded)));
+        assert_eq!(queue.queue_length().await, 1);
+        assert_eq!(queue.expire_overdue().await, 0);
+    }
+    
+    #[tokio::test]
+    async fn reenqueued_job_gets_a_fresh_deadline() {
+        let queue = deadline_queue();
+        let (id, _rx) = queue.enqueue("a:30".to_string()).await.unwrap();
+        let timeout = Duration::from_millis(20);
+        
+        // Started in time, then lost its worker past the deadline
+        queue.dequeue().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(40)).await;
+        queue.reap_stuck(timeout, 1).await;
+        assert!(matches!(queue.get_status(&id).await, Some(JobStatus::Queued)));
+        
+        let job = queue.dequeue().await.unwrap();
+        assert_eq!((job.id.as_str(), job.attempts), (id.as_str(), 1));
+    }
+    
+    #[tokio::test]
+    async fn queued_jobs_survive_a_restart() {
+        let path = std::env::temp_dir().join(format!("queue-{}.journal", Uuid::new_v4()));
+        let journal = || {
+            QueueJournal::<String>::new(
+                path.clone(),
+                |request: &String| request.as_bytes().to_vec(),
+                |bytes: &[u8]| String::from_utf8(bytes.to_vec()).ok(),
+            )
+        };
+        
+        let queue = TestQueue::new(10).with_journal(journal());
+        let ids = enqueue_all(&queue, &["a", "b", "c"]).await;
+        // Jobs already processing are not persisted
+        assert_eq!(queue.dequeue().await.unwrap().request, "a");
+        drop(queue);
+        
+        let restarted = TestQueue::new(10).with_journal(journal());
+        assert_eq!(restarted.recover().await.unwrap(), 2);
+        assert!(matches!(restarted.get_status(&ids[1]).await, Some(JobStatus::Queued)));
+        assert_eq!(restarted.dequeue().await.unwrap().request, "b");
+        assert_eq!(restarted.dequeue().await.unwrap().request, "c");
+        
+        let _ = std::fs::remove_file(&path);
+    }
+    
+    #[tokio::test]
+    async fn subscribers_see_each_transition() {
+        let queue = TestQueue::new(10);
+        let mut events = Box::pin(crate::queue::events::event_stream(queue.subscribe()));
+        
+        let (id, _rx) = queue.enqueue("a".to_string()).await.unwrap();
+        queue.dequeue().await.unwrap();
+        queue.update_status(&id, JobStatus::Completed).await;
+        
+        for expected in ["queued", "processing", "completed"] {
+            let event = futures::StreamExt::next(&mut events).await.unwrap().unwrap();
+            assert_eq!((event.job_id.as_str(), event.status.as_str()), (id.as_str(), expected));
+        }
+    }
+    
+    #[tokio::test]
+    async fn affinity_pins_each_key_to_one_worker() {
+        let workers = 3;
+        let queue = TestQueue::new(100).with_affinity(|request| request.as_str(), Duration::from_secs(60));
+        let keys = ["base", "anime", "photo", "inpaint"];
+        for _ in 0..5 {
+            enqueue_all(&queue, &keys).await;
+        }
+        
+        let mut taken_by: HashMap<String, Vec<usize>> = HashMap::new();
+        for worker in 0..workers {
+            while let Some(job) = queue.dequeue_for(worker, workers).await {
+                taken_by.entry(job.request).or_default().push(worker);
+            }
+        }
+        
+        assert_eq!(queue.queue_length().await, 0);
+        for key in keys {
+            let expected = affinity_worker(key, workers);
+            assert_eq!(taken_by[key], vec![expected; 5], "{}", key);
+        }
+    }
+    
+    #[tokio::test]
+    async fn pinned_job_falls_back_to_any_worker() {
+        let workers = 2;
+        let queue = TestQueue::new(10).with_affinity(|request| request.as_str(), Duration::from_millis(20));
+        enqueue_all(&queue, &["base"]).await;
+        let other = 1 - affinity_worker("base", workers);
+        
+        assert!(queue.dequeue_for(other, workers).await.is_none());
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert_eq!(queue.dequeue_for(other, workers).await.unwrap().request, "base");
+    }
+    
+    #[tokio::test]
+    async fn full_queue_accepts_a_retried_enqueue_once_it_frees_up() {
+        let queue = TestQueue::new(1);
+        enqueue_all(&queue, &["a"]).await;
+        assert!(matches!(queue.enqueue("b".to_string()).await, Err(DiffusionError::QueueFull)));
+        
+        let worker = queue.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(30)).await;
+            worker.dequeue().await;
+        });
+        let retried = queue.enqueue_with_retry("b".to_string(), 10, Duration::from_millis(5), Duration::from_secs(2)).await;
+        assert!(retried.is_ok());
+        assert_eq!(queue.dequeue().await.unwrap().request, "b");
+        
+        // A queue that stays full gives up within the budget
+        enqueue_all(&queue, &["c"]).await;
+        let start = Instant::now();
+        let retried = queue.enqueue_with_retry("d".to_string(), 10, Duration::from_millis(5), Duration::from_millis(40)).await;
+        assert!(matches!(retried, Err(DiffusionError::QueueFull)));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+    
+    /// Takes `count` jobs off the queue and marks them completed
+    async fn finish(queue: &TestQueue, count: usize) {
+        for _ in 0..count {
+            let job = queue.dequeue().await.unwrap();
+            queue.update_status(&job.id, JobStatus::Completed).await;
+        }
+    }
+    
+    #[tokio::test]
+    async fn cursor_pages_survive_eviction_and_insertion() {
+        let queue = TestQueue::new(100);
+        let ids = enqueue_all(&queue, &["a", "b", "c", "d", "e", "f"]).await;
+        
+        let (page, mut cursor) = queue.list_jobs(None, 2).await.unwrap();
+        let mut seen: Vec<String> = page.into_iter().map(|(id, _)| id).collect();
+        assert_eq!(seen, ids[..2]);
+        
+        // Between pages the first three jobs finish and are evicted, and
+        // more arrive
+        finish(&queue, 3).await;
+        assert_eq!(queue.evict_finished(0).await, 3);
+        let more = enqueue_all(&queue, &["g", "h"]).await;
+        
+        while let Some(after) = cursor {
+            let (page, next) = queue.list_jobs(Some(&after), 2).await.unwrap();
+            seen.extend(page.into_iter().map(|(id, _)| id));
+            cursor = next;
+        }
+        let expected: Vec<String> = ids[..2].iter().chain(&ids[3..]).chain(&more).cloned().collect();
+        assert_eq!(seen, expected);
+        assert!(queue.get_status(&ids[0]).await.is_none());
+    }
+    
+    #[tokio::test]
+    async fn eviction_keeps_the_newest_finished_jobs() {
+        let queue = TestQueue::new(100);
+        let ids = enqueue_all(&queue, &["a", "b", "c", "d"]).await;
+        finish(&queue, 3).await;
+        queue.store_result(&ids[0], Ok(())).await;
+        
+        assert_eq!(queue.evict_finished(1).await, 2);
+        assert!(queue.get_status(&ids[0]).await.is_none());
+        assert!(queue.result(&ids[0]).await.is_none());
+        assert!(queue.get_status(&ids[1]).await.is_none());
+        assert!(matches!(queue.get_status(&ids[2]).await, Some(JobStatus::Completed)));
+        // Queued jobs are never evicted
+        assert!(matches!(queue.get_status(&ids[3]).await, Some(JobStatus::Queued)));
+        assert_eq!(queue.evict_finished(1).await, 0);
+    }
+    
+    #[tokio::test]
+    async fn overflow_jobs_are_promoted_and_processed() {
+        let queue = TestQueue::new(2).with_overflow(2, None);
+        let ids = enqueue_all(&queue, &["a", "b", "c", "d"]).await;
+        assert_eq!(queue.queue.lock().await.len(), 2);
+        assert_eq!(queue.queue_length().await, 4);
+        assert!(matches!(queue.enqueue("e".to_string()).await, Err(DiffusionError::QueueFull)));
+        
+        // Each dequeue makes room for the oldest spilled job
+        let mut processed = Vec::new();
+        while let Some(job) = queue.dequeue().await {
+            processed.push(job.request.clone());
+            queue.update_status(&job.id, JobStatus::Completed).await;
+        }
+        assert_eq!(processed, ["a", "b", "c", "d"]);
+        assert!(matches!(queue.get_status(&ids[3]).await, Some(JobStatus::Completed)));
+    }
+    
+    #[tokio::test]
+    async fn spilled_jobs_survive_a_restart() {
+        let path = std::env::temp_dir().join(format!("overflow-{}.journal", Uuid::new_v4()));
+        let journal = || {
+            Some(QueueJournal::<String>::new(
+                path.clone(),
+                |request: &String| request.as_bytes().to_vec(),
+                |bytes: &[u8]| String::from_utf8(bytes.to_vec()).ok(),
+            ))
+        };
+        
+        let queue = TestQueue::new(1).with_overflow(4, journal());
+        enqueue_all(&queue, &["a", "b", "c"]).await;
+        drop(queue);
+        
+        let restarted = TestQueue::new(1).with_overflow(4, journal());
+        assert_eq!(restarted.recover_overflow().await.unwrap(), 2);
+        assert_eq!(restarted.queue_length().await, 2);
+        // The primary queue had room, so the oldest moved straight into it
+        assert_eq!(restarted.dequeue().await.unwrap().request, "b");
+        assert_eq!(restarted.dequeue().await.unwrap().request, "c");
+        
+        let _ = std::fs::remove_file(&path);
+    }
+    
+    #[tokio::test]
+    async fn dropped_cancel_guard_cancels_the_queued_job() {
+        let queue = TestQueue::new(10);
+        let ids = enqueue_all(&queue, &["abandoned", "delivered"]).await;
+        
+        drop(CancelGuard::new(&queue, &ids[0]));
+        CancelGuard::new(&queue, &ids[1]).disarm();
+        
+        // The cancel runs on a spawned task
+        for _ in 0..100 {
+            if matches!(queue.get_status(&ids[0]).await, Some(JobStatus::Cancelled)) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        assert!(matches!(queue.get_status(&ids[0]).await, Some(JobStatus::Cancelled)));
+        assert!(matches!(queue.get_status(&ids[1]).await, Some(JobStatus::Queued)));
+        assert_eq!(queue.queue_length().await, 1);
+    }
+    
+    #[tokio::test]
+    async fn on_cancel_runs_only_when_the_job_is_taken_back() {
+        use std::sync::atomic::AtomicUsize;
+        
+        let queue = TestQueue::new(10);
+        let ids = enqueue_all(&queue, &["running", "abandoned"]).await;
+        let running = queue.dequeue().await.unwrap();
+        assert_eq!(running.id, ids[0]);
+        
+        let refunds = Arc::new(AtomicUsize::new(0));
+        for id in &ids {
+            let refunds = Arc::clone(&refunds);
+            drop(CancelGuard::new(&queue, id).on_cancel(move || {
+                refunds.fetch_add(1, Ordering::SeqCst);
+            }));
+        }
+        
+        for _ in 0..100 {
+            if matches!(queue.get_status(&ids[1]).await, Some(JobStatus::Cancelled)) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(refunds.load(Ordering::SeqCst), 1);
+    }
+}
@@ -1,3 +1,11 @@
+pub mod batching;
+pub mod cursor;
+pub mod events;
 pub mod memory;
+pub mod persistence;
+pub mod weighted;
 
+pub use events::{JobEvent, JobEvents};
 pub use memory::MemoryQueue;
+pub use persistence::QueueJournal;
+pub use weighted::WeightedQueue;
@@ -0,0 +1,94 @@
+use crate::errors::{DiffusionError, Result};
+use std::path::PathBuf;
+use std::sync::PoisonError;
+use tokio::sync::Mutex;
+
+/// On-disk copy of the jobs still waiting in a `MemoryQueue`, so they can be
+/// re-enqueued after a crash. Jobs already being processed are not kept.
+///
+/// The file holds one job per line: the job id and the base64 encoded
+/// request, separated by a tab. Changes are staged under the queue lock and
+/// written out in full by `flush` once the lock is released.
+pub struct QueueJournal<Req> {
+    path: PathBuf,
+    encode: fn(&Req) -> Vec<u8>,
+    decode: fn(&[u8]) -> Option<Req>,
+    /// Latest staged contents not yet written
+    pending: std::sync::Mutex<Option<String>>,
+    /// Serializes writes, so an older snapshot never replaces a newer one
+    writing: Mutex<()>,
+}
+
+impl<Req> QueueJournal<Req> {
+    pub fn new(
+        path: impl Into<PathBuf>,
+        encode: fn(&Req) -> Vec<u8>,
+        decode: fn(&[u8]) -> Option<Req>,
+    ) -> Self {
+        Self {
+            path: path.into(),
+            encode,
+            decode,
+            pending: std::sync::Mutex::new(None),
+            writing: Mutex::new(()),
+        }
+    }
+
+    /// Records the queued jobs, in queue order, for the next `flush`. No
+    /// I/O happens here, so it is called with the queue locked; the
+    /// snapshot replaces any staged one not yet written.
+    pub fn stage<'a>(&self, jobs: impl Iterator<Item = (&'a str, &'a Req)>)
+    where
+        Req: 'a,
+    {
+        let mut contents = String::new();
+        for (id, request) in jobs {
+            contents.push_str(id);
+            contents.push('\t');
+            contents.push_str(&base64::encode((self.encode)(request)));
+            contents.push('\n');
+        }
+
+        *self.pending.lock().unwrap_or_else(PoisonError::into_inner) = Some(contents);
+    }
+
+    /// Writes the latest staged snapshot, if any
+    pub async fn flush(&self) -> Result<()> {
+        let _writing = self.writing.lock().await;
+        let contents = match self.pending.lock().unwrap_or_else(PoisonError::into_inner).take() {
+            Some(contents) => contents,
+            None => return Ok(()),
+        };
+
+        // Write then rename so a crash mid-write leaves the previous journal intact
+        let tmp = self.path.with_extension("tmp");
+        let result = async {
+            tokio::fs::write(&tmp, contents).await?;
+            tokio::fs::rename(&tmp, &self.path).await
+        }
+        .await;
+
+        result.map_err(|e| DiffusionError::Storage(format!("Failed to write queue journal: {}", e)))
+    }
+
+    /// Reads back the persisted jobs. A missing journal means an empty queue;
+    /// entries that fail to decode are skipped.
+    pub async fn load(&self) -> Result<Vec<(String, Req)>> {
+        let contents = match tokio::fs::read_to_string(&self.path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(DiffusionError::Storage(format!("Failed to read queue journal: {}", e)));
+            }
+        };
+
+        Ok(contents
+            .lines()
+            .filter_map(|line| {
+                let (id, encoded) = line.split_once('\t')?;
+                let bytes = base64::decode(encoded).ok()?;
+                Some((id.to_string(), (self.decode)(&bytes)?))
+            })
+            .collect())
+    }
+}
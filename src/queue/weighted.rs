@@ -0,0 +1,201 @@
+use std::collections::{BTreeMap, VecDeque};
+
+/// Class used for requests that don't name one (or name an unknown one)
+pub const DEFAULT_CLASS: &str = "normal";
+
+struct Class<T> {
+    name: String,
+    weight: i64,
+    /// Smooth weighted round-robin credit
+    current: i64,
+    items: VecDeque<T>,
+}
+
+/// Priority classes served in proportion to their weights (smooth weighted
+/// round-robin), so low weight classes still make progress under load.
+/// Items pushed to the front lane bypass the classes and are served first.
+pub struct WeightedQueue<T> {
+    front: VecDeque<T>,
+    classes: Vec<Class<T>>,
+}
+
+impl<T> WeightedQueue<T> {
+    /// With no weights every item shares one FIFO class
+    pub fn new(weights: &BTreeMap<String, u32>) -> Self {
+        let mut classes: Vec<Class<T>> = weights
+            .iter()
+            .map(|(name, weight)| Class {
+                name: name.clone(),
+                weight: (*weight).max(1) as i64,
+                current: 0,
+                items: VecDeque::new(),
+            })
+            .collect();
+        
+        if classes.is_empty() {
+            classes.push(Class {
+                name: DEFAULT_CLASS.to_string(),
+                weight: 1,
+                current: 0,
+                items: VecDeque::new(),
+            });
+        }
+        
+        Self { front: VecDeque::new(), classes }
+    }
+    
+    /// Index of the named class, falling back to `DEFAULT_CLASS` and then
+    /// to the first class
+    pub fn class_index(&self, name: Option<&str>) -> usize {
+        let find = |name: &str| self.classes.iter().position(|class| class.name == name);
+        name.and_then(find)
+            .or_else(|| find(DEFAULT_CLASS))
+            .unwrap_or(0)
+    }
+    
+    pub fn push_back(&mut self, class: usize, item: T) {
+        self.classes[class].items.push_back(item);
+    }
+    
+    pub fn push_front(&mut self, item: T) {
+        self.front.push_front(item);
+    }
+    
+    pub fn pop_front(&mut self) -> Option<T> {
+        self.pop_front_where(|_| true)
+    }
+    
+    /// Like `pop_front`, considering only items matching `predicate`
+    pub fn pop_front_where(&mut self, predicate: impl Fn(&T) -> bool) -> Option<T> {
+        if let Some(pos) = self.front.iter().position(&predicate) {
+            return self.front.remove(pos);
+        }
+        
+        // Every class with a candidate earns its weight; the richest is
+        // served and pays back the total
+        let eligible: Vec<bool> = self.classes
+            .iter()
+            .map(|class| class.items.iter().any(&predicate))
+            .collect();
+        let mut total = 0;
+        for (class, _) in self.classes.iter_mut().zip(&eligible).filter(|&(_, &e)| e) {
+            class.current += class.weight;
+            total += class.weight;
+        }
+        
+        // Reversed so ties go to the class listed first
+        let best = self.classes
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(index, _)| eligible[*index])
+            .max_by_key(|(_, class)| class.current)
+            .map(|(index, _)| index)?;
+        
+        let class = &mut self.classes[best];
+        class.current -= total;
+        let pos = class.items.iter().position(&predicate)?;
+        class.items.remove(pos)
+    }
+    
+    /// Like `pop_front_where`, but takes the matching item with the smallest
+    /// `key` regardless of class weights. The front lane still goes first;
+    /// ties go to the class listed first, then to the older item.
+    pub fn pop_min_where<K: Ord>(
+        &mut self,
+        predicate: impl Fn(&T) -> bool,
+        key: impl Fn(&T) -> K,
+    ) -> Option<T> {
+        if let Some(pos) = self.front.iter().position(&predicate) {
+            return self.front.remove(pos);
+        }
+        
+        let predicate = &predicate;
+        let (class, pos) = self.classes
+            .iter()
+            .enumerate()
+            .flat_map(|(index, class)| {
+                class.items
+                    .iter()
+                    .enumerate()
+                    .filter(move |(_, item)| predicate(item))
+                    .map(move |(pos, item)| (index, pos, item))
+            })
+            .min_by_key(|(_, _, item)| key(item))
+            .map(|(index, pos, _)| (index, pos))?;
+        self.classes[class].items.remove(pos)
+    }
+    
+    /// Removes and returns every item matching `predicate`
+    pub fn remove_all_where(&mut self, predicate: impl Fn(&T) -> bool) -> Vec<T> {
+        let mut removed = Vec::new();
+        let lanes = std::iter::once(&mut self.front).chain(self.classes.iter_mut().map(|class| &mut class.items));
+        for lane in lanes {
+            let mut kept = VecDeque::with_capacity(lane.len());
+            for item in lane.drain(..) {
+                if predicate(&item) {
+                    removed.push(item);
+                } else {
+                    kept.push_back(item);
+                }
+            }
+            *lane = kept;
+        }
+        removed
+    }
+    
+    /// Removes and returns the first item matching `predicate`
+    pub fn remove_where(&mut self, predicate: impl Fn(&T) -> bool) -> Option<T> {
+        if let Some(pos) = self.front.iter().position(&predicate) {
+            return self.front.remove(pos);
+        }
+        self.classes.iter_mut().find_map(|class| {
+            let pos = class.items.iter().position(&predicate)?;
+            class.items.remove(pos)
+        })
+    }
+    
+    pub fn len(&self) -> usize {
+        self.front.len() + self.classes.iter().map(|class| class.items.len()).sum::<usize>()
+    }
+    
+    /// Front lane first, then each class in turn
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.front.iter().chain(self.classes.iter().flat_map(|class| class.items.iter()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn classes_are_served_in_proportion_to_their_weights() {
+        let weights = BTreeMap::from([("high".to_string(), 3), ("low".to_string(), 1)]);
+        let mut queue = WeightedQueue::new(&weights);
+        let (high, low) = (queue.class_index(Some("high")), queue.class_index(Some("low")));
+        for i in 0..400 {
+            queue.push_back(high, ("high", i));
+            queue.push_back(low, ("low", i));
+        }
+        
+        let served: Vec<&str> = (0..400).map(|_| queue.pop_front().unwrap().0).collect();
+        let high_served = served.iter().filter(|class| **class == "high").count();
+        assert_eq!((high_served, served.len() - high_served), (300, 100));
+        // Interleaved rather than served in runs: the low class gets a turn
+        // in every window of four
+        assert!(served.chunks(4).all(|window| window.contains(&"low")));
+        
+        // Each class stays FIFO
+        let lows: Vec<i32> = std::iter::from_fn(|| queue.pop_front()).filter(|(class, _)| *class == "low").map(|(_, i)| i).collect();
+        assert_eq!(lows, (100..400).collect::<Vec<_>>());
+    }
+    
+    #[test]
+    fn unknown_classes_fall_back_to_the_default() {
+        let weights = BTreeMap::from([("high".to_string(), 3), (DEFAULT_CLASS.to_string(), 1)]);
+        let queue = WeightedQueue::<()>::new(&weights);
+        assert_eq!(queue.class_index(Some("premium")), queue.class_index(None));
+        assert_ne!(queue.class_index(Some("high")), queue.class_index(None));
+    }
+}
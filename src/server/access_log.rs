@@ -0,0 +1,118 @@
+use crate::config::LoggingConfig;
+use crate::errors::{DiffusionError, Result};
+use actix_web::http::{Method, StatusCode};
+use std::time::Duration;
+use tracing::info;
+
+/// Why a request made it into the access log
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleReason {
+    /// 4xx and 5xx responses are always logged
+    Error,
+    /// So are requests slower than `access_slow_ms`
+    Slow,
+    /// The rest are logged at `access_sample_rate`
+    Sampled,
+}
+
+impl SampleReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SampleReason::Error => "error",
+            SampleReason::Slow => "slow",
+            SampleReason::Sampled => "sampled",
+        }
+    }
+}
+
+/// One line per REST request, minus a share of the fast successful ones.
+/// Each line records why it was kept and the sample rate, so sampled
+/// counts can be scaled back up.
+#[derive(Debug, Clone, Copy)]
+pub struct AccessLog {
+    sample_rate: f64,
+    slow: Duration,
+}
+
+impl AccessLog {
+    pub fn new(config: &LoggingConfig) -> Result<Self> {
+        if !(0.0..=1.0).contains(&config.access_sample_rate) {
+            return Err(DiffusionError::Config(
+                "logging.access_sample_rate must be between 0.0 and 1.0".to_string()
+            ));
+        }
+        Ok(Self {
+            sample_rate: config.access_sample_rate,
+            slow: Duration::from_millis(config.access_slow_ms),
+        })
+    }
+
+    /// Whether a request is logged, and why
+    pub fn decide(&self, status: StatusCode, elapsed: Duration) -> Option<SampleReason> {
+        if status.is_client_error() || status.is_server_error() {
+            Some(SampleReason::Error)
+        } else if elapsed >= self.slow {
+            Some(SampleReason::Slow)
+        } else if rand::random::<f64>() < self.sample_rate {
+            Some(SampleReason::Sampled)
+        } else {
+            None
+        }
+    }
+
+    pub fn record(&self, method: &Method, path: &str, status: StatusCode, elapsed: Duration) {
+        let Some(reason) = self.decide(status, elapsed) else {
+            return;
+        };
+        info!(
+            %method,
+            path,
+            status = status.as_u16(),
+            elapsed_ms = elapsed.as_millis() as u64,
+            sampled_by = reason.as_str(),
+            sample_rate = self.sample_rate,
+            "Access"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn access_log(sample_rate: f64) -> AccessLog {
+        AccessLog::new(&LoggingConfig { access_sample_rate: sample_rate, access_slow_ms: 1000 }).unwrap()
+    }
+
+    #[test]
+    fn errors_and_slow_requests_are_always_logged() {
+        let log = access_log(0.0);
+        let fast = Duration::from_millis(10);
+
+        for status in [StatusCode::BAD_REQUEST, StatusCode::TOO_MANY_REQUESTS, StatusCode::INTERNAL_SERVER_ERROR] {
+            assert_eq!(log.decide(status, fast), Some(SampleReason::Error));
+        }
+        assert_eq!(log.decide(StatusCode::OK, Duration::from_secs(1)), Some(SampleReason::Slow));
+        assert_eq!(log.decide(StatusCode::OK, fast), None);
+        assert_eq!(access_log(1.0).decide(StatusCode::OK, fast), Some(SampleReason::Sampled));
+    }
+
+    #[test]
+    fn successes_are_sampled_at_the_configured_rate() {
+        let log = access_log(0.25);
+        let logged = (0..10_000)
+            .filter(|_| log.decide(StatusCode::OK, Duration::from_millis(10)).is_some())
+            .count();
+
+        // About 7 standard deviations either side
+        assert!((2200..=2800).contains(&logged), "logged {} of 10000", logged);
+    }
+
+    #[test]
+    fn sample_rate_must_be_a_fraction() {
+        for rate in [-0.1, 1.5, f64::NAN] {
+            let config = LoggingConfig { access_sample_rate: rate, access_slow_ms: 1000 };
+            assert!(matches!(AccessLog::new(&config), Err(DiffusionError::Config(_))));
+        }
+    }
+}
@@ -0,0 +1,32 @@
+use crate::errors::DiffusionError;
+use std::io::{Cursor, Write};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+/// Builds a ZIP archive in memory from (file name, contents) pairs
+pub fn build_zip<'a>(
+    files: impl IntoIterator<Item = (String, &'a [u8])>,
+) -> Result<Vec<u8>, DiffusionError> {
+    let archive_error = |e: &dyn std::fmt::Display| {
+        DiffusionError::Internal(format!("Failed to build ZIP archive: {}", e))
+    };
+
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+
+    for (name, contents) in files {
+        // PNGs are already compressed; deflating them again only costs time
+        let method = if name.ends_with(".png") {
+            CompressionMethod::Stored
+        } else {
+            CompressionMethod::Deflated
+        };
+
+        writer
+            .start_file(name, FileOptions::default().compression_method(method))
+            .map_err(|e| archive_error(&e))?;
+        writer.write_all(contents).map_err(|e| archive_error(&e))?;
+    }
+
+    let cursor = writer.finish().map_err(|e| archive_error(&e))?;
+    Ok(cursor.into_inner())
+}
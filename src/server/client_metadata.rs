@@ -0,0 +1,25 @@
+use crate::config::ServerConfig;
+use crate::errors::DiffusionError;
+use std::collections::HashMap;
+
+/// Bounds the opaque metadata clients attach to a request. It is echoed
+/// back unmodified, so the server only limits its size.
+pub fn validate(metadata: &HashMap<String, String>, config: &ServerConfig) -> Result<(), DiffusionError> {
+    if metadata.len() > config.max_metadata_entries {
+        return Err(DiffusionError::InvalidParameters(format!(
+            "metadata may have at most {} entries, got {}",
+            config.max_metadata_entries,
+            metadata.len()
+        )));
+    }
+
+    let size: usize = metadata.iter().map(|(k, v)| k.len() + v.len()).sum();
+    if size > config.max_metadata_bytes {
+        return Err(DiffusionError::InvalidParameters(format!(
+            "metadata may total at most {} bytes, got {}",
+            config.max_metadata_bytes, size
+        )));
+    }
+
+    Ok(())
+}
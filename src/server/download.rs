@@ -0,0 +1,72 @@
+//! Browser-friendly file names for downloaded images and archives
+
+/// Longest prompt slug kept in a file name
+const MAX_SLUG_LEN: usize = 48;
+
+/// Lowercase ASCII letters and digits, with every other run of characters
+/// collapsed to a single '-'
+pub fn slugify(text: &str) -> String {
+    let mut slug = String::new();
+    for c in text.chars() {
+        if c.is_ascii_alphanumeric() {
+            if slug.len() == MAX_SLUG_LEN {
+                break;
+            }
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+
+    let slug = slug.trim_end_matches('-');
+    if slug.is_empty() { "image".to_string() } else { slug.to_string() }
+}
+
+/// Expands `{prompt}`, `{seed}` and `{index}` in `pattern` and appends
+/// `extension`. Anything outside `[A-Za-z0-9._-]` is replaced, so neither
+/// the prompt nor the configured pattern can add path separators, quotes or
+/// header line breaks.
+pub fn file_name(pattern: &str, prompt: &str, seed: i64, index: usize, extension: &str) -> String {
+    let name = pattern
+        .replace("{prompt}", &slugify(prompt))
+        .replace("{seed}", &seed.to_string())
+        .replace("{index}", &index.to_string());
+
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-') { c } else { '_' })
+        .collect();
+    let name = name.trim_start_matches('.');
+
+    format!("{}.{}", if name.is_empty() { "image" } else { name }, extension)
+}
+
+/// `Content-Disposition` value for a name built by `file_name`
+pub fn attachment(file_name: &str) -> String {
+    format!("attachment; filename=\"{}\"", file_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prompt_is_slugified() {
+        assert_eq!(slugify("A Lighthouse, at Dusk!"), "a-lighthouse-at-dusk");
+        assert_eq!(slugify("  --  "), "image");
+        assert_eq!(slugify(&"x".repeat(100)).len(), MAX_SLUG_LEN);
+        assert_eq!(file_name("{prompt}_{seed}", "A Lighthouse", 42, 0, "png"), "a-lighthouse_42.png");
+        assert_eq!(file_name("{index}-{seed}", "", -7, 3, "webp"), "3--7.webp");
+    }
+
+    #[test]
+    fn prompt_and_pattern_cannot_inject() {
+        let name = file_name("{prompt}_{seed}", "cat\"\r\nSet-Cookie: a=1; ../../etc/passwd", 1, 0, "png");
+        assert_eq!(name, "cat-set-cookie-a-1-etc-passwd_1.png");
+
+        let name = file_name("../{seed}\r\n\"/x", "cat", 1, 0, "png");
+        assert!(name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-')), "{}", name);
+        assert!(!name.starts_with('.'));
+        assert_eq!(attachment(&name), format!("attachment; filename=\"{}\"", name));
+    }
+}
@@ -0,0 +1,52 @@
+//! `X-Generation-*` response headers carrying the resolved parameters of a
+//! generation, for responses whose body is the image itself
+
+use crate::inference::pipeline::{GenerationResult, METADATA_VERSION};
+
+pub const SEED: &str = "X-Generation-Seed";
+pub const STEPS: &str = "X-Generation-Steps";
+pub const GUIDANCE_SCALE: &str = "X-Generation-Guidance-Scale";
+pub const MODEL: &str = "X-Generation-Model";
+pub const SCHEDULER: &str = "X-Generation-Scheduler";
+pub const METADATA_VERSION_HEADER: &str = "X-Generation-Metadata-Version";
+
+/// Percent-encodes everything outside printable ASCII, plus '%' itself, so
+/// configured strings such as model ids can't break the header line
+pub fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if (0x20..0x7f).contains(&byte) && byte != b'%' {
+            escaped.push(byte as char);
+        } else {
+            escaped.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    escaped
+}
+
+/// Every header for `result`, values already escaped. `seed` is that of the
+/// returned image, which differs from the batch seed after a retry or when
+/// an earlier image failed.
+pub fn for_result(result: &GenerationResult, seed: i64) -> [(&'static str, String); 6] {
+    [
+        (METADATA_VERSION_HEADER, METADATA_VERSION.to_string()),
+        (SEED, seed.to_string()),
+        (STEPS, result.steps_taken.to_string()),
+        (GUIDANCE_SCALE, result.guidance_scale.to_string()),
+        (MODEL, escape(&result.model_used)),
+        (SCHEDULER, escape(result.scheduler.as_str())),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_values_are_escaped() {
+        assert_eq!(escape("sd-1.5"), "sd-1.5");
+        assert_eq!(escape("bad\r\nSet-Cookie: x"), "bad%0D%0ASet-Cookie: x");
+        assert_eq!(escape("50%"), "50%25");
+        assert_eq!(escape("café"), "caf%C3%A9");
+    }
+}
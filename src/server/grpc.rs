@@ -1,8 +1,27 @@
-use crate::config::Config;
+use crate::config::{Config, InferenceConfig, KeyPriorityConfig};
 use crate::errors::DiffusionError;
-use crate::inference::pipeline::InferencePipeline;
-use crate::queue::memory::MemoryQueue;
-use tonic::{transport::Server, Request, Response, Status};
+use crate::inference::pipeline::{
+    InferencePipeline, MAX_CLIP_SKIP, MAX_DPI, MAX_TEMPERATURE, MAX_UPSCALE, MIN_DIMENSION,
+};
+use crate::inference::{
+    format, negative_prompts, palette, prompt_embeds, prompt_schedule, references, OutputFormat,
+    QualityPreset, StrengthPreset,
+};
+use crate::queue::events;
+use crate::queue::memory::{CancelGuard, MemoryQueue};
+use crate::server::client_metadata;
+use crate::server::result_id;
+use crate::server::health::{self, HealthSnapshot};
+use crate::server::parameters::{self, ParameterDefault};
+use crate::server::quota::{QuotaDecision, QuotaTracker};
+use crate::server::uploads::UploadStore;
+use futures::{Stream, StreamExt};
+use std::collections::{BTreeMap, HashMap};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tonic::metadata::MetadataMap;
+use tonic::{transport::Server, Code, Request, Response, Status, Streaming};
 use tracing::info;
 
 pub mod proto {
@@ -12,12 +31,285 @@ pub mod proto {
 use proto::diffusion_service_server::{DiffusionService, DiffusionServiceServer};
 use proto::*;
 
-type JobQueue = MemoryQueue<GenerateImageRequest, GenerateImageResponse>;
+pub type JobQueue = MemoryQueue<GenerateImageRequest, GenerateImageResponse>;
+
+const SEED_METADATA_KEY: &str = "x-generation-seed";
+const API_KEY_METADATA_KEY: &str = "x-api-key";
+const RETRYABLE_METADATA_KEY: &str = "x-retryable";
+const ATTEMPTS_METADATA_KEY: &str = "x-attempts-made";
+const RETRY_AFTER_METADATA_KEY: &str = "retry-after";
+const QUOTA_REMAINING_METADATA_KEY: &str = "x-quota-remaining";
+const ADMIN_KEY_METADATA_KEY: &str = "x-admin-key";
+
+/// Same check as the REST admin endpoints: refused outright unless an
+/// `admin_key` is configured
+fn authorize_admin(metadata: &MetadataMap, config: &Config) -> std::result::Result<(), Status> {
+    let expected = match &config.server.admin_key {
+        Some(key) if !key.is_empty() => key,
+        _ => return Err(Status::permission_denied("Admin API is disabled")),
+    };
+    
+    let provided = metadata
+        .get(ADMIN_KEY_METADATA_KEY)
+        .and_then(|v| v.to_str().ok());
+    
+    if provided != Some(expected.as_str()) {
+        return Err(Status::unauthenticated("Invalid admin key"));
+    }
+    
+    Ok(())
+}
+
+/// Checks a non-default guidance_scale against the range of the scheduler
+/// the job runs with: the request's, its quality preset's, else the model's
+/// default. An unknown model or scheduler is reported by the pipeline.
+fn check_guidance(pipeline: &InferencePipeline, req: &GenerateImageRequest) -> std::result::Result<(), Status> {
+    if req.guidance_scale == 0.0 {
+        return Ok(());
+    }
+    
+    let requested = req.scheduler.clone().or_else(|| {
+        let quality = req.quality.as_deref()?.parse::<QualityPreset>().ok()?;
+        Some(quality.scheduler().to_string())
+    });
+    let scheduler = pipeline
+        .models()
+        .resolve(Some(&req.model_id))
+        .and_then(|model_id| pipeline.resolve_scheduler(requested.as_deref(), &model_id));
+    if let Ok(scheduler) = scheduler {
+        let (min, max) = pipeline.guidance_range(scheduler);
+        if !(min..=max).contains(&req.guidance_scale) {
+            return Err(Status::invalid_argument(format!(
+                "guidance_scale must be between {} and {} for the {} scheduler (or 0 for the default)",
+                min, max, scheduler
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Checks an integer field at the proto boundary. 0 is the proto3 default
+/// and means "use the server default".
+fn check_int_field(field: &str, value: i32, min: i32, max: i32) -> std::result::Result<(), Status> {
+    if value != 0 && (value < min || value > max) {
+        return Err(Status::invalid_argument(format!(
+            "{} must be between {} and {} (or 0 for the default), got {}",
+            field, min, max, value
+        )));
+    }
+    Ok(())
+}
+
+/// Status for a failed generation. Retryable failures are UNAVAILABLE, the
+/// rest INTERNAL; either way the retry advice goes in the metadata.
+fn failure_status(e: &DiffusionError) -> Status {
+    if let DiffusionError::DeadlineExceeded = e {
+        return Status::deadline_exceeded(e.to_string());
+    }
+    
+    let retry = e.retry_info();
+    let code = if retry.retryable { Code::Unavailable } else { Code::Internal };
+    
+    let mut metadata = MetadataMap::new();
+    let advice = [
+        (RETRYABLE_METADATA_KEY, Some(retry.retryable.to_string())),
+        (ATTEMPTS_METADATA_KEY, Some(retry.attempts_made.to_string())),
+        (RETRY_AFTER_METADATA_KEY, retry.retry_after_seconds.map(|secs| secs.to_string())),
+    ];
+    for (key, value) in advice {
+        if let Some(Ok(value)) = value.map(|value| value.parse()) {
+            metadata.insert(key, value);
+        }
+    }
+    
+    Status::with_metadata(code, format!("Generation failed: {}", e), metadata)
+}
+
+/// Joins the chunks of an upload, failing as soon as they exceed `max_bytes`
+async fn read_upload(
+    mut chunks: impl Stream<Item = std::result::Result<ImageChunk, Status>> + Unpin,
+    max_bytes: usize,
+) -> std::result::Result<Vec<u8>, Status> {
+    let mut data = Vec::new();
+    
+    while let Some(chunk) = chunks.next().await.transpose()? {
+        if data.len() + chunk.data.len() > max_bytes {
+            return Err(Status::out_of_range(
+                format!("Upload exceeds the {} byte limit", max_bytes),
+            ));
+        }
+        data.extend_from_slice(&chunk.data);
+    }
+    
+    if image::guess_format(&data).is_err() {
+        return Err(Status::invalid_argument("Upload is not a recognized image format"));
+    }
+    
+    Ok(data)
+}
+
+/// Rejects out-of-range values before they are enqueued, so malformed
+/// clients fail fast instead of occupying a queue slot
+fn validate_request(req: &GenerateImageRequest, config: &InferenceConfig) -> std::result::Result<(), Status> {
+    // The step range depends on the model, so the upper end is checked
+    // against it by the caller
+    if req.num_inference_steps < 0 {
+        return Err(Status::invalid_argument(
+            "num_inference_steps must be positive (or 0 for the model's default)",
+        ));
+    }
+    check_int_field("width", req.width, MIN_DIMENSION, config.max_width)?;
+    check_int_field("height", req.height, MIN_DIMENSION, config.max_height)?;
+    check_int_field("num_images", req.num_images, 1, config.max_batch_size)?;
+    
+    if let Some(clip_skip) = req.clip_skip {
+        if clip_skip < 1 || clip_skip > MAX_CLIP_SKIP {
+            return Err(Status::invalid_argument(
+                format!("clip_skip must be between 1 and {}, got {}", MAX_CLIP_SKIP, clip_skip),
+            ));
+        }
+    }
+    
+    // The range depends on the scheduler, so is checked by the caller
+    if !req.guidance_scale.is_finite() {
+        return Err(Status::invalid_argument("guidance_scale must be finite"));
+    }
+    
+    if let Some(strength) = req.strength {
+        if !(0.0..=1.0).contains(&strength) {
+            return Err(Status::invalid_argument("strength must be between 0.0 and 1.0"));
+        }
+    }
+    
+    if req.deadline_ms == Some(0) {
+        return Err(Status::invalid_argument("deadline_ms must be positive"));
+    }
+    
+    if let Some(megapixels) = req.megapixels {
+        if !(megapixels.is_finite() && megapixels > 0.0) {
+            return Err(Status::invalid_argument("megapixels must be positive"));
+        }
+        if req.width != 0 || req.height != 0 {
+            return Err(Status::invalid_argument("megapixels cannot be combined with explicit width/height"));
+        }
+    }
+    
+    if let Some(upscale) = req.upscale {
+        if !(1..=MAX_UPSCALE).contains(&upscale) {
+            return Err(Status::invalid_argument(format!("upscale must be between 1 and {}", MAX_UPSCALE)));
+        }
+    }
+    
+    if let Some(strength) = req.subseed_strength {
+        if req.subseed.is_none() {
+            return Err(Status::invalid_argument("subseed_strength requires a subseed"));
+        }
+        if !(0.0..=1.0).contains(&strength) {
+            return Err(Status::invalid_argument("subseed_strength must be between 0.0 and 1.0"));
+        }
+    }
+    
+    if let Some(temperature) = req.temperature {
+        if !(0.0..=MAX_TEMPERATURE).contains(&temperature) {
+            return Err(Status::invalid_argument(
+                format!("temperature must be between 0.0 and {}", MAX_TEMPERATURE),
+            ));
+        }
+    }
+    
+    if let Some(preset) = &req.strength_preset {
+        if req.strength.is_some() {
+            return Err(Status::invalid_argument("strength and strength_preset are mutually exclusive"));
+        }
+        preset.parse::<StrengthPreset>().map_err(Status::invalid_argument)?;
+    }
+    
+    if let Some(weight) = req.face_restore_weight {
+        if !(0.0..=1.0).contains(&weight) {
+            return Err(Status::invalid_argument("face_restore_weight must be between 0.0 and 1.0"));
+        }
+    }
+    
+    if let Some(dpi) = req.dpi {
+        if dpi < 1 || dpi > MAX_DPI {
+            return Err(Status::invalid_argument(format!("dpi must be between 1 and {}", MAX_DPI)));
+        }
+    }
+    
+    if let Some(format) = &req.format {
+        format.parse::<OutputFormat>().map_err(Status::invalid_argument)?;
+    }
+    format::parse_list(&req.output_formats).map_err(Status::invalid_argument)?;
+    
+    if let Some(quality) = &req.quality {
+        quality.parse::<QualityPreset>().map_err(Status::invalid_argument)?;
+    }
+    
+    if !req.prompt_embeds.is_empty() {
+        if !req.prompt.is_empty() || !req.prompt_schedule.is_empty() {
+            return Err(Status::invalid_argument(
+                "prompt_embeds replaces the prompt; prompt and prompt_schedule must be empty",
+            ));
+        }
+        if config.moderation.enabled && !config.moderation.allow_prompt_embeds {
+            return Err(Status::invalid_argument(
+                "prompt_embeds can't be moderated, so it is unavailable while moderation is enabled",
+            ));
+        }
+    }
+    
+    let schedule: Vec<(f64, String)> = req.prompt_schedule
+        .iter()
+        .map(|entry| (entry.fraction, entry.prompt.clone()))
+        .collect();
+    prompt_schedule::validate(&schedule).map_err(Status::invalid_argument)?;
+    
+    let negatives: Vec<(String, f64)> = req.negative_prompts
+        .iter()
+        .map(|entry| (entry.prompt.clone(), entry.weight))
+        .collect();
+    if !negatives.is_empty() && !req.negative_prompt.is_empty() {
+        return Err(Status::invalid_argument("negative_prompt and negative_prompts are mutually exclusive"));
+    }
+    negative_prompts::validate(&negatives).map_err(Status::invalid_argument)?;
+    
+    let references: Vec<(&[u8], f64)> = req.reference_images
+        .iter()
+        .map(|reference| (reference.image.as_slice(), reference.weight))
+        .collect();
+    references::validate(&references).map_err(Status::invalid_argument)?;
+    
+    if let Some(region) = &req.vary_region {
+        if region.image.is_empty() || region.mask.is_empty() {
+            return Err(Status::invalid_argument("vary_region needs both an image and a mask"));
+        }
+        if !req.init_image.is_empty() || !req.init_image_handle.is_empty() {
+            return Err(Status::invalid_argument("vary_region and init_image are mutually exclusive"));
+        }
+    }
+    palette::parse(&req.color_palette).map_err(Status::invalid_argument)?;
+    
+    Ok(())
+}
+
+/// Priority class for a request from an API key tier. The request's own
+/// class is kept when it weighs no more than the tier's `max_priority`,
+/// otherwise it is lowered to that cap.
+fn tier_priority(requested: Option<&str>, tier: &KeyPriorityConfig, weights: &BTreeMap<String, u32>) -> String {
+    let weight = |class: &str| weights.get(class).copied().unwrap_or(0);
+    match (requested, &tier.max_priority) {
+        (Some(requested), Some(cap)) if weight(requested) <= weight(cap) => requested.to_string(),
+        (Some(_), Some(cap)) => cap.clone(),
+        _ => tier.priority.clone(),
+    }
+}
 
 pub struct DiffusionGrpcService {
     config: Config,
     pipeline: InferencePipeline,
     queue: JobQueue,
+    uploads: UploadStore,
+    quotas: Arc<QuotaTracker>,
 }
 
 impl DiffusionGrpcService {
@@ -26,83 +318,321 @@ impl DiffusionGrpcService {
         pipeline: InferencePipeline,
         queue: JobQueue,
     ) -> Self {
-        Self { config, pipeline, queue }
+        let uploads = UploadStore::new(Duration::from_secs(config.server.upload_ttl_seconds));
+        let quotas = Arc::new(QuotaTracker::new(
+            config.queue.key_quotas.clone(),
+            config.queue.default_key_quota,
+            config.queue.quota_action,
+        ));
+        Self { config, pipeline, queue, uploads, quotas }
+    }
+    
+    /// Meters against `quotas`, shared with the REST server, instead of a
+    /// tracker of its own
+    pub fn with_quotas(mut self, quotas: Arc<QuotaTracker>) -> Self {
+        self.quotas = quotas;
+        self
     }
 }
 
 #[tonic::async_trait]
 impl DiffusionService for DiffusionGrpcService {
+    type SubscribeJobEventsStream =
+        Pin<Box<dyn Stream<Item = std::result::Result<JobEvent, Status>> + Send>>;
+    
     async fn generate_image(
         &self,
         request: Request<GenerateImageRequest>,
     ) -> std::result::Result<Response<GenerateImageResponse>, Status> {
-        let req = request.into_inner();
+        let api_key = request
+            .metadata()
+            .get(API_KEY_METADATA_KEY)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        let mut req = request.into_inner();
         
         info!("Received generation request: {}", req.prompt);
         
+        let maintenance = &self.config.server.maintenance;
+        if maintenance.enabled {
+            return Err(Status::unavailable(maintenance.message.clone()));
+        }
+        
+        validate_request(&req, &self.config.inference)?;
+        let (min_steps, max_steps, _) = self.pipeline.step_range(Some(&req.model_id));
+        check_int_field("num_inference_steps", req.num_inference_steps, min_steps, max_steps)?;
+        if !req.prompt_embeds.is_empty() {
+            // The shape depends on the model's text encoder
+            let dim = self.pipeline.encoder_dim(Some(&req.model_id));
+            prompt_embeds::parse(&req.prompt_embeds, dim).map_err(Status::invalid_argument)?;
+        }
+        check_guidance(&self.pipeline, &req)?;
+        if let Some(priority) = &req.priority {
+            let classes = &self.config.queue.class_weights;
+            if !classes.is_empty() && !classes.contains_key(priority) {
+                return Err(Status::invalid_argument(format!("Unknown priority class '{}'", priority)));
+            }
+        }
+        let queue_config = &self.config.queue;
+        let tier = api_key
+            .as_ref()
+            .and_then(|key| queue_config.key_priorities.get(key))
+            .or(queue_config.default_key_priority.as_ref());
+        if let Some(tier) = tier {
+            req.priority = Some(tier_priority(req.priority.as_deref(), tier, &queue_config.class_weights));
+        }
+        client_metadata::validate(&req.metadata, &self.config.server)
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        
+        if !req.init_image_handle.is_empty() {
+            if !req.init_image.is_empty() {
+                return Err(Status::invalid_argument(
+                    "init_image and init_image_handle are mutually exclusive",
+                ));
+            }
+            req.init_image = self.uploads
+                .get(&req.init_image_handle)
+                .await
+                .ok_or_else(|| Status::not_found("Unknown or expired init_image_handle"))?;
+        }
+        
+        // Request format > gRPC default > global default (in the pipeline)
+        if req.format.is_none() {
+            req.format = self.config.server.grpc_output_format.clone();
+        }
+        
+        // Held until the response, so draining the model waits for this job
+        let _admission = self.pipeline
+            .models()
+            .admit(Some(&req.model_id))
+            .map_err(|e| match e {
+                DiffusionError::Overloaded(message) => Status::unavailable(message),
+                e => Status::invalid_argument(e.to_string()),
+            })?;
+        
+        // Counted last, so requests rejected above don't use up quota
+        let mut quota = None;
+        match self.quotas.reserve(api_key.as_deref(), req.num_images.max(1) as u32) {
+            QuotaDecision::Unmetered => {}
+            QuotaDecision::Granted(reservation) => {
+                req.num_images = reservation.images as i32;
+                quota = Some(reservation);
+            }
+            QuotaDecision::Exceeded { remaining } => {
+                let mut metadata = MetadataMap::new();
+                metadata.insert(QUOTA_REMAINING_METADATA_KEY, remaining.into());
+                return Err(Status::with_metadata(
+                    Code::ResourceExhausted,
+                    DiffusionError::QuotaExceeded { remaining }.to_string(),
+                    metadata,
+                ));
+            }
+        }
+        let refund = || {
+            if let Some(reservation) = &quota {
+                self.quotas.refund(reservation);
+            }
+        };
+        
         // Enqueue job
-        let (job_id, rx) = self.queue
-            .enqueue(req)
-            .await
-            .map_err(|e| Status::resource_exhausted(format!("Queue full: {}", e)))?;
+        let enqueued = self.queue
+            .enqueue_with_retry(
+                req,
+                queue_config.enqueue_retries,
+                Duration::from_millis(queue_config.enqueue_retry_backoff_ms),
+                Duration::from_millis(queue_config.enqueue_retry_budget_ms),
+            )
+            .await;
+        let (job_id, rx) = match enqueued {
+            Ok(enqueued) => enqueued,
+            Err(e) => {
+                refund();
+                return Err(Status::resource_exhausted(format!("Queue full: {}", e)));
+            }
+        };
         
-        // Wait for result
-        let result = rx
-            .await
-            .map_err(|_| Status::internal("Worker dropped response"))?
-            .map_err(|e| Status::internal(format!("Generation failed: {}", e)))?;
+        // Wait for result. If the client disconnects first, tonic drops this
+        // future and the guard takes the job back out of the queue, refunding
+        // its quota. A job a worker already has still counts.
+        let mut cancel_guard = CancelGuard::new(&self.queue, &job_id);
+        if let Some(reservation) = quota.clone() {
+            let quotas = Arc::clone(&self.quotas);
+            cancel_guard = cancel_guard.on_cancel(move || quotas.refund(&reservation));
+        }
+        let result = rx.await;
+        cancel_guard.disarm();
+        let result = result
+            .map_err(|_| Status::internal("Worker dropped response"))
+            .and_then(|result| result.map_err(|e| failure_status(&e)))
+            .map_err(|status| {
+                refund();
+                status
+            })?;
+        
+        let seed = result.metadata.as_ref().map(|m| m.seed);
+        let mut response = Response::new(result);
+        if let Some(seed) = seed {
+            if let Ok(value) = seed.to_string().parse() {
+                response.metadata_mut().insert(SEED_METADATA_KEY, value);
+            }
+        }
+        if let Some(reservation) = &quota {
+            response.metadata_mut().insert(QUOTA_REMAINING_METADATA_KEY, reservation.remaining.into());
+        }
         
-        Ok(Response::new(result))
+        Ok(response)
     }
     
     async fn get_job_status(
         &self,
         request: Request<JobStatusRequest>,
     ) -> std::result::Result<Response<JobStatusResponse>, Status> {
-        let req = request.into_inner();
+        let mut req = request.into_inner();
+        // A result id names the job plus one image's seed
+        if let Some(job_id) = result_id::decode(&req.job_id).map(|(job_id, _)| job_id.to_string()) {
+            req.job_id = job_id;
+        }
         
         let status = self.queue.get_status(&req.job_id).await;
         
         match status {
             Some(s) => {
                 let status_str = format!("{:?}", s);
+                // Only jobs whose client went away (e.g. recovered after a
+                // restart) have a stored result
+                let (result, error) = match self.queue.result(&req.job_id).await {
+                    Some(Ok(result)) => (Some(result), None),
+                    Some(Err(error)) => (None, Some(error)),
+                    None => (None, None),
+                };
                 Ok(Response::new(JobStatusResponse {
                     job_id: req.job_id,
                     status: status_str,
-                    result: None,
-                    error: None,
+                    result,
+                    error,
                 }))
             }
             None => Err(Status::not_found("Job not found")),
         }
     }
     
+    async fn upload_image(
+        &self,
+        request: Request<Streaming<ImageChunk>>,
+    ) -> std::result::Result<Response<UploadImageResponse>, Status> {
+        let data = read_upload(request.into_inner(), self.config.server.max_upload_bytes).await?;
+        let size_bytes = data.len() as i64;
+        let handle = self.uploads.insert(data).await;
+        
+        info!("Stored {} byte upload as {}", size_bytes, handle);
+        
+        Ok(Response::new(UploadImageResponse {
+            handle,
+            size_bytes,
+            expires_in_seconds: self.uploads.ttl().as_secs() as i64,
+        }))
+    }
+    
+    async fn subscribe_job_events(
+        &self,
+        request: Request<SubscribeJobEventsRequest>,
+    ) -> std::result::Result<Response<Self::SubscribeJobEventsStream>, Status> {
+        // Every client's jobs are in the feed
+        authorize_admin(request.metadata(), &self.config)?;
+        
+        let stream = events::event_stream(self.queue.subscribe()).map(|item| {
+            Ok(match item {
+                Ok(event) => JobEvent {
+                    job_id: event.job_id,
+                    status: event.status.as_str().to_string(),
+                    timestamp: event.timestamp,
+                    skipped: 0,
+                },
+                Err(skipped) => JobEvent {
+                    skipped,
+                    ..Default::default()
+                },
+            })
+        });
+        
+        Ok(Response::new(Box::pin(stream)))
+    }
+    
     async fn health_check(
         &self,
         _request: Request<HealthCheckRequest>,
     ) -> std::result::Result<Response<HealthCheckResponse>, Status> {
         let queue_len = self.queue.queue_length().await;
         
+        let models = self.pipeline.models().states();
+        let status = health::evaluate(&self.config.health, &HealthSnapshot {
+            queue_length: queue_len,
+            max_queue_size: self.config.queue.max_queue_size,
+            failure_rate: self.pipeline.failure_rate(self.config.health.failure_min_samples),
+            models: &models,
+            maintenance: self.config.server.maintenance.enabled,
+        });
+        
+        let mut system_info: HashMap<String, String> = models
+            .into_iter()
+            .map(|(id, state)| (format!("model.{}", id), state.as_str().to_string()))
+            .collect();
+        system_info.insert("total_generations".to_string(), self.pipeline.total_generations().to_string());
+        
         Ok(Response::new(HealthCheckResponse {
-            status: "healthy".to_string(),
+            status: status.as_str().to_string(),
             model_loaded: true,
             queue_length: queue_len as i32,
             active_workers: self.config.queue.worker_threads as i32,
-            system_info: Default::default(),
+            system_info,
         }))
     }
+    
+    async fn get_parameter_schema(
+        &self,
+        _request: Request<ParameterSchemaRequest>,
+    ) -> std::result::Result<Response<ParameterSchemaResponse>, Status> {
+        let parameters = parameters::schema(&self.config.inference)
+            .into_iter()
+            .map(|spec| ParameterSpec {
+                name: spec.name.to_string(),
+                r#type: spec.kind.to_string(),
+                min: spec.min,
+                max: spec.max,
+                default: spec.default.map(|default| match default {
+                    ParameterDefault::Number(n) => parameter_spec::Default::DefaultNumber(n),
+                    ParameterDefault::Text(t) => parameter_spec::Default::DefaultText(t),
+                }),
+                options: spec.options,
+            })
+            .collect();
+        
+        Ok(Response::new(ParameterSchemaResponse { parameters }))
+    }
 }
 
 pub async fn start_grpc_server(
     config: Config,
     pipeline: InferencePipeline,
     queue: JobQueue,
+    quotas: Arc<QuotaTracker>,
 ) -> Result<(), DiffusionError> {
     let addr = format!("{}:{}", config.server.grpc_host, config.server.grpc_port)
         .parse()
         .map_err(|e| DiffusionError::Config(format!("Invalid address: {}", e)))?;
     
-    let service = DiffusionGrpcService::new(config, pipeline, queue);
+    let queue_config = &config.queue;
+    // Keys are secrets, so errors name only the class
+    for tier in queue_config.key_priorities.values().chain(&queue_config.default_key_priority) {
+        let mut classes = std::iter::once(&tier.priority).chain(&tier.max_priority);
+        if let Some(class) = classes.find(|c| !queue_config.class_weights.contains_key(*c)) {
+            return Err(DiffusionError::Config(
+                format!("key_priorities uses unknown priority class '{}'", class)
+            ));
+        }
+    }
+    
+    let service = DiffusionGrpcService::new(config, pipeline, queue).with_quotas(quotas);
     
     info!("Starting gRPC server on {}", addr);
     
@@ -114,3 +644,270 @@ pub async fn start_grpc_server(
     
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ModelDefinition;
+    use crate::inference::pipeline::tests::{mock_config, mock_pipeline, model};
+    
+    fn png() -> Vec<u8> {
+        let mut bytes = Vec::new();
+        image::DynamicImage::new_rgb8(64, 64)
+            .write_to(&mut std::io::Cursor::new(&mut bytes), image::ImageOutputFormat::Png)
+            .unwrap();
+        bytes
+    }
+    
+    fn chunks(data: &[u8], size: usize) -> impl Stream<Item = std::result::Result<ImageChunk, Status>> + Unpin {
+        let chunks: Vec<_> = data.chunks(size).map(|chunk| Ok(ImageChunk { data: chunk.to_vec() })).collect();
+        futures::stream::iter(chunks)
+    }
+    
+    #[tokio::test]
+    async fn chunked_upload_is_reassembled() {
+        let image = png();
+        assert_eq!(read_upload(chunks(&image, 16), image.len()).await.unwrap(), image);
+    }
+    
+    #[tokio::test]
+    async fn oversized_or_non_image_upload_is_rejected() {
+        let image = png();
+        let err = read_upload(chunks(&image, 16), image.len() - 1).await.unwrap_err();
+        assert_eq!(err.code(), Code::OutOfRange);
+        
+        let err = read_upload(chunks(b"not an image", 4), 1024).await.unwrap_err();
+        assert_eq!(err.code(), Code::InvalidArgument);
+    }
+    
+    #[test]
+    fn integer_fields_are_bounded() {
+        let config = Config::default().inference;
+        let request = |set: &dyn Fn(&mut GenerateImageRequest, i32), value| {
+            let mut req = GenerateImageRequest { prompt: "a lighthouse".to_string(), ..Default::default() };
+            set(&mut req, value);
+            validate_request(&req, &config)
+        };
+        let fields: [(&str, &dyn Fn(&mut GenerateImageRequest, i32)); 4] = [
+            ("num_inference_steps", &|req, value| req.num_inference_steps = value),
+            ("width", &|req, value| req.width = value),
+            ("height", &|req, value| req.height = value),
+            ("num_images", &|req, value| req.num_images = value),
+        ];
+        
+        for (field, set) in fields {
+            // 0 is the proto default: use the server's
+            assert!(request(set, 0).is_ok(), "{}", field);
+            for value in [-1, i32::MIN] {
+                assert_eq!(request(set, value).unwrap_err().code(), Code::InvalidArgument, "{} = {}", field, value);
+            }
+            if field != "num_inference_steps" {
+                assert_eq!(request(set, i32::MAX).unwrap_err().code(), Code::InvalidArgument, "{}", field);
+            }
+        }
+        // Steps are checked against the model's own range
+        assert!(check_int_field("num_inference_steps", i32::MAX, 1, config.max_steps).is_err());
+        assert!(check_int_field("num_inference_steps", 0, 1, config.max_steps).is_ok());
+    }
+    
+    /// Sends `req` through `generate_image` with a stand-in worker that
+    /// answers the job, and returns the request as the worker received it
+    async fn enqueued_request(config: Config, req: GenerateImageRequest) -> GenerateImageRequest {
+        let queue = JobQueue::new(config.queue.max_queue_size);
+        let service = DiffusionGrpcService::new(config.clone(), mock_pipeline(&config), queue.clone());
+        let worker = tokio::spawn(async move {
+            loop {
+                if let Some(job) = queue.dequeue().await {
+                    job.respond(Ok(GenerateImageResponse::default()));
+                    return job.request;
+                }
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        });
+        service.generate_image(Request::new(req)).await.unwrap();
+        worker.await.unwrap()
+    }
+    
+    #[tokio::test]
+    async fn grpc_uses_its_default_format_unless_the_request_sets_one() {
+        let mut config = mock_config();
+        config.server.rest_output_format = Some("jpeg".to_string());
+        config.server.grpc_output_format = Some("webp".to_string());
+        let request = |format: Option<&str>| GenerateImageRequest {
+            prompt: "a lighthouse".to_string(),
+            format: format.map(str::to_string),
+            ..Default::default()
+        };
+        
+        let received = enqueued_request(config.clone(), request(None)).await;
+        assert_eq!(received.format.as_deref(), Some("webp"));
+        let received = enqueued_request(config.clone(), request(Some("png"))).await;
+        assert_eq!(received.format.as_deref(), Some("png"));
+        
+        // Without a transport default the pipeline's global default applies
+        config.server.grpc_output_format = None;
+        assert_eq!(enqueued_request(config, request(None)).await.format, None);
+    }
+    
+    #[test]
+    fn guidance_is_checked_against_the_scheduler_floor() {
+        let config = mock_config();
+        let pipeline = mock_pipeline(&config);
+        let request = |scheduler: &str, guidance_scale: f64| GenerateImageRequest {
+            prompt: "a lighthouse".to_string(),
+            scheduler: Some(scheduler.to_string()),
+            guidance_scale,
+            ..Default::default()
+        };
+        
+        // 1.5 is inside the global range but below euler_ancestral's floor
+        assert!(check_guidance(&pipeline, &request("ddim", 1.5)).is_ok());
+        let err = check_guidance(&pipeline, &request("euler_ancestral", 1.5)).unwrap_err();
+        assert_eq!(err.code(), Code::InvalidArgument);
+        assert!(err.message().contains("euler_ancestral scheduler"), "{}", err.message());
+        assert!(check_guidance(&pipeline, &request("euler_ancestral", 0.0)).is_ok());
+        
+        // The draft preset runs euler_ancestral too
+        let draft = GenerateImageRequest { scheduler: None, quality: Some("draft".to_string()), ..request("", 1.5) };
+        assert!(check_guidance(&pipeline, &draft).is_err());
+    }
+    
+    #[tokio::test]
+    async fn premium_key_dequeues_before_earlier_free_key() {
+        let mut config = mock_config();
+        config.queue.class_weights = [("high", 8), ("normal", 2), ("low", 1)]
+            .into_iter()
+            .map(|(class, weight)| (class.to_string(), weight))
+            .collect();
+        let tier = |priority: &str, max_priority: Option<&str>| KeyPriorityConfig {
+            priority: priority.to_string(),
+            max_priority: max_priority.map(str::to_string),
+        };
+        config.queue.key_priorities.insert("premium-key".to_string(), tier("high", Some("high")));
+        config.queue.default_key_priority = Some(tier("low", Some("normal")));
+        
+        let queue = JobQueue::new(config.queue.max_queue_size)
+            .with_class_weights(&config.queue.class_weights, |req| req.priority.as_deref());
+        let service = Arc::new(DiffusionGrpcService::new(config.clone(), mock_pipeline(&config), queue.clone()));
+        let submit = |prompt: &str, key: Option<&str>, priority: Option<&str>| {
+            let mut request = Request::new(GenerateImageRequest {
+                prompt: prompt.to_string(),
+                priority: priority.map(str::to_string),
+                ..Default::default()
+            });
+            if let Some(key) = key {
+                request.metadata_mut().insert(API_KEY_METADATA_KEY, key.parse().unwrap());
+            }
+            let service = Arc::clone(&service);
+            tokio::spawn(async move { service.generate_image(request).await })
+        };
+        
+        // Unlisted and missing keys fall back to the default tier, capped
+        // below "high" even when they ask for it
+        let mut calls = vec![submit("free", Some("free-key"), None), submit("anonymous", None, Some("high"))];
+        while queue.queue_length().await < 2 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        calls.push(submit("premium", Some("premium-key"), None));
+        while queue.queue_length().await < 3 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        
+        let mut dequeued = Vec::new();
+        while let Some(job) = queue.dequeue().await {
+            dequeued.push((job.request.prompt.clone(), job.request.priority.clone()));
+            job.respond(Ok(GenerateImageResponse::default()));
+        }
+        assert_eq!(dequeued[0], ("premium".to_string(), Some("high".to_string())));
+        assert!(dequeued.contains(&("free".to_string(), Some("low".to_string()))));
+        assert!(dequeued.contains(&("anonymous".to_string(), Some("normal".to_string()))));
+        for call in calls {
+            call.await.unwrap().unwrap();
+        }
+    }
+    
+    #[tokio::test]
+    async fn dropping_the_handler_cancels_its_queued_job() {
+        let config = mock_config();
+        let queue = JobQueue::new(config.queue.max_queue_size);
+        let service = Arc::new(DiffusionGrpcService::new(config.clone(), mock_pipeline(&config), queue.clone()));
+        
+        // No worker, so the handler waits on the queued job until aborted
+        let handler = {
+            let service = Arc::clone(&service);
+            tokio::spawn(async move {
+                let request = GenerateImageRequest { prompt: "a lighthouse".to_string(), ..Default::default() };
+                service.generate_image(Request::new(request)).await
+            })
+        };
+        while queue.queue_length().await == 0 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        
+        handler.abort();
+        for _ in 0..100 {
+            if queue.queue_length().await == 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        assert_eq!(queue.queue_length().await, 0);
+        assert!(queue.dequeue().await.is_none());
+    }
+    
+    #[tokio::test]
+    async fn keyless_requests_share_the_default_quota_and_disconnects_refund_it() {
+        let mut config = mock_config();
+        config.queue.default_key_quota = Some(1);
+        let queue = JobQueue::new(config.queue.max_queue_size);
+        let service = Arc::new(DiffusionGrpcService::new(config.clone(), mock_pipeline(&config), queue.clone()));
+        let request = || Request::new(GenerateImageRequest { prompt: "a lighthouse".to_string(), ..Default::default() });
+        
+        // No worker, so the first request waits queued on the day's only image
+        let handler = {
+            let service = Arc::clone(&service);
+            tokio::spawn(async move { service.generate_image(request()).await })
+        };
+        while queue.queue_length().await == 0 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        let err = service.generate_image(request()).await.unwrap_err();
+        assert_eq!(err.code(), Code::ResourceExhausted);
+        assert_eq!(err.metadata().get(QUOTA_REMAINING_METADATA_KEY).unwrap().to_str().unwrap(), "0");
+        
+        handler.abort();
+        while queue.queue_length().await > 0 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        
+        // Refunded, so the next one is queued and waits instead of failing
+        let waiting = tokio::time::timeout(Duration::from_millis(50), service.generate_image(request())).await;
+        assert!(waiting.is_err());
+    }
+    
+    #[tokio::test]
+    async fn prompt_embeds_must_fit_the_model_and_pass_moderation() {
+        let embeds = |dim: usize| crate::inference::latents::to_safetensors(&[1, 77, dim], &vec![0.5; 77 * dim]);
+        let request = |dim: usize| GenerateImageRequest {
+            prompt_embeds: embeds(dim),
+            model_id: "sd2".to_string(),
+            ..Default::default()
+        };
+        let mut config = mock_config();
+        config.model.models = vec![ModelDefinition { encoder_dim: Some(1024), ..model("sd2") }];
+        assert!(validate_request(&request(768), &config.inference).is_ok());
+        
+        let queue = JobQueue::new(config.queue.max_queue_size);
+        let service = DiffusionGrpcService::new(config.clone(), mock_pipeline(&config), queue.clone());
+        let err = service.generate_image(Request::new(request(768))).await.unwrap_err();
+        assert_eq!(err.code(), Code::InvalidArgument);
+        assert_eq!(queue.queue_length().await, 0);
+        
+        config.inference.moderation.enabled = true;
+        let err = validate_request(&request(1024), &config.inference).unwrap_err();
+        assert_eq!(err.code(), Code::InvalidArgument);
+        config.inference.moderation.allow_prompt_embeds = true;
+        assert!(validate_request(&request(1024), &config.inference).is_ok());
+    }
+}
@@ -0,0 +1,122 @@
+use crate::config::HealthConfig;
+use crate::inference::ModelState;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HealthStatus {
+    Healthy,
+    Degraded,
+    Unhealthy,
+    /// Planned downtime, see `server.maintenance`
+    Maintenance,
+}
+
+impl HealthStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HealthStatus::Healthy => "healthy",
+            HealthStatus::Degraded => "degraded",
+            HealthStatus::Unhealthy => "unhealthy",
+            HealthStatus::Maintenance => "maintenance",
+        }
+    }
+}
+
+/// Inputs a health check is judged on
+pub struct HealthSnapshot<'a> {
+    pub queue_length: usize,
+    pub max_queue_size: usize,
+    pub failure_rate: Option<f64>,
+    pub models: &'a HashMap<String, ModelState>,
+    pub maintenance: bool,
+}
+
+/// Maintenance overrides everything else. Unhealthy when a model failed to load or failures pass the unhealthy
+/// threshold; degraded when the queue is filling up or failures pass the
+/// degraded threshold.
+pub fn evaluate(config: &HealthConfig, snapshot: &HealthSnapshot<'_>) -> HealthStatus {
+    if snapshot.maintenance {
+        return HealthStatus::Maintenance;
+    }
+
+    let failure_rate = snapshot.failure_rate.unwrap_or(0.0);
+
+    if snapshot.models.values().any(|state| *state == ModelState::Failed)
+        || failure_rate >= config.unhealthy_failure_rate
+    {
+        return HealthStatus::Unhealthy;
+    }
+
+    let queue_fraction = if snapshot.max_queue_size > 0 {
+        snapshot.queue_length as f64 / snapshot.max_queue_size as f64
+    } else {
+        0.0
+    };
+
+    if queue_fraction >= config.degraded_queue_fraction
+        || failure_rate >= config.degraded_failure_rate
+    {
+        return HealthStatus::Degraded;
+    }
+
+    HealthStatus::Healthy
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::DiffusionError;
+    use crate::inference::outcomes::OutcomeWindow;
+
+    #[test]
+    fn rising_failure_rate_degrades_then_fails_health() {
+        let config = HealthConfig::default();
+        let window = OutcomeWindow::new(config.failure_window);
+        let models = HashMap::from([("default".to_string(), ModelState::Loaded)]);
+        let status = |window: &OutcomeWindow| {
+            evaluate(&config, &HealthSnapshot {
+                queue_length: 0,
+                max_queue_size: 100,
+                failure_rate: window.failure_rate(config.failure_min_samples),
+                models: &models,
+                maintenance: false,
+            })
+        };
+
+        for _ in 0..10 {
+            window.record(&Ok(()));
+        }
+        assert_eq!(status(&window), HealthStatus::Healthy);
+
+        // 3 of 13 failed: 23%
+        for _ in 0..3 {
+            window.record::<()>(&Err(DiffusionError::Inference("boom".to_string())));
+        }
+        assert_eq!(status(&window), HealthStatus::Degraded);
+
+        // 10 of 20 failed: 50%
+        for _ in 0..7 {
+            window.record::<()>(&Err(DiffusionError::Inference("boom".to_string())));
+        }
+        assert_eq!(status(&window), HealthStatus::Unhealthy);
+    }
+
+    #[test]
+    fn full_queue_degrades_and_failed_model_is_unhealthy() {
+        let config = HealthConfig::default();
+        let mut models = HashMap::from([("default".to_string(), ModelState::Loaded)]);
+        fn snapshot(queue_length: usize, models: &HashMap<String, ModelState>) -> HealthSnapshot<'_> {
+            HealthSnapshot {
+                queue_length,
+                max_queue_size: 100,
+                failure_rate: None,
+                models,
+                maintenance: false,
+            }
+        }
+
+        assert_eq!(evaluate(&config, &snapshot(80, &models)), HealthStatus::Degraded);
+        models.insert("other".to_string(), ModelState::Failed);
+        assert_eq!(evaluate(&config, &snapshot(0, &models)), HealthStatus::Unhealthy);
+    }
+}
@@ -1,5 +1,17 @@
+pub mod access_log;
+pub mod archive;
+pub mod client_metadata;
+pub mod download;
+pub mod generation_headers;
 pub mod grpc;
+pub mod health;
+pub mod parameters;
+pub mod problem;
+pub mod quota;
+pub mod recipes;
+pub mod result_id;
 pub mod rest;
+pub mod uploads;
 
 pub use grpc::start_grpc_server;
 pub use rest::start_rest_server;
@@ -0,0 +1,124 @@
+//! Ranges and defaults of the tunable request fields, so clients can build
+//! sliders and pickers without hardcoding the server's limits
+
+use crate::config::InferenceConfig;
+use crate::inference::pipeline::{
+    self, DEFAULT_CLIP_SKIP, DEFAULT_STRENGTH, DEFAULT_TEMPERATURE, MAX_CLIP_SKIP, MAX_DPI, MAX_TEMPERATURE,
+    MAX_UPSCALE, MIN_DIMENSION,
+};
+use crate::inference::SchedulerKind;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum ParameterDefault {
+    Number(f64),
+    Text(String),
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ParameterSpec {
+    pub name: &'static str,
+    /// "integer", "number" or "string"
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default: Option<ParameterDefault>,
+    /// Accepted values of a string parameter
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub options: Vec<String>,
+}
+
+fn integer(name: &'static str, min: i64, max: i64, default: Option<i64>) -> ParameterSpec {
+    ParameterSpec {
+        name,
+        kind: "integer",
+        min: Some(min as f64),
+        max: Some(max as f64),
+        default: default.map(|d| ParameterDefault::Number(d as f64)),
+        options: Vec::new(),
+    }
+}
+
+fn number(name: &'static str, min: f64, max: f64, default: f64) -> ParameterSpec {
+    ParameterSpec {
+        name,
+        kind: "number",
+        min: Some(min),
+        max: Some(max),
+        default: Some(ParameterDefault::Number(default)),
+        options: Vec::new(),
+    }
+}
+
+fn choice(name: &'static str, options: Vec<String>, default: &str) -> ParameterSpec {
+    ParameterSpec {
+        name,
+        kind: "string",
+        min: None,
+        max: None,
+        default: Some(ParameterDefault::Text(default.to_string())),
+        options,
+    }
+}
+
+/// Schema of every tunable field, in the order of `GenerateImageRequest`
+pub fn schema(config: &InferenceConfig) -> Vec<ParameterSpec> {
+    let dimension = |name, max, default| integer(name, MIN_DIMENSION as i64, max as i64, Some(default as i64));
+    // Widest range any scheduler accepts; the chosen one may narrow it
+    let guidance = SchedulerKind::ALL
+        .iter()
+        .map(|&kind| pipeline::guidance_range(config, kind))
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), (low, high)| (min.min(low), max.max(high)));
+
+    vec![
+        integer("num_inference_steps", 1, config.max_steps as i64, Some(config.default_steps as i64)),
+        number("guidance_scale", guidance.0, guidance.1, config.default_guidance_scale),
+        dimension("width", config.max_width, config.default_width),
+        dimension("height", config.max_height, config.default_height),
+        integer("num_images", 1, config.max_batch_size as i64, Some(1)),
+        integer("clip_skip", 1, MAX_CLIP_SKIP as i64, Some(DEFAULT_CLIP_SKIP as i64)),
+        number("strength", 0.0, 1.0, DEFAULT_STRENGTH),
+        choice(
+            "scheduler",
+            SchedulerKind::ALL.iter().map(|kind| kind.to_string()).collect(),
+            &config.default_scheduler,
+        ),
+        number("subseed_strength", 0.0, 1.0, 0.0),
+        number("temperature", 0.0, MAX_TEMPERATURE, DEFAULT_TEMPERATURE),
+        integer("dpi", 1, MAX_DPI as i64, None),
+        choice("format", vec!["png".to_string(), "jpeg".to_string()], &config.output_format),
+        integer("upscale", 1, MAX_UPSCALE as i64, Some(1)),
+        integer("border_width", 0, config.max_decode_dimension as i64 / 2, Some(0)),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, GuidanceRangeConfig};
+
+    fn spec<'a>(schema: &'a [ParameterSpec], name: &str) -> &'a ParameterSpec {
+        schema.iter().find(|spec| spec.name == name).unwrap()
+    }
+
+    #[test]
+    fn schema_follows_the_config() {
+        let mut config = Config::default().inference;
+        config.max_width = 1536;
+        config.default_width = 768;
+        config.scheduler_guidance.insert("euler".to_string(), GuidanceRangeConfig { min: 0.5, max: 30.0 });
+
+        let schema = schema(&config);
+        let width = spec(&schema, "width");
+        assert_eq!(width.max, Some(1536.0));
+        assert!(matches!(width.default, Some(ParameterDefault::Number(d)) if d == 768.0));
+
+        let guidance = spec(&schema, "guidance_scale");
+        assert_eq!((guidance.min, guidance.max), (Some(0.5), Some(30.0)));
+    }
+}
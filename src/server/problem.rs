@@ -0,0 +1,250 @@
+//! RFC 7807 `application/problem+json` error bodies for the REST API
+
+use crate::errors::DiffusionError;
+use actix_web::error::{InternalError, JsonPayloadError};
+use actix_web::http::{header, StatusCode};
+use actix_web::{HttpRequest, HttpResponse};
+use serde::Serialize;
+
+pub const PROBLEM_CONTENT_TYPE: &str = "application/problem+json";
+
+/// Problem types are relative URIs so they stay stable across deployments
+const PROBLEM_TYPE_BASE: &str = "/problems/";
+
+#[derive(Debug, Serialize)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    problem_type: String,
+    title: String,
+    status: u16,
+    detail: String,
+    instance: String,
+    /// Individual validation failures
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    errors: Vec<String>,
+    /// Whether sending the same request again may succeed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retryable: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    attempts_made: Option<u32>,
+    /// Also sent as `Retry-After`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retry_after_seconds: Option<u64>,
+}
+
+impl Problem {
+    pub fn new(status: StatusCode, slug: &str, title: &str, detail: impl Into<String>, instance: &str) -> Self {
+        Self {
+            problem_type: format!("{}{}", PROBLEM_TYPE_BASE, slug),
+            title: title.to_string(),
+            status: status.as_u16(),
+            detail: detail.into(),
+            instance: instance.to_string(),
+            errors: Vec::new(),
+            retryable: None,
+            attempts_made: None,
+            retry_after_seconds: None,
+        }
+    }
+
+    pub fn from_error(err: &DiffusionError, instance: &str) -> Self {
+        let (status, slug, title) = match err {
+            DiffusionError::InvalidParameters(_) | DiffusionError::Validation(_) => {
+                (StatusCode::BAD_REQUEST, "invalid-parameters", "Invalid parameters")
+            }
+            DiffusionError::Unauthorized(_) => {
+                (StatusCode::UNAUTHORIZED, "unauthorized", "Unauthorized")
+            }
+            DiffusionError::Forbidden(_) => {
+                (StatusCode::FORBIDDEN, "forbidden", "Forbidden")
+            }
+            DiffusionError::JobNotFound(_) => {
+                (StatusCode::NOT_FOUND, "job-not-found", "Job not found")
+            }
+            DiffusionError::JobNotQueued(_) => {
+                (StatusCode::CONFLICT, "job-not-queued", "Job is not queued")
+            }
+            DiffusionError::QueueFull => {
+                (StatusCode::SERVICE_UNAVAILABLE, "queue-full", "Queue full")
+            }
+            DiffusionError::Overloaded(_) => {
+                (StatusCode::SERVICE_UNAVAILABLE, "overloaded", "Server overloaded")
+            }
+            DiffusionError::QuotaExceeded { .. } => {
+                (StatusCode::TOO_MANY_REQUESTS, "quota-exceeded", "Quota exceeded")
+            }
+            DiffusionError::Timeout => {
+                (StatusCode::GATEWAY_TIMEOUT, "timeout", "Generation timed out")
+            }
+            DiffusionError::DeadlineExceeded => {
+                (StatusCode::GATEWAY_TIMEOUT, "deadline-exceeded", "Deadline exceeded")
+            }
+            DiffusionError::ModelLoad(_) => {
+                (StatusCode::SERVICE_UNAVAILABLE, "model-load-failed", "Model unavailable")
+            }
+            DiffusionError::Inference(_) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, "inference-failed", "Inference failed")
+            }
+            DiffusionError::Config(_) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, "configuration-error", "Configuration error")
+            }
+            DiffusionError::Storage(_) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, "storage-error", "Storage error")
+            }
+            DiffusionError::Internal(_) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, "internal-error", "Internal error")
+            }
+            DiffusionError::Unrecoverable(_) => {
+                (StatusCode::UNPROCESSABLE_ENTITY, "generation-failed", "Generation failed")
+            }
+            DiffusionError::Exhausted { source, .. } => {
+                let mut problem = Self::from_error(source, instance);
+                problem.detail = err.to_string();
+                problem.set_retry(err);
+                return problem;
+            }
+        };
+
+        let mut problem = Self::new(status, slug, title, err.to_string(), instance);
+        if let DiffusionError::Validation(errors) = err {
+            problem.errors = errors.clone();
+        }
+        // A rejected request only succeeds once it changes, so retry advice
+        // is for failures of requests that were accepted
+        if !status.is_client_error() || status == StatusCode::UNPROCESSABLE_ENTITY {
+            problem.set_retry(err);
+        }
+        problem
+    }
+
+    fn set_retry(&mut self, err: &DiffusionError) {
+        let retry = err.retry_info();
+        self.retryable = Some(retry.retryable);
+        self.attempts_made = Some(retry.attempts_made);
+        self.retry_after_seconds = retry.retry_after_seconds;
+    }
+
+    pub fn maintenance(message: &str, instance: &str) -> Self {
+        Self::new(StatusCode::SERVICE_UNAVAILABLE, "maintenance", "Under maintenance", message, instance)
+    }
+
+    pub fn not_found(instance: &str) -> Self {
+        Self::new(StatusCode::NOT_FOUND, "not-found", "Not found", "No such endpoint", instance)
+    }
+
+    pub fn response(&self) -> HttpResponse {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        let mut response = HttpResponse::build(status);
+        response.content_type(PROBLEM_CONTENT_TYPE);
+        if let Some(secs) = self.retry_after_seconds {
+            response.insert_header((header::RETRY_AFTER, secs));
+        }
+        response.json(self)
+    }
+}
+
+pub fn new_instance_id() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
+
+/// Fallback for unmatched routes
+pub async fn not_found() -> HttpResponse {
+    Problem::not_found(&new_instance_id()).response()
+}
+
+/// Maps JSON extractor failures (malformed or oversized bodies) to problem+json
+pub fn json_error_handler(err: JsonPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    let instance = new_instance_id();
+    let problem = match &err {
+        JsonPayloadError::Overflow { .. } | JsonPayloadError::OverflowKnownLength { .. } => {
+            Problem::new(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "payload-too-large",
+                "Payload too large",
+                err.to_string(),
+                &instance,
+            )
+        }
+        _ => Problem::new(
+            StatusCode::BAD_REQUEST,
+            "invalid-body",
+            "Invalid request body",
+            err.to_string(),
+            &instance,
+        ),
+    };
+
+    InternalError::from_response(err, problem.response()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_map_to_stable_types_and_statuses() {
+        let cases = [
+            (DiffusionError::InvalidParameters("bad".to_string()), 400, "/problems/invalid-parameters"),
+            (DiffusionError::Validation(vec!["bad".to_string()]), 400, "/problems/invalid-parameters"),
+            (DiffusionError::Unauthorized("no key".to_string()), 401, "/problems/unauthorized"),
+            (DiffusionError::Forbidden("off".to_string()), 403, "/problems/forbidden"),
+            (DiffusionError::JobNotFound("j".to_string()), 404, "/problems/job-not-found"),
+            (DiffusionError::JobNotQueued("j".to_string()), 409, "/problems/job-not-queued"),
+            (DiffusionError::QueueFull, 503, "/problems/queue-full"),
+            (DiffusionError::Timeout, 504, "/problems/timeout"),
+            (DiffusionError::Internal("oops".to_string()), 500, "/problems/internal-error"),
+        ];
+
+        for (err, status, problem_type) in cases {
+            let problem = Problem::from_error(&err, "request-1");
+            assert_eq!(problem.status, status, "{:?}", err);
+            assert_eq!(problem.problem_type, problem_type);
+            assert_eq!(problem.instance, "request-1");
+
+            let response = problem.response();
+            assert_eq!(response.status().as_u16(), status);
+            assert_eq!(response.headers().get(header::CONTENT_TYPE).unwrap(), PROBLEM_CONTENT_TYPE);
+        }
+    }
+
+    #[test]
+    fn validation_problem_lists_every_error() {
+        let err = DiffusionError::Validation(vec!["width".to_string(), "steps".to_string()]);
+        let body = serde_json::to_value(Problem::from_error(&err, "request-1")).unwrap();
+        assert_eq!(body["errors"], serde_json::json!(["width", "steps"]));
+        assert_eq!(body["type"], "/problems/invalid-parameters");
+    }
+
+    #[test]
+    fn retry_advice_follows_the_error() {
+        let body = |err: &DiffusionError| serde_json::to_value(Problem::from_error(err, "request-1")).unwrap();
+
+        // Rejected requests carry no retry fields at all
+        for err in [
+            DiffusionError::Validation(vec!["width".to_string()]),
+            DiffusionError::JobNotFound("j".to_string()),
+        ] {
+            let body = body(&err);
+            for field in ["retryable", "attempts_made", "retry_after_seconds"] {
+                assert!(body.get(field).is_none(), "{} on {:?}", field, err);
+            }
+        }
+
+        let body = body(&DiffusionError::Unrecoverable("none fit in max_response_bytes".to_string()));
+        assert_eq!(body["status"], 422);
+        assert_eq!(body["retryable"], false);
+        assert!(body.get("retry_after_seconds").is_none());
+
+        let exhausted = DiffusionError::Exhausted {
+            source: Box::new(DiffusionError::Inference("CUDA out of memory".to_string())),
+            attempts: 3,
+        };
+        let problem = Problem::from_error(&exhausted, "request-1");
+        let response = problem.response();
+        assert_eq!(response.headers().get(header::RETRY_AFTER).unwrap(), "8");
+        let body = serde_json::to_value(&problem).unwrap();
+        assert_eq!(body["retryable"], true);
+        assert_eq!(body["attempts_made"], 3);
+        assert_eq!(body["retry_after_seconds"], 8);
+    }
+}
@@ -0,0 +1,232 @@
+use crate::config::QuotaAction;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Usage bucket of requests under `queue.default_key_quota`. Not a valid
+/// header value, so no listed key can collide with it.
+const DEFAULT_BUCKET: &str = "";
+
+/// Outcome of asking for images against an API key's daily quota
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QuotaDecision {
+    /// The key has no quota
+    Unmetered,
+    /// Images were counted, fewer than asked for when clamped
+    Granted(Reservation),
+    /// Nothing was counted
+    Exceeded { remaining: u32 },
+}
+
+/// Images counted against one quota, to refund if they aren't generated
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reservation {
+    bucket: String,
+    day: u64,
+    pub images: u32,
+    /// Left for the day after this reservation
+    pub remaining: u32,
+}
+
+/// Images generated per API key and UTC day, against `queue.key_quotas`.
+/// Requests without a key, or with one that isn't listed, share
+/// `queue.default_key_quota`; without one they are unmetered. Usage lives
+/// in memory and starts over when the day changes.
+pub struct QuotaTracker {
+    limits: BTreeMap<String, u32>,
+    default_limit: Option<u32>,
+    action: QuotaAction,
+    /// bucket -> (day since the Unix epoch, images used that day)
+    usage: Mutex<HashMap<String, (u64, u32)>>,
+}
+
+impl QuotaTracker {
+    pub fn new(limits: BTreeMap<String, u32>, default_limit: Option<u32>, action: QuotaAction) -> Self {
+        Self {
+            limits,
+            default_limit,
+            action,
+            usage: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Counts `images` against the key's quota. A batch that doesn't fit is
+    /// rejected, or clamped to what is left when `quota_action = "clamp"`.
+    pub fn reserve(&self, key: Option<&str>, images: u32) -> QuotaDecision {
+        self.reserve_on(Self::current_day(), key, images)
+    }
+
+    fn reserve_on(&self, day: u64, key: Option<&str>, images: u32) -> QuotaDecision {
+        let Some((bucket, limit)) = self.bucket(key) else {
+            return QuotaDecision::Unmetered;
+        };
+
+        let mut usage = self.usage.lock().unwrap_or_else(PoisonError::into_inner);
+        let used = Self::used_on(&mut usage, bucket, day);
+        let remaining = limit.saturating_sub(*used);
+        let granted = match self.action {
+            _ if images <= remaining => images,
+            QuotaAction::Clamp if remaining > 0 => remaining,
+            _ => return QuotaDecision::Exceeded { remaining },
+        };
+
+        *used += granted;
+        QuotaDecision::Granted(Reservation {
+            bucket: bucket.to_string(),
+            day,
+            images: granted,
+            remaining: remaining - granted,
+        })
+    }
+
+    /// Gives back the images of a batch that failed or was cancelled.
+    /// Reservations from an earlier day have nothing left to refund.
+    pub fn refund(&self, reservation: &Reservation) {
+        let mut usage = self.usage.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some((day, used)) = usage.get_mut(&reservation.bucket) {
+            if *day == reservation.day {
+                *used = used.saturating_sub(reservation.images);
+            }
+        }
+    }
+
+    /// The usage bucket and daily limit of a request with `key`
+    fn bucket<'a>(&'a self, key: Option<&'a str>) -> Option<(&'a str, u32)> {
+        match key.and_then(|key| self.limits.get_key_value(key)) {
+            Some((key, &limit)) => Some((key.as_str(), limit)),
+            None => self.default_limit.map(|limit| (DEFAULT_BUCKET, limit)),
+        }
+    }
+
+    /// The bucket's usage on `day`, reset when the day has changed
+    fn used_on<'a>(usage: &'a mut HashMap<String, (u64, u32)>, bucket: &str, day: u64) -> &'a mut u32 {
+        let entry = usage.entry(bucket.to_string()).or_insert((day, 0));
+        if entry.0 != day {
+            *entry = (day, 0);
+        }
+        &mut entry.1
+    }
+
+    fn current_day() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() / 86_400)
+            .unwrap_or(0)
+    }
+}
+
+/// Refunds a reservation when dropped, unless kept. A REST generation runs
+/// inside its handler, so a client disconnecting (actix drops the handler)
+/// or a failed generation gives the images back.
+pub struct QuotaHold {
+    quotas: Arc<QuotaTracker>,
+    reservation: Option<Reservation>,
+}
+
+impl QuotaHold {
+    pub fn new(quotas: Arc<QuotaTracker>, reservation: Reservation) -> Self {
+        Self { quotas, reservation: Some(reservation) }
+    }
+
+    /// Images granted, fewer than asked for when clamped
+    pub fn images(&self) -> u32 {
+        self.reservation.as_ref().map_or(0, |reservation| reservation.images)
+    }
+
+    /// Keeps the images counted, returning what is left for the day
+    pub fn keep(mut self) -> u32 {
+        self.reservation.take().map_or(0, |reservation| reservation.remaining)
+    }
+}
+
+impl Drop for QuotaHold {
+    fn drop(&mut self) {
+        if let Some(reservation) = self.reservation.take() {
+            self.quotas.refund(&reservation);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracker(action: QuotaAction) -> QuotaTracker {
+        let limits = BTreeMap::from([("free-key".to_string(), 10)]);
+        QuotaTracker::new(limits, Some(3), action)
+    }
+
+    fn granted(decision: QuotaDecision) -> Reservation {
+        match decision {
+            QuotaDecision::Granted(reservation) => reservation,
+            other => panic!("expected a grant, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn batch_over_quota_is_rejected_with_the_remaining_count() {
+        let quotas = tracker(QuotaAction::Reject);
+
+        assert_eq!(granted(quotas.reserve_on(100, Some("free-key"), 8)).remaining, 2);
+        assert_eq!(quotas.reserve_on(100, Some("free-key"), 4), QuotaDecision::Exceeded { remaining: 2 });
+        assert_eq!(granted(quotas.reserve_on(100, Some("free-key"), 2)).remaining, 0);
+    }
+
+    #[test]
+    fn clamp_grants_what_is_left() {
+        let quotas = tracker(QuotaAction::Clamp);
+
+        granted(quotas.reserve_on(100, Some("free-key"), 8));
+        let reservation = granted(quotas.reserve_on(100, Some("free-key"), 4));
+        assert_eq!((reservation.images, reservation.remaining), (2, 0));
+        assert_eq!(quotas.reserve_on(100, Some("free-key"), 1), QuotaDecision::Exceeded { remaining: 0 });
+    }
+
+    #[test]
+    fn quota_resets_after_the_day() {
+        let quotas = tracker(QuotaAction::Reject);
+
+        granted(quotas.reserve_on(100, Some("free-key"), 10));
+        assert_eq!(quotas.reserve_on(100, Some("free-key"), 1), QuotaDecision::Exceeded { remaining: 0 });
+
+        assert_eq!(granted(quotas.reserve_on(101, Some("free-key"), 1)).remaining, 9);
+    }
+
+    #[test]
+    fn unlisted_and_missing_keys_share_the_default_quota() {
+        let quotas = tracker(QuotaAction::Reject);
+
+        granted(quotas.reserve_on(100, None, 2));
+        assert_eq!(granted(quotas.reserve_on(100, Some("made-up-key"), 1)).remaining, 0);
+        assert_eq!(quotas.reserve_on(100, None, 1), QuotaDecision::Exceeded { remaining: 0 });
+        // Listed keys keep their own
+        assert_eq!(granted(quotas.reserve_on(100, Some("free-key"), 1)).remaining, 9);
+
+        let unmetered = QuotaTracker::new(BTreeMap::new(), None, QuotaAction::Reject);
+        assert_eq!(unmetered.reserve_on(100, Some("any-key"), 1_000), QuotaDecision::Unmetered);
+    }
+
+    #[test]
+    fn refunds_give_images_back_only_on_the_same_day() {
+        let quotas = tracker(QuotaAction::Reject);
+
+        let reservation = granted(quotas.reserve_on(100, Some("free-key"), 10));
+        quotas.refund(&reservation);
+        let today = granted(quotas.reserve_on(100, Some("free-key"), 10));
+
+        granted(quotas.reserve_on(101, Some("free-key"), 4));
+        quotas.refund(&today);
+        assert_eq!(quotas.reserve_on(101, Some("free-key"), 7), QuotaDecision::Exceeded { remaining: 6 });
+    }
+
+    #[test]
+    fn dropped_hold_refunds_and_kept_hold_counts() {
+        let quotas = Arc::new(tracker(QuotaAction::Reject));
+
+        let hold = QuotaHold::new(Arc::clone(&quotas), granted(quotas.reserve(Some("free-key"), 6)));
+        drop(hold);
+        let hold = QuotaHold::new(Arc::clone(&quotas), granted(quotas.reserve(Some("free-key"), 6)));
+        assert_eq!(hold.keep(), 4);
+        assert_eq!(quotas.reserve(Some("free-key"), 6), QuotaDecision::Exceeded { remaining: 4 });
+    }
+}
@@ -0,0 +1,33 @@
+//! Shared generation presets stored as JSON files, e.g. the `recipe.json`
+//! of a ZIP download
+
+use crate::errors::DiffusionError;
+use serde::de::DeserializeOwned;
+use std::path::Path;
+
+/// Recipe names map directly to file names, so only a safe subset is allowed
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Loads `<dir>/<name>.json`. Unknown, unreadable or malformed recipes are
+/// reported as invalid parameters.
+pub async fn load<T: DeserializeOwned>(dir: Option<&Path>, name: &str) -> Result<T, DiffusionError> {
+    let dir = dir.ok_or_else(|| {
+        DiffusionError::InvalidParameters("Recipes are not enabled on this server".to_string())
+    })?;
+
+    if !valid_name(name) {
+        return Err(DiffusionError::InvalidParameters(format!("Invalid recipe name '{}'", name)));
+    }
+
+    let path = dir.join(format!("{}.json", name));
+    let contents = tokio::fs::read(&path)
+        .await
+        .map_err(|_| DiffusionError::InvalidParameters(format!("Unknown recipe '{}'", name)))?;
+
+    serde_json::from_slice(&contents).map_err(|e| {
+        DiffusionError::InvalidParameters(format!("Recipe '{}' is invalid: {}", name, e))
+    })
+}
@@ -1,30 +1,353 @@
-use crate::config::Config;
+use crate::config::{Config, MaintenanceConfig, ServerConfig};
 use crate::errors::DiffusionError;
-use crate::inference::pipeline::{GenerationParams, InferencePipeline};
-use actix_web::{web, App, HttpResponse, HttpServer, Responder};
+use crate::inference::pipeline::{GenerationParams, GenerationResult, ImageResult, InferencePipeline, METADATA_VERSION};
+use crate::inference::postprocess::Degradation;
+use crate::inference::QualityPreset;
+use crate::queue::events;
+use crate::server::access_log::AccessLog;
+use crate::server::archive;
+use crate::server::client_metadata;
+use crate::server::download;
+use crate::server::generation_headers;
+use crate::server::grpc::JobQueue;
+use crate::server::health::{self, HealthSnapshot, HealthStatus};
+use crate::server::parameters::{self, ParameterSpec};
+use crate::server::problem::{self, Problem};
+use crate::server::quota::{QuotaDecision, QuotaHold, QuotaTracker};
+use crate::server::recipes;
+use crate::server::result_id;
+use actix_web::dev::Service;
+use actix_web::http::header;
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer, Responder};
+use futures::StreamExt;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
+use std::time::Instant;
 use tracing::info;
 
-#[derive(Debug, Deserialize)]
+/// Body of the generate endpoints. Fields accept a few names other SDKs use
+/// (`steps`, `cfg`, `n`, ...); responses and saved recipes always use the
+/// canonical ones.
+#[derive(Debug, Serialize, Deserialize)]
 pub struct GenerateRequest {
+    #[serde(default)]
+    version: Option<String>,
+    /// Saved recipe used as the base; fields set here override it
+    #[serde(default)]
+    recipe: Option<String>,
+    /// May be omitted when the recipe provides one
+    #[serde(default)]
+    prompt: String,
+    /// `[[fraction, prompt], ...]`, switching prompts partway through
+    #[serde(default)]
+    prompt_schedule: Vec<(f64, String)>,
+    #[serde(default, alias = "negative")]
+    negative_prompt: Option<String>,
+    /// `["#rrggbb", ...]` brand colors to steer and grade the output toward
+    #[serde(default)]
+    color_palette: Vec<String>,
+    /// `[[prompt, weight], ...]`, instead of `negative_prompt`
+    #[serde(default)]
+    negative_prompts: Vec<(String, f64)>,
+    #[serde(default, alias = "steps")]
+    num_inference_steps: Option<i32>,
+    #[serde(default, alias = "cfg", alias = "cfg_scale")]
+    guidance_scale: Option<f64>,
+    #[serde(default)]
+    width: Option<i32>,
+    #[serde(default)]
+    height: Option<i32>,
+    #[serde(default)]
+    aspect_ratio: Option<String>,
+    /// Target size in megapixels at `aspect_ratio`, instead of width/height
+    #[serde(default)]
+    megapixels: Option<f64>,
+    seed: Option<i64>,
+    /// Variation seed blended in by `subseed_strength` (0-1)
+    #[serde(default)]
+    subseed: Option<i64>,
+    #[serde(default)]
+    subseed_strength: Option<f64>,
+    /// Sampler noise scale (0-2); higher gives more variation
+    #[serde(default)]
+    temperature: Option<f64>,
+    #[serde(default)]
+    clip_skip: Option<i32>,
+    #[serde(default)]
+    model_id: Option<String>,
+    #[serde(default, alias = "n", alias = "batch_size")]
+    num_images: Option<i32>,
+    #[serde(default)]
+    precision: Option<String>,
+    #[serde(default)]
+    scheduler: Option<String>,
+    /// Preset for steps, guidance and scheduler; explicit fields override it
+    #[serde(default)]
+    quality: Option<String>,
+    /// Skip the model's configured prompt prefix / suffix
+    #[serde(default)]
+    disable_auto_prompt: Option<bool>,
+    #[serde(default)]
+    return_latents: Option<bool>,
+    /// Include the per-step sigmas in the metadata
+    #[serde(default)]
+    return_schedule: Option<bool>,
+    /// Also return a GIF of the denoising steps per image
+    #[serde(default)]
+    return_steps_animation: Option<bool>,
+    /// Log this request at trace level; needs `allow_debug_requests`
+    #[serde(default)]
+    debug: Option<bool>,
+    /// Longer side of an extra JPEG thumbnail
+    #[serde(default)]
+    thumbnail: Option<u32>,
+    /// Print resolution recorded in the PNG
+    #[serde(default)]
+    dpi: Option<u32>,
+    /// "png" or "jpeg"
+    #[serde(default)]
+    format: Option<String>,
+    /// Also encode each image in these formats, e.g. `["png", "jpeg"]`
+    #[serde(default)]
+    output_formats: Vec<String>,
+    /// RGBA output with the background cut out; PNG only
+    #[serde(default)]
+    transparent_background: Option<bool>,
+    /// Upscale factor (1-4) applied after generation
+    #[serde(default)]
+    upscale: Option<u32>,
+    /// Re-encode images larger than this many bytes (JPEG at lower
+    /// quality, then smaller) instead of returning them as-is
+    #[serde(default)]
+    max_response_bytes: Option<u64>,
+    /// Solid frame around each image, in pixels per side
+    #[serde(default)]
+    border_width: Option<u32>,
+    /// "#rrggbb"; white by default
+    #[serde(default)]
+    border_color: Option<String>,
+    /// Always generate, ignoring cached results (If-None-Match) and the
+    /// cached prompt conditioning. The fresh result is still cacheable.
+    #[serde(default)]
+    no_cache: Option<bool>,
+    #[serde(default)]
+    face_restore: Option<bool>,
+    #[serde(default)]
+    face_restore_weight: Option<f64>,
+    /// Opaque client data echoed back in the response
+    #[serde(default)]
+    metadata: HashMap<String, String>,
+    /// Fields this server doesn't know; rejected with `strict_request_fields`
+    #[serde(flatten, skip_serializing)]
+    unknown: BTreeMap<String, serde_json::Value>,
+}
+
+/// Body of `POST /v1/generate/seed-search`
+#[derive(Debug, Deserialize)]
+pub struct SeedSearchRequest {
     prompt: String,
     #[serde(default)]
     negative_prompt: Option<String>,
-    #[serde(default = "default_steps")]
+    /// 0 takes the model's default
+    #[serde(default)]
     num_inference_steps: i32,
     #[serde(default = "default_guidance")]
     guidance_scale: f64,
-    #[serde(default = "default_size")]
-    width: i32,
-    #[serde(default = "default_size")]
-    height: i32,
-    seed: Option<i64>,
+    #[serde(default)]
+    seed_start: Option<i64>,
+    #[serde(default = "default_seed_search_count")]
+    count: i32,
+    #[serde(default)]
+    clip_skip: Option<i32>,
+    #[serde(default)]
+    model_id: Option<String>,
+    #[serde(default)]
+    precision: Option<String>,
+    #[serde(default)]
+    scheduler: Option<String>,
+    #[serde(flatten)]
+    unknown: BTreeMap<String, serde_json::Value>,
 }
 
-fn default_steps() -> i32 { 50 }
 fn default_guidance() -> f64 { 7.5 }
-fn default_size() -> i32 { 512 }
+fn default_num_images() -> i32 { 1 }
+fn default_seed_search_count() -> i32 { 9 }
+fn default_list_limit() -> usize { 50 }
+fn default_drain_timeout() -> u64 { 300 }
+
+fn is_zero(value: &u32) -> bool { *value == 0 }
+
+/// In strict mode, a body with fields the server doesn't know (often a
+/// typo like `step`) is rejected instead of silently using defaults
+fn check_unknown_fields(
+    unknown: &BTreeMap<String, serde_json::Value>,
+    server: &ServerConfig,
+) -> Result<(), DiffusionError> {
+    if !server.strict_request_fields || unknown.is_empty() {
+        return Ok(());
+    }
+    Err(DiffusionError::Validation(
+        unknown.keys().map(|field| format!("Unknown field '{}'", field)).collect(),
+    ))
+}
+
+const DEFAULT_API_VERSION: &str = "v1";
+const ADMIN_KEY_HEADER: &str = "X-Admin-Key";
+const API_KEY_HEADER: &str = "X-Api-Key";
+/// Lowercase, as `HeaderName::from_static` requires
+const QUOTA_REMAINING_HEADER: &str = "x-quota-remaining";
+const MAX_LIST_LIMIT: usize = 500;
+
+impl GenerateRequest {
+    /// Fills every field this request leaves unset from `recipe`, flags
+    /// included, so an explicit `false` turns off a recipe's `true`. Client
+    /// metadata is merged, request keys winning.
+    fn with_recipe(self, recipe: GenerateRequest) -> GenerateRequest {
+        let mut metadata = recipe.metadata;
+        metadata.extend(self.metadata);
+
+        GenerateRequest {
+            version: self.version.or(recipe.version),
+            recipe: self.recipe,
+            prompt: if self.prompt.is_empty() { recipe.prompt } else { self.prompt },
+            prompt_schedule: if self.prompt_schedule.is_empty() {
+                recipe.prompt_schedule
+            } else {
+                self.prompt_schedule
+            },
+            negative_prompt: self.negative_prompt.or(recipe.negative_prompt),
+            color_palette: if self.color_palette.is_empty() {
+                recipe.color_palette
+            } else {
+                self.color_palette
+            },
+            negative_prompts: if self.negative_prompts.is_empty() {
+                recipe.negative_prompts
+            } else {
+                self.negative_prompts
+            },
+            num_inference_steps: self.num_inference_steps.or(recipe.num_inference_steps),
+            guidance_scale: self.guidance_scale.or(recipe.guidance_scale),
+            width: self.width.or(recipe.width),
+            height: self.height.or(recipe.height),
+            aspect_ratio: self.aspect_ratio.or(recipe.aspect_ratio),
+            megapixels: self.megapixels.or(recipe.megapixels),
+            seed: self.seed.or(recipe.seed),
+            subseed: self.subseed.or(recipe.subseed),
+            subseed_strength: self.subseed_strength.or(recipe.subseed_strength),
+            temperature: self.temperature.or(recipe.temperature),
+            clip_skip: self.clip_skip.or(recipe.clip_skip),
+            model_id: self.model_id.or(recipe.model_id),
+            num_images: self.num_images.or(recipe.num_images),
+            precision: self.precision.or(recipe.precision),
+            scheduler: self.scheduler.or(recipe.scheduler),
+            quality: self.quality.or(recipe.quality),
+            disable_auto_prompt: self.disable_auto_prompt.or(recipe.disable_auto_prompt),
+            return_latents: self.return_latents.or(recipe.return_latents),
+            return_schedule: self.return_schedule.or(recipe.return_schedule),
+            return_steps_animation: self.return_steps_animation.or(recipe.return_steps_animation),
+            debug: self.debug.or(recipe.debug),
+            thumbnail: self.thumbnail.or(recipe.thumbnail),
+            dpi: self.dpi.or(recipe.dpi),
+            format: self.format.or(recipe.format),
+            output_formats: if self.output_formats.is_empty() {
+                recipe.output_formats
+            } else {
+                self.output_formats
+            },
+            transparent_background: self.transparent_background.or(recipe.transparent_background),
+            upscale: self.upscale.or(recipe.upscale),
+            max_response_bytes: self.max_response_bytes.or(recipe.max_response_bytes),
+            border_width: self.border_width.or(recipe.border_width),
+            border_color: self.border_color.or(recipe.border_color),
+            no_cache: self.no_cache.or(recipe.no_cache),
+            face_restore: self.face_restore.or(recipe.face_restore),
+            face_restore_weight: self.face_restore_weight.or(recipe.face_restore_weight),
+            metadata,
+            unknown: self.unknown,
+        }
+    }
+
+    /// Maps the request body onto `GenerationParams` according to its schema
+    /// version, applying REST transport defaults
+    fn to_params(&self, server: &ServerConfig) -> Result<GenerationParams, DiffusionError> {
+        check_unknown_fields(&self.unknown, server)?;
+
+        match self.version.as_deref().unwrap_or(DEFAULT_API_VERSION) {
+            "v1" => {
+                let quality = self.quality
+                    .as_deref()
+                    .map(str::parse::<QualityPreset>)
+                    .transpose()
+                    .map_err(DiffusionError::InvalidParameters)?;
+
+                Ok(GenerationParams {
+                    prompt: self.prompt.clone(),
+                    prompt_embeds: None,
+                    prompt_schedule: self.prompt_schedule.clone(),
+                    negative_prompts: self.negative_prompts.clone(),
+                    negative_prompt: self.negative_prompt.clone(),
+                    // 0 takes the model's default
+                    num_inference_steps: self.num_inference_steps
+                        .or(quality.map(|q| q.steps()))
+                        .unwrap_or(0),
+                    guidance_scale: self.guidance_scale
+                        .or(quality.map(|q| q.guidance_scale()))
+                        .unwrap_or_else(default_guidance),
+                    width: self.width,
+                    height: self.height,
+                    aspect_ratio: self.aspect_ratio.clone(),
+                    megapixels: self.megapixels,
+                    init_image: None,
+                    strength: None,
+                    reference_images: Vec::new(),
+                    vary_region: None,
+                    strength_preset: None,
+                    seed: self.seed,
+                    subseed: self.subseed,
+                    subseed_strength: self.subseed_strength,
+                    temperature: self.temperature,
+                    clip_skip: self.clip_skip,
+                    model_id: self.model_id.clone(),
+                    num_images: self.num_images.unwrap_or_else(default_num_images),
+                    precision: self.precision.clone(),
+                    scheduler: self.scheduler
+                        .clone()
+                        .or_else(|| quality.map(|q| q.scheduler().to_string())),
+                    disable_auto_prompt: self.disable_auto_prompt.unwrap_or(false),
+                    no_cache: self.no_cache.unwrap_or(false),
+                    return_latents: self.return_latents.unwrap_or(false),
+                    return_schedule: self.return_schedule.unwrap_or(false),
+                    return_steps_animation: self.return_steps_animation.unwrap_or(false),
+                    debug: self.debug.unwrap_or(false),
+                    thumbnail: self.thumbnail,
+                    dpi: self.dpi,
+                    format: self.format.clone().or_else(|| server.rest_output_format.clone()),
+                    output_formats: self.output_formats.clone(),
+                    transparent_background: self.transparent_background.unwrap_or(false),
+                    upscale: self.upscale,
+                    max_response_bytes: self.max_response_bytes,
+                    border_width: self.border_width,
+                    border_color: self.border_color.clone(),
+                    color_palette: self.color_palette.clone(),
+                    face_restore: self.face_restore,
+                    face_restore_weight: self.face_restore_weight,
+                    quality: quality.map(|q| q.to_string()),
+                    queue_depth: 0,
+                    job_id: None,
+                })
+            }
+            // Reserved for the next request schema
+            "v2" => Err(DiffusionError::InvalidParameters(
+                "Request version v2 is reserved and not yet supported".to_string()
+            )),
+            other => Err(DiffusionError::InvalidParameters(
+                format!("Unknown request version: {}", other)
+            )),
+        }
+    }
+}
 
 #[derive(Debug, Serialize)]
 pub struct GenerateResponse {
@@ -33,17 +356,272 @@ pub struct GenerateResponse {
     #[serde(skip_serializing_if = "Option::is_none")]
     images_base64: Option<Vec<String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    results: Option<Vec<ImageResultResponse>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     metadata: Option<ResponseMetadata>,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    client_metadata: HashMap<String, String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImageResultResponse {
+    index: usize,
+    seed: i64,
+    /// Job id and seed as one shareable token
+    result_id: String,
+    status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    image_base64: Option<String>,
+    /// Safetensors bytes of the final latent, when requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    latent_base64: Option<String>,
+    /// Perceptual hash as 16 hex digits; compare by Hamming distance
+    #[serde(skip_serializing_if = "Option::is_none")]
+    phash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thumbnail_base64: Option<String>,
+    /// GIF of the denoising steps
+    #[serde(skip_serializing_if = "Option::is_none")]
+    animation_base64: Option<String>,
+    /// The image in each requested `output_formats`, keyed by format
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    encodings_base64: BTreeMap<String, String>,
+    /// How the image was re-encoded to fit max_response_bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    degraded: Option<DegradationResponse>,
     #[serde(skip_serializing_if = "Option::is_none")]
     error: Option<String>,
 }
 
+#[derive(Debug, Serialize)]
+pub struct DegradationResponse {
+    format: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quality: Option<u8>,
+    width: u32,
+    height: u32,
+}
+
+impl From<&Degradation> for DegradationResponse {
+    fn from(degradation: &Degradation) -> Self {
+        Self {
+            format: degradation.format.to_string(),
+            quality: degradation.quality,
+            width: degradation.width,
+            height: degradation.height,
+        }
+    }
+}
+
 #[derive(Debug, Serialize)]
 pub struct ResponseMetadata {
+    metadata_version: u32,
     generation_time_seconds: f64,
     model_used: String,
+    final_prompt: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    final_negative_prompt: Option<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    prompt_fallback: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    prompt_schedule: Vec<(f64, String)>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    negative_prompts: Vec<(String, f64)>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    color_palette: Vec<String>,
+    conditioning_cache_hit: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    model_load_time_seconds: Option<f64>,
     seed: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    subseed: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    subseed_strength: Option<f64>,
+    temperature: f64,
     actual_steps: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    requested_steps: Option<i32>,
+    #[serde(skip_serializing_if = "is_zero")]
+    degenerate_retries: u32,
+    guidance_scale: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quality: Option<String>,
+    width: i32,
+    height: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    requested_width: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    requested_height: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    face_restore_weight: Option<f64>,
+    clip_skip: i32,
+    precision: String,
+    format: String,
+    scheduler: String,
+    beta_start: f64,
+    beta_end: f64,
+    beta_schedule: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sigmas: Option<Vec<f64>>,
+    prompt_moderation: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    moderated_terms: Vec<String>,
+}
+
+impl ResponseMetadata {
+    fn from_result(result: &GenerationResult) -> Self {
+        Self {
+            metadata_version: METADATA_VERSION,
+            generation_time_seconds: result.generation_time,
+            model_used: result.model_used.clone(),
+            final_prompt: result.final_prompt.clone(),
+            final_negative_prompt: result.final_negative_prompt.clone(),
+            prompt_fallback: result.prompt_fallback,
+            conditioning_cache_hit: result.conditioning_cache_hit,
+            color_palette: result.color_palette.clone(),
+            prompt_schedule: result.prompt_schedule.clone(),
+            negative_prompts: result.negative_prompts.clone(),
+            model_load_time_seconds: result.model_load_time,
+            seed: result.seed,
+            subseed: result.subseed,
+            subseed_strength: result.subseed_strength,
+            temperature: result.temperature,
+            actual_steps: result.steps_taken,
+            requested_steps: result.requested_steps,
+            degenerate_retries: result.degenerate_retries,
+            guidance_scale: result.guidance_scale,
+            quality: result.quality.clone(),
+            width: result.width,
+            height: result.height,
+            requested_width: result.requested_dimensions.map(|(w, _)| w),
+            requested_height: result.requested_dimensions.map(|(_, h)| h),
+            face_restore_weight: result.face_restore_weight,
+            clip_skip: result.clip_skip,
+            precision: result.precision.to_string(),
+            format: result.format.to_string(),
+            scheduler: result.scheduler.to_string(),
+            beta_start: result.noise_schedule.beta_start,
+            beta_end: result.noise_schedule.beta_end,
+            beta_schedule: result.noise_schedule.beta_schedule.to_string(),
+            sigmas: result.sigmas.clone(),
+            prompt_moderation: result.moderation.as_str().to_string(),
+            moderated_terms: result.moderation.matched_terms().to_vec(),
+        }
+    }
+}
+
+/// `metadata.json` entry of a ZIP download
+#[derive(Debug, Serialize)]
+pub struct ArchiveManifest {
+    job_id: String,
+    metadata: ResponseMetadata,
+    images: Vec<ArchiveImage>,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    client_metadata: HashMap<String, String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ArchiveImage {
+    index: usize,
+    seed: i64,
+    status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SeedSearchResponse {
+    metadata_version: u32,
+    grid_base64: String,
+    columns: u32,
+    rows: u32,
+    cell_width: u32,
+    cell_height: u32,
+    cells: Vec<SeedCellResponse>,
+    generation_time_seconds: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SeedCellResponse {
+    index: usize,
+    seed: i64,
+    row: u32,
+    column: u32,
+    status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EstimateResponse {
+    estimated_seconds: f64,
+    seconds_per_step: f64,
+    steps: i32,
+    num_images: i32,
+    /// Recent generations the per-step time is based on (0 = configured fallback)
+    samples: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    estimated_cost: Option<f64>,
+}
+
+/// Payload of a `/v1/events` server-sent event
+#[derive(Debug, Serialize)]
+pub struct JobEventResponse {
+    job_id: String,
+    status: String,
+    timestamp: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JobActionResponse {
+    job_id: String,
+    status: String,
+}
+
+/// Body of `GET /v1/results/{result_id}`
+#[derive(Debug, Serialize)]
+pub struct ResultLookupResponse {
+    job_id: String,
+    /// Seed that reproduces the image
+    seed: i64,
+    /// Status of the job when it went through the queue; null for direct
+    /// REST generations, which aren't tracked
+    status: Option<String>,
+}
+
+/// Query of `GET /v1/admin/jobs`
+#[derive(Debug, Deserialize)]
+pub struct ListJobsQuery {
+    /// `next_cursor` of the previous page
+    #[serde(default)]
+    cursor: Option<String>,
+    #[serde(default = "default_list_limit")]
+    limit: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ListJobsResponse {
+    jobs: Vec<JobActionResponse>,
+    /// Absent on the last page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_cursor: Option<String>,
+}
+
+/// Query of `POST /v1/admin/models/{id}/drain`
+#[derive(Debug, Deserialize)]
+pub struct DrainQuery {
+    /// Give up waiting (the model keeps draining) after this long
+    #[serde(default = "default_drain_timeout")]
+    timeout_seconds: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ModelActionResponse {
+    model_id: String,
+    /// "drained", "draining" (jobs still running at the timeout) or "accepting"
+    status: String,
 }
 
 #[derive(Debug, Serialize)]
@@ -52,14 +630,141 @@ pub struct HealthResponse {
     model_loaded: bool,
     version: String,
     device: String,
+    models: HashMap<String, String>,
+    /// Successful generations over the server's lifetime
+    total_generations: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ModelsResponse {
+    models: Vec<ModelInfo>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ModelInfo {
+    id: String,
+    state: String,
+    /// Used by requests that don't name a model
+    default: bool,
+    /// Effective step range and default: the model's own, else the global one
+    min_steps: i32,
+    max_steps: i32,
+    default_steps: i32,
 }
 
 struct AppState {
     pipeline: Arc<InferencePipeline>,
+    queue: JobQueue,
     config: Config,
+    /// Shared with the gRPC server, so a key's quota covers both
+    quotas: Arc<QuotaTracker>,
+}
+
+/// ETag for a deterministic generation. Requests without a seed produce a
+/// different image each time and get none.
+fn generation_etag(params: &GenerationParams) -> Option<String> {
+    params.seed?;
+
+    // Serialized in field order, so it covers every field (including ones
+    // added later) and equal params always hash the same
+    let canonical = serde_json::to_vec(&(env!("CARGO_PKG_VERSION"), params)).ok()?;
+    Some(format!("\"{:016x}\"", fnv1a(&canonical)))
+}
+
+/// 64-bit FNV-1a. Unlike `DefaultHasher` it is the same in every build, so
+/// ETags survive restarts and upgrades of the toolchain.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .fold(0xcbf2_9ce4_8422_2325, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+fn etag_matches(req: &HttpRequest, etag: &str) -> bool {
+    req.headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| {
+            v.split(',')
+                .map(|tag| tag.trim().trim_start_matches("W/"))
+                .any(|tag| tag == etag || tag == "*")
+        })
+        .unwrap_or(false)
+}
+
+fn authorize_admin(req: &HttpRequest, config: &Config) -> Result<(), DiffusionError> {
+    let expected = match &config.server.admin_key {
+        Some(key) if !key.is_empty() => key,
+        _ => return Err(DiffusionError::Forbidden("Admin API is disabled".to_string())),
+    };
+
+    let provided = req.headers()
+        .get(ADMIN_KEY_HEADER)
+        .and_then(|v| v.to_str().ok());
+
+    if provided != Some(expected.as_str()) {
+        return Err(DiffusionError::Unauthorized("Invalid admin key".to_string()));
+    }
+
+    Ok(())
+}
+
+/// Applies the request's named recipe, if any, underneath its own fields
+async fn resolve_recipe(req: GenerateRequest, config: &Config) -> Result<GenerateRequest, DiffusionError> {
+    let name = match &req.recipe {
+        Some(name) => name.clone(),
+        None => return Ok(req),
+    };
+
+    let recipe = recipes::load(config.server.recipes_dir.as_deref(), &name).await?;
+    info!("REST API: Using recipe '{}'", name);
+    Ok(req.with_recipe(recipe))
+}
+
+/// 503 with the configured message while maintenance mode is on
+fn maintenance_response(config: &MaintenanceConfig, instance: &str) -> Option<HttpResponse> {
+    if !config.enabled {
+        return None;
+    }
+
+    let mut response = Problem::maintenance(&config.message, instance).response();
+    if let Some(secs) = config.retry_after {
+        response.headers_mut().insert(header::RETRY_AFTER, header::HeaderValue::from(secs));
+    }
+    Some(response)
+}
+
+/// Counts `images` against the daily quota of the request's API key. The
+/// hold refunds them unless kept, so a failed generation or a client that
+/// goes away (actix drops the handler) doesn't use up quota.
+fn reserve_quota(
+    http_req: &HttpRequest,
+    quotas: &Arc<QuotaTracker>,
+    images: i32,
+    instance: &str,
+) -> Result<Option<QuotaHold>, HttpResponse> {
+    let key = http_req.headers()
+        .get(API_KEY_HEADER)
+        .and_then(|v| v.to_str().ok());
+
+    match quotas.reserve(key, images.max(1) as u32) {
+        QuotaDecision::Unmetered => Ok(None),
+        QuotaDecision::Granted(reservation) => Ok(Some(QuotaHold::new(Arc::clone(quotas), reservation))),
+        QuotaDecision::Exceeded { remaining } => {
+            let e = DiffusionError::QuotaExceeded { remaining };
+            let mut response = Problem::from_error(&e, instance).response();
+            response.headers_mut().insert(
+                header::HeaderName::from_static(QUOTA_REMAINING_HEADER),
+                header::HeaderValue::from(remaining),
+            );
+            Err(response)
+        }
+    }
 }
 
 async fn generate_image(
+    http_req: HttpRequest,
     req: web::Json<GenerateRequest>,
     data: web::Data<AppState>,
 ) -> impl Responder {
@@ -67,93 +772,743 @@ async fn generate_image(
 
     let job_id = uuid::Uuid::new_v4().to_string();
 
-    let params = GenerationParams {
-        prompt: req.prompt.clone(),
-        negative_prompt: req.negative_prompt.clone(),
-        num_inference_steps: req.num_inference_steps,
-        guidance_scale: req.guidance_scale,
-        width: req.width,
-        height: req.height,
-        seed: req.seed,
+    if let Some(response) = maintenance_response(&data.config.server.maintenance, &job_id) {
+        return response;
+    }
+
+    let req = match resolve_recipe(req.into_inner(), &data.config).await {
+        Ok(req) => req,
+        Err(e) => return Problem::from_error(&e, &job_id).response(),
     };
 
+    if let Err(e) = client_metadata::validate(&req.metadata, &data.config.server) {
+        return Problem::from_error(&e, &job_id).response();
+    }
+
+    let mut params = match req.to_params(&data.config.server) {
+        Ok(params) => params,
+        Err(e) => return Problem::from_error(&e, &job_id).response(),
+    };
+    params.queue_depth = data.queue.queue_length().await;
+    params.job_id = Some(job_id.clone());
+
+    // Held until the response, so draining the model waits for this request
+    let _admission = match data.pipeline.models().admit(params.model_id.as_deref()) {
+        Ok(admission) => admission,
+        Err(e) => return Problem::from_error(&e, &job_id).response(),
+    };
+    let quota = match reserve_quota(&http_req, &data.quotas, params.num_images, &job_id) {
+        Ok(quota) => quota,
+        Err(response) => return response,
+    };
+    if let Some(quota) = &quota {
+        params.num_images = quota.images() as i32;
+    }
     match data.pipeline.generate(params).await {
         Ok(result) => {
             // Convert to base64
-            let images_base64: Vec<String> = result.images
-                .iter()
+            let images_base64: Vec<String> = result.images()
                 .map(|img| base64::encode(img))
                 .collect();
 
-            HttpResponse::Ok().json(GenerateResponse {
-                job_id: job_id.clone(),
-                status: "completed".to_string(),
-                images_base64: Some(images_base64),
-                metadata: Some(ResponseMetadata {
-                    generation_time_seconds: result.generation_time,
-                    model_used: "stable-diffusion-v1-5".to_string(),
-                    seed: result.seed,
-                    actual_steps: result.steps_taken,
-                }),
-                error: None,
-            })
-        }
-        Err(e) => {
-            HttpResponse::InternalServerError().json(GenerateResponse {
-                job_id,
-                status: "error".to_string(),
-                images_base64: None,
-                metadata: None,
-                error: Some(format!("Generation failed: {}", e)),
-            })
+            let results: Vec<ImageResultResponse> = result.results
+                .iter()
+                .map(|r| match &r.outcome {
+                    Ok(img) => ImageResultResponse {
+                        index: r.index,
+                        seed: r.seed,
+                        result_id: result_id::encode(&job_id, r.seed),
+                        status: "completed".to_string(),
+                        image_base64: Some(base64::encode(img)),
+                        latent_base64: r.latent.as_ref().map(base64::encode),
+                        phash: r.phash.map(|hash| format!("{:016x}", hash)),
+                        thumbnail_base64: r.thumbnail.as_ref().map(base64::encode),
+                        animation_base64: r.animation.as_ref().map(base64::encode),
+                        encodings_base64: r.encodings
+                            .iter()
+                            .map(|(format, bytes)| (format.to_string(), base64::encode(bytes)))
+                            .collect(),
+                        degraded: r.degraded.as_ref().map(DegradationResponse::from),
+                        error: None,
+                    },
+                    Err(reason) => ImageResultResponse {
+                        index: r.index,
+                        seed: r.seed,
+                        result_id: result_id::encode(&job_id, r.seed),
+                        status: "failed".to_string(),
+                        image_base64: None,
+                        latent_base64: None,
+                        phash: None,
+                        thumbnail_base64: None,
+                        animation_base64: None,
+                        encodings_base64: BTreeMap::new(),
+                        degraded: None,
+                        error: Some(reason.clone()),
+                    },
+                })
+                .collect();
+
+            // The seed that reproduces the first image, which differs from
+            // the batch seed after a degenerate retry
+            let seed = result.first_image().map_or(result.seed, |r| r.seed);
+            let mut response = HttpResponse::Ok();
+            response.insert_header((generation_headers::SEED, seed.to_string()));
+            if let Some(remaining) = quota.map(QuotaHold::keep) {
+                response.insert_header((QUOTA_REMAINING_HEADER, remaining.to_string()));
+            }
+            response
+                .json(GenerateResponse {
+                    job_id: job_id.clone(),
+                    status: "completed".to_string(),
+                    images_base64: Some(images_base64),
+                    results: Some(results),
+                    metadata: Some(ResponseMetadata::from_result(&result)),
+                    client_metadata: req.metadata.clone(),
+                })
         }
+        Err(e) => Problem::from_error(&e, &job_id).response(),
     }
 }
 
 async fn generate_image_binary(
+    http_req: HttpRequest,
     req: web::Json<GenerateRequest>,
     data: web::Data<AppState>,
 ) -> impl Responder {
     info!("REST API: Generate binary image for prompt: {}", req.prompt);
 
+    let request_id = problem::new_instance_id();
+
+    if let Some(response) = maintenance_response(&data.config.server.maintenance, &request_id) {
+        return response;
+    }
+
+    let req = match resolve_recipe(req.into_inner(), &data.config).await {
+        Ok(req) => req,
+        Err(e) => return Problem::from_error(&e, &request_id).response(),
+    };
+
+    let mut params = match req.to_params(&data.config.server) {
+        Ok(params) => params,
+        Err(e) => return Problem::from_error(&e, &request_id).response(),
+    };
+
+    let etag = if data.config.server.etag_enabled {
+        generation_etag(&params)
+    } else {
+        None
+    };
+
+    // The client already holds this exact deterministic image. Invalid
+    // requests still get their 400.
+    if let (Some(etag), false) = (&etag, req.no_cache.unwrap_or(false)) {
+        if etag_matches(&http_req, etag) {
+            if let Err(e) = data.pipeline.validate(&params) {
+                return Problem::from_error(&e, &request_id).response();
+            }
+            return HttpResponse::NotModified()
+                .insert_header((header::ETAG, etag.clone()))
+                .finish();
+        }
+    }
+
+    params.queue_depth = data.queue.queue_length().await;
+    params.job_id = Some(request_id.clone());
+
+    let _admission = match data.pipeline.models().admit(params.model_id.as_deref()) {
+        Ok(admission) => admission,
+        Err(e) => return Problem::from_error(&e, &request_id).response(),
+    };
+    let quota = match reserve_quota(&http_req, &data.quotas, params.num_images, &request_id) {
+        Ok(quota) => quota,
+        Err(response) => return response,
+    };
+    if let Some(quota) = &quota {
+        params.num_images = quota.images() as i32;
+    }
+    match data.pipeline.generate(params).await {
+        Ok(result) => {
+            let first = result.first_image()
+                .and_then(|r| Some((r, r.outcome.as_ref().ok()?, r.format(result.format))));
+            if let Some((image, img_bytes, format)) = first {
+                let mut response = HttpResponse::Ok();
+                // Named after the image returned, which is not the first of
+                // the batch when an earlier one failed
+                let file_name = download::file_name(
+                    &data.config.server.download_filename,
+                    &req.prompt,
+                    image.seed,
+                    image.index,
+                    format.extension(),
+                );
+                response
+                    .content_type(format.mime_type())
+                    .insert_header((header::CONTENT_DISPOSITION, download::attachment(&file_name)));
+                for generation_header in generation_headers::for_result(&result, image.seed) {
+                    response.insert_header(generation_header);
+                }
+                // A step-reduced image isn't the one the ETag describes
+                if let (Some(etag), None) = (etag, result.requested_steps) {
+                    response.insert_header((header::ETAG, etag));
+                }
+                if let Some(remaining) = quota.map(QuotaHold::keep) {
+                    response.insert_header((QUOTA_REMAINING_HEADER, remaining.to_string()));
+                }
+                response.body(img_bytes.clone())
+            } else {
+                let e = DiffusionError::Internal("No image generated".to_string());
+                Problem::from_error(&e, &request_id).response()
+            }
+        }
+        Err(e) => Problem::from_error(&e, &request_id).response(),
+    }
+}
+
+/// Generates a batch and returns every image plus `metadata.json` and the
+/// `recipe.json` that produced it as one ZIP archive
+async fn generate_image_zip(
+    http_req: HttpRequest,
+    req: web::Json<GenerateRequest>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    info!("REST API: Generate ZIP archive for prompt: {}", req.prompt);
+
+    let job_id = uuid::Uuid::new_v4().to_string();
+
+    if let Some(response) = maintenance_response(&data.config.server.maintenance, &job_id) {
+        return response;
+    }
+
+    let req = match resolve_recipe(req.into_inner(), &data.config).await {
+        Ok(req) => req,
+        Err(e) => return Problem::from_error(&e, &job_id).response(),
+    };
+
+    if let Err(e) = client_metadata::validate(&req.metadata, &data.config.server) {
+        return Problem::from_error(&e, &job_id).response();
+    }
+
+    let mut params = match req.to_params(&data.config.server) {
+        Ok(params) => params,
+        Err(e) => return Problem::from_error(&e, &job_id).response(),
+    };
+    params.queue_depth = data.queue.queue_length().await;
+    params.job_id = Some(job_id.clone());
+
+    let _admission = match data.pipeline.models().admit(params.model_id.as_deref()) {
+        Ok(admission) => admission,
+        Err(e) => return Problem::from_error(&e, &job_id).response(),
+    };
+    let quota = match reserve_quota(&http_req, &data.quotas, params.num_images, &job_id) {
+        Ok(quota) => quota,
+        Err(response) => return response,
+    };
+    if let Some(quota) = &quota {
+        params.num_images = quota.images() as i32;
+    }
+    let result = match data.pipeline.generate(params).await {
+        Ok(result) => result,
+        Err(e) => return Problem::from_error(&e, &job_id).response(),
+    };
+
+    let pattern = &data.config.server.download_filename;
+    let file_name = |r: &ImageResult| {
+        let extension = r.format(result.format).extension();
+        download::file_name(&format!("{:03}_{}", r.index, pattern), &req.prompt, r.seed, r.index, extension)
+    };
+
+    let manifest = ArchiveManifest {
+        job_id: job_id.clone(),
+        metadata: ResponseMetadata::from_result(&result),
+        images: result.results
+            .iter()
+            .map(|r| ArchiveImage {
+                index: r.index,
+                seed: r.seed,
+                status: if r.outcome.is_ok() { "completed" } else { "failed" }.to_string(),
+                file: r.outcome.as_ref().ok().map(|_| file_name(r)),
+                error: r.outcome.as_ref().err().cloned(),
+            })
+            .collect(),
+        client_metadata: req.metadata.clone(),
+    };
+
+    let json = (serde_json::to_vec_pretty(&manifest), serde_json::to_vec_pretty(&req));
+    let (manifest_json, recipe_json) = match json {
+        (Ok(manifest), Ok(recipe)) => (manifest, recipe),
+        (Err(e), _) | (_, Err(e)) => {
+            let e = DiffusionError::Internal(format!("Failed to serialize archive metadata: {}", e));
+            return Problem::from_error(&e, &job_id).response();
+        }
+    };
+
+    let images = result.results.iter().filter_map(|r| {
+        let bytes = r.outcome.as_ref().ok()?;
+        Some((file_name(r), bytes.as_slice()))
+    });
+    let files = images.chain([
+        ("metadata.json".to_string(), manifest_json.as_slice()),
+        ("recipe.json".to_string(), recipe_json.as_slice()),
+    ]);
+
+    // Named after the first image in the archive, not the batch's base seed
+    let first = result.first_image();
+    let first_seed = first.map_or(result.seed, |r| r.seed);
+    let archive_name = download::file_name(
+        pattern,
+        &req.prompt,
+        first_seed,
+        first.map_or(0, |r| r.index),
+        "zip",
+    );
+
+    match archive::build_zip(files) {
+        Ok(zip) => {
+            let mut response = HttpResponse::Ok();
+            response
+                .content_type("application/zip")
+                .insert_header((header::CONTENT_DISPOSITION, download::attachment(&archive_name)));
+            for generation_header in generation_headers::for_result(&result, first_seed) {
+                response.insert_header(generation_header);
+            }
+            if let Some(remaining) = quota.map(QuotaHold::keep) {
+                response.insert_header((QUOTA_REMAINING_HEADER, remaining.to_string()));
+            }
+            response.body(zip)
+        }
+        Err(e) => Problem::from_error(&e, &job_id).response(),
+    }
+}
+
+async fn seed_search(
+    http_req: HttpRequest,
+    req: web::Json<SeedSearchRequest>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    info!("REST API: Seed search of {} cells for prompt: {}", req.count, req.prompt);
+
+    let request_id = problem::new_instance_id();
+
+    if let Some(response) = maintenance_response(&data.config.server.maintenance, &request_id) {
+        return response;
+    }
+
+    if let Err(e) = check_unknown_fields(&req.unknown, &data.config.server) {
+        return Problem::from_error(&e, &request_id).response();
+    }
+
     let params = GenerationParams {
         prompt: req.prompt.clone(),
+        prompt_embeds: None,
+        prompt_schedule: Vec::new(),
         negative_prompt: req.negative_prompt.clone(),
+        negative_prompts: Vec::new(),
         num_inference_steps: req.num_inference_steps,
         guidance_scale: req.guidance_scale,
-        width: req.width,
-        height: req.height,
-        seed: req.seed,
+        width: None,
+        height: None,
+        aspect_ratio: None,
+        megapixels: None,
+        init_image: None,
+        strength: None,
+        reference_images: Vec::new(),
+        vary_region: None,
+        strength_preset: None,
+        seed: req.seed_start,
+        subseed: None,
+        subseed_strength: None,
+        temperature: None,
+        clip_skip: req.clip_skip,
+        model_id: req.model_id.clone(),
+        num_images: 1,
+        precision: req.precision.clone(),
+        scheduler: req.scheduler.clone(),
+        disable_auto_prompt: false,
+        no_cache: false,
+        return_latents: false,
+        return_schedule: false,
+        return_steps_animation: false,
+        debug: false,
+        thumbnail: None,
+        dpi: None,
+        format: data.config.server.rest_output_format.clone(),
+        output_formats: Vec::new(),
+        transparent_background: false,
+        upscale: None,
+        max_response_bytes: None,
+        border_width: None,
+        border_color: None,
+        color_palette: Vec::new(),
+        face_restore: None,
+        face_restore_weight: None,
+        quality: None,
+        queue_depth: data.queue.queue_length().await,
+        job_id: Some(request_id.clone()),
     };
 
-    match data.pipeline.generate(params).await {
-        Ok(result) => {
-            if let Some(img_bytes) = result.images.first() {
-                HttpResponse::Ok()
-                    .content_type("image/png")
-                    .body(img_bytes.clone())
-            } else {
-                HttpResponse::InternalServerError().body("No image generated")
+    let _admission = match data.pipeline.models().admit(params.model_id.as_deref()) {
+        Ok(admission) => admission,
+        Err(e) => return Problem::from_error(&e, &request_id).response(),
+    };
+    // Every cell is an image
+    let quota = match reserve_quota(&http_req, &data.quotas, req.count, &request_id) {
+        Ok(quota) => quota,
+        Err(response) => return response,
+    };
+    let count = quota.as_ref().map_or(req.count, |quota| quota.images() as i32);
+    match data.pipeline.seed_search(params, count).await {
+        Ok(grid) => {
+            let mut response = HttpResponse::Ok();
+            if let Some(remaining) = quota.map(QuotaHold::keep) {
+                response.insert_header((QUOTA_REMAINING_HEADER, remaining.to_string()));
             }
+            response.json(SeedSearchResponse {
+                metadata_version: METADATA_VERSION,
+                grid_base64: base64::encode(&grid.image),
+                columns: grid.columns,
+                rows: grid.rows,
+                cell_width: grid.cell_width,
+                cell_height: grid.cell_height,
+                cells: grid.cells
+                    .into_iter()
+                    .map(|cell| SeedCellResponse {
+                        index: cell.index,
+                        seed: cell.seed,
+                        row: cell.row,
+                        column: cell.column,
+                        status: if cell.error.is_some() { "failed" } else { "completed" }.to_string(),
+                        error: cell.error,
+                    })
+                    .collect(),
+                generation_time_seconds: grid.generation_time,
+            })
         }
-        Err(e) => {
-            HttpResponse::InternalServerError().body(format!("Generation failed: {}", e))
-        }
+        Err(e) => Problem::from_error(&e, &request_id).response(),
+    }
+}
+
+async fn estimate(
+    req: web::Json<GenerateRequest>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let request_id = problem::new_instance_id();
+
+    let estimate = resolve_recipe(req.into_inner(), &data.config)
+        .await
+        .and_then(|req| req.to_params(&data.config.server))
+        .and_then(|params| data.pipeline.estimate(&params));
+
+    match estimate {
+        Ok(estimate) => HttpResponse::Ok().json(EstimateResponse {
+            estimated_seconds: estimate.seconds,
+            seconds_per_step: estimate.seconds_per_step,
+            steps: estimate.steps,
+            num_images: estimate.num_images,
+            samples: estimate.samples,
+            estimated_cost: estimate.cost,
+        }),
+        Err(e) => Problem::from_error(&e, &request_id).response(),
+    }
+}
+
+/// Server-sent event stream of every job status transition. Subscribers
+/// that fall behind get a `lagged` event with the number of events skipped.
+/// The feed covers every client's jobs, so it needs the admin key.
+async fn job_events(http_req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
+    if let Err(e) = authorize_admin(&http_req, &data.config) {
+        return Problem::from_error(&e, &problem::new_instance_id()).response();
+    }
+
+    let stream = events::event_stream(data.queue.subscribe()).map(|item| {
+        let frame = match item {
+            Ok(event) => {
+                let payload = JobEventResponse {
+                    job_id: event.job_id,
+                    status: event.status.as_str().to_string(),
+                    timestamp: event.timestamp,
+                };
+                let json = serde_json::to_string(&payload).unwrap_or_default();
+                format!("event: job\ndata: {}\n\n", json)
+            }
+            Err(skipped) => format!("event: lagged\ndata: {{\"skipped\":{}}}\n\n", skipped),
+        };
+        Ok::<_, actix_web::Error>(web::Bytes::from(frame))
+    });
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .streaming(stream)
+}
+
+/// Resolves a result id from a generate response back to its job and the
+/// seed that reproduces the image
+async fn lookup_result(path: web::Path<String>, data: web::Data<AppState>) -> impl Responder {
+    let request_id = problem::new_instance_id();
+    let token = path.into_inner();
+
+    let (job_id, seed) = match result_id::decode(&token) {
+        Some(decoded) => decoded,
+        None => return Problem::from_error(&DiffusionError::JobNotFound(token.clone()), &request_id).response(),
+    };
+    let status = data.queue.get_status(job_id).await;
+    HttpResponse::Ok().json(ResultLookupResponse {
+        job_id: job_id.to_string(),
+        seed,
+        status: status.map(|status| status.as_str().to_string()),
+    })
+}
+
+async fn promote_job(
+    http_req: HttpRequest,
+    path: web::Path<String>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let request_id = problem::new_instance_id();
+
+    if let Err(e) = authorize_admin(&http_req, &data.config) {
+        return Problem::from_error(&e, &request_id).response();
+    }
+
+    let job_id = path.into_inner();
+    info!("REST API: Admin promote for job {}", job_id);
+
+    match data.queue.promote(&job_id).await {
+        Ok(()) => HttpResponse::Ok().json(JobActionResponse {
+            job_id,
+            status: "promoted".to_string(),
+        }),
+        Err(e) => Problem::from_error(&e, &request_id).response(),
+    }
+}
+
+async fn list_jobs(
+    http_req: HttpRequest,
+    query: web::Query<ListJobsQuery>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let request_id = problem::new_instance_id();
+
+    if let Err(e) = authorize_admin(&http_req, &data.config) {
+        return Problem::from_error(&e, &request_id).response();
+    }
+
+    let limit = query.limit.clamp(1, MAX_LIST_LIMIT);
+    match data.queue.list_jobs(query.cursor.as_deref(), limit).await {
+        Ok((jobs, next_cursor)) => HttpResponse::Ok().json(ListJobsResponse {
+            jobs: jobs
+                .into_iter()
+                .map(|(job_id, status)| JobActionResponse {
+                    job_id,
+                    status: status.as_str().to_string(),
+                })
+                .collect(),
+            next_cursor,
+        }),
+        Err(e) => Problem::from_error(&e, &request_id).response(),
+    }
+}
+
+/// Stops admitting jobs for a model and answers once its queued and
+/// running jobs are done, so the model can be swapped. 202 when the
+/// timeout passes first. Other models are unaffected.
+async fn drain_model(
+    http_req: HttpRequest,
+    path: web::Path<String>,
+    query: web::Query<DrainQuery>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let request_id = problem::new_instance_id();
+
+    if let Err(e) = authorize_admin(&http_req, &data.config) {
+        return Problem::from_error(&e, &request_id).response();
+    }
+
+    let model_id = path.into_inner();
+    info!("REST API: Admin drain for model {}", model_id);
+
+    let timeout = std::time::Duration::from_secs(query.timeout_seconds);
+    match data.pipeline.models().drain(&model_id, timeout).await {
+        Ok(true) => HttpResponse::Ok().json(ModelActionResponse {
+            model_id,
+            status: "drained".to_string(),
+        }),
+        Ok(false) => HttpResponse::Accepted().json(ModelActionResponse {
+            model_id,
+            status: "draining".to_string(),
+        }),
+        Err(e) => Problem::from_error(&e, &request_id).response(),
+    }
+}
+
+/// Admits jobs for a drained model again
+async fn resume_model(
+    http_req: HttpRequest,
+    path: web::Path<String>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let request_id = problem::new_instance_id();
+
+    if let Err(e) = authorize_admin(&http_req, &data.config) {
+        return Problem::from_error(&e, &request_id).response();
+    }
+
+    let model_id = path.into_inner();
+    info!("REST API: Admin resume for model {}", model_id);
+
+    match data.pipeline.models().resume(&model_id) {
+        Ok(()) => HttpResponse::Ok().json(ModelActionResponse {
+            model_id,
+            status: "accepting".to_string(),
+        }),
+        Err(e) => Problem::from_error(&e, &request_id).response(),
     }
 }
 
 async fn health_check(data: web::Data<AppState>) -> impl Responder {
-    HttpResponse::Ok().json(HealthResponse {
-        status: "healthy".to_string(),
+    let states = data.pipeline.models().states();
+    let status = health::evaluate(&data.config.health, &HealthSnapshot {
+        queue_length: data.queue.queue_length().await,
+        max_queue_size: data.config.queue.max_queue_size,
+        failure_rate: data.pipeline.failure_rate(data.config.health.failure_min_samples),
+        models: &states,
+        maintenance: data.config.server.maintenance.enabled,
+    });
+
+    let models = states
+        .into_iter()
+        .map(|(id, state)| (id, state.as_str().to_string()))
+        .collect();
+
+    // Readiness probes only look at the status code
+    let mut response = match status {
+        HealthStatus::Unhealthy | HealthStatus::Maintenance => HttpResponse::ServiceUnavailable(),
+        HealthStatus::Healthy | HealthStatus::Degraded => HttpResponse::Ok(),
+    };
+    let maintenance = &data.config.server.maintenance;
+    if let (HealthStatus::Maintenance, Some(secs)) = (status, maintenance.retry_after) {
+        response.insert_header((header::RETRY_AFTER, secs));
+    }
+
+    response.json(HealthResponse {
+        status: status.as_str().to_string(),
         model_loaded: true,
         version: env!("CARGO_PKG_VERSION").to_string(),
         device: data.config.model.device.clone(),
+        models,
+        total_generations: data.pipeline.total_generations(),
+        message: (status == HealthStatus::Maintenance).then(|| maintenance.message.clone()),
     })
 }
 
+/// Prometheus text exposition of the lifetime counter and queue depth
+async fn metrics(data: web::Data<AppState>) -> impl Responder {
+    let body = format!(
+        "# HELP diffusion_generations_total Successful generations over the server's lifetime\n\
+         # TYPE diffusion_generations_total counter\n\
+         diffusion_generations_total {}\n\
+         # HELP diffusion_queue_length Jobs waiting in the queue\n\
+         # TYPE diffusion_queue_length gauge\n\
+         diffusion_queue_length {}\n",
+        data.pipeline.total_generations(),
+        data.queue.queue_length().await,
+    );
+
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(body)
+}
+
+async fn list_models(data: web::Data<AppState>) -> impl Responder {
+    let registry = data.pipeline.models();
+    let default_model = registry.resolve(None).ok();
+    let models = registry
+        .ids()
+        .into_iter()
+        .map(|id| {
+            let (min_steps, max_steps, default_steps) = data.pipeline.step_range(Some(&id));
+            ModelInfo {
+                state: registry.state(&id).map_or("unknown", |state| state.as_str()).to_string(),
+                default: default_model.as_deref() == Some(id.as_str()),
+                min_steps,
+                max_steps,
+                default_steps,
+                id,
+            }
+        })
+        .collect();
+
+    HttpResponse::Ok().json(ModelsResponse { models })
+}
+
+#[derive(Debug, Serialize)]
+pub struct ParametersResponse {
+    parameters: Vec<ParameterSpec>,
+}
+
+async fn parameter_schema(data: web::Data<AppState>) -> impl Responder {
+    HttpResponse::Ok().json(ParametersResponse {
+        parameters: parameters::schema(&data.config.inference),
+    })
+}
+
+fn routes() -> Vec<(&'static str, actix_web::Route)> {
+    vec![
+        ("/health", web::get().to(health_check)),
+        ("/metrics", web::get().to(metrics)),
+        ("/v1/generate", web::post().to(generate_image)),
+        ("/v1/generate/binary", web::post().to(generate_image_binary)),
+        ("/v1/generate/zip", web::post().to(generate_image_zip)),
+        ("/v1/generate/seed-search", web::post().to(seed_search)),
+        ("/v1/estimate", web::post().to(estimate)),
+        ("/v1/parameters", web::get().to(parameter_schema)),
+        ("/v1/models", web::get().to(list_models)),
+        ("/v1/results/{result_id}", web::get().to(lookup_result)),
+        ("/v1/events", web::get().to(job_events)),
+        ("/v1/admin/jobs", web::get().to(list_jobs)),
+        ("/v1/admin/jobs/{id}/promote", web::post().to(promote_job)),
+        ("/v1/admin/models/{id}/drain", web::post().to(drain_model)),
+        ("/v1/admin/models/{id}/resume", web::post().to(resume_model)),
+    ]
+}
+
+/// Rejects `rest_routes` entries that aren't routes, so a typo fails startup
+/// instead of silently hiding an endpoint
+pub fn check_routes(allowed: &[String]) -> Result<(), DiffusionError> {
+    let paths: Vec<&str> = routes().into_iter().map(|(path, _)| path).collect();
+    let unknown: Vec<&str> = allowed
+        .iter()
+        .map(String::as_str)
+        .filter(|path| !paths.contains(path))
+        .collect();
+
+    if !unknown.is_empty() {
+        return Err(DiffusionError::Config(format!(
+            "Unknown rest_routes: {} (available: {})",
+            unknown.join(", "),
+            paths.join(", ")
+        )));
+    }
+
+    Ok(())
+}
+
+/// Registers every route, or only those in `allowed` when set. Anything
+/// left out falls through to the 404 default service.
+fn register_routes(cfg: &mut web::ServiceConfig, allowed: Option<&[String]>) {
+    for (path, route) in routes() {
+        let enabled = allowed.map_or(true, |allowed| allowed.iter().any(|p| p == path));
+        if enabled {
+            cfg.route(path, route);
+        }
+    }
+}
+
 pub async fn start_rest_server(
     config: Config,
     pipeline: InferencePipeline,
+    queue: JobQueue,
+    quotas: Arc<QuotaTracker>,
 ) -> Result<(), DiffusionError> {
     let addr = format!("{}:{}", config.server.rest_host, config.server.rest_port);
     
@@ -161,15 +1516,34 @@ pub async fn start_rest_server(
 
     let app_state = web::Data::new(AppState {
         pipeline: Arc::new(pipeline),
+        queue,
         config: config.clone(),
+        quotas,
     });
 
+    let allowed_routes = config.server.rest_routes.clone();
+    if let Some(allowed) = &allowed_routes {
+        info!("REST routes restricted to: {}", allowed.join(", "));
+    }
+    let access_log = AccessLog::new(&config.logging)?;
+
     HttpServer::new(move || {
         App::new()
             .app_data(app_state.clone())
-            .route("/health", web::get().to(health_check))
-            .route("/v1/generate", web::post().to(generate_image))
-            .route("/v1/generate/binary", web::post().to(generate_image_binary))
+            .app_data(web::JsonConfig::default().error_handler(problem::json_error_handler))
+            .configure(|cfg| register_routes(cfg, allowed_routes.as_deref()))
+            .default_service(web::route().to(problem::not_found))
+            .wrap_fn(move |req, srv| {
+                let start = Instant::now();
+                let method = req.method().clone();
+                let path = req.path().to_string();
+                let response = srv.call(req);
+                async move {
+                    let response = response.await?;
+                    access_log.record(&method, &path, response.status(), start.elapsed());
+                    Ok(response)
+                }
+            })
     })
     .bind(&addr)
     .map_err(|e| DiffusionError::Internal(format!("Failed to bind server: {}", e)))?
@@ -179,3 +1553,488 @@ pub async fn start_rest_server(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inference::pipeline::tests::{mock_config, mock_pipeline};
+    use actix_web::dev::ServiceResponse;
+    use actix_web::test;
+
+    fn request(body: serde_json::Value) -> GenerateRequest {
+        serde_json::from_value(body).unwrap()
+    }
+
+    /// Sends `req` to an app with the configured routes, backed by a mock
+    /// pipeline
+    async fn call(config: Config, req: test::TestRequest) -> ServiceResponse {
+        let allowed = config.server.rest_routes.clone();
+        let state = web::Data::new(AppState {
+            pipeline: Arc::new(mock_pipeline(&config)),
+            queue: JobQueue::new(config.queue.max_queue_size),
+            quotas: Arc::new(QuotaTracker::new(
+                config.queue.key_quotas.clone(),
+                config.queue.default_key_quota,
+                config.queue.quota_action,
+            )),
+            config,
+        });
+        let app = test::init_service(
+            App::new()
+                .app_data(state)
+                .configure(|cfg| register_routes(cfg, allowed.as_deref()))
+                .default_service(web::route().to(problem::not_found)),
+        )
+        .await;
+        test::call_service(&app, req.to_request()).await
+    }
+
+    async fn post(config: Config, path: &str, body: serde_json::Value) -> ServiceResponse {
+        call(config, test::TestRequest::post().uri(path).set_json(body)).await
+    }
+
+    #[test]
+    fn alias_fields_deserialize_like_canonical_ones() {
+        let server = Config::default().server;
+        let canonical = request(serde_json::json!({
+            "prompt": "a lighthouse",
+            "negative_prompt": "fog",
+            "num_inference_steps": 12,
+            "guidance_scale": 5.5,
+            "num_images": 2,
+        }))
+        .to_params(&server)
+        .unwrap();
+
+        for body in [
+            serde_json::json!({"prompt": "a lighthouse", "negative": "fog", "steps": 12, "cfg": 5.5, "n": 2}),
+            serde_json::json!({"prompt": "a lighthouse", "negative": "fog", "steps": 12, "cfg_scale": 5.5, "batch_size": 2}),
+        ] {
+            let aliased = request(body).to_params(&server).unwrap();
+            assert_eq!(serde_json::to_value(&aliased).unwrap(), serde_json::to_value(&canonical).unwrap());
+        }
+    }
+
+    #[actix_web::test]
+    async fn metrics_report_lifetime_generations() {
+        let response = call(mock_config(), test::TestRequest::get().uri("/metrics")).await;
+        assert_eq!(response.status(), 200);
+
+        let body = test::read_body(response).await;
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.contains("# TYPE diffusion_generations_total counter"));
+        assert!(body.contains("diffusion_generations_total 0\n"));
+        assert!(body.contains("diffusion_queue_length 0\n"));
+    }
+
+    #[actix_web::test]
+    async fn generations_are_metered_and_failures_refunded() {
+        let mut config = mock_config();
+        config.queue.key_quotas.insert("free-key".to_string(), 2);
+        config.queue.default_key_quota = Some(0);
+        let state = web::Data::new(AppState {
+            pipeline: Arc::new(mock_pipeline(&config)),
+            queue: JobQueue::new(config.queue.max_queue_size),
+            quotas: Arc::new(QuotaTracker::new(
+                config.queue.key_quotas.clone(),
+                config.queue.default_key_quota,
+                config.queue.quota_action,
+            )),
+            config,
+        });
+        let app = test::init_service(App::new().app_data(state).configure(|cfg| register_routes(cfg, None))).await;
+        let generate = |key: Option<&str>, width: u32| {
+            let body = serde_json::json!({"prompt": "a lighthouse", "width": width, "height": 64, "steps": 4});
+            let mut request = test::TestRequest::post().uri("/v1/generate/binary").set_json(body);
+            if let Some(key) = key {
+                request = request.insert_header((API_KEY_HEADER, key));
+            }
+            request.to_request()
+        };
+        let remaining = |response: &ServiceResponse| {
+            response.headers().get(QUOTA_REMAINING_HEADER).unwrap().to_str().unwrap().to_string()
+        };
+
+        // Rejected by validation after the reservation, so refunded
+        let response = test::call_service(&app, generate(Some("free-key"), 100_000)).await;
+        assert_eq!(response.status(), 400);
+        for left in ["1", "0"] {
+            let response = test::call_service(&app, generate(Some("free-key"), 64)).await;
+            assert_eq!(response.status(), 200);
+            assert_eq!(remaining(&response), left);
+        }
+        let response = test::call_service(&app, generate(Some("free-key"), 64)).await;
+        assert_eq!(response.status(), 429);
+        assert_eq!(remaining(&response), "0");
+
+        // Without a listed key the default quota applies
+        for key in [None, Some("made-up-key")] {
+            assert_eq!(test::call_service(&app, generate(key, 64)).await.status(), 429);
+        }
+    }
+
+    #[actix_web::test]
+    async fn unknown_fields_are_rejected_only_in_strict_mode() {
+        // `step` is a typo of `steps`
+        let body = serde_json::json!({"prompt": "a lighthouse", "width": 64, "height": 64, "step": 4});
+        let seed_search = serde_json::json!({"prompt": "a lighthouse", "count": 2, "colour": "red"});
+
+        let response = post(mock_config(), "/v1/generate", body.clone()).await;
+        assert_eq!(response.status(), 200);
+
+        let mut config = mock_config();
+        config.server.strict_request_fields = true;
+        let response = post(config.clone(), "/v1/generate", body).await;
+        assert_eq!(response.status(), 400);
+        let problem: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(problem["errors"], serde_json::json!(["Unknown field 'step'"]));
+
+        let response = post(config, "/v1/generate/seed-search", seed_search).await;
+        assert_eq!(response.status(), 400);
+    }
+
+    #[test]
+    fn v1_body_maps_onto_params() {
+        let server = Config::default().server;
+
+        for body in [
+            serde_json::json!({"prompt": "a lighthouse", "steps": 20}),
+            serde_json::json!({"version": "v1", "prompt": "a lighthouse", "steps": 20}),
+        ] {
+            let params = request(body).to_params(&server).unwrap();
+            assert_eq!(params.prompt, "a lighthouse");
+            assert_eq!(params.num_inference_steps, 20);
+        }
+    }
+
+    #[test]
+    fn unknown_version_is_rejected() {
+        let server = Config::default().server;
+
+        for version in ["v2", "v9"] {
+            let body = serde_json::json!({"version": version, "prompt": "a lighthouse"});
+            assert!(matches!(
+                request(body).to_params(&server),
+                Err(DiffusionError::InvalidParameters(_))
+            ));
+        }
+    }
+
+    #[actix_web::test]
+    async fn binary_endpoint_sets_seed_header() {
+        let body = serde_json::json!({"prompt": "a lighthouse", "width": 64, "height": 64, "steps": 4});
+        let response = post(mock_config(), "/v1/generate/binary", body).await;
+        assert_eq!(response.status(), 200);
+        let seed = response.headers().get(generation_headers::SEED).unwrap();
+        assert!(seed.to_str().unwrap().parse::<i64>().is_ok());
+
+        let body = serde_json::json!({"prompt": "a lighthouse", "width": 64, "height": 64, "steps": 4, "seed": 42});
+        let response = post(mock_config(), "/v1/generate/binary", body).await;
+        assert_eq!(response.headers().get(generation_headers::SEED).unwrap(), "42");
+    }
+
+    #[actix_web::test]
+    async fn metadata_version_is_reported_in_json_and_headers() {
+        let body = serde_json::json!({"prompt": "a lighthouse", "width": 64, "height": 64, "steps": 4});
+        let response: serde_json::Value = test::read_body_json(post(mock_config(), "/v1/generate", body.clone()).await).await;
+        assert_eq!(response["metadata"]["metadata_version"], METADATA_VERSION);
+
+        let response = post(mock_config(), "/v1/generate/binary", body).await;
+        assert_eq!(response.status(), 200);
+        let version = response.headers().get(generation_headers::METADATA_VERSION_HEADER).unwrap();
+        assert_eq!(version.to_str().unwrap(), METADATA_VERSION.to_string());
+    }
+
+    #[actix_web::test]
+    async fn unknown_routes_and_bad_bodies_are_problems() {
+        let response = call(mock_config(), test::TestRequest::get().uri("/v1/nope")).await;
+        assert_eq!(response.status(), 404);
+        assert_eq!(response.headers().get(header::CONTENT_TYPE).unwrap(), problem::PROBLEM_CONTENT_TYPE);
+
+        let body = serde_json::json!({"prompt": "a lighthouse", "width": 8});
+        let response = post(mock_config(), "/v1/generate", body).await;
+        assert_eq!(response.status(), 400);
+        let problem: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(problem["type"], "/problems/invalid-parameters");
+    }
+
+    #[actix_web::test]
+    async fn matching_etag_returns_304_without_body() {
+        let body = serde_json::json!({"prompt": "a lighthouse", "width": 64, "height": 64, "steps": 4, "seed": 7});
+        let response = post(mock_config(), "/v1/generate/binary", body.clone()).await;
+        assert_eq!(response.status(), 200);
+        let etag = response.headers().get(header::ETAG).unwrap().clone();
+
+        let revalidate = test::TestRequest::post()
+            .uri("/v1/generate/binary")
+            .insert_header((header::IF_NONE_MATCH, etag.clone()))
+            .set_json(body);
+        let response = call(mock_config(), revalidate).await;
+        assert_eq!(response.status(), 304);
+        assert_eq!(response.headers().get(header::ETAG).unwrap(), &etag);
+        assert!(test::read_body(response).await.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn invalid_request_is_not_answered_from_cache() {
+        let body = serde_json::json!({"prompt": "a lighthouse", "width": 8, "height": 64, "seed": 7});
+        let request = test::TestRequest::post()
+            .uri("/v1/generate/binary")
+            .insert_header((header::IF_NONE_MATCH, "*"))
+            .set_json(body);
+        assert_eq!(call(mock_config(), request).await.status(), 400);
+    }
+
+    #[test]
+    fn etag_is_stable_and_covers_every_field() {
+        let params = |seed| GenerationParams { seed: Some(seed), ..crate::inference::pipeline::tests::params("a") };
+        assert_eq!(generation_etag(&params(1)), generation_etag(&params(1)));
+        assert_ne!(generation_etag(&params(1)), generation_etag(&params(2)));
+        assert_eq!(generation_etag(&GenerationParams { seed: None, ..params(1) }), None);
+    }
+
+    #[actix_web::test]
+    async fn client_metadata_round_trips() {
+        let body = serde_json::json!({
+            "prompt": "a lighthouse", "width": 64, "height": 64, "steps": 4,
+            "metadata": {"user": "u-17", "trace": "abc"},
+        });
+        let response: serde_json::Value = test::read_body_json(post(mock_config(), "/v1/generate", body).await).await;
+        assert_eq!(response["client_metadata"], serde_json::json!({"user": "u-17", "trace": "abc"}));
+
+        let mut config = mock_config();
+        config.server.max_metadata_entries = 1;
+        let body = serde_json::json!({"prompt": "a lighthouse", "metadata": {"a": "1", "b": "2"}});
+        assert_eq!(post(config, "/v1/generate", body).await.status(), 400);
+    }
+
+    #[test]
+    fn quality_presets_expand_unless_overridden() {
+        let server = Config::default().server;
+        let params = |body: serde_json::Value| request(body).to_params(&server).unwrap();
+
+        let draft = params(serde_json::json!({"prompt": "a lighthouse", "quality": "draft"}));
+        let high = params(serde_json::json!({"prompt": "a lighthouse", "quality": "high"}));
+        assert_eq!(draft.num_inference_steps, QualityPreset::Draft.steps());
+        assert_eq!(high.num_inference_steps, QualityPreset::High.steps());
+        assert!(draft.num_inference_steps < high.num_inference_steps);
+        assert_eq!(high.scheduler.as_deref(), Some("ddim"));
+        assert_eq!(high.quality.as_deref(), Some("high"));
+
+        let explicit = params(serde_json::json!({"prompt": "a lighthouse", "quality": "high", "steps": 20}));
+        assert_eq!(explicit.num_inference_steps, 20);
+        assert_eq!(explicit.guidance_scale, QualityPreset::High.guidance_scale());
+    }
+
+    #[actix_web::test]
+    async fn zip_holds_every_image_and_the_metadata() {
+        let body = serde_json::json!({
+            "prompt": "a lighthouse", "width": 64, "height": 64, "steps": 4, "seed": 7, "num_images": 3,
+        });
+        let response = post(mock_config(), "/v1/generate/zip", body).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers().get(header::CONTENT_TYPE).unwrap(), "application/zip");
+        let disposition = response.headers().get(header::CONTENT_DISPOSITION).unwrap().to_str().unwrap();
+        assert_eq!(disposition, "attachment; filename=\"a-lighthouse_7.zip\"");
+
+        let bytes = test::read_body(response).await;
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(bytes.to_vec())).unwrap();
+        let names: Vec<String> = archive.file_names().map(str::to_string).collect();
+        assert_eq!(names.iter().filter(|name| name.ends_with(".png")).count(), 3);
+        assert!(names.iter().any(|name| name == "metadata.json"));
+        assert!(names.iter().any(|name| name == "recipe.json"));
+
+        let manifest: serde_json::Value = serde_json::from_reader(archive.by_name("metadata.json").unwrap()).unwrap();
+        let seeds: Vec<i64> = manifest["images"].as_array().unwrap().iter().map(|image| image["seed"].as_i64().unwrap()).collect();
+        assert_eq!(seeds.len(), 3);
+        assert_eq!(seeds[0], 7);
+    }
+
+    #[actix_web::test]
+    async fn event_feed_needs_the_admin_key() {
+        let mut config = mock_config();
+        let response = call(config.clone(), test::TestRequest::get().uri("/v1/events")).await;
+        assert_eq!(response.status(), 403);
+
+        config.server.admin_key = Some("secret".to_string());
+        let response = call(config.clone(), test::TestRequest::get().uri("/v1/events")).await;
+        assert_eq!(response.status(), 401);
+
+        let request = test::TestRequest::get().uri("/v1/events").insert_header((ADMIN_KEY_HEADER, "secret"));
+        let response = call(config, request).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers().get(header::CONTENT_TYPE).unwrap(), "text/event-stream");
+    }
+
+    #[actix_web::test]
+    async fn routes_outside_the_allowlist_are_404() {
+        let mut config = mock_config();
+        config.server.rest_routes = Some(vec!["/health".to_string(), "/v1/generate".to_string()]);
+        let body = serde_json::json!({"prompt": "a lighthouse", "width": 64, "height": 64, "steps": 4});
+
+        let response = post(config.clone(), "/v1/generate/binary", body.clone()).await;
+        assert_eq!(response.status(), 404);
+        assert_eq!(response.headers().get(header::CONTENT_TYPE).unwrap(), problem::PROBLEM_CONTENT_TYPE);
+        let response = post(config.clone(), "/v1/generate", body).await;
+        assert_eq!(response.status(), 200);
+        let response = call(config, test::TestRequest::get().uri("/health")).await;
+        assert_ne!(response.status(), 404);
+    }
+
+    #[test]
+    fn unknown_allowlisted_routes_fail_the_check() {
+        assert!(check_routes(&["/health".to_string(), "/v1/admin/jobs/{id}/promote".to_string()]).is_ok());
+        let error = check_routes(&["/v1/generate".to_string(), "/v1/genrate".to_string()]).unwrap_err();
+        assert!(matches!(&error, DiffusionError::Config(message) if message.contains("/v1/genrate")));
+    }
+
+    #[actix_web::test]
+    async fn maintenance_refuses_generation_while_admin_stays_up() {
+        let mut config = mock_config();
+        config.server.admin_key = Some("secret".to_string());
+        config.server.maintenance.enabled = true;
+        config.server.maintenance.message = "Upgrading GPUs".to_string();
+        config.server.maintenance.retry_after = Some(600);
+        let body = serde_json::json!({"prompt": "a lighthouse", "width": 64, "height": 64, "steps": 4});
+
+        for path in ["/v1/generate", "/v1/generate/binary", "/v1/generate/zip"] {
+            let response = post(config.clone(), path, body.clone()).await;
+            assert_eq!(response.status(), 503, "{}", path);
+            assert_eq!(response.headers().get(header::RETRY_AFTER).unwrap(), "600");
+            let problem: serde_json::Value = test::read_body_json(response).await;
+            assert!(problem["detail"].as_str().unwrap().contains("Upgrading GPUs"));
+        }
+
+        let request = test::TestRequest::get().uri("/v1/admin/jobs").insert_header((ADMIN_KEY_HEADER, "secret"));
+        assert_eq!(call(config.clone(), request).await.status(), 200);
+
+        let response = call(config, test::TestRequest::get().uri("/health")).await;
+        assert_eq!(response.status(), 503);
+        let health: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(health["status"], "maintenance");
+        assert_eq!(health["message"], "Upgrading GPUs");
+    }
+
+    #[actix_web::test]
+    async fn request_fields_override_the_recipe() {
+        let dir = std::env::temp_dir().join(format!("recipes-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let recipe = serde_json::json!({
+            "prompt": "a castle", "num_inference_steps": 30, "guidance_scale": 9.0, "seed": 5,
+            "return_schedule": true, "debug": true, "metadata": {"team": "art", "run": "recipe"},
+        });
+        std::fs::write(dir.join("castle.json"), recipe.to_string()).unwrap();
+        let mut config = mock_config();
+        config.server.recipes_dir = Some(dir.clone());
+
+        let body = request(serde_json::json!({
+            "recipe": "castle", "steps": 12, "return_schedule": false, "metadata": {"run": "request"},
+        }));
+        let merged = resolve_recipe(body, &config).await.unwrap();
+        let params = merged.to_params(&config.server).unwrap();
+        assert_eq!(params.prompt, "a castle");
+        assert_eq!(params.num_inference_steps, 12);
+        assert_eq!((params.guidance_scale, params.seed), (9.0, Some(5)));
+        // An explicit false overrides the recipe; unset flags inherit it
+        assert!(!params.return_schedule);
+        assert!(params.debug);
+        assert_eq!(merged.metadata["team"], "art");
+        assert_eq!(merged.metadata["run"], "request");
+
+        let missing = request(serde_json::json!({"recipe": "moat"}));
+        assert!(matches!(resolve_recipe(missing, &config).await, Err(DiffusionError::InvalidParameters(_))));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[actix_web::test]
+    async fn binary_download_name_is_a_safe_slug() {
+        let body = serde_json::json!({
+            "prompt": "A cat\"\r\nSet-Cookie: x=1/../evil",
+            "width": 64, "height": 64, "steps": 4, "seed": 42
+        });
+        let response = post(mock_config(), "/v1/generate/binary", body).await;
+        assert_eq!(response.status(), 200);
+
+        let disposition = response.headers().get(header::CONTENT_DISPOSITION).unwrap().to_str().unwrap();
+        assert!(
+            disposition.starts_with("attachment; filename=\"a-cat-set-cookie-x-1-evil_42."),
+            "{}",
+            disposition
+        );
+        assert!(!disposition.contains(['\r', '\n', '/']));
+    }
+
+    #[actix_web::test]
+    async fn result_id_round_trips_to_its_seed() {
+        let body = serde_json::json!({"prompt": "a lighthouse", "width": 64, "height": 64, "steps": 4, "seed": 1234});
+        let response: serde_json::Value = test::read_body_json(post(mock_config(), "/v1/generate", body).await).await;
+        let token = response["results"][0]["result_id"].as_str().unwrap().to_string();
+        assert!(token.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.')), "{}", token);
+
+        let (job_id, seed) = result_id::decode(&token).unwrap();
+        assert_eq!(seed, response["results"][0]["seed"].as_i64().unwrap());
+        assert_eq!(seed, 1234);
+
+        let uri = format!("/v1/results/{}", token);
+        let lookup = call(mock_config(), test::TestRequest::get().uri(&uri)).await;
+        assert_eq!(lookup.status(), 200);
+        let lookup: serde_json::Value = test::read_body_json(lookup).await;
+        assert_eq!(lookup["job_id"], job_id);
+        assert_eq!(lookup["seed"], 1234);
+
+        let lookup = call(mock_config(), test::TestRequest::get().uri("/v1/results/not-a-result")).await;
+        assert_eq!(lookup.status(), 404);
+    }
+
+    #[actix_web::test]
+    async fn rest_uses_its_default_format_unless_the_request_sets_one() {
+        let mut config = mock_config();
+        config.server.rest_output_format = Some("jpeg".to_string());
+        config.server.grpc_output_format = Some("webp".to_string());
+        let format_of = |response: serde_json::Value| {
+            let bytes = base64::decode(response["images_base64"][0].as_str().unwrap()).unwrap();
+            image::guess_format(&bytes).unwrap()
+        };
+
+        let body = serde_json::json!({"prompt": "a lighthouse", "width": 64, "height": 64, "steps": 4});
+        let response = test::read_body_json(post(config.clone(), "/v1/generate", body).await).await;
+        assert_eq!(format_of(response), image::ImageFormat::Jpeg);
+
+        let body = serde_json::json!({"prompt": "a lighthouse", "width": 64, "height": 64, "steps": 4, "format": "png"});
+        let response = test::read_body_json(post(config, "/v1/generate", body).await).await;
+        assert_eq!(format_of(response), image::ImageFormat::Png);
+    }
+
+    #[actix_web::test]
+    async fn problem_lists_every_validation_error() {
+        let body = serde_json::json!({"prompt": "a lighthouse", "width": 100000, "steps": 9999, "num_images": 0});
+        let response = post(mock_config(), "/v1/generate", body).await;
+        assert_eq!(response.status(), 400);
+
+        let problem: serde_json::Value = test::read_body_json(response).await;
+        let errors: Vec<&str> = problem["errors"].as_array().unwrap().iter().filter_map(|e| e.as_str()).collect();
+        assert!(errors.len() >= 3, "{:?}", errors);
+        for expected in ["Width", "Steps", "num_images"] {
+            assert!(errors.iter().any(|e| e.starts_with(expected)), "{} missing from {:?}", expected, errors);
+        }
+    }
+
+    #[actix_web::test]
+    async fn binary_headers_match_the_resolved_parameters() {
+        let body = serde_json::json!({
+            "prompt": "a lighthouse", "width": 64, "height": 64, "steps": 6,
+            "guidance_scale": 5.5, "scheduler": "euler", "seed": 77
+        });
+        let response = post(mock_config(), "/v1/generate/binary", body).await;
+        assert_eq!(response.status(), 200);
+
+        let header = |name: &str| response.headers().get(name).unwrap().to_str().unwrap().to_string();
+        assert_eq!(header(generation_headers::SEED), "77");
+        assert_eq!(header(generation_headers::STEPS), "6");
+        assert_eq!(header(generation_headers::GUIDANCE_SCALE), "5.5");
+        assert_eq!(header(generation_headers::SCHEDULER), "euler");
+        assert_eq!(header(generation_headers::MODEL), mock_config().model.default_model);
+        assert_eq!(header(generation_headers::METADATA_VERSION_HEADER), METADATA_VERSION.to_string());
+    }
+}
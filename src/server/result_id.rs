@@ -0,0 +1,17 @@
+//! Result ids: one URL-safe token naming a job and the seed of one of its
+//! images, e.g. `6f1c...-9d2e.1234`
+
+/// Separator between the job id and the seed. Unreserved in URLs and never
+/// part of a UUID.
+const SEPARATOR: char = '.';
+
+pub fn encode(job_id: &str, seed: i64) -> String {
+    format!("{}{}{}", job_id, SEPARATOR, seed)
+}
+
+/// Splits a result id back into its job id and seed
+pub fn decode(result_id: &str) -> Option<(&str, i64)> {
+    let (job_id, seed) = result_id.rsplit_once(SEPARATOR)?;
+    uuid::Uuid::parse_str(job_id).ok()?;
+    Some((job_id, seed.parse().ok()?))
+}
@@ -0,0 +1,55 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+struct Upload {
+    data: Vec<u8>,
+    expires_at: Instant,
+}
+
+/// Images uploaded ahead of a generation request, referenced by handle
+/// until they expire
+pub struct UploadStore {
+    uploads: Mutex<HashMap<String, Upload>>,
+    ttl: Duration,
+}
+
+impl UploadStore {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            uploads: Mutex::new(HashMap::new()),
+            ttl,
+        }
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Stores the upload and returns its handle
+    pub async fn insert(&self, data: Vec<u8>) -> String {
+        let handle = Uuid::new_v4().to_string();
+        let mut uploads = self.uploads.lock().await;
+
+        Self::purge_expired(&mut uploads);
+        uploads.insert(handle.clone(), Upload {
+            data,
+            expires_at: Instant::now() + self.ttl,
+        });
+
+        handle
+    }
+
+    pub async fn get(&self, handle: &str) -> Option<Vec<u8>> {
+        let mut uploads = self.uploads.lock().await;
+
+        Self::purge_expired(&mut uploads);
+        uploads.get(handle).map(|upload| upload.data.clone())
+    }
+
+    fn purge_expired(uploads: &mut HashMap<String, Upload>) {
+        let now = Instant::now();
+        uploads.retain(|_, upload| upload.expires_at > now);
+    }
+}